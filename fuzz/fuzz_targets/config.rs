@@ -0,0 +1,11 @@
+// the configuration and metadata blobs, both taken from fw_cfg as the host passes them
+
+#![no_main]
+
+use igd_assignment::{config::IgdConfig, fw_cfg::MemoryFwCfg, metadata};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = IgdConfig::parse(data);
+	let _ = metadata::load(&mut MemoryFwCfg(&[("etc/igd-metadata", data)]));
+});
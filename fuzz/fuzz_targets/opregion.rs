@@ -0,0 +1,24 @@
+// OpRegion, extended VBT and VBT block parsing, on whatever the host might pass as etc/igd-opregion
+
+#![no_main]
+
+use igd_assignment::opregion;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let signatures = [*opregion::SIGNATURE];
+
+	// the size the blob claims to have is checked against its real one, so try both
+	for blob_size in [data.len(), opregion::declared_size(data)] {
+		if let Ok(version) = opregion::validate_header(data, blob_size, &signatures) {
+			let _ = opregion::extended_vbt(data, version);
+		}
+	}
+
+	if let Some(blocks) = opregion::blocks(data) {
+		blocks.for_each(drop);
+	}
+
+	opregion::dump(data, log::Level::Trace);
+	opregion::hexdump(data, log::Level::Trace);
+});
@@ -0,0 +1,204 @@
+// Host-side preparation of the fw_cfg files igd-assignment expects: the OpRegion, the extended VBT
+// and the stolen memory size, read from the host's IGD through sysfs and /dev/mem. Prints the QEMU
+// arguments passing them, so users don't have to assemble the blobs and file names by hand.
+//
+// Needs root for the full config space and /dev/mem. The OpRegion lives in ACPI NVS, which
+// CONFIG_STRICT_DEVMEM still allows reading; CONFIG_IO_STRICT_DEVMEM may not.
+
+#[allow(dead_code)]
+#[path = "../../src/generation.rs"]
+mod generation;
+
+use std::{
+	env,
+	fs::{self, File},
+	io::{Read, Seek, SeekFrom},
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+use generation::Generation;
+
+const PCI_VENDOR_INTEL: u16 = 0x8086;
+const PCI_CLASS_VGA: u32 = 0x030000;
+
+const PCI_CFG_GGC_OFFSET: usize = 0x50;
+const PCI_CFG_ASLS_OFFSET: usize = 0xFC;
+
+// the same constants as in src/opregion.rs
+const OPREGION_SIGNATURE: &[u8; 16] = b"IntelGraphicsMem";
+const OPREGION_HEADER_SIZE: usize = 0x100;
+const HEADER_SIZE_OFFSET: usize = 0x10;
+const HEADER_OVER_OFFSET: usize = 0x14;
+const RVDA_OFFSET: usize = 0x3BA;
+const RVDS_OFFSET: usize = 0x3C2;
+
+const OPREGION_FILE: &str = "igd-opregion.bin";
+const VBT_FILE: &str = "igd-vbt.bin";
+const BDSM_SIZE_FILE: &str = "igd-bdsm-size.bin";
+
+struct Args {
+	device: String,
+	out: PathBuf,
+}
+
+fn usage() -> String {
+	"usage: igd-prep [--device seg:bus:dev.func] [--out DIR]".to_string()
+}
+
+fn parse_args() -> Result<Args, String> {
+	let mut args = Args {
+		device: "0000:00:02.0".to_string(),
+		out: PathBuf::from("."),
+	};
+
+	let mut iter = env::args().skip(1);
+
+	while let Some(arg) = iter.next() {
+		match arg.as_str() {
+			"--device" => args.device = iter.next().ok_or_else(usage)?,
+			"--out" => args.out = iter.next().ok_or_else(usage)?.into(),
+			"--help" | "-h" => return Err(usage()),
+			_ => return Err(format!("unknown argument '{}'\n{}", arg, usage())),
+		}
+	}
+
+	Ok(args)
+}
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], offset: usize) -> u64 {
+	u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_phys(addr: u64, len: usize) -> Result<Vec<u8>, String> {
+	let mut mem = File::open("/dev/mem").map_err(|e| format!("can't open /dev/mem: {}", e))?;
+	let mut buf = vec![0u8; len];
+
+	mem.seek(SeekFrom::Start(addr))
+		.and_then(|_| mem.read_exact(&mut buf))
+		.map_err(|e| format!("can't read {} bytes at {:#x} from /dev/mem: {}", len, addr, e))?;
+
+	Ok(buf)
+}
+
+fn write_file(dir: &Path, name: &str, data: &[u8]) -> Result<PathBuf, String> {
+	let path = dir.join(name);
+	fs::write(&path, data).map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+
+	Ok(path)
+}
+
+// the OpRegion ASLS points at, in the size its header states
+fn read_opregion(asls: u64) -> Result<Vec<u8>, String> {
+	if asls == 0 {
+		return Err("ASLS is 0, the host firmware didn't set up an OpRegion".to_string());
+	}
+
+	let header = read_phys(asls, OPREGION_HEADER_SIZE)?;
+
+	if &header[..OPREGION_SIGNATURE.len()] != OPREGION_SIGNATURE {
+		return Err(format!("no OpRegion signature at ASLS {:#x}", asls));
+	}
+
+	let size = le32(&header, HEADER_SIZE_OFFSET) as usize * 1024;
+
+	if size < OPREGION_HEADER_SIZE {
+		return Err(format!("OpRegion at ASLS {:#x} states a size of only {} bytes", asls, size));
+	}
+
+	read_phys(asls, size)
+}
+
+// the extended VBT referenced by RVDA/RVDS, `None` if the OpRegion has none
+fn read_extended_vbt(asls: u64, opregion: &[u8]) -> Result<Option<Vec<u8>>, String> {
+	let (minor, major) = (opregion[HEADER_OVER_OFFSET + 2], opregion[HEADER_OVER_OFFSET + 3]);
+
+	if major < 2 || opregion.len() < RVDS_OFFSET + 4 {
+		return Ok(None);
+	}
+
+	let (rvda, rvds) = (le64(opregion, RVDA_OFFSET), le32(opregion, RVDS_OFFSET));
+
+	if rvda == 0 || rvds == 0 {
+		return Ok(None);
+	}
+
+	// 2.0 has an absolute address, 2.1 and newer an offset from the OpRegion
+	let addr = if (major, minor) == (2, 0) { rvda } else { asls + rvda };
+	read_phys(addr, rvds as usize).map(Some)
+}
+
+fn run() -> Result<(), String> {
+	let args = parse_args()?;
+	let sysfs = Path::new("/sys/bus/pci/devices").join(&args.device);
+
+	let config = fs::read(sysfs.join("config")).map_err(|e| format!("can't read the config space of {}: {}", args.device, e))?;
+
+	if config.len() <= PCI_CFG_ASLS_OFFSET {
+		return Err(format!("only {} bytes of config space readable, run as root", config.len()));
+	}
+
+	let (vendor, device) = (le16(&config, 0x00), le16(&config, 0x02));
+	let class = le32(&config, 0x08) >> 8;
+
+	if vendor != PCI_VENDOR_INTEL || class != PCI_CLASS_VGA {
+		return Err(format!("{} ({:04x}:{:04x}, class {:06x}) is not an Intel VGA controller", args.device, vendor, device, class));
+	}
+
+	let generation = Generation::from_device_id(device);
+	eprintln!("{}: {:04x}:{:04x} ({:?})", args.device, vendor, device, generation);
+
+	match fs::read_link(sysfs.join("driver")) {
+		Ok(driver) if driver.file_name().is_some_and(|d| d != "vfio-pci") => {
+			eprintln!("warning: {} is bound to {}, not vfio-pci", args.device, driver.display());
+		}
+		_ => {}
+	}
+
+	let asls = le32(&config, PCI_CFG_ASLS_OFFSET) as u64;
+	let opregion = read_opregion(asls)?;
+	eprintln!("OpRegion {}.{} @ {:#x}, {} bytes", opregion[HEADER_OVER_OFFSET + 3], opregion[HEADER_OVER_OFFSET + 2], asls, opregion.len());
+
+	let ggc = le16(&config, PCI_CFG_GGC_OFFSET);
+	let bdsm_size = generation.and_then(|g| g.decode_gms(ggc))
+		.ok_or_else(|| format!("can't decode the stolen memory size from GGC {:#06x}", ggc))?;
+	eprintln!("Stolen memory: {} MiB (GGC {:#06x})", bdsm_size / 1024 / 1024, ggc);
+
+	fs::create_dir_all(&args.out).map_err(|e| format!("can't create {}: {}", args.out.display(), e))?;
+
+	let opregion_path = write_file(&args.out, OPREGION_FILE, &opregion)?;
+	let bdsm_size_path = write_file(&args.out, BDSM_SIZE_FILE, &(bdsm_size as u64).to_le_bytes())?;
+	let vbt_path = match read_extended_vbt(asls, &opregion)? {
+		Some(vbt) => Some(write_file(&args.out, VBT_FILE, &vbt)?),
+		None => None,
+	};
+
+	// QEMU's own OpRegion passthrough would add a second etc/igd-opregion
+	println!("-device vfio-pci,host={},addr=02.0,x-igd-opregion=off", args.device);
+	println!("-fw_cfg name=etc/igd-opregion,file={}", opregion_path.display());
+	println!("-fw_cfg name=etc/igd-bdsm-size,file={}", bdsm_size_path.display());
+
+	if let Some(vbt_path) = vbt_path {
+		println!("-fw_cfg name=etc/igd-vbt,file={}", vbt_path.display());
+	}
+
+	Ok(())
+}
+
+fn main() -> ExitCode {
+	match run() {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("igd-prep: {}", e);
+			ExitCode::FAILURE
+		}
+	}
+}
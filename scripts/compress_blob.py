@@ -0,0 +1,37 @@
+#!/usr/bin/env python3
+
+# Compresses an OpRegion, VBT, GOP driver or option ROM for passing through fw_cfg: the `IGDZ`
+# header with the method and the decompressed size, followed by raw DEFLATE data.
+
+import argparse
+import pathlib
+import struct
+import sys
+import zlib
+
+MAGIC = b"IGDZ"
+METHOD_DEFLATE = 1
+
+
+def compress(data):
+    compressor = zlib.compressobj(9, zlib.DEFLATED, -15)
+    return struct.pack("<4sII", MAGIC, METHOD_DEFLATE, len(data)) + compressor.compress(data) + compressor.flush()
+
+
+def main():
+    parser = argparse.ArgumentParser(description="compress a blob for passing through fw_cfg")
+    parser.add_argument("input", type=pathlib.Path)
+    parser.add_argument("output", type=pathlib.Path)
+    args = parser.parse_args()
+
+    data = args.input.read_bytes()
+    if len(data) > 0xFFFFFFFF:
+        sys.exit(f"{args.input} is too large")
+
+    compressed = compress(data)
+    args.output.write_bytes(compressed)
+    print(f"{args.input}: {len(data)} -> {len(compressed)} bytes")
+
+
+if __name__ == "__main__":
+    main()
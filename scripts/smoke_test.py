@@ -0,0 +1,217 @@
+#!/usr/bin/env python3
+
+# End-to-end smoke test: boots OVMF with synthetic fw_cfg files, loads the driver from the UEFI
+# shell and checks that ASLS and BDSM were programmed by dumping the IGD's config space afterwards.
+#
+# Stock QEMU has no Intel display controller model, so the device at 00:02.0 has to be provided
+# with --device, e.g. an assigned IGD (vfio-pci,host=00:02.0) or a patched QEMU exposing 8086:xxxx
+# with class 0x030000.
+
+import argparse
+import ctypes
+import pathlib
+import re
+import shutil
+import subprocess
+import sys
+import tempfile
+
+PAGE_SIZE = 0x1000
+
+OPREGION_SIGNATURE = b"IntelGraphicsMem"
+OPREGION_SIZE = 8 * 1024
+
+PCI_CFG_BDSM_MIRROR_OFFSET = 0x5C
+PCI_CFG_BDSM64_OFFSET = 0xC0
+PCI_CFG_ASLS_OFFSET = 0xFC
+
+CONFIG_MAGIC = b"IGDC"
+CONFIG_VERSION = 1
+TAG_REQUIRE_DEVICE = 0x0002
+
+# fw_cfg, aligned allocation and IGD config space
+SELFTEST_CHECKS = 3
+
+
+class OpRegionHeader(ctypes.Structure):
+    _pack_ = 1
+    _fields_ = [
+        ("signature", ctypes.c_char * 16),
+        ("size", ctypes.c_uint32),
+        ("over_reserved", ctypes.c_uint8),
+        ("over_revision", ctypes.c_uint8),
+        ("over_minor", ctypes.c_uint8),
+        ("over_major", ctypes.c_uint8),
+        ("sver", ctypes.c_char * 32),
+    ]
+
+
+def synthetic_opregion():
+    header = OpRegionHeader()
+    header.signature = OPREGION_SIGNATURE
+    header.size = OPREGION_SIZE // 1024
+    header.over_major = 2
+    header.over_minor = 0
+    header.sver = b"igd-assignment smoke test"
+
+    blob = bytearray(OPREGION_SIZE)
+    raw = bytes(header)
+    blob[: len(raw)] = raw
+    return bytes(blob)
+
+
+def config_blob():
+    blob = bytearray(CONFIG_MAGIC + CONFIG_VERSION.to_bytes(2, "little"))
+    blob += b"\x00" * (8 - len(blob))
+    blob += TAG_REQUIRE_DEVICE.to_bytes(2, "little") + (1).to_bytes(2, "little") + b"\x01"
+    return bytes(blob)
+
+
+# the shell's `pci` command prints the first 256 bytes as "  00000010: xx xx ..." lines
+def parse_config_dump(output):
+    config = bytearray(256)
+
+    for match in re.finditer(r"^\s*([0-9A-Fa-f]{8}):\s+((?:[0-9A-Fa-f]{2}[\s*-]+){1,16})", output, re.M):
+        offset = int(match.group(1), 16)
+        values = [int(b, 16) for b in re.findall(r"[0-9A-Fa-f]{2}", match.group(2))]
+
+        if offset + len(values) <= len(config):
+            config[offset : offset + len(values)] = bytes(values)
+
+    return config
+
+
+def dword(config, offset):
+    return int.from_bytes(config[offset : offset + 4], "little")
+
+
+parser = argparse.ArgumentParser()
+parser.add_argument(
+    "--ovmf-code", type=pathlib.Path, required=True, help="OVMF_CODE.fd firmware image"
+)
+parser.add_argument(
+    "--ovmf-vars", type=pathlib.Path, required=True, help="OVMF_VARS.fd template, copied before use"
+)
+parser.add_argument(
+    "--efi",
+    type=pathlib.Path,
+    default=pathlib.Path("target/x86_64-unknown-uefi/release/igd-assignment.efi"),
+    help="driver binary to test",
+)
+parser.add_argument(
+    "--device",
+    required=True,
+    help="QEMU -device argument for the Intel display controller, placed at 00:02.0",
+)
+parser.add_argument(
+    "--bdsm-size",
+    type=lambda x: int(x, 0),
+    default=8 * 1024 * 1024,
+    help="stolen memory size passed in etc/igd-bdsm-size",
+)
+parser.add_argument(
+    "--selftest", action="store_true", help="run the built-in self-test instead of the driver setup"
+)
+parser.add_argument("--qemu", default="qemu-system-x86_64", help="QEMU binary")
+parser.add_argument("--timeout", type=int, default=120, help="seconds until the run is aborted")
+
+args = parser.parse_args()
+
+with tempfile.TemporaryDirectory() as tmp:
+    tmp = pathlib.Path(tmp)
+    esp = tmp / "esp"
+    esp.mkdir()
+
+    shutil.copy(args.efi, esp / "igd-assignment.efi")
+    (esp / "startup.nsh").write_text(
+        ("fs0:\\igd-assignment.efi --selftest\r\n" if args.selftest else "load fs0:\\igd-assignment.efi\r\n")
+        + "echo IGD-SMOKE-BEGIN\r\n"
+        "pci 00 02 00\r\n"
+        "echo IGD-SMOKE-END\r\n"
+        "reset -s\r\n"
+    )
+
+    opregion = tmp / "igd-opregion"
+    opregion.write_bytes(synthetic_opregion())
+    bdsm_size = tmp / "igd-bdsm-size"
+    bdsm_size.write_bytes(args.bdsm_size.to_bytes(8, "little"))
+    config = tmp / "igd-config"
+    config.write_bytes(config_blob())
+    ovmf_vars = tmp / "OVMF_VARS.fd"
+    shutil.copy(args.ovmf_vars, ovmf_vars)
+
+    command = [
+        args.qemu,
+        "-machine", "q35",
+        "-m", "512M",
+        "-nographic",
+        "-no-reboot",
+        "-vga", "none",
+        "-drive", f"if=pflash,format=raw,readonly=on,file={args.ovmf_code}",
+        "-drive", f"if=pflash,format=raw,file={ovmf_vars}",
+        "-drive", f"format=raw,file=fat:rw:{esp}",
+        "-fw_cfg", f"name=etc/igd-opregion,file={opregion}",
+        "-fw_cfg", f"name=etc/igd-bdsm-size,file={bdsm_size}",
+        "-fw_cfg", f"name=etc/igd-config,file={config}",
+        "-device", f"{args.device},addr=02.0",
+    ]
+
+    print(" ".join(command))
+
+    try:
+        run = subprocess.run(command, capture_output=True, text=True, errors="replace", timeout=args.timeout)
+    except subprocess.TimeoutExpired:
+        sys.exit(f"QEMU did not finish within {args.timeout} seconds")
+
+output = run.stdout
+(pathlib.Path("smoke_test.log")).write_text(output)
+
+begin = output.find("IGD-SMOKE-BEGIN")
+end = output.find("IGD-SMOKE-END", begin)
+if begin < 0 or end < 0:
+    sys.exit("the shell script didn't run to completion, see smoke_test.log")
+
+if args.selftest:
+    checks = re.findall(r"\[(PASS|FAIL)\] (.*)", output)
+    for result, line in checks:
+        print(f"{result}: {line.strip()}")
+
+    if len(checks) != SELFTEST_CHECKS or any(result == "FAIL" for result, _ in checks):
+        sys.exit("the self-test failed, see smoke_test.log")
+
+    print("PASS")
+    sys.exit(0)
+
+config = parse_config_dump(output[begin:end])
+asls = dword(config, PCI_CFG_ASLS_OFFSET)
+# Gen11 and newer use the 64-bit register at 0xC0 instead of the mirror
+bdsm = dword(config, PCI_CFG_BDSM_MIRROR_OFFSET) or (
+    dword(config, PCI_CFG_BDSM64_OFFSET) | dword(config, PCI_CFG_BDSM64_OFFSET + 4) << 32
+)
+
+print(f"ASLS = {asls:#010x}")
+print(f"BDSM = {bdsm:#x}")
+
+failed = False
+
+if asls == 0 or asls % PAGE_SIZE != 0:
+    print("FAIL: ASLS was not programmed with a page-aligned OpRegion address")
+    failed = True
+
+if bdsm == 0 or bdsm % (1024 * 1024) != 0:
+    print("FAIL: BDSM was not programmed with a 1 MiB aligned stolen memory address")
+    failed = True
+
+if "OpRegion @" not in output or "StolenMemory @" not in output:
+    print("FAIL: the driver didn't report both setup steps, see smoke_test.log")
+    failed = True
+
+for line in output.splitlines():
+    if "[ERROR]" in line or "igd-assignment: " in line:
+        print(f"FAIL: {line.strip()}")
+        failed = True
+
+if failed:
+    sys.exit(1)
+
+print("PASS")
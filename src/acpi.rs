@@ -0,0 +1,138 @@
+// ACPI table access through EFI_ACPI_TABLE_PROTOCOL, which installs and removes tables, and
+// EFI_ACPI_SDT_PROTOCOL, which lists the installed ones along with the keys needed to remove them.
+
+use alloc::vec::Vec;
+use core::{ffi::c_void, slice};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol}, proto::{unsafe_protocol, ProtocolPointer}, Status};
+
+pub const HEADER_SIZE: usize = 36;
+const LENGTH_OFFSET: usize = 4;
+const CHECKSUM_OFFSET: usize = 9;
+
+const OEM_ID: &[u8; 6] = b"IGDASN";
+const CREATOR_ID: &[u8; 4] = b"IGDA";
+
+#[repr(C)]
+struct AcpiTableProtocol {
+	install_acpi_table: unsafe extern "efiapi" fn(*const AcpiTableProtocol, *const c_void, usize, *mut usize) -> Status,
+	uninstall_acpi_table: unsafe extern "efiapi" fn(*const AcpiTableProtocol, usize) -> Status,
+}
+
+#[unsafe_protocol("ffe06bdd-6107-46a6-7bb2-5a9c7ec5275c")]
+struct AcpiTable(AcpiTableProtocol);
+
+#[repr(C)]
+struct AcpiSdtProtocol {
+	acpi_version: u32,
+	get_acpi_table: unsafe extern "efiapi" fn(usize, *mut *const u8, *mut u32, *mut usize) -> Status,
+	// followed by the AML functions, which aren't used
+}
+
+#[unsafe_protocol("eb97088e-cfdf-49c6-be4b-d906a5b20e86")]
+struct AcpiSdt(AcpiSdtProtocol);
+
+fn open<P: ProtocolPointer + ?Sized>() -> Option<ScopedProtocol<P>> {
+	let handle = boot::get_handle_for_protocol::<P>().ok()?;
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	unsafe { boot::open_protocol::<P>(params, OpenProtocolAttributes::GetProtocol) }.ok()
+}
+
+// a table header with a zero length and checksum, which `finish` fills in
+pub fn header(signature: &[u8; 4], revision: u8, oem_table_id: &[u8; 8]) -> Vec<u8> {
+	let mut table = alloc::vec![0u8; HEADER_SIZE];
+	table[0..4].copy_from_slice(signature);
+	table[8] = revision;
+	table[10..16].copy_from_slice(OEM_ID);
+	table[16..24].copy_from_slice(oem_table_id);
+	table[24..28].copy_from_slice(&1u32.to_le_bytes());
+	table[28..32].copy_from_slice(CREATOR_ID);
+	table[32..36].copy_from_slice(&1u32.to_le_bytes());
+	table
+}
+
+// update the length and checksum after the table was built or modified
+pub fn finish(table: &mut [u8]) {
+	let len = table.len() as u32;
+	table[LENGTH_OFFSET..LENGTH_OFFSET + 4].copy_from_slice(&len.to_le_bytes());
+	table[CHECKSUM_OFFSET] = 0;
+
+	let sum = table.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+	table[CHECKSUM_OFFSET] = 0u8.wrapping_sub(sum);
+}
+
+// the protocol copies the table, returns its key
+pub fn install(table: &[u8]) -> Result<usize, Status> {
+	let acpi = open::<AcpiTable>().ok_or(Status::NOT_FOUND)?;
+	let mut key = 0;
+
+	match unsafe { (acpi.0.install_acpi_table)(&acpi.0, table.as_ptr() as *const c_void, table.len(), &mut key) } {
+		Status::SUCCESS => Ok(key),
+		status => Err(status),
+	}
+}
+
+pub fn uninstall(key: usize) -> Result<(), Status> {
+	let acpi = open::<AcpiTable>().ok_or(Status::NOT_FOUND)?;
+
+	match unsafe { (acpi.0.uninstall_acpi_table)(&acpi.0, key) } {
+		Status::SUCCESS => Ok(()),
+		status => Err(status),
+	}
+}
+
+// a copy of the first installed table with `signature`, and its key
+pub fn find(signature: &[u8; 4]) -> Option<(Vec<u8>, usize)> {
+	let sdt = open::<AcpiSdt>()?;
+
+	for index in 0.. {
+		let mut table: *const u8 = core::ptr::null();
+		let mut version = 0;
+		let mut key = 0;
+
+		if unsafe { (sdt.0.get_acpi_table)(index, &mut table, &mut version, &mut key) }.is_error() {
+			break;
+		}
+
+		let header = unsafe { slice::from_raw_parts(table, HEADER_SIZE) };
+
+		if &header[0..4] != signature {
+			continue;
+		}
+
+		let len = u32::from_le_bytes(header[LENGTH_OFFSET..LENGTH_OFFSET + 4].try_into().unwrap()) as usize;
+
+		if len < HEADER_SIZE {
+			continue;
+		}
+
+		return Some((unsafe { slice::from_raw_parts(table, len) }.to_vec(), key));
+	}
+
+	None
+}
+
+// QEMU describes the MMIO fw_cfg of ARM machines in the DSDT, as a `QEMU0002` device whose `_CRS` is a
+// single Memory32Fixed descriptor right behind its `_HID`
+#[cfg(target_arch = "aarch64")]
+pub fn fw_cfg_base() -> Option<usize> {
+	const HID: &[u8] = b"QEMU0002\0";
+	// Memory32Fixed: the tag, a length of 9 and the read/write flag, then the base and length
+	const MEMORY32_FIXED: [u8; 3] = [0x86, 0x09, 0x00];
+	const SEARCH_WINDOW: usize = 128;
+
+	let (dsdt, _) = find(b"DSDT")?;
+	let aml = dsdt.get(HEADER_SIZE..)?;
+
+	let hid = aml.windows(HID.len()).position(|w| w == HID)?;
+	let window = &aml[hid..aml.len().min(hid + SEARCH_WINDOW)];
+	let descriptor = window.windows(MEMORY32_FIXED.len()).position(|w| w == MEMORY32_FIXED)?;
+
+	let base = window.get(descriptor + 4..descriptor + 8)?;
+	Some(u32::from_le_bytes(base.try_into().unwrap()) as usize)
+}
@@ -0,0 +1,46 @@
+// The devices `etc/igd-device-id` restricts setup to, for hosts passing through an Intel discrete card
+// besides the IGD, where programming ASLS on the wrong function is harmful. The file is text, with
+// entries separated by whitespace or commas, each either a hexadecimal `vendor:device` ID pair or a
+// `[seg:]bus:dev.func` location as in `etc/igd-bdf`.
+
+use alloc::vec::Vec;
+
+use crate::args::{self, Location};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Entry {
+	Id { vendor: u16, device: u16 },
+	Location(Location),
+}
+
+fn parse_entry(entry: &str) -> Option<Entry> {
+	// only locations have a function number
+	if entry.contains('.') {
+		return args::parse_bdf(entry).map(Entry::Location);
+	}
+
+	let (vendor, device) = entry.split_once(':')?;
+
+	Some(Entry::Id {
+		vendor: u16::from_str_radix(vendor, 16).ok()?,
+		device: u16::from_str_radix(device, 16).ok()?,
+	})
+}
+
+// `Err` holds the first malformed entry, an empty list would match nothing and is rejected as well
+pub fn parse(text: &str) -> Result<Vec<Entry>, &str> {
+	let entries = text.split(|c: char| c.is_whitespace() || c == ',' || c == '\0')
+		.filter(|e| !e.is_empty())
+		.map(|e| parse_entry(e).ok_or(e))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	if entries.is_empty() {
+		return Err("");
+	}
+
+	Ok(entries)
+}
+
+pub fn allows(entries: &[Entry], vendor: u16, device: u16, location: Location) -> bool {
+	entries.iter().any(|&e| e == Entry::Id { vendor, device } || e == Entry::Location(location))
+}
@@ -0,0 +1,110 @@
+use alloc::string::{String, ToString};
+use log::{error, warn};
+use uefi::{boot, proto::{loaded_image::LoadedImage, shell_params::ShellParameters}};
+
+// PCI segment, bus, device and function, in the order `PciIo::get_location` returns them
+pub type Location = (usize, usize, usize, usize);
+
+#[derive(Default)]
+pub struct Args {
+	// only configure the device at this location instead of scanning
+	pub configure: Option<Location>,
+	// validate the setup without changing anything
+	pub check: bool,
+	// print what would be done without changing anything
+	pub dry_run: bool,
+	// report the current OpRegion and stolen memory state
+	pub verify: bool,
+	// exercise fw_cfg, the allocator and config space access for CI
+	pub selftest: bool,
+	// debug log output
+	pub verbose: bool,
+	// where the IGD is, overriding `etc/igd-bdf`
+	pub bdf: Option<Location>,
+	// only set up the OpRegion
+	pub no_bdsm: bool,
+	// image to start once setup is done, overriding `etc/igd-chainload`
+	pub chainload: Option<String>,
+	// started from the UEFI shell, where someone is watching the console
+	pub interactive: bool,
+}
+
+// options are only present when started from the shell or a boot option, never as an option ROM
+fn load_options() -> Option<String> {
+	let image = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()).ok()?;
+	let options = image.load_options_as_cstr16().ok()?;
+
+	Some(options.to_string())
+}
+
+// the shell installs its parameters protocol on every image it starts
+fn started_from_shell() -> bool {
+	boot::open_protocol_exclusive::<ShellParameters>(boot::image_handle()).is_ok()
+}
+
+pub fn parse() -> Args {
+	let mut args = Args { interactive: started_from_shell(), ..Args::default() };
+
+	let Some(options) = load_options() else {
+		return args;
+	};
+
+	// the first word is the image path when started from the shell
+	let mut words = options.split_whitespace().skip_while(|w| !w.starts_with("--"));
+
+	while let Some(arg) = words.next() {
+		match arg {
+			"--check" => args.check = true,
+			"--dry-run" => args.dry_run = true,
+			"--verify" => args.verify = true,
+			"--selftest" => args.selftest = true,
+			"--verbose" => args.verbose = true,
+			"--no-bdsm" => args.no_bdsm = true,
+			"--bdf" => args.bdf = words.next().and_then(bdf_arg),
+			"--chainload" => args.chainload = words.next().map(String::from),
+			_ => {
+				if let Some(bdf) = arg.strip_prefix("--configure=") {
+					args.configure = bdf_arg(bdf);
+				} else if let Some(bdf) = arg.strip_prefix("--bdf=") {
+					args.bdf = bdf_arg(bdf);
+				} else if let Some(path) = arg.strip_prefix("--chainload=") {
+					args.chainload = Some(String::from(path));
+				} else {
+					warn!("Ignoring unknown argument '{}'", arg);
+				}
+			}
+		}
+	}
+
+	args
+}
+
+fn bdf_arg(bdf: &str) -> Option<Location> {
+	let location = parse_bdf(bdf);
+
+	if location.is_none() {
+		error!("Invalid BDF '{}', expected [seg:]bus:dev.func!", bdf);
+	}
+
+	location
+}
+
+// accepts hexadecimal `bus:dev.func` or `seg:bus:dev.func`, e.g. `0:2.0` or `0000:00:02.0`
+pub fn parse_bdf(bdf: &str) -> Option<Location> {
+	let (rest, func) = bdf.rsplit_once('.')?;
+	let mut parts = rest.rsplit(':');
+
+	let dev = usize::from_str_radix(parts.next()?, 16).ok()?;
+	let bus = usize::from_str_radix(parts.next()?, 16).ok()?;
+	let seg = match parts.next() {
+		Some(seg) => usize::from_str_radix(seg, 16).ok()?,
+		None => 0,
+	};
+	let func = usize::from_str_radix(func, 16).ok()?;
+
+	if parts.next().is_some() || bus > 0xFF || dev > 0x1F || func > 0x7 {
+		return None;
+	}
+
+	Some((seg, bus, dev, func))
+}
@@ -0,0 +1,91 @@
+// Report of the IGD's current state, for inspecting what earlier firmware components or a previous
+// run already did. Only reads: config space, the memory map and the OpRegion ASLS points at.
+
+use log::{error, info, warn, Level};
+use uefi::{boot::{self, MemoryType, ScopedProtocol}, mem::memory_map::MemoryMap, proto::pci::PciIo, Status};
+
+use crate::{
+	existing, generation::Generation, igd_location, match_device, opregion, pci, quirks::Gms, read_bdsm, CONFIG, PAGE_SIZE,
+	PCI_CFG_ASLS_OFFSET, PCI_CFG_GGC_OFFSET,
+};
+
+// the memory map entry `addr` is in, `None` if it isn't described at all
+fn memory_type_at(addr: usize) -> Option<MemoryType> {
+	let mmap = boot::memory_map(MemoryType::LOADER_DATA).ok()?;
+
+	let addr = addr as u64;
+
+	mmap.entries().find(|d| addr >= d.phys_start && addr < d.phys_start + d.page_count * PAGE_SIZE as u64).map(|d| d.ty)
+}
+
+fn audit_opregion(asls: usize) {
+	if asls == 0 {
+		info!("ASLS:  not programmed");
+		return;
+	}
+
+	let ty = memory_type_at(asls);
+	info!("ASLS:  {:#010x} ({:?})", asls, ty);
+
+	if ty != Some(MemoryType::ACPI_NON_VOLATILE) && ty != Some(MemoryType::RESERVED) {
+		warn!("ASLS doesn't point at ACPI NVS or reserved memory, not reading from it");
+		return;
+	}
+
+	let Some(existing) = existing::at_asls(asls, unsafe { CONFIG.signatures() }) else {
+		warn!("No valid OpRegion signature at ASLS");
+		return;
+	};
+
+	let data = unsafe { core::slice::from_raw_parts(existing.base as *const u8, existing.size) };
+
+	match opregion::validate_header(data, existing.size, unsafe { CONFIG.signatures() }) {
+		Ok(version) => info!("OpRegion {}.{}, header is valid", version.major, version.minor),
+		Err(reason) => warn!("OpRegion header is invalid: {}", reason),
+	}
+
+	opregion::dump(data, Level::Info);
+}
+
+fn audit_stolen_memory(pci_io: &mut ScopedProtocol<PciIo>, generation: Option<Generation>, device: u16) {
+	match read_bdsm(pci_io, generation) {
+		Some(0) => info!("BDSM:  not programmed"),
+		Some(bdsm) => info!("BDSM:  {:#x} ({:?})", bdsm, memory_type_at(bdsm)),
+		None => error!("Failed to read BDSM!"),
+	}
+
+	match (pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET), Gms::of(generation, device)) {
+		(Some(ggc), Some(gms)) => info!("GGC:   {:#06x}, GTT stolen memory {} KiB", ggc,
+			gms.gtt_stolen_memory_size(ggc).unwrap_or(0) / 1024),
+		(Some(ggc), None) => info!("GGC:   {:#06x}", ggc),
+		(None, _) => error!("Failed to read GGC!"),
+	}
+}
+
+pub fn run() -> Status {
+	let igd = igd_location();
+	let (seg, bus, dev, func) = igd;
+
+	let Some(mut pci_io) = pci::open_shared(igd) else {
+		error!("No PCI device at {:04x}:{:02x}:{:02x}.{}!", seg, bus, dev, func);
+		return Status::NOT_FOUND;
+	};
+
+	let Some(device) = match_device(&mut pci_io) else {
+		error!("{:04x}:{:02x}:{:02x}.{} is not an Intel display controller!", seg, bus, dev, func);
+		return Status::UNSUPPORTED;
+	};
+
+	let generation = Generation::from_device_id(device);
+	info!("Device {:04x} ({:?}) at {:04x}:{:02x}:{:02x}.{}", device, generation, seg, bus, dev, func);
+
+	let Some(asls) = pci::read_u32(&mut pci_io, PCI_CFG_ASLS_OFFSET) else {
+		error!("Failed to read ASLS!");
+		return Status::DEVICE_ERROR;
+	};
+
+	audit_opregion(asls as usize);
+	audit_stolen_memory(&mut pci_io, generation, device);
+
+	Status::SUCCESS
+}
@@ -0,0 +1,167 @@
+// UEFI Driver Binding and Component Name 2 support, as an alternative to the PciIo notify. The
+// firmware's ConnectController flow then decides when we attach to a device, and the driver shows
+// up by name in tools like the shell's `drivers`. The protocols are declared here as laid out in the
+// UEFI specification, sections 11.1 and 11.5.
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use igd_assignment::{configure_device, igd_location, match_device, protocol, publish_results};
+use log::{error, info};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol}, cstr16, guid, proto::pci::PciIo, CStr16, Guid, Handle, Status};
+
+const DRIVER_BINDING_GUID: Guid = guid!("18a031ab-b443-4d1a-a5c0-0c09261e9f71");
+const COMPONENT_NAME2_GUID: Guid = guid!("6a7a5cff-e8d9-4f70-bada-75ab3025ce14");
+
+// drivers with a higher version win when several support the same controller
+const DRIVER_VERSION: u32 = 0x10;
+const DRIVER_NAME: &CStr16 = cstr16!("Intel IGD assignment");
+// RFC 4646 language codes, as Component Name 2 expects them
+const SUPPORTED_LANGUAGES: &[u8] = b"en\0";
+
+type RawHandle = *mut c_void;
+
+#[repr(C)]
+struct DriverBindingProtocol {
+	supported: unsafe extern "efiapi" fn(*const DriverBindingProtocol, RawHandle, *const c_void) -> Status,
+	start: unsafe extern "efiapi" fn(*const DriverBindingProtocol, RawHandle, *const c_void) -> Status,
+	stop: unsafe extern "efiapi" fn(*const DriverBindingProtocol, RawHandle, usize, *const RawHandle) -> Status,
+	version: u32,
+	image_handle: RawHandle,
+	driver_binding_handle: RawHandle,
+}
+
+#[repr(C)]
+struct ComponentName2Protocol {
+	get_driver_name: unsafe extern "efiapi" fn(*const ComponentName2Protocol, *const u8, *mut *const u16) -> Status,
+	get_controller_name: unsafe extern "efiapi" fn(*const ComponentName2Protocol, RawHandle, RawHandle, *const u8, *mut *const u16) -> Status,
+	supported_languages: *const u8,
+}
+
+static mut DRIVER_BINDING: DriverBindingProtocol = DriverBindingProtocol {
+	supported,
+	start,
+	stop,
+	version: DRIVER_VERSION,
+	image_handle: core::ptr::null_mut(),
+	driver_binding_handle: core::ptr::null_mut(),
+};
+
+static mut COMPONENT_NAME2: ComponentName2Protocol = ComponentName2Protocol {
+	get_driver_name,
+	get_controller_name,
+	supported_languages: SUPPORTED_LANGUAGES.as_ptr(),
+};
+
+// PciIo stays opened BY_DRIVER for as long as we manage the controller, dropping it closes it
+static mut BOUND: Vec<(Handle, ScopedProtocol<PciIo>)> = Vec::new();
+
+fn open_by_driver(controller: Handle) -> uefi::Result<ScopedProtocol<PciIo>> {
+	let params = OpenProtocolParams {
+		handle: controller,
+		agent: boot::image_handle(),
+		controller: Some(controller),
+	};
+
+	unsafe { boot::open_protocol::<PciIo>(params, OpenProtocolAttributes::ByDriver) }
+}
+
+unsafe extern "efiapi" fn supported(_this: *const DriverBindingProtocol, controller: RawHandle, _remaining: *const c_void) -> Status {
+	let Some(controller) = Handle::from_ptr(controller) else {
+		return Status::INVALID_PARAMETER;
+	};
+
+	// ALREADY_STARTED or ACCESS_DENIED have to be passed on as they are
+	let mut pci_io = match open_by_driver(controller) {
+		Ok(p) => p,
+		Err(e) => return e.status(),
+	};
+
+	match match_device(&mut pci_io) {
+		Some(_) => Status::SUCCESS,
+		None => Status::UNSUPPORTED,
+	}
+}
+
+unsafe extern "efiapi" fn start(_this: *const DriverBindingProtocol, controller: RawHandle, _remaining: *const c_void) -> Status {
+	let Some(controller) = Handle::from_ptr(controller) else {
+		return Status::INVALID_PARAMETER;
+	};
+
+	let mut pci_io = match open_by_driver(controller) {
+		Ok(p) => p,
+		Err(e) => {
+			error!("Failed to open PciIo BY_DRIVER: {:?}!", e.status());
+			return e.status();
+		}
+	};
+
+	if !configure_device(&mut pci_io, igd_location()) {
+		return Status::UNSUPPORTED;
+	}
+
+	publish_results();
+
+	if let Ok(location) = pci_io.get_location() {
+		protocol::install(controller, location);
+	}
+
+	BOUND.push((controller, pci_io));
+
+	Status::SUCCESS
+}
+
+// the OpRegion and stolen memory belong to the guest OS now, so stopping only releases the device
+unsafe extern "efiapi" fn stop(_this: *const DriverBindingProtocol, controller: RawHandle, _children: usize, _child_handles: *const RawHandle) -> Status {
+	let Some(controller) = Handle::from_ptr(controller) else {
+		return Status::INVALID_PARAMETER;
+	};
+
+	match BOUND.iter().position(|(h, _)| *h == controller) {
+		Some(i) => {
+			protocol::uninstall(controller);
+			BOUND.remove(i);
+			Status::SUCCESS
+		}
+		None => Status::DEVICE_ERROR,
+	}
+}
+
+unsafe extern "efiapi" fn get_driver_name(_this: *const ComponentName2Protocol, language: *const u8, driver_name: *mut *const u16) -> Status {
+	if language.is_null() || driver_name.is_null() {
+		return Status::INVALID_PARAMETER;
+	}
+
+	if core::ffi::CStr::from_ptr(language.cast()).to_bytes() != b"en" {
+		return Status::UNSUPPORTED;
+	}
+
+	*driver_name = DRIVER_NAME.as_ptr().cast();
+	Status::SUCCESS
+}
+
+unsafe extern "efiapi" fn get_controller_name(_this: *const ComponentName2Protocol, _controller: RawHandle, _child: RawHandle, _language: *const u8, _name: *mut *const u16) -> Status {
+	Status::UNSUPPORTED
+}
+
+// install both protocols on our image handle, the firmware takes it from there
+pub unsafe fn install() -> Status {
+	let image = boot::image_handle();
+
+	DRIVER_BINDING.image_handle = image.as_ptr();
+	DRIVER_BINDING.driver_binding_handle = image.as_ptr();
+
+	let interface = core::ptr::addr_of!(DRIVER_BINDING) as *const c_void;
+	if let Err(e) = boot::install_protocol_interface(Some(image), &DRIVER_BINDING_GUID, interface) {
+		error!("Failed to install the driver binding protocol: {:?}!", e.status());
+		return e.status();
+	}
+
+	// only cosmetic, a failure leaves the binding working
+	let interface = core::ptr::addr_of!(COMPONENT_NAME2) as *const c_void;
+	if let Err(e) = boot::install_protocol_interface(Some(image), &COMPONENT_NAME2_GUID, interface) {
+		error!("Failed to install the component name protocol: {:?}!", e.status());
+	}
+
+	info!("Installed the driver binding protocol");
+	Status::SUCCESS
+}
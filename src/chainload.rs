@@ -0,0 +1,118 @@
+// Starting the next boot stage once setup is done, for direct-boot setups where this image is run as
+// an application, e.g. through `-kernel`, and no boot manager starts the guest's own loader after it.
+// The path comes from `--chainload` or `etc/igd-chainload`, e.g. `\EFI\BOOT\grubx64.efi`, and is
+// looked for on every file system. The image is loaded through a full device path, so a loader
+// finds its configuration next to it.
+
+use alloc::{string::String, vec, vec::Vec};
+use log::{error, info};
+use qemu_fw_cfg::FwCfg;
+use uefi::{
+	boot::{self, LoadImageSource, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol, SearchType},
+	proto::{device_path::{build::{self, DevicePathBuilder}, DevicePath}, media::{file::{FileAttribute, FileMode}, fs::SimpleFileSystem}, ProtocolPointer},
+	CStr16, CString16, Handle, Status,
+};
+
+const CHAINLOAD_FILE: &str = "etc/igd-chainload";
+
+// the argument takes precedence over the fw_cfg file, like `--bdf` does
+fn configured_path(fw_cfg: &mut FwCfg, arg: Option<String>) -> Option<String> {
+	if arg.is_some() {
+		return arg;
+	}
+
+	let file = fw_cfg.find_file(CHAINLOAD_FILE)?;
+	let mut buf = vec![0u8; file.size()];
+	fw_cfg.read_file_to_buffer(&file, &mut buf);
+
+	match core::str::from_utf8(&buf) {
+		Ok(path) => Some(String::from(path.trim_end_matches(['\0', '\n', '\r']))),
+		Err(_) => {
+			error!("{} isn't a UTF-8 path!", CHAINLOAD_FILE);
+			None
+		}
+	}
+}
+
+// `/` is accepted as a separator too, and the path is made absolute
+fn uefi_path(path: &str) -> Option<CString16> {
+	let mut converted = String::from("\\");
+	converted.extend(path.trim_start_matches(['/', '\\']).chars().map(|c| if c == '/' { '\\' } else { c }));
+
+	CString16::try_from(converted.as_str()).ok()
+}
+
+fn open<P: ProtocolPointer + ?Sized>(handle: Handle) -> Option<ScopedProtocol<P>> {
+	let params = OpenProtocolParams { handle, agent: boot::image_handle(), controller: None };
+	unsafe { boot::open_protocol::<P>(params, OpenProtocolAttributes::GetProtocol) }.ok()
+}
+
+fn has_file(handle: Handle, path: &CStr16) -> bool {
+	let Some(mut fs) = open::<SimpleFileSystem>(handle) else {
+		return false;
+	};
+
+	match fs.open_volume() {
+		Ok(mut root) => root.open(path, FileMode::Read, FileAttribute::empty()).is_ok(),
+		Err(_) => false,
+	}
+}
+
+// the device path of the file system `fs`, followed by `path` on it
+fn file_device_path<'a>(storage: &'a mut Vec<u8>, fs: Handle, path: &CStr16) -> Option<&'a DevicePath> {
+	let fs_path = open::<DevicePath>(fs)?;
+	let mut builder = DevicePathBuilder::with_vec(storage);
+
+	for node in fs_path.node_iter() {
+		builder = builder.push(&node).ok()?;
+	}
+
+	builder.push(&build::media::FilePath { path_name: path }).ok()?.finalize().ok()
+}
+
+// returns what the started image returned, or `status` if there is nothing to start
+pub(crate) fn run(fw_cfg: &mut FwCfg, arg: Option<String>, status: Status) -> Status {
+	let Some(path) = configured_path(fw_cfg, arg) else {
+		return status;
+	};
+
+	let Some(file_path) = uefi_path(&path) else {
+		error!("Can't chainload '{}', not a valid UEFI path!", path);
+		return status;
+	};
+
+	let fs = boot::locate_handle_buffer(SearchType::from_proto::<SimpleFileSystem>()).ok()
+		.and_then(|handles| handles.iter().copied().find(|&h| has_file(h, &file_path)));
+
+	let Some(fs) = fs else {
+		error!("No file system has {} to chainload!", file_path);
+		return status;
+	};
+
+	let mut storage = Vec::new();
+	let Some(device_path) = file_device_path(&mut storage, fs, &file_path) else {
+		error!("Failed to build the device path of {}!", file_path);
+		return status;
+	};
+
+	let source = LoadImageSource::FromDevicePath { device_path, from_boot_manager: false };
+	let handle = match boot::load_image(boot::image_handle(), source) {
+		Ok(h) => h,
+		Err(e) => {
+			error!("Failed to load {}: {:?}!", file_path, e.status());
+			return status;
+		}
+	};
+
+	info!("Chainloading {}", file_path);
+
+	// a boot loader handing over to the OS doesn't come back
+	match boot::start_image(handle) {
+		Ok(()) => Status::SUCCESS,
+		Err(e) => {
+			error!("{} returned {:?}!", file_path, e.status());
+			let _ = boot::unload_image(handle);
+			e.status()
+		}
+	}
+}
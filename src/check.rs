@@ -0,0 +1,120 @@
+// Pre-flight check of everything a real run needs, without allocating memory or writing to any device.
+
+use log::{error, info};
+use qemu_fw_cfg::FwCfg;
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams, SearchType}, proto::pci::PciIo, Status};
+
+use crate::{compressed, embedded, find_opregion_file, fw_cfg_open, fw_cfg_raw, generation::Generation, igd_location, match_device,
+	opregion, quirks::Gms, read_bdsm_size, scratch::Scratch, CONFIG};
+
+pub(crate) fn report(name: &str, result: Result<(), &str>) -> bool {
+	match result {
+		Ok(()) => info!("[PASS] {}", name),
+		Err(reason) => error!("[FAIL] {}: {}", name, reason),
+	}
+
+	result.is_ok()
+}
+
+fn check_opregion(fw_cfg: &mut FwCfg) -> Result<(), &'static str> {
+	let mut header: [u8; opregion::HEADER_SIZE] = [0; opregion::HEADER_SIZE];
+
+	// the one the IGD would get, with several GPUs the others' files aren't checked
+	let size = match (find_opregion_file(fw_cfg, Some(igd_location())), embedded::OPREGION) {
+		(Some((name, _)), _) if compressed::is_compressed(&name) => {
+			// the header is only known after decompressing all of it
+			let size = compressed::size(&name, 0);
+			if size > unsafe { CONFIG.max_opregion_size } {
+				return Err("exceeds the maximum size");
+			}
+
+			let mut data = Scratch::new(size);
+			compressed::read(&name, &mut data).map_err(|_| "failed to decompress")?;
+
+			let len = size.min(header.len());
+			header[..len].copy_from_slice(&data[..len]);
+			size
+		}
+		(Some((name, _)), _) => {
+			// read just the header through the raw interface, so we don't need a buffer for the whole file
+			let file = fw_cfg_raw::find_file(&name).ok_or("not in the fw_cfg directory")?;
+			let len = file.size.min(header.len());
+			fw_cfg_raw::select(file.key);
+			fw_cfg_raw::read(&mut header[..len]);
+			file.size
+		}
+		(None, Some(blob)) => {
+			let len = blob.len().min(header.len());
+			header[..len].copy_from_slice(&blob[..len]);
+			blob.len()
+		}
+		(None, None) => return Err("not passed through"),
+	};
+
+	if size > unsafe { CONFIG.max_opregion_size } {
+		return Err("exceeds the maximum size");
+	}
+
+	let version = opregion::validate_header(&header, size, unsafe { CONFIG.signatures() })?;
+	info!("OpRegion version {}.{}, {} bytes", version.major, version.minor, size);
+
+	Ok(())
+}
+
+fn check_bdsm_size(fw_cfg: &mut FwCfg, gms: Option<Gms>) -> Result<(), &'static str> {
+	match read_bdsm_size(fw_cfg, gms) {
+		Ok(Some(_)) => Ok(()),
+		// probing the BAR would mean writing to the device, so only its presence in the config is checked
+		Ok(None) if unsafe { CONFIG.stolen_memory_bar.is_some() } => Ok(()),
+		Ok(None) => Err("not passed through"),
+		Err(_) => Err("invalid"),
+	}
+}
+
+// the device ID of the IGD
+fn check_device() -> Result<u16, &'static str> {
+	let handles = boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()).map_err(|_| "no PCI devices")?;
+	let igd = igd_location();
+
+	for handle in handles.iter() {
+		let params = OpenProtocolParams {
+			handle: *handle,
+			agent: boot::image_handle(),
+			controller: None,
+		};
+
+		// shared access only, an exclusive open would disconnect the drivers managing the device
+		let Ok(mut pci_io) = (unsafe { boot::open_protocol::<PciIo>(params, OpenProtocolAttributes::GetProtocol) }) else {
+			continue;
+		};
+
+		if let Some(device) = match_device(&mut pci_io) && pci_io.get_location().is_ok_and(|l| l == igd) {
+			return Ok(device);
+		}
+	}
+
+	Err("no Intel display controller at the IGD location")
+}
+
+pub fn run() -> Status {
+	let Some(mut fw_cfg) = (unsafe { fw_cfg_open() }) else {
+		report("fw_cfg present", Err("not found"));
+		return Status::NOT_FOUND;
+	};
+
+	let mut passed = report("fw_cfg present", Ok(()));
+	passed &= report("OpRegion", check_opregion(&mut fw_cfg));
+	// GMS in a passed-through GGC is decoded per generation, or as a quirk of the device has it
+	let device = check_device();
+	let gms = device.ok().and_then(|d| Gms::of(Generation::from_device_id(d), d));
+	passed &= report("Stolen memory size", check_bdsm_size(&mut fw_cfg, gms));
+	passed &= report("IGD device", device.map(|_| ()));
+
+	if passed {
+		info!("All checks passed");
+		Status::SUCCESS
+	} else {
+		error!("Some checks failed!");
+		Status::ABORTED
+	}
+}
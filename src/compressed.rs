@@ -0,0 +1,91 @@
+// Compressed fw_cfg payloads, for large VBT, GOP and OpRegion files. A compressed file starts with a
+// 12-byte header, `IGDZ`, the `u32` method (1 for raw DEFLATE) and the `u32` decompressed size, all
+// little-endian, followed by the compressed data. Everything that sizes a file goes by the
+// decompressed size, and the data is inflated straight into the buffer it is meant for. A file not
+// starting with the magic is used as is.
+
+use log::{debug, error};
+use qemu_fw_cfg::{FwCfg, FwCfgFile};
+use uefi::Status;
+
+use crate::{fw_cfg_raw, inflate, read_chunked, scratch::Scratch};
+
+const MAGIC: [u8; 4] = *b"IGDZ";
+const HEADER_SIZE: usize = 12;
+const METHOD_DEFLATE: u32 = 1;
+
+// method and decompressed size of `name`, `None` if it isn't compressed
+fn header(name: &str) -> Option<(u32, usize)> {
+	let file = fw_cfg_raw::find_file(name)?;
+	if file.size < HEADER_SIZE {
+		return None;
+	}
+
+	let mut header = [0u8; HEADER_SIZE];
+	fw_cfg_raw::select(file.key);
+	fw_cfg_raw::read(&mut header);
+
+	if header[..4] != MAGIC {
+		return None;
+	}
+
+	let method = u32::from_le_bytes(header[4..8].try_into().unwrap());
+	let size = u32::from_le_bytes(header[8..12].try_into().unwrap());
+
+	Some((method, size as usize))
+}
+
+pub fn is_compressed(name: &str) -> bool {
+	header(name).is_some()
+}
+
+// size of the contents of `name`, which is `size` for a file that isn't compressed
+pub fn size(name: &str, size: usize) -> usize {
+	header(name).map_or(size, |(_, size)| size)
+}
+
+// decompresses `name` into `out`, which has to be as large as the decompressed size
+pub fn read(name: &str, out: &mut [u8]) -> Result<(), Status> {
+	let Some(file) = fw_cfg_raw::find_file(name) else {
+		error!("{} disappeared from the fw_cfg directory!", name);
+		return Err(Status::NOT_FOUND);
+	};
+
+	let Some((method, size)) = header(name) else {
+		error!("{} isn't compressed!", name);
+		return Err(Status::INVALID_PARAMETER);
+	};
+
+	if method != METHOD_DEFLATE {
+		error!("{} uses unsupported compression method {}!", name, method);
+		return Err(Status::UNSUPPORTED);
+	}
+
+	assert!(size == out.len());
+
+	let mut data = Scratch::new(file.size);
+	if !read_chunked(name, &mut data) {
+		return Err(Status::NOT_FOUND);
+	}
+
+	if let Err(reason) = inflate::inflate(&data[HEADER_SIZE..], out) {
+		error!("Failed to decompress {}: {}!", name, reason);
+		return Err(Status::VOLUME_CORRUPTED);
+	}
+
+	debug!("Decompressed {} from {} to {} bytes", name, file.size, size);
+	Ok(())
+}
+
+// `file` in a fresh buffer, decompressed if need be
+pub fn load(fw_cfg: &mut FwCfg, name: &str, file: &FwCfgFile) -> Result<Scratch, Status> {
+	let mut buf = Scratch::new(size(name, file.size()));
+
+	if is_compressed(name) {
+		read(name, &mut buf)?;
+	} else {
+		fw_cfg.read_file_to_buffer(file, &mut buf);
+	}
+
+	Ok(buf)
+}
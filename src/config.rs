@@ -0,0 +1,539 @@
+use log::{error, info, LevelFilter};
+use uefi::boot::MemoryType;
+
+use crate::{args::Location, embedded, fw_cfg::FwCfgSource, opregion, options::SetupOptions, scratch::Scratch};
+
+// optional configuration blob passed in by the host
+const CONFIG_FILE: &str = "etc/igd-config";
+
+// the blob starts with a fixed header, followed by a list of (tag, length, value) entries
+const CONFIG_MAGIC: [u8; 4] = *b"IGDC";
+const CONFIG_VERSION: u16 = 1;
+const CONFIG_HEADER_SIZE: usize = 8;
+const CONFIG_ENTRY_HEADER_SIZE: usize = 4;
+
+const TAG_ALLOW_PARTIAL: u16 = 0x0001;
+const TAG_REQUIRE_DEVICE: u16 = 0x0002;
+const TAG_STOLEN_MEMORY_REQUIRES_OPREGION: u16 = 0x0003;
+const TAG_TOLUD_PLACEMENT: u16 = 0x0004;
+const TAG_MAX_OPREGION_SIZE: u16 = 0x0005;
+const TAG_REUSE_OPREGION: u16 = 0x0006;
+const TAG_FORCE: u16 = 0x0007;
+const TAG_INTER_STEP_DELAY: u16 = 0x0008;
+const TAG_STRICT_MATCH: u16 = 0x0009;
+const TAG_WRITE_RESULT: u16 = 0x000A;
+const TAG_KEEP_LISTENING: u16 = 0x000B;
+const TAG_VERIFY_ZEROED: u16 = 0x000C;
+const TAG_OPREGION_SIGNATURES: u16 = 0x000D;
+const TAG_STOLEN_MEMORY_BAR: u16 = 0x000E;
+const TAG_ACCEPT_LARGER_VBT: u16 = 0x000F;
+const TAG_UPCONVERT_OPREGION: u16 = 0x0010;
+const TAG_GTT_STOLEN_MEMORY: u16 = 0x0011;
+const TAG_DRIVER_BINDING: u16 = 0x0012;
+const TAG_ALLOCATION_CEILING: u16 = 0x0013;
+const TAG_OPREGION_MEMORY_TYPE: u16 = 0x0014;
+const TAG_STOLEN_MEMORY_MEMORY_TYPE: u16 = 0x0015;
+const TAG_OPREGION_ADDRESS: u16 = 0x0016;
+const TAG_STOLEN_MEMORY_ADDRESS: u16 = 0x0017;
+const TAG_MEASURE: u16 = 0x0018;
+const TAG_SKIP_STOLEN_MEMORY: u16 = 0x0019;
+const TAG_LEGACY_BDSM: u16 = 0x001A;
+const TAG_SERIAL_LOG: u16 = 0x001B;
+const TAG_IGD_SEGMENT: u16 = 0x001C;
+const TAG_LPC_IDS: u16 = 0x001D;
+const TAG_SSDT: u16 = 0x001E;
+const TAG_DEFER_TO_READY_TO_BOOT: u16 = 0x001F;
+const TAG_MBOX_CLEAR: u16 = 0x0020;
+const TAG_VIRTUAL_FUNCTION: u16 = 0x0021;
+const TAG_OPREGION_READ_ONLY: u16 = 0x0022;
+const TAG_STRICT_FAILURE: u16 = 0x0023;
+const TAG_IGD_LOCATION: u16 = 0x0024;
+const TAG_LOG_LEVEL: u16 = 0x0025;
+const TAG_STOLEN_MEMORY_ALIGNMENT: u16 = 0x0026;
+const TAG_DUAL_BDSM: u16 = 0x0027;
+const TAG_STATUS_SERIAL: u16 = 0x0028;
+const TAG_PVPANIC: u16 = 0x0029;
+const TAG_ENABLE_DECODE: u16 = 0x002A;
+const TAG_SUBSYSTEM_IDS: u16 = 0x002B;
+const TAG_PCON: u16 = 0x002C;
+const TAG_TRACE_PAGES: u16 = 0x002D;
+const TAG_OPREGION_MIRROR: u16 = 0x002E;
+const TAG_MCH_MIRROR: u16 = 0x002F;
+const TAG_VGA_DECODE: u16 = 0x0030;
+const TAG_WAIT_FOR_ENUMERATION: u16 = 0x0031;
+const TAG_MEDIATED: u16 = 0x0032;
+
+// only configure a device matching all of these exactly
+#[derive(Clone, Copy)]
+pub struct StrictMatch {
+	pub device_id: u16,
+	pub subsystem_vendor: u16,
+	pub subsystem_device: u16,
+	pub location: Location,
+}
+
+// IDs the ISA bridge at 00:1f.0 is programmed with, like QEMU's x-igd-lpc copies them from the host
+#[derive(Clone, Copy)]
+pub struct LpcIds {
+	pub vendor: u16,
+	pub device: u16,
+	pub revision: u8,
+	pub subsystem_vendor: u16,
+	pub subsystem_device: u16,
+}
+
+// the least stolen memory alignment BDSM can hold
+const MIN_STOLEN_MEMORY_ALIGNMENT: usize = 1024 * 1024;
+
+// the standard signature plus a few alternates used by engineering samples
+const MAX_OPREGION_SIGNATURES: usize = 4;
+
+// generous enough for an OpRegion with an appended extended VBT
+const DEFAULT_MAX_OPREGION_SIZE: usize = 256 * 1024;
+
+#[derive(Clone, Copy)]
+pub struct IgdConfig {
+	// treat a successful OpRegion setup as overall success even if stolen memory setup failed
+	pub allow_partial: bool,
+	// fail if no matching device was configured by the time the driver entry point returns
+	pub require_device: bool,
+	// skip stolen memory setup on a device whose OpRegion setup failed
+	pub stolen_memory_requires_opregion: bool,
+	// place stolen memory directly below the host bridge's TOLUD and warn if it ends up above
+	pub tolud_placement: bool,
+	// upper bound for the OpRegion size, so a corrupt fw_cfg entry can't trigger a huge allocation
+	pub max_opregion_size: usize,
+	// reuse a valid OpRegion an earlier boot stage left in ACPI NVS instead of allocating a new one
+	pub reuse_opregion: bool,
+	// always set everything up from scratch, regardless of what is already in place
+	pub force: bool,
+	// stall between programming the OpRegion and stolen memory of a device, in microseconds
+	pub inter_step_delay_us: u32,
+	pub strict_match: Option<StrictMatch>,
+	// write a result record to the writable fw_cfg file `etc/igd-result`
+	pub write_result: bool,
+	// keep the PciIo notify registered after the IGD was configured, so hot-plugged devices are set up too
+	pub keep_listening: bool,
+	// spot-check that stolen memory reads back as zero after clearing it, always done in debug builds
+	pub verify_zeroed: bool,
+	// accepted OpRegion signatures, the standard one always comes first
+	signatures: [[u8; 16]; MAX_OPREGION_SIGNATURES],
+	signature_count: usize,
+	// size stolen memory like this BAR of the IGD if the host passes no explicit size
+	pub stolen_memory_bar: Option<u8>,
+	// accept an `etc/igd-vbt` larger than RVDS and grow RVDS to match, instead of rejecting it
+	pub accept_larger_vbt: bool,
+	// turn a 2.0 OpRegion into 2.1 with the extended VBT directly behind it, instead of relocating the VBT
+	pub upconvert_opregion: bool,
+	// allocate the GTT stolen memory separately and program BGSM, for hosts that don't include it in
+	// the stolen memory size
+	pub gtt_stolen_memory: bool,
+	// install the Driver Binding protocol and let ConnectController attach us, instead of the PciIo notify
+	pub driver_binding: bool,
+	// highest address OpRegion and stolen memory may occupy, for guests with tighter constraints than
+	// the registers themselves. Only lowers the 4 GiB limit of 32-bit registers, never raises it.
+	pub allocation_ceiling: Option<u64>,
+	// UEFI memory type of each allocation, which decides the E820 type the guest OS sees
+	pub opregion_memory_type: MemoryType,
+	pub stolen_memory_memory_type: MemoryType,
+	// fixed guest-physical addresses, so the layout is the same on every boot for migration and snapshots
+	pub opregion_address: Option<u64>,
+	pub stolen_memory_address: Option<u64>,
+	// measure the OpRegion and extended VBT into the TPM if there is one
+	pub measure: bool,
+	// only set up the OpRegion, for guests that bring their own stolen memory
+	pub skip_stolen_memory: bool,
+	// program the 32-bit BDSM at 0x5C even on generations that have the 64-bit one
+	pub legacy_bdsm: bool,
+	// also log through the first Serial I/O protocol, defaults to on with the `serial-log` feature
+	pub serial_log: bool,
+	// PCI segment the IGD is expected on at 00:02.0, for multi-segment topologies e.g. with pxb-pcie
+	pub igd_segment: Option<u16>,
+	// make the guest's ISA bridge look like the host's LPC bridge, which pre-Gen11 Windows drivers check
+	pub lpc_ids: Option<LpcIds>,
+	// install an SSDT describing the IGD, its OpRegion and a backlight-capable panel
+	pub ssdt: bool,
+	// only record matching devices from the notify, and configure them at ReadyToBoot
+	pub defer_to_ready_to_boot: bool,
+	// MBOX bits cleared in a fresh OpRegion copy, for mailboxes nothing in the guest platform services
+	pub mbox_clear: u32,
+	// treat the device as an SR-IOV VF, or as a PF, instead of detecting it
+	pub virtual_function: Option<bool>,
+	// write-protect a fresh OpRegion copy for the rest of boot, off by default as a GOP loaded by other
+	// means than fw_cfg, e.g. a romfile option ROM, faults on its first mailbox write
+	pub opregion_read_only: bool,
+	// stop boot on a setup failure instead of logging it and carrying on
+	pub strict_failure: bool,
+	// where the IGD is, like `etc/igd-bdf`, which takes precedence
+	pub igd_location: Option<Location>,
+	// like `etc/igd-loglevel`, which takes precedence
+	pub log_level: Option<LevelFilter>,
+	// stolen memory base alignment instead of what the generation asks for
+	pub stolen_memory_alignment: Option<usize>,
+	// program both BDSM locations on devices of unknown generation
+	pub dual_bdsm: bool,
+	// also print the status record to the serial console
+	pub status_serial: bool,
+	// signal pvpanic when the strict failure policy stops boot
+	pub pvpanic: bool,
+	// enable decoding and bus mastering once the device is set up
+	pub enable_decode: bool,
+	// subsystem vendor and device ID the IGD should report
+	pub subsystem_ids: Option<(u16, u16)>,
+	// PCON bits to set and clear in a fresh OpRegion copy, instead of what the generation table has
+	pub pcon: Option<(u32, u32)>,
+	// pages of ACPI NVS to keep a copy of the log in for the OS, 0 for none
+	pub trace_pages: u8,
+	// memory BAR and offset a vfio quirk mirrors the host OpRegion to, read if fw_cfg has none
+	pub opregion_mirror: Option<(u8, u32)>,
+	// mirror BDSM into a Q35 host bridge, where some guest drivers look for it
+	pub mch_mirror: bool,
+	// route the legacy VGA ranges to the IGD for output before the OS driver loads
+	pub vga_decode: bool,
+	// scan once the PCI Enumeration Complete protocol is installed instead of on every PciIo install
+	pub wait_for_enumeration: bool,
+	// `None` to detect whether the device is a GVT-g vGPU
+	pub mediated: Option<bool>,
+}
+
+impl IgdConfig {
+	pub const fn new() -> Self {
+		IgdConfig {
+			allow_partial: false,
+			require_device: false,
+			stolen_memory_requires_opregion: true,
+			tolud_placement: false,
+			max_opregion_size: DEFAULT_MAX_OPREGION_SIZE,
+			reuse_opregion: false,
+			force: false,
+			inter_step_delay_us: 0,
+			strict_match: None,
+			write_result: false,
+			keep_listening: false,
+			verify_zeroed: false,
+			signatures: [*opregion::SIGNATURE; MAX_OPREGION_SIGNATURES],
+			signature_count: 1,
+			stolen_memory_bar: None,
+			accept_larger_vbt: false,
+			upconvert_opregion: false,
+			gtt_stolen_memory: false,
+			driver_binding: false,
+			allocation_ceiling: None,
+			opregion_memory_type: MemoryType::ACPI_NON_VOLATILE,
+			stolen_memory_memory_type: MemoryType::ACPI_NON_VOLATILE,
+			opregion_address: None,
+			stolen_memory_address: None,
+			measure: true,
+			skip_stolen_memory: false,
+			legacy_bdsm: false,
+			serial_log: cfg!(feature = "serial-log"),
+			igd_segment: None,
+			lpc_ids: None,
+			ssdt: false,
+			defer_to_ready_to_boot: false,
+			mbox_clear: opregion::MBOX_UNSERVICED,
+			virtual_function: None,
+			opregion_read_only: false,
+			strict_failure: false,
+			igd_location: None,
+			log_level: None,
+			stolen_memory_alignment: None,
+			dual_bdsm: false,
+			status_serial: false,
+			pvpanic: false,
+			enable_decode: false,
+			subsystem_ids: None,
+			pcon: None,
+			trace_pages: 0,
+			opregion_mirror: None,
+			mch_mirror: false,
+			vga_decode: false,
+			wait_for_enumeration: false,
+			mediated: None,
+		}
+	}
+
+	pub fn signatures(&self) -> &[[u8; 16]] {
+		&self.signatures[..self.signature_count]
+	}
+
+	// sources in order of precedence: fw_cfg, the embedded blob, built-in defaults
+	pub fn load<S: FwCfgSource>(fw_cfg: &mut S) -> Self {
+		if let Some(file) = fw_cfg.find_file(CONFIG_FILE) {
+			let mut blob = Scratch::new(fw_cfg.file_size(&file));
+			fw_cfg.read_file(&file, &mut blob);
+
+			match Self::parse(&blob) {
+				Some(config) => {
+					info!("Using configuration from {}", CONFIG_FILE);
+					return config;
+				}
+				None => error!("Ignoring malformed {}!", CONFIG_FILE),
+			}
+		}
+
+		if let Some(blob) = embedded::CONFIG {
+			match Self::parse(blob) {
+				Some(config) => {
+					info!("Using embedded configuration");
+					return config;
+				}
+				None => error!("Ignoring malformed embedded configuration!"),
+			}
+		}
+
+		Self::new()
+	}
+
+	pub fn parse(blob: &[u8]) -> Option<Self> {
+		if blob.len() < CONFIG_HEADER_SIZE || blob[0..4] != CONFIG_MAGIC {
+			error!("Configuration blob has an invalid signature!");
+			return None;
+		}
+
+		let version = u16::from_le_bytes([blob[4], blob[5]]);
+		if version != CONFIG_VERSION {
+			error!("Configuration blob version {} is not supported!", version);
+			return None;
+		}
+
+		let mut config = Self::new();
+		let mut entries = &blob[CONFIG_HEADER_SIZE..];
+
+		while !entries.is_empty() {
+			if entries.len() < CONFIG_ENTRY_HEADER_SIZE {
+				error!("Configuration blob has a truncated entry header!");
+				return None;
+			}
+
+			let tag = u16::from_le_bytes([entries[0], entries[1]]);
+			let len = u16::from_le_bytes([entries[2], entries[3]]) as usize;
+			let Some(value) = entries.get(CONFIG_ENTRY_HEADER_SIZE..CONFIG_ENTRY_HEADER_SIZE + len) else {
+				error!("Configuration entry {:#x} overruns the blob!", tag);
+				return None;
+			};
+
+			if !config.apply(tag, value) {
+				error!("Configuration entry {:#x} has an invalid value!", tag);
+				return None;
+			}
+
+			entries = &entries[CONFIG_ENTRY_HEADER_SIZE + len..];
+		}
+
+		Some(config)
+	}
+
+	fn apply(&mut self, tag: u16, value: &[u8]) -> bool {
+		match tag {
+			TAG_ALLOW_PARTIAL => parse_bool(value).map(|v| self.allow_partial = v).is_some(),
+			TAG_REQUIRE_DEVICE => parse_bool(value).map(|v| self.require_device = v).is_some(),
+			TAG_STOLEN_MEMORY_REQUIRES_OPREGION => parse_bool(value).map(|v| self.stolen_memory_requires_opregion = v).is_some(),
+			TAG_TOLUD_PLACEMENT => parse_bool(value).map(|v| self.tolud_placement = v).is_some(),
+			TAG_MAX_OPREGION_SIZE => parse_u32(value).map(|v| self.max_opregion_size = v as usize).is_some(),
+			TAG_REUSE_OPREGION => parse_bool(value).map(|v| self.reuse_opregion = v).is_some(),
+			TAG_FORCE => parse_bool(value).map(|v| self.force = v).is_some(),
+			TAG_INTER_STEP_DELAY => parse_u32(value).map(|v| self.inter_step_delay_us = v).is_some(),
+			TAG_STRICT_MATCH => parse_strict_match(value).map(|v| self.strict_match = Some(v)).is_some(),
+			TAG_WRITE_RESULT => parse_bool(value).map(|v| self.write_result = v).is_some(),
+			TAG_KEEP_LISTENING => parse_bool(value).map(|v| self.keep_listening = v).is_some(),
+			TAG_VERIFY_ZEROED => parse_bool(value).map(|v| self.verify_zeroed = v).is_some(),
+			TAG_OPREGION_SIGNATURES => self.add_signatures(value),
+			TAG_STOLEN_MEMORY_BAR => parse_bar(value).map(|v| self.stolen_memory_bar = Some(v)).is_some(),
+			TAG_ACCEPT_LARGER_VBT => parse_bool(value).map(|v| self.accept_larger_vbt = v).is_some(),
+			TAG_UPCONVERT_OPREGION => parse_bool(value).map(|v| self.upconvert_opregion = v).is_some(),
+			TAG_GTT_STOLEN_MEMORY => parse_bool(value).map(|v| self.gtt_stolen_memory = v).is_some(),
+			TAG_DRIVER_BINDING => parse_bool(value).map(|v| self.driver_binding = v).is_some(),
+			TAG_ALLOCATION_CEILING => parse_u64(value).map(|v| self.allocation_ceiling = Some(v)).is_some(),
+			TAG_OPREGION_MEMORY_TYPE => parse_memory_type(value).map(|v| self.opregion_memory_type = v).is_some(),
+			TAG_STOLEN_MEMORY_MEMORY_TYPE => parse_memory_type(value).map(|v| self.stolen_memory_memory_type = v).is_some(),
+			TAG_OPREGION_ADDRESS => parse_u64(value).map(|v| self.opregion_address = Some(v)).is_some(),
+			TAG_STOLEN_MEMORY_ADDRESS => parse_u64(value).map(|v| self.stolen_memory_address = Some(v)).is_some(),
+			TAG_MEASURE => parse_bool(value).map(|v| self.measure = v).is_some(),
+			TAG_SKIP_STOLEN_MEMORY => parse_bool(value).map(|v| self.skip_stolen_memory = v).is_some(),
+			TAG_LEGACY_BDSM => parse_bool(value).map(|v| self.legacy_bdsm = v).is_some(),
+			TAG_SERIAL_LOG => parse_bool(value).map(|v| self.serial_log = v).is_some(),
+			TAG_IGD_SEGMENT => parse_u16(value).map(|v| self.igd_segment = Some(v)).is_some(),
+			TAG_LPC_IDS => parse_lpc_ids(value).map(|v| self.lpc_ids = Some(v)).is_some(),
+			TAG_SSDT => parse_bool(value).map(|v| self.ssdt = v).is_some(),
+			TAG_DEFER_TO_READY_TO_BOOT => parse_bool(value).map(|v| self.defer_to_ready_to_boot = v).is_some(),
+			TAG_MBOX_CLEAR => parse_u32(value).map(|v| self.mbox_clear = v).is_some(),
+			TAG_VIRTUAL_FUNCTION => parse_bool(value).map(|v| self.virtual_function = Some(v)).is_some(),
+			TAG_OPREGION_READ_ONLY => parse_bool(value).map(|v| self.opregion_read_only = v).is_some(),
+			TAG_STRICT_FAILURE => parse_bool(value).map(|v| self.strict_failure = v).is_some(),
+			TAG_IGD_LOCATION => parse_location(value).map(|v| self.igd_location = Some(v)).is_some(),
+			TAG_LOG_LEVEL => parse_log_level(value).map(|v| self.log_level = Some(v)).is_some(),
+			TAG_STOLEN_MEMORY_ALIGNMENT => parse_alignment(value).map(|v| self.stolen_memory_alignment = Some(v)).is_some(),
+			TAG_DUAL_BDSM => parse_bool(value).map(|v| self.dual_bdsm = v).is_some(),
+			TAG_STATUS_SERIAL => parse_bool(value).map(|v| self.status_serial = v).is_some(),
+			TAG_PVPANIC => parse_bool(value).map(|v| self.pvpanic = v).is_some(),
+			TAG_ENABLE_DECODE => parse_bool(value).map(|v| self.enable_decode = v).is_some(),
+			TAG_SUBSYSTEM_IDS => parse_subsystem_ids(value).map(|v| self.subsystem_ids = Some(v)).is_some(),
+			TAG_PCON => parse_pcon(value).map(|v| self.pcon = Some(v)).is_some(),
+			TAG_TRACE_PAGES => parse_u8(value).map(|v| self.trace_pages = v).is_some(),
+			TAG_OPREGION_MIRROR => parse_mirror(value).map(|v| self.opregion_mirror = Some(v)).is_some(),
+			TAG_MCH_MIRROR => parse_bool(value).map(|v| self.mch_mirror = v).is_some(),
+			TAG_VGA_DECODE => parse_bool(value).map(|v| self.vga_decode = v).is_some(),
+			TAG_WAIT_FOR_ENUMERATION => parse_bool(value).map(|v| self.wait_for_enumeration = v).is_some(),
+			TAG_MEDIATED => parse_bool(value).map(|v| self.mediated = Some(v)).is_some(),
+			// unknown tags are skipped so newer hosts can talk to older builds
+			_ => true,
+		}
+	}
+
+	// the setup UI can only turn things on, so a blob enabling something isn't overridden
+	pub fn apply_options(&mut self, options: &SetupOptions) {
+		self.skip_stolen_memory |= options.skip_stolen_memory;
+		self.legacy_bdsm |= options.legacy_bdsm;
+
+		if let Some(level) = options.log_level {
+			log::set_max_level(level);
+		}
+	}
+
+	// a list of 16-byte signatures, accepted in addition to the standard one
+	fn add_signatures(&mut self, value: &[u8]) -> bool {
+		if value.is_empty() || value.len() % 16 != 0 {
+			return false;
+		}
+
+		for signature in value.chunks_exact(16) {
+			if self.signature_count == MAX_OPREGION_SIGNATURES {
+				return false;
+			}
+
+			self.signatures[self.signature_count].copy_from_slice(signature);
+			self.signature_count += 1;
+		}
+
+		true
+	}
+}
+
+impl Default for IgdConfig {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn parse_bool(value: &[u8]) -> Option<bool> {
+	match value {
+		[0] => Some(false),
+		[1] => Some(true),
+		_ => None,
+	}
+}
+
+fn parse_u8(value: &[u8]) -> Option<u8> {
+	match value {
+		&[v] => Some(v),
+		_ => None,
+	}
+}
+
+fn parse_u16(value: &[u8]) -> Option<u16> {
+	Some(u16::from_le_bytes(value.try_into().ok()?))
+}
+
+fn parse_u32(value: &[u8]) -> Option<u32> {
+	Some(u32::from_le_bytes(value.try_into().ok()?))
+}
+
+fn parse_u64(value: &[u8]) -> Option<u64> {
+	Some(u64::from_le_bytes(value.try_into().ok()?))
+}
+
+// a UEFI memory type value, only the two that survive ExitBootServices untouched make sense here
+fn parse_memory_type(value: &[u8]) -> Option<MemoryType> {
+	match MemoryType(parse_u32(value)?) {
+		ty @ (MemoryType::RESERVED | MemoryType::ACPI_NON_VOLATILE) => Some(ty),
+		_ => None,
+	}
+}
+
+fn parse_bar(value: &[u8]) -> Option<u8> {
+	match value {
+		&[bar @ 0..=5] => Some(bar),
+		_ => None,
+	}
+}
+
+// device ID, subsystem vendor and device ID, segment (all u16), then bus, device and function (u8)
+fn parse_strict_match(value: &[u8]) -> Option<StrictMatch> {
+	let &[d0, d1, sv0, sv1, sd0, sd1, seg0, seg1, bus, dev, func] = value else {
+		return None;
+	};
+
+	Some(StrictMatch {
+		device_id: u16::from_le_bytes([d0, d1]),
+		subsystem_vendor: u16::from_le_bytes([sv0, sv1]),
+		subsystem_device: u16::from_le_bytes([sd0, sd1]),
+		location: (u16::from_le_bytes([seg0, seg1]) as usize, bus as usize, dev as usize, func as usize),
+	})
+}
+
+// segment (u16), then bus, device and function (u8)
+fn parse_location(value: &[u8]) -> Option<Location> {
+	let &[seg0, seg1, bus, dev @ 0..=31, func @ 0..=7] = value else {
+		return None;
+	};
+
+	Some((u16::from_le_bytes([seg0, seg1]) as usize, bus as usize, dev as usize, func as usize))
+}
+
+// 0 (off) to 5 (trace), in the order of `LevelFilter`
+fn parse_log_level(value: &[u8]) -> Option<LevelFilter> {
+	let &[level] = value else {
+		return None;
+	};
+
+	LevelFilter::iter().nth(level as usize)
+}
+
+// a power of two no smaller than what BDSM can hold
+fn parse_alignment(value: &[u8]) -> Option<usize> {
+	let alignment = parse_u32(value)? as usize;
+	(alignment.is_power_of_two() && alignment >= MIN_STOLEN_MEMORY_ALIGNMENT).then_some(alignment)
+}
+
+// bits to set, then bits to clear (u32 each)
+fn parse_pcon(value: &[u8]) -> Option<(u32, u32)> {
+	let &[s0, s1, s2, s3, c0, c1, c2, c3] = value else {
+		return None;
+	};
+
+	Some((u32::from_le_bytes([s0, s1, s2, s3]), u32::from_le_bytes([c0, c1, c2, c3])))
+}
+
+// BAR (u8), then a dword-aligned offset into it (u32)
+fn parse_mirror(value: &[u8]) -> Option<(u8, u32)> {
+	let &[bar @ 0..=5, o0, o1, o2, o3] = value else {
+		return None;
+	};
+
+	let offset = u32::from_le_bytes([o0, o1, o2, o3]);
+	(offset % 4 == 0).then_some((bar, offset))
+}
+
+// subsystem vendor and device ID (u16 each)
+fn parse_subsystem_ids(value: &[u8]) -> Option<(u16, u16)> {
+	let &[v0, v1, d0, d1] = value else {
+		return None;
+	};
+
+	Some((u16::from_le_bytes([v0, v1]), u16::from_le_bytes([d0, d1])))
+}
+
+fn parse_lpc_ids(value: &[u8]) -> Option<LpcIds> {
+	let &[v0, v1, d0, d1, revision, sv0, sv1, sd0, sd1] = value else {
+		return None;
+	};
+
+	Some(LpcIds {
+		vendor: u16::from_le_bytes([v0, v1]),
+		device: u16::from_le_bytes([d0, d1]),
+		revision,
+		subsystem_vendor: u16::from_le_bytes([sv0, sv1]),
+		subsystem_device: u16::from_le_bytes([sd0, sd1]),
+	})
+}
@@ -0,0 +1,214 @@
+// Discovery of the IGD and its OpRegion reservation from the flattened device tree QEMU hands to
+// ARM guests. There is no upstream binding for this, so we rely on the generic conventions:
+//
+// - the IGD is a child of the PCI host bridge node, with a `compatible` entry of the form
+//   `pci8086,<device>` and the BDF encoded in the phys.hi cell of `reg` (bus 23:16, dev 15:11, func 10:8)
+// - the OpRegion is reserved by a `/reserved-memory/igd-opregion@<addr>` node, whose `reg` uses
+//   two address and two size cells as is customary for 64-bit machines
+
+use core::ffi::CStr;
+use log::{debug, error, info};
+use uefi::{guid, Guid};
+
+const DTB_GUID: Guid = guid!("b1b621d5-f19c-41a5-830b-d9152c69aae0");
+
+const FDT_MAGIC: u32 = 0xD00DFEED;
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+// deep enough for /pcie@.../pci-bridge@.../display@...
+const FDT_MAX_DEPTH: usize = 16;
+
+#[derive(Clone, Copy, Default)]
+pub struct DtIgdInfo {
+	pub location: Option<(u8, u8, u8)>,
+	pub opregion: Option<(u64, u64)>,
+}
+
+struct Fdt<'a> {
+	structs: &'a [u8],
+	strings: &'a [u8],
+}
+
+fn be32(buf: &[u8], offset: usize) -> Option<u32> {
+	buf.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(buf: &[u8], offset: usize) -> Option<u64> {
+	Some(((be32(buf, offset)? as u64) << 32) | be32(buf, offset + 4)? as u64)
+}
+
+impl<'a> Fdt<'a> {
+	fn new(blob: &'a [u8]) -> Option<Self> {
+		if be32(blob, 0)? != FDT_MAGIC || blob.len() < FDT_HEADER_SIZE {
+			return None;
+		}
+
+		let off_structs = be32(blob, 8)? as usize;
+		let off_strings = be32(blob, 12)? as usize;
+		let size_strings = be32(blob, 32)? as usize;
+		let size_structs = be32(blob, 36)? as usize;
+
+		Some(Fdt {
+			structs: blob.get(off_structs..off_structs.checked_add(size_structs)?)?,
+			strings: blob.get(off_strings..off_strings.checked_add(size_strings)?)?,
+		})
+	}
+
+	fn string(&self, offset: usize) -> Option<&'a str> {
+		CStr::from_bytes_until_nul(self.strings.get(offset..)?).ok()?.to_str().ok()
+	}
+
+	// calls `f` with the node path and every property, returns `None` on a malformed structure block
+	fn walk(&self, mut f: impl FnMut(&[&'a str], &'a str, &'a [u8])) -> Option<()> {
+		let mut path: [&str; FDT_MAX_DEPTH] = [""; FDT_MAX_DEPTH];
+		let mut depth = 0;
+		let mut offset = 0;
+
+		loop {
+			let token = be32(self.structs, offset)?;
+			offset += 4;
+
+			match token {
+				FDT_BEGIN_NODE => {
+					let name = CStr::from_bytes_until_nul(self.structs.get(offset..)?).ok()?;
+					offset += (name.to_bytes().len() + 1).next_multiple_of(4);
+
+					if depth == FDT_MAX_DEPTH {
+						return None;
+					}
+
+					path[depth] = name.to_str().ok()?;
+					depth += 1;
+				}
+				FDT_END_NODE => depth = depth.checked_sub(1)?,
+				FDT_PROP => {
+					let len = be32(self.structs, offset)? as usize;
+					let name = self.string(be32(self.structs, offset + 4)? as usize)?;
+					let value = self.structs.get(offset + 8..offset + 8 + len)?;
+					offset += 8 + len.next_multiple_of(4);
+
+					f(&path[..depth], name, value);
+				}
+				FDT_NOP => {}
+				FDT_END => return Some(()),
+				_ => return None,
+			}
+		}
+	}
+}
+
+fn find_dtb() -> Option<&'static [u8]> {
+	let addr = uefi::system::with_config_table(|entries| {
+		entries.iter().find(|e| e.guid == DTB_GUID).map(|e| e.address as *const u8)
+	})?;
+
+	// only the header is known to be valid until we have read the total size from it
+	let header = unsafe { core::slice::from_raw_parts(addr, FDT_HEADER_SIZE) };
+	if be32(header, 0)? != FDT_MAGIC {
+		return None;
+	}
+
+	let size = be32(header, 4)? as usize;
+	Some(unsafe { core::slice::from_raw_parts(addr, size) })
+}
+
+// The MMIO fw_cfg of ARM virt machines, a `qemu,fw-cfg-mmio` node whose `reg` uses two address cells.
+// It is looked up before anything else, so without the log lines of `discover`.
+pub fn fw_cfg_base() -> Option<usize> {
+	let fdt = Fdt::new(find_dtb()?)?;
+
+	let mut base = None;
+	let mut node_ptr: *const u8 = core::ptr::null();
+	let mut fw_cfg = false;
+	let mut reg: Option<u64> = None;
+
+	fdt.walk(|path, prop, value| {
+		let Some(&node) = path.last() else {
+			return;
+		};
+
+		if node.as_ptr() != node_ptr {
+			node_ptr = node.as_ptr();
+			fw_cfg = false;
+			reg = None;
+		}
+
+		match prop {
+			"compatible" => fw_cfg = value.split(|&b| b == 0).any(|c| c == b"qemu,fw-cfg-mmio"),
+			"reg" => reg = be64(value, 0),
+			_ => {}
+		}
+
+		if fw_cfg && let Some(addr) = reg && base.is_none() {
+			base = Some(addr as usize);
+		}
+	})?;
+
+	base
+}
+
+pub fn discover() -> Option<DtIgdInfo> {
+	let Some(blob) = find_dtb() else {
+		debug!("No device tree installed");
+		return None;
+	};
+
+	let Some(fdt) = Fdt::new(blob) else {
+		error!("Device tree has an invalid header!");
+		return None;
+	};
+
+	let mut info = DtIgdInfo::default();
+	// properties of a node may come in any order, so collect `compatible` and `reg` per node. Node
+	// names are slices into the blob, which makes their address a unique node identifier.
+	let mut node_ptr: *const u8 = core::ptr::null();
+	let mut intel = false;
+	let mut reg_hi: Option<u32> = None;
+
+	let walked = fdt.walk(|path, prop, value| {
+		let Some(&node) = path.last() else {
+			return;
+		};
+
+		if node.as_ptr() != node_ptr {
+			node_ptr = node.as_ptr();
+			intel = false;
+			reg_hi = None;
+		}
+
+		match prop {
+			"compatible" => intel = value.split(|&b| b == 0).any(|c| c.starts_with(b"pci8086,")),
+			"reg" if path.len() == 3 && path[1] == "reserved-memory" && node.starts_with("igd-opregion") => {
+				if let (Some(base), Some(size)) = (be64(value, 0), be64(value, 8)) {
+					info.opregion = Some((base, size));
+				}
+			}
+			"reg" => reg_hi = be32(value, 0),
+			_ => {}
+		}
+
+		if intel && let Some(hi) = reg_hi && info.location.is_none() {
+			info.location = Some(((hi >> 16) as u8, ((hi >> 11) & 0x1F) as u8, ((hi >> 8) & 0x7) as u8));
+		}
+	});
+
+	if walked.is_none() {
+		error!("Device tree structure block is malformed!");
+		return None;
+	}
+
+	if let Some((bus, dev, func)) = info.location {
+		info!("Device tree describes an Intel device at {:02x}:{:02x}.{}", bus, dev, func);
+	}
+
+	if let Some((base, size)) = info.opregion {
+		info!("Device tree reserves OpRegion @ {:#x} ({} bytes)", base, size);
+	}
+
+	Some(info)
+}
@@ -0,0 +1,168 @@
+// With a vIOMMU, i915 DMAs into stolen memory only work if the range is in an RMRR of the DMAR. QEMU
+// doesn't add one, so the DMAR is reinstalled with an RMRR covering our stolen memory and scoped to the
+// IGD. Without a DMAR there is no vIOMMU and nothing to do.
+//
+// OVMF installs QEMU's ACPI tables only once the root bridges are connected, which is usually after
+// the IGD was configured, so a missing DMAR is looked for again at ReadyToBoot.
+
+use core::{ffi::c_void, ptr::NonNull};
+use log::{debug, error, info};
+use uefi::{boot::{self, EventType, Tpl}, guid, Event, Guid};
+
+use crate::{acpi, args::Location, outcome::Region};
+
+const DMAR_SIGNATURE: &[u8; 4] = b"DMAR";
+// the host address width, flags and reserved bytes following the table header
+const DMAR_REMAPPING_OFFSET: usize = acpi::HEADER_SIZE + 12;
+
+const DMAR_TYPE_DRHD: u16 = 0;
+const DMAR_TYPE_RMRR: u16 = 1;
+const DMAR_SCOPE_PCI_ENDPOINT: u8 = 1;
+
+const RMRR_HEADER_SIZE: usize = 24;
+const SCOPE_SIZE: usize = 8;
+
+const READY_TO_BOOT_GUID: Guid = guid!("7ce88fb3-4bd7-4679-87a8-a8d8dee50d2b");
+
+struct Pending {
+	location: Location,
+	stolen_memory: Region,
+	event: Event,
+}
+
+static mut PENDING: Option<Pending> = None;
+
+fn rmrr(location: Location, stolen_memory: Region) -> [u8; RMRR_HEADER_SIZE + SCOPE_SIZE] {
+	let (seg, bus, dev, func) = location;
+	let base = stolen_memory.base as u64;
+	let limit = base + stolen_memory.size as u64 - 1;
+
+	let mut entry = [0u8; RMRR_HEADER_SIZE + SCOPE_SIZE];
+	entry[0..2].copy_from_slice(&DMAR_TYPE_RMRR.to_le_bytes());
+	entry[2..4].copy_from_slice(&((RMRR_HEADER_SIZE + SCOPE_SIZE) as u16).to_le_bytes());
+	entry[6..8].copy_from_slice(&(seg as u16).to_le_bytes());
+	entry[8..16].copy_from_slice(&base.to_le_bytes());
+	entry[16..24].copy_from_slice(&limit.to_le_bytes());
+
+	// a single PCI endpoint scope, with a path of one device and function below the start bus
+	let scope = &mut entry[RMRR_HEADER_SIZE..];
+	scope[0] = DMAR_SCOPE_PCI_ENDPOINT;
+	scope[1] = SCOPE_SIZE as u8;
+	scope[5] = bus as u8;
+	scope[6] = dev as u8;
+	scope[7] = func as u8;
+
+	entry
+}
+
+// RMRRs follow the DRHDs and precede all other structures, returns `None` if an RMRR for the same range
+// is in there already
+fn insert_offset(dmar: &[u8], base: u64) -> Option<usize> {
+	let mut offset = DMAR_REMAPPING_OFFSET;
+	let mut insert = offset;
+
+	while offset + 4 <= dmar.len() {
+		let ty = u16::from_le_bytes([dmar[offset], dmar[offset + 1]]);
+		let len = u16::from_le_bytes([dmar[offset + 2], dmar[offset + 3]]) as usize;
+
+		if len < 4 || offset + len > dmar.len() {
+			break;
+		}
+
+		if ty == DMAR_TYPE_RMRR && len >= RMRR_HEADER_SIZE
+			&& u64::from_le_bytes(dmar[offset + 8..offset + 16].try_into().unwrap()) == base {
+			return None;
+		}
+
+		if ty == DMAR_TYPE_DRHD || ty == DMAR_TYPE_RMRR {
+			insert = offset + len;
+		}
+
+		offset += len;
+	}
+
+	Some(insert)
+}
+
+// `false` if there is no DMAR (yet)
+fn update(location: Location, stolen_memory: Region) -> bool {
+	let Some((dmar, key)) = acpi::find(DMAR_SIGNATURE) else {
+		return false;
+	};
+
+	if dmar.len() < DMAR_REMAPPING_OFFSET {
+		error!("DMAR is only {} bytes, not adding an RMRR!", dmar.len());
+		return true;
+	}
+
+	let Some(offset) = insert_offset(&dmar, stolen_memory.base as u64) else {
+		debug!("DMAR already has an RMRR for the stolen memory");
+		return true;
+	};
+
+	let mut updated = dmar[..offset].to_vec();
+	updated.extend_from_slice(&rmrr(location, stolen_memory));
+	updated.extend_from_slice(&dmar[offset..]);
+	acpi::finish(&mut updated);
+
+	if let Err(status) = acpi::uninstall(key) {
+		error!("Failed to uninstall the DMAR: {:?}!", status);
+		return true;
+	}
+
+	if let Err(status) = acpi::install(&updated) {
+		error!("Failed to install the updated DMAR: {:?}, restoring the original!", status);
+
+		if acpi::install(&dmar).is_err() {
+			error!("Failed to restore the DMAR, the vIOMMU is no longer described!");
+		}
+
+		return true;
+	}
+
+	info!("Added an RMRR for the stolen memory at {:#x} to the DMAR", stolen_memory.base);
+	true
+}
+
+unsafe extern "efiapi" fn ready_to_boot(_event: Event, _ctx: Option<NonNull<c_void>>) {
+	if let Some(pending) = PENDING.take() {
+		if !update(pending.location, pending.stolen_memory) {
+			debug!("No DMAR at ReadyToBoot, no vIOMMU to add an RMRR to");
+		}
+
+		let _ = boot::close_event(pending.event);
+	}
+}
+
+// add the RMRR now if the DMAR is there already, at ReadyToBoot otherwise
+pub unsafe fn reserve(location: Location, stolen_memory: Region) {
+	cancel();
+
+	if update(location, stolen_memory) {
+		return;
+	}
+
+	debug!("No DMAR yet, looking again at ReadyToBoot");
+
+	let group = NonNull::from(&READY_TO_BOOT_GUID);
+	let event = match boot::create_event_ex(EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, Some(ready_to_boot), None, Some(group)) {
+		Ok(e) => e,
+		Err(e) => {
+			error!("Failed to create the ReadyToBoot event: {:?}!", e.status());
+			return;
+		}
+	};
+
+	PENDING = Some(Pending {
+		location,
+		stolen_memory,
+		event,
+	});
+}
+
+// drop a pending ReadyToBoot update, which must not outlive the image
+pub unsafe fn cancel() {
+	if let Some(pending) = PENDING.take() {
+		let _ = boot::close_event(pending.event);
+	}
+}
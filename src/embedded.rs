@@ -0,0 +1,11 @@
+// blobs baked into the binary at build time, used as a last resort when the host passes nothing
+
+#[cfg(feature = "embedded-config")]
+pub const CONFIG: Option<&[u8]> = Some(include_bytes!(env!("IGD_EMBEDDED_CONFIG")));
+#[cfg(not(feature = "embedded-config"))]
+pub const CONFIG: Option<&[u8]> = None;
+
+#[cfg(feature = "embedded-opregion")]
+pub const OPREGION: Option<&[u8]> = Some(include_bytes!(env!("IGD_EMBEDDED_OPREGION")));
+#[cfg(not(feature = "embedded-opregion"))]
+pub const OPREGION: Option<&[u8]> = None;
@@ -0,0 +1,70 @@
+// OpRegions a previous boot stage left in memory, found through ASLS or by scanning ACPI NVS. Unlike
+// `opregion`, this reads physical memory and the UEFI memory map.
+
+use core::mem::offset_of;
+use uefi::{boot::{self, MemoryType}, mem::memory_map::{MemoryDescriptor, MemoryMap}};
+
+use crate::{opregion::{Header, SIGNATURE}, outcome::Region, PAGE_SIZE};
+
+fn has_signature(addr: usize, signatures: &[[u8; 16]]) -> bool {
+	let signature = unsafe { core::slice::from_raw_parts(addr as *const u8, SIGNATURE.len()) };
+	signatures.iter().any(|s| signature == s)
+}
+
+fn header_size(addr: usize) -> usize {
+	let size = unsafe { ((addr + offset_of!(Header, size)) as *const u32).read_unaligned() };
+	size as usize * 1024
+}
+
+fn range_contains(d: &MemoryDescriptor, addr: usize, size: usize) -> bool {
+	let start = d.phys_start as usize;
+	let end = start + d.page_count as usize * PAGE_SIZE;
+
+	addr >= start && addr + size <= end
+}
+
+fn nvs_contains(d: &MemoryDescriptor, addr: usize, size: usize) -> bool {
+	d.ty == MemoryType::ACPI_NON_VOLATILE && range_contains(d, addr, size)
+}
+
+// the OpRegion ASLS points at, which is only trusted if it is inside ACPI NVS or reserved memory
+pub fn at_asls(asls: usize, signatures: &[[u8; 16]]) -> Option<Region> {
+	if asls == 0 {
+		return None;
+	}
+
+	let mmap = boot::memory_map(MemoryType::LOADER_DATA).ok()?;
+
+	// reserved memory is accepted as well, as the OpRegion may have been allocated like that on purpose
+	let contains = |addr, size| mmap.entries().any(|d| {
+		nvs_contains(d, addr, size) || (d.ty == MemoryType::RESERVED && range_contains(d, addr, size))
+	});
+
+	if !contains(asls, SIGNATURE.len()) || !has_signature(asls, signatures) {
+		return None;
+	}
+
+	let size = header_size(asls);
+	contains(asls, size).then_some(Region { base: asls, size })
+}
+
+// an OpRegion a previous boot stage already placed in ACPI NVS, preferring the one ASLS points at
+pub fn find_existing(asls: usize, signatures: &[[u8; 16]]) -> Option<Region> {
+	if let Some(region) = at_asls(asls, signatures) {
+		return Some(region);
+	}
+
+	let mmap = boot::memory_map(MemoryType::LOADER_DATA).ok()?;
+
+	for d in mmap.entries().filter(|d| d.ty == MemoryType::ACPI_NON_VOLATILE) {
+		for page in 0..d.page_count as usize {
+			let addr = d.phys_start as usize + page * PAGE_SIZE;
+
+			if has_signature(addr, signatures) && nvs_contains(d, addr, header_size(addr)) {
+				return Some(Region { base: addr, size: header_size(addr) });
+			}
+		}
+	}
+
+	None
+}
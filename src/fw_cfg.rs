@@ -0,0 +1,52 @@
+// Where fw_cfg files come from. The real source is QEMU's fw_cfg device, `MemoryFwCfg` serves a fixed
+// set of files from memory, so parsing and size decoding can run without one.
+
+use qemu_fw_cfg::{FwCfg, FwCfgFile};
+
+pub trait FwCfgSource {
+	type File;
+
+	fn find_file(&mut self, name: &str) -> Option<Self::File>;
+	fn file_size(&self, file: &Self::File) -> usize;
+	fn read_file(&mut self, file: &Self::File, buf: &mut [u8]);
+}
+
+impl FwCfgSource for FwCfg {
+	type File = FwCfgFile;
+
+	fn find_file(&mut self, name: &str) -> Option<FwCfgFile> {
+		FwCfg::find_file(self, name)
+	}
+
+	fn file_size(&self, file: &FwCfgFile) -> usize {
+		file.size()
+	}
+
+	fn read_file(&mut self, file: &FwCfgFile, buf: &mut [u8]) {
+		self.read_file_to_buffer(file, buf);
+	}
+}
+
+// files as (name, contents)
+pub struct MemoryFwCfg<'a>(pub &'a [(&'a str, &'a [u8])]);
+
+impl FwCfgSource for MemoryFwCfg<'_> {
+	// index into the file list
+	type File = usize;
+
+	fn find_file(&mut self, name: &str) -> Option<usize> {
+		self.0.iter().position(|&(n, _)| n == name)
+	}
+
+	fn file_size(&self, &file: &usize) -> usize {
+		self.0[file].1.len()
+	}
+
+	// like the device, which reads zeroes past the end of a file
+	fn read_file(&mut self, &file: &usize, buf: &mut [u8]) {
+		let data = self.0[file].1;
+		let len = buf.len().min(data.len());
+		buf[..len].copy_from_slice(&data[..len]);
+		buf[len..].fill(0);
+	}
+}
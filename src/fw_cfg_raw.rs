@@ -0,0 +1,284 @@
+// Raw access to the fw_cfg interface, for what qemu_fw_cfg doesn't offer: reading a file in several
+// pieces after selecting it once, and reading or writing files through the DMA interface. On x86 it
+// sits at IO ports 0x510 and up, elsewhere, e.g. on ARM virt machines, it is memory-mapped at a base
+// described by the device tree or the DSDT.
+
+use alloc::{string::String, vec::Vec};
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+use core::arch::asm;
+use core::{ptr::addr_of_mut, sync::atomic::{fence, Ordering}};
+use qemu_fw_cfg::FwCfg;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const FW_CFG_PORT_SELECTOR: u16 = 0x510;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const FW_CFG_PORT_DATA: u16 = 0x511;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const FW_CFG_PORT_DMA: u16 = 0x514;
+
+// register offsets of the MMIO interface, where the selector is big-endian as well
+const FW_CFG_MMIO_DATA: usize = 0x00;
+const FW_CFG_MMIO_SELECTOR: usize = 0x08;
+const FW_CFG_MMIO_DMA: usize = 0x10;
+
+const FW_CFG_SIGNATURE: u16 = 0x00;
+const FW_CFG_SIGNATURE_QEMU: &[u8; 4] = b"QEMU";
+const FW_CFG_ID: u16 = 0x01;
+const FW_CFG_ID_DMA: u32 = 1 << 1;
+
+const FW_CFG_DMA_CTL_ERROR: u32 = 0x01;
+const FW_CFG_DMA_CTL_READ: u32 = 0x02;
+const FW_CFG_DMA_CTL_SELECT: u32 = 0x08;
+const FW_CFG_DMA_CTL_WRITE: u32 = 0x10;
+
+const FW_CFG_FILE_DIR: u16 = 0x19;
+const FW_CFG_FILE_ENTRY_SIZE: usize = 64;
+const FW_CFG_FILE_NAME_OFFSET: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+	// the selector, data and DMA ports at 0x510
+	#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+	Io,
+	// the registers at this physical address
+	Mmio(usize),
+}
+
+static mut TRANSPORT: Option<Transport> = None;
+
+// all fields are big-endian
+#[repr(C)]
+struct DmaAccess {
+	control: u32,
+	length: u32,
+	address: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct FileEntry {
+	pub key: u16,
+	pub size: usize,
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+unsafe fn outw(port: u16, value: u16) {
+	asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack, preserves_flags));
+}
+
+// starts DMA transfers, so unlike the other port accesses it may not be moved across memory accesses
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+unsafe fn outl(port: u16, value: u32) {
+	asm!("out dx, eax", in("dx") port, in("eax") value, options(nostack, preserves_flags));
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+unsafe fn inb(port: u16) -> u8 {
+	let value: u8;
+	asm!("in al, dx", in("dx") port, out("al") value, options(nomem, nostack, preserves_flags));
+	value
+}
+
+// the IO ports are always there to probe
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn detect() -> Option<Transport> {
+	Some(Transport::Io)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect() -> Option<Transport> {
+	let base = crate::devicetree::fw_cfg_base().or_else(crate::acpi::fw_cfg_base)?;
+	log::debug!("fw_cfg is memory-mapped @ {:#x}", base);
+
+	Some(Transport::Mmio(base))
+}
+
+// how fw_cfg is reached, looked up on first use
+pub fn transport() -> Option<Transport> {
+	unsafe {
+		if TRANSPORT.is_none() {
+			TRANSPORT = detect();
+		}
+
+		TRANSPORT
+	}
+}
+
+// selecting an item resets the read offset to its start
+pub fn select(key: u16) {
+	match transport() {
+		#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+		Some(Transport::Io) => unsafe { outw(FW_CFG_PORT_SELECTOR, key) },
+		Some(Transport::Mmio(base)) => unsafe { ((base + FW_CFG_MMIO_SELECTOR) as *mut u16).write_volatile(key.to_be()) },
+		None => {}
+	}
+}
+
+// continues reading the selected item where the previous read stopped
+pub fn read(buf: &mut [u8]) {
+	let Some(transport) = transport() else {
+		buf.fill(0xFF);
+		return;
+	};
+
+	for b in buf.iter_mut() {
+		*b = match transport {
+			#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+			Transport::Io => unsafe { inb(FW_CFG_PORT_DATA) },
+			Transport::Mmio(base) => unsafe { ((base + FW_CFG_MMIO_DATA) as *const u8).read_volatile() },
+		};
+	}
+}
+
+// Without a fw_cfg device, e.g. on bare metal or another hypervisor, nothing decodes the ports and the
+// data port reads back as all ones, so this is safe to probe anywhere. The MMIO interface is only
+// probed where the platform describes one.
+pub fn present() -> bool {
+	if transport().is_none() {
+		return false;
+	}
+
+	let mut signature = [0u8; 4];
+	select(FW_CFG_SIGNATURE);
+	read(&mut signature);
+
+	&signature == FW_CFG_SIGNATURE_QEMU
+}
+
+// the qemu_fw_cfg handle for the same interface
+pub unsafe fn open() -> Option<FwCfg> {
+	match transport()? {
+		#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+		Transport::Io => FwCfg::new_for_x86().ok(),
+		Transport::Mmio(base) => FwCfg::new_memory_mapped(base as *mut ()).ok(),
+	}
+}
+
+pub fn find_file(name: &str) -> Option<FileEntry> {
+	// the directory is a big-endian count followed by big-endian (size, select, reserved, name) entries
+	select(FW_CFG_FILE_DIR);
+
+	let mut count: [u8; 4] = [0; 4];
+	read(&mut count);
+
+	for _ in 0..u32::from_be_bytes(count) {
+		let mut entry: [u8; FW_CFG_FILE_ENTRY_SIZE] = [0; FW_CFG_FILE_ENTRY_SIZE];
+		read(&mut entry);
+
+		let entry_name = &entry[FW_CFG_FILE_NAME_OFFSET..];
+		let len = entry_name.iter().position(|&b| b == 0).unwrap_or(entry_name.len());
+
+		if &entry_name[..len] == name.as_bytes() {
+			return Some(FileEntry {
+				key: u16::from_be_bytes([entry[4], entry[5]]),
+				size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize,
+			});
+		}
+	}
+
+	None
+}
+
+// every file in the directory, with names that aren't valid UTF-8 replaced
+pub fn list_files() -> Vec<(String, FileEntry)> {
+	select(FW_CFG_FILE_DIR);
+
+	let mut count: [u8; 4] = [0; 4];
+	read(&mut count);
+
+	(0..u32::from_be_bytes(count)).map(|_| {
+		let mut entry: [u8; FW_CFG_FILE_ENTRY_SIZE] = [0; FW_CFG_FILE_ENTRY_SIZE];
+		read(&mut entry);
+
+		let entry_name = &entry[FW_CFG_FILE_NAME_OFFSET..];
+		let len = entry_name.iter().position(|&b| b == 0).unwrap_or(entry_name.len());
+		let file = FileEntry {
+			key: u16::from_be_bytes([entry[4], entry[5]]),
+			size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize,
+		};
+
+		(String::from_utf8_lossy(&entry_name[..len]).into_owned(), file)
+	}).collect()
+}
+
+pub fn dma_supported() -> bool {
+	select(FW_CFG_ID);
+
+	let mut id: [u8; 4] = [0; 4];
+	read(&mut id);
+
+	u32::from_le_bytes(id) & FW_CFG_ID_DMA != 0
+}
+
+fn dma(control: u32, buf: *mut u8, len: usize) -> bool {
+	// the device writes the control field back, so it is only ever accessed through this pointer
+	let mut access = DmaAccess {
+		control: control.to_be(),
+		length: (len as u32).to_be(),
+		address: (buf as u64).to_be(),
+	};
+	let access = addr_of_mut!(access);
+
+	let addr = access as u64;
+	let (high, low) = (((addr >> 32) as u32).to_be(), (addr as u32).to_be());
+
+	// the device has to see the descriptor before the transfer starts
+	fence(Ordering::SeqCst);
+
+	// the address register is big-endian, writing its low half starts the transfer
+	match transport() {
+		#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+		Some(Transport::Io) => unsafe {
+			outl(FW_CFG_PORT_DMA, high);
+			outl(FW_CFG_PORT_DMA + 4, low);
+		},
+		Some(Transport::Mmio(base)) => unsafe {
+			((base + FW_CFG_MMIO_DMA) as *mut u32).write_volatile(high);
+			((base + FW_CFG_MMIO_DMA + 4) as *mut u32).write_volatile(low);
+		},
+		None => return false,
+	}
+
+	// QEMU completes the transfer synchronously, but the spec allows for it to be asynchronous
+	loop {
+		let control = u32::from_be(unsafe { addr_of_mut!((*access).control).read_volatile() });
+
+		if control & FW_CFG_DMA_CTL_ERROR != 0 {
+			return false;
+		}
+
+		// the device is done with the buffer, nothing may read it any earlier
+		if control == 0 {
+			fence(Ordering::SeqCst);
+			return true;
+		}
+
+		core::hint::spin_loop();
+	}
+}
+
+// a whole file in a single transfer, which is much faster than the data port for large files.
+// `false` if DMA isn't available or failed, in which case the caller falls back to the data port.
+pub fn read_file_dma(file: FileEntry, buf: &mut [u8]) -> bool {
+	if !dma_supported() || buf.len() > file.size {
+		return false;
+	}
+
+	dma(((file.key as u32) << 16) | FW_CFG_DMA_CTL_SELECT | FW_CFG_DMA_CTL_READ, buf.as_mut_ptr(), buf.len())
+}
+
+// guest writes are only possible through DMA, and only for items the host made writable
+pub fn write_file(name: &str, data: &[u8]) -> bool {
+	if !dma_supported() {
+		return false;
+	}
+
+	let Some(file) = find_file(name) else {
+		return false;
+	};
+
+	if data.len() > file.size {
+		return false;
+	}
+
+	dma(((file.key as u32) << 16) | FW_CFG_DMA_CTL_SELECT | FW_CFG_DMA_CTL_WRITE, data.as_ptr() as *mut u8, data.len())
+}
@@ -0,0 +1,187 @@
+// Mapping of Intel GPU device IDs to their graphics generation, which decides the register layout
+// used for stolen memory. The ranges follow the platform groups in Linux' i915_pciids.h; a range may
+// include a few unassigned IDs in between, which is harmless as only real devices are looked up.
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Generation {
+	Gen4,
+	Gen5,
+	Gen6,
+	Gen7,
+	Gen8,
+	Gen9,
+	Gen11,
+	Gen12,
+	// DG2/Alchemist
+	XeHpg,
+	// Meteor Lake, Arrow Lake
+	XeLpg,
+	// Lunar Lake, Battlemage
+	Xe2,
+}
+
+// first and last device ID of each range, sorted by the first ID
+const DEVICE_RANGES: &[(u16, u16, Generation)] = &[
+	(0x0042, 0x0046, Generation::Gen5), // Ironlake
+	(0x0102, 0x0126, Generation::Gen6), // Sandy Bridge
+	(0x0152, 0x016A, Generation::Gen7), // Ivy Bridge
+	(0x0402, 0x042A, Generation::Gen7), // Haswell
+	(0x0A02, 0x0A2E, Generation::Gen7), // Haswell ULT
+	(0x0A84, 0x0A84, Generation::Gen9), // Broxton
+	(0x0C02, 0x0C2E, Generation::Gen7), // Haswell SDV
+	(0x0D02, 0x0D2E, Generation::Gen7), // Haswell CRW
+	(0x0F30, 0x0F33, Generation::Gen7), // Valleyview
+	(0x1602, 0x163E, Generation::Gen8), // Broadwell
+	(0x1902, 0x193D, Generation::Gen9), // Skylake
+	(0x1A84, 0x1A85, Generation::Gen9), // Broxton
+	(0x22B0, 0x22B3, Generation::Gen8), // Cherryview
+	(0x2972, 0x29A2, Generation::Gen4), // Broadwater, Crestline
+	(0x2A02, 0x2A12, Generation::Gen4), // Crestline
+	(0x2A42, 0x2A42, Generation::Gen4), // Cantiga
+	(0x2E02, 0x2E92, Generation::Gen4), // Eaglelake
+	(0x3184, 0x3185, Generation::Gen9), // Gemini Lake
+	(0x3E90, 0x3EA9, Generation::Gen9), // Coffee Lake
+	(0x4500, 0x4571, Generation::Gen11), // Elkhart Lake
+	(0x4680, 0x46D4, Generation::Gen12), // Alder Lake
+	(0x4905, 0x4909, Generation::Gen12), // DG1
+	(0x4C80, 0x4C9A, Generation::Gen12), // Rocket Lake
+	(0x4E51, 0x4E71, Generation::Gen11), // Jasper Lake
+	(0x5690, 0x56C2, Generation::XeHpg), // DG2
+	(0x5902, 0x593B, Generation::Gen9), // Kaby Lake
+	(0x5A84, 0x5A85, Generation::Gen9), // Broxton
+	(0x6420, 0x64B0, Generation::Xe2), // Lunar Lake
+	(0x7D40, 0x7DD5, Generation::XeLpg), // Meteor Lake, Arrow Lake
+	(0x87C0, 0x87CA, Generation::Gen9), // Amber Lake
+	(0x8A50, 0x8A71, Generation::Gen11), // Ice Lake
+	(0x9A40, 0x9AF8, Generation::Gen12), // Tiger Lake
+	(0x9B21, 0x9BF6, Generation::Gen9), // Comet Lake
+	(0xA720, 0xA7AD, Generation::Gen12), // Raptor Lake
+	(0xE202, 0xE212, Generation::Xe2), // Battlemage
+];
+
+// discrete cards among the ranges above, which have no stolen memory, sorted by the first ID
+const DISCRETE_RANGES: &[(u16, u16)] = &[
+	(0x4905, 0x4909), // DG1
+	(0x5690, 0x56C2), // DG2
+	(0xE202, 0xE212), // Battlemage
+];
+
+const MIB: usize = 1024 * 1024;
+
+impl Generation {
+	pub fn from_device_id(device: u16) -> Option<Self> {
+		DEVICE_RANGES.iter()
+			.find(|&&(first, last, _)| (first..=last).contains(&device))
+			.map(|&(_, _, generation)| generation)
+	}
+
+	pub fn is_discrete(device: u16) -> bool {
+		DISCRETE_RANGES.iter().any(|&(first, last)| (first..=last).contains(&device))
+	}
+
+	// Ironlake through Broadwell, only set up when built with the `legacy` feature
+	pub fn is_legacy(self) -> bool {
+		self < Generation::Gen9
+	}
+
+	// the graphics IP major version as i915 reports it
+	pub const fn number(self) -> u8 {
+		match self {
+			Generation::Gen4 => 4,
+			Generation::Gen5 => 5,
+			Generation::Gen6 => 6,
+			Generation::Gen7 => 7,
+			Generation::Gen8 => 8,
+			Generation::Gen9 => 9,
+			Generation::Gen11 => 11,
+			Generation::Gen12 | Generation::XeHpg | Generation::XeLpg => 12,
+			Generation::Xe2 => 20,
+		}
+	}
+
+	// Gen11 moved BDSM to a 64-bit register at 0xC0, older parts only have the 32-bit one at 0x5C
+	pub fn has_bdsm64(self) -> bool {
+		self >= Generation::Gen11
+	}
+
+	// Gen12 and newer mirror BDSM in the GFX MMIO BAR, which is what the guest driver reads on some of them
+	pub fn has_bdsm_mmio(self) -> bool {
+		self >= Generation::Gen12
+	}
+
+	// Alignment of the data stolen memory base. BDSM only holds bits 31:20 (63:20 for the 64-bit one),
+	// so 1 MiB is the least any generation takes. Meteor Lake and newer firmware places DSM on an
+	// 8 MiB boundary, which the guest driver's view of the stolen memory layout assumes.
+	pub fn dsm_alignment(self) -> usize {
+		match self {
+			Generation::XeLpg | Generation::Xe2 => 8 * MIB,
+			_ => MIB,
+		}
+	}
+
+	// BGSM holds bits 31:20 as well, GTT stolen memory has no stricter requirement on any generation
+	pub fn gsm_alignment(self) -> usize {
+		MIB
+	}
+
+	// Graphics Mode Select field of GGC, as (mask, value) for a stolen memory size of `size` bytes.
+	// Gen6/7 encode the size in 32 MiB units in bits 7:3. Gen8 and newer have bits 15:8, with 0xF0 and
+	// up encoding 4 MiB steps from 4 MiB. The Gen4/5 GMCH encoding isn't supported.
+	pub fn encode_gms(self, size: usize) -> Option<(u16, u16)> {
+		match self {
+			#[cfg(feature = "legacy")]
+			Generation::Gen4 | Generation::Gen5 => None,
+			#[cfg(feature = "legacy")]
+			Generation::Gen6 | Generation::Gen7 => {
+				let units = (size % (32 * MIB) == 0).then_some(size / (32 * MIB))?;
+				(1..=0x1F).contains(&units).then_some((0x00F8, (units as u16) << 3))
+			}
+			_ => {
+				let value = if size % (32 * MIB) == 0 && (1..=0x40).contains(&(size / (32 * MIB))) {
+					size / (32 * MIB)
+				} else if size % (4 * MIB) == 0 && (1..=0x0F).contains(&(size / (4 * MIB))) {
+					0xF0 + size / (4 * MIB) - 1
+				} else {
+					return None;
+				};
+
+				Some((0xFF00, (value as u16) << 8))
+			}
+		}
+	}
+
+	// the stolen memory size GMS in `ggc` selects, the inverse of `encode_gms`; `None` if GMS is zero
+	// or a value the generation doesn't define
+	pub fn decode_gms(self, ggc: u16) -> Option<usize> {
+		let size = match self {
+			#[cfg(feature = "legacy")]
+			Generation::Gen4 | Generation::Gen5 => return None,
+			#[cfg(feature = "legacy")]
+			Generation::Gen6 | Generation::Gen7 => ((ggc >> 3) & 0x1F) as usize * 32 * MIB,
+			_ => match ggc >> 8 {
+				value @ 0x01..=0x40 => value as usize * 32 * MIB,
+				value @ 0xF0..=0xFE => (value as usize - 0xF0 + 1) * 4 * MIB,
+				_ => 0,
+			},
+		};
+
+		(size > 0).then_some(size)
+	}
+
+	// size of the GTT stolen memory selected by the GGMS field of GGC, `None` if there is none.
+	// Gen6/7 have it in bits 9:8 in 1 MiB steps, Gen8 and newer in bits 7:6 as 2, 4 or 8 MiB.
+	pub fn gtt_stolen_memory_size(self, ggc: u16) -> Option<usize> {
+		let size = match self {
+			#[cfg(feature = "legacy")]
+			Generation::Gen4 | Generation::Gen5 => return None,
+			#[cfg(feature = "legacy")]
+			Generation::Gen6 | Generation::Gen7 => ((ggc >> 8) & 0x3) as usize * MIB,
+			_ => match (ggc >> 6) & 0x3 {
+				0 => 0,
+				ggms => (1usize << ggms) * MIB,
+			},
+		};
+
+		(size > 0).then_some(size)
+	}
+}
@@ -0,0 +1,72 @@
+// Chain-loading the Intel GOP driver passed in `etc/igd-gop`, for pre-OS video output from the IGD.
+// It needs the OpRegion and stolen memory, so it is only started once those are set up, and it is
+// connected to the IGD alone rather than left for BDS to connect to whatever it supports.
+//
+// LoadImage and StartImage can't be used from the notify, so this only happens from the entry point.
+
+use log::{error, info};
+use qemu_fw_cfg::FwCfg;
+use uefi::{boot::{self, LoadImageSource}, Handle};
+
+use crate::{compressed, igd_location, integrity, oprom, pci};
+
+const GOP_FILE: &str = "etc/igd-gop";
+
+// whether a GOP driver will be started, from `etc/igd-gop` or an EFI option ROM image
+pub(crate) fn present(fw_cfg: &mut FwCfg) -> bool {
+	fw_cfg.find_file(GOP_FILE).is_some() || fw_cfg.find_file(oprom::OPROM_FILE).is_some()
+}
+
+pub fn load(fw_cfg: &mut FwCfg) {
+	let Some(file) = fw_cfg.find_file(GOP_FILE) else {
+		return;
+	};
+
+	let (seg, bus, dev, func) = igd_location();
+	let Some(igd) = pci::find_handle(igd_location()) else {
+		error!("No IGD at {:04x}:{:02x}:{:02x}.{} to connect the GOP driver to!", seg, bus, dev, func);
+		return;
+	};
+
+	let Ok(image) = compressed::load(fw_cfg, GOP_FILE, &file) else {
+		return;
+	};
+
+	if integrity::verify(fw_cfg, GOP_FILE, &image).is_err() {
+		return;
+	}
+
+	if dispatch(&image, igd) {
+		info!("GOP driver from {} ({} bytes) connected to the IGD", GOP_FILE, image.len());
+	}
+}
+
+// load, start and connect a driver image to `igd`, logging what failed
+pub(crate) fn dispatch(image: &[u8], igd: Handle) -> bool {
+	let source = LoadImageSource::FromBuffer {
+		buffer: image,
+		file_path: None,
+	};
+
+	// with Secure Boot, this fails for a driver not signed by a trusted key
+	let handle = match boot::load_image(boot::image_handle(), source) {
+		Ok(h) => h,
+		Err(e) => {
+			error!("Failed to load the driver image: {:?}!", e.status());
+			return false;
+		}
+	};
+
+	if let Err(e) = boot::start_image(handle) {
+		error!("Driver image failed to start: {:?}!", e.status());
+		let _ = boot::unload_image(handle);
+		return false;
+	}
+
+	if let Err(e) = boot::connect_controller(igd, Some(handle), None, true) {
+		error!("Failed to connect the driver to the IGD: {:?}!", e.status());
+		return false;
+	}
+
+	true
+}
@@ -0,0 +1,63 @@
+// The strict failure policy (tag 0x0023): rather than booting on with a half set up IGD, which only
+// shows up as a black screen in the guest much later, a failure stops boot with the error on screen.
+
+use core::fmt::Write;
+use igd_assignment::{CONFIG, OUTCOME};
+use log::error;
+use uefi::{boot, Status};
+
+use crate::pvpanic;
+
+pub(crate) fn halt(what: &str, status: Status) -> ! {
+	error!("{} ({:?}), stopping boot as the failure policy is strict", what, status);
+
+	uefi::system::with_stdout(|stdout| {
+		let _ = stdout.clear();
+		let _ = writeln!(stdout, "IGD assignment failed: {}", what);
+		let _ = writeln!(stdout, "Status: {:?}", status);
+		let _ = writeln!(stdout);
+		let _ = writeln!(stdout, "Boot was stopped by the strict failure policy (tag 0x0023 in etc/igd-config).");
+		let _ = writeln!(stdout, "The log above this screen, or on the debug console, has the details.");
+	});
+
+	if unsafe { CONFIG.pvpanic } {
+		pvpanic::signal();
+	}
+
+	loop {
+		boot::stall(1_000_000);
+	}
+}
+
+// Once setup is over, whether its outcome is acceptable under `require_device` (tag 0x0002) and
+// `allow_partial` (tag 0x0001). Halts if it isn't and the failure policy is strict, otherwise returns
+// the status to report.
+pub(crate) fn enforce_policy() -> Result<(), Status> {
+	unsafe {
+		if !OUTCOME.attempted() {
+			if !CONFIG.require_device {
+				return Ok(());
+			}
+
+			if CONFIG.strict_failure {
+				halt("No matching device was configured", Status::NOT_FOUND);
+			}
+
+			error!("No matching device was configured!");
+			return Err(Status::NOT_FOUND);
+		}
+
+		let status = OUTCOME.status(CONFIG.allow_partial);
+
+		if status.is_error() {
+			if CONFIG.strict_failure {
+				halt("Setup failed", status);
+			}
+
+			error!("Setup failed with status {:?}!", status);
+			return Err(status);
+		}
+	}
+
+	Ok(())
+}
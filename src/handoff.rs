@@ -0,0 +1,83 @@
+// A last look at ASLS and BDSM at ExitBootServices, to tell whether anything running after us in DXE
+// or BDS, e.g. a GOP driver or PCI resource rebalancing, clobbered them. Nothing may be allocated from
+// the callback, so the PciIo interface is kept from setup and used directly, and the logger sticks to
+// the ports while `EXITING` is set.
+
+use core::{ffi::c_void, ptr::NonNull, sync::atomic::{AtomicBool, Ordering}};
+use log::{error, info};
+use uefi::{boot::{self, EventType, ScopedProtocol, Tpl}, proto::pci::PciIo, Event};
+
+use crate::{outcome::DeviceOutcome, pci, read_bdsm, PCI_CFG_ASLS_OFFSET};
+
+pub static EXITING: AtomicBool = AtomicBool::new(false);
+
+struct Watch {
+	pci_io: *mut PciIo,
+	device: DeviceOutcome,
+	event: Event,
+}
+
+static mut WATCH: Option<Watch> = None;
+
+unsafe extern "efiapi" fn exit_boot_services(_event: Event, _ctx: Option<NonNull<c_void>>) {
+	let Some(watch) = WATCH.take() else {
+		return;
+	};
+
+	EXITING.store(true, Ordering::Release);
+
+	let pci_io = &mut *watch.pci_io;
+	let d = &watch.device;
+	let mut intact = true;
+
+	if let Some(opregion) = d.opregion {
+		match pci::read_u32(pci_io, PCI_CFG_ASLS_OFFSET) {
+			Some(asls) if asls as usize == opregion.base => {}
+			asls => {
+				error!("ASLS was clobbered before ExitBootServices: {:08x?} instead of {:#010x}!", asls, opregion.base);
+				intact = false;
+			}
+		}
+	}
+
+	if let Some(stolen_memory) = d.stolen_memory {
+		match read_bdsm(pci_io, d.generation) {
+			Some(bdsm) if bdsm == stolen_memory.base => {}
+			bdsm => {
+				error!("BDSM was clobbered before ExitBootServices: {:x?} instead of {:#x}!", bdsm, stolen_memory.base);
+				intact = false;
+			}
+		}
+	}
+
+	if intact {
+		info!("ASLS and BDSM are still as programmed at ExitBootServices");
+	}
+}
+
+// check `d` again at ExitBootServices, replacing an earlier watch
+pub unsafe fn watch(pci_io: &mut ScopedProtocol<PciIo>, d: &DeviceOutcome) {
+	cancel();
+
+	let event = match boot::create_event(EventType::SIGNAL_EXIT_BOOT_SERVICES, Tpl::NOTIFY, Some(exit_boot_services), None) {
+		Ok(e) => e,
+		Err(e) => {
+			error!("Failed to create the ExitBootServices event: {:?}!", e.status());
+			return;
+		}
+	};
+
+	// the interface outlives the protocol being closed by us, as long as the handle keeps it installed
+	WATCH = Some(Watch {
+		pci_io: &mut **pci_io,
+		device: *d,
+		event,
+	});
+}
+
+// the event must not outlive the image
+pub unsafe fn cancel() {
+	if let Some(watch) = WATCH.take() {
+		let _ = boot::close_event(watch.event);
+	}
+}
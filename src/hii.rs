@@ -0,0 +1,312 @@
+// Setup form for the options in `igd_assignment::options`, shown in the firmware setup UI under
+// Device Manager. The form uses an EFI variable varstore, so the form browser reads and writes the
+// variable itself and Config Access only has to decline the requests meant for other storage.
+//
+// The packages are assembled by hand as there is no VFR compiler in a cargo build. Opcode and
+// package layouts follow the UEFI specification, sections 33.3.6 (strings) and 33.3.8 (IFR).
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use igd_assignment::options::{LEGACY_BDSM_OFFSET, LOG_LEVEL_OFFSET, OPTIONS_SIZE, OPTIONS_VARIABLE_GUID, OPTIONS_VARIABLE_NAME, SKIP_STOLEN_MEMORY_OFFSET};
+use log::{error, info};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, guid, proto::unsafe_protocol, Guid, Status};
+
+const FORMSET_GUID: Guid = guid!("7a1f3c52-b8e4-4d09-a6f1-2c9e5b7d4a13");
+const PACKAGE_LIST_GUID: Guid = FORMSET_GUID;
+// EFI_HII_PLATFORM_SETUP_FORMSET_GUID, which puts the form under Device Manager
+const PLATFORM_SETUP_CLASS_GUID: Guid = guid!("93039971-8545-4b04-b45e-32eb8326040e");
+const DEVICE_PATH_GUID: Guid = guid!("09576e91-6d3f-11d2-8e39-00a0c969723b");
+const CONFIG_ACCESS_GUID: Guid = guid!("330d4706-f2a0-4e4f-a369-b66fa8d54385");
+
+const EFI_HII_PACKAGE_FORMS: u8 = 0x02;
+const EFI_HII_PACKAGE_STRINGS: u8 = 0x04;
+const EFI_HII_PACKAGE_END: u8 = 0xDF;
+
+const EFI_HII_SIBT_END: u8 = 0x00;
+const EFI_HII_SIBT_STRING_UCS2: u8 = 0x14;
+
+const EFI_IFR_FORM_OP: u8 = 0x01;
+const EFI_IFR_ONE_OF_OP: u8 = 0x05;
+const EFI_IFR_CHECKBOX_OP: u8 = 0x06;
+const EFI_IFR_ONE_OF_OPTION_OP: u8 = 0x09;
+const EFI_IFR_FORM_SET_OP: u8 = 0x0E;
+const EFI_IFR_VARSTORE_EFI_OP: u8 = 0x26;
+const EFI_IFR_END_OP: u8 = 0x29;
+const EFI_IFR_SCOPE: u8 = 0x80;
+const EFI_IFR_OPTION_DEFAULT: u8 = 0x10;
+const EFI_IFR_TYPE_NUM_SIZE_8: u8 = 0x00;
+const EFI_IFR_NUMERIC_SIZE_1: u8 = 0x00;
+
+const EFI_VARIABLE_NON_VOLATILE: u32 = 0x1;
+const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+
+const VARSTORE_ID: u16 = 1;
+const FORM_ID: u16 = 1;
+const LANGUAGE: &[u8] = b"en-US\0";
+
+// string IDs start at 1, in this order
+const STRINGS: &[&str] = &[
+	"English",
+	"IGD Assignment",
+	"Options of the Intel IGD passthrough driver",
+	"Skip stolen memory",
+	"Only set up the OpRegion, don't allocate stolen memory or program BDSM",
+	"Force legacy BDSM offset",
+	"Program the 32-bit BDSM at 0x5C even on Gen11 and newer",
+	"Log level",
+	"Verbosity of the driver's log output",
+	"Default",
+	"Error",
+	"Warning",
+	"Info",
+	"Debug",
+	"Trace",
+];
+
+const STR_LANGUAGE: u16 = 1;
+const STR_TITLE: u16 = 2;
+const STR_HELP: u16 = 3;
+const STR_SKIP_STOLEN_MEMORY: u16 = 4;
+const STR_SKIP_STOLEN_MEMORY_HELP: u16 = 5;
+const STR_LEGACY_BDSM: u16 = 6;
+const STR_LEGACY_BDSM_HELP: u16 = 7;
+const STR_LOG_LEVEL: u16 = 8;
+const STR_LOG_LEVEL_HELP: u16 = 9;
+// followed by one string per log level
+const STR_LOG_LEVEL_DEFAULT: u16 = 10;
+const LOG_LEVELS: u8 = 6;
+
+#[repr(C)]
+struct HiiDatabaseProtocol {
+	new_package_list: unsafe extern "efiapi" fn(*const HiiDatabaseProtocol, *const u8, *mut c_void, *mut *mut c_void) -> Status,
+	// followed by the remaining functions, which aren't used
+}
+
+#[unsafe_protocol("ef9fc172-a1b2-4693-b327-6d32fc416042")]
+struct HiiDatabase(HiiDatabaseProtocol);
+
+#[repr(C)]
+struct ConfigAccessProtocol {
+	extract_config: unsafe extern "efiapi" fn(*const ConfigAccessProtocol, *const u16, *mut *const u16, *mut *mut u16) -> Status,
+	route_config: unsafe extern "efiapi" fn(*const ConfigAccessProtocol, *const u16, *mut *const u16) -> Status,
+	callback: unsafe extern "efiapi" fn(*const ConfigAccessProtocol, usize, u16, u8, *mut c_void, *mut usize) -> Status,
+}
+
+static CONFIG_ACCESS: ConfigAccessProtocol = ConfigAccessProtocol {
+	extract_config,
+	route_config,
+	callback,
+};
+
+// a vendor hardware node and the end node, giving the form a device path of its own
+#[repr(C, packed)]
+struct VendorDevicePath {
+	ty: u8,
+	sub_type: u8,
+	length: [u8; 2],
+	guid: Guid,
+	end: [u8; 4],
+}
+
+static DEVICE_PATH: VendorDevicePath = VendorDevicePath {
+	ty: 0x01,
+	sub_type: 0x04,
+	length: 20u16.to_le_bytes(),
+	guid: FORMSET_GUID,
+	end: [0x7F, 0xFF, 0x04, 0x00],
+};
+
+// the storage is our EFI variable, which the browser handles itself
+unsafe extern "efiapi" fn extract_config(_this: *const ConfigAccessProtocol, request: *const u16, progress: *mut *const u16, _results: *mut *mut u16) -> Status {
+	if progress.is_null() {
+		return Status::INVALID_PARAMETER;
+	}
+
+	*progress = request;
+	Status::NOT_FOUND
+}
+
+unsafe extern "efiapi" fn route_config(_this: *const ConfigAccessProtocol, configuration: *const u16, progress: *mut *const u16) -> Status {
+	if progress.is_null() {
+		return Status::INVALID_PARAMETER;
+	}
+
+	*progress = configuration;
+	Status::NOT_FOUND
+}
+
+unsafe extern "efiapi" fn callback(_this: *const ConfigAccessProtocol, _action: usize, _question: u16, _ty: u8, _value: *mut c_void, _request: *mut usize) -> Status {
+	Status::UNSUPPORTED
+}
+
+fn op(ifr: &mut Vec<u8>, opcode: u8, scope: bool, body: &[u8]) {
+	let len = (2 + body.len()) as u8;
+	ifr.extend_from_slice(&[opcode, len | if scope { EFI_IFR_SCOPE } else { 0 }]);
+	ifr.extend_from_slice(body);
+}
+
+fn end(ifr: &mut Vec<u8>) {
+	op(ifr, EFI_IFR_END_OP, false, &[]);
+}
+
+// EFI_IFR_QUESTION_HEADER for a question stored at `offset` in our variable, the question ID is the
+// offset plus one as zero isn't allowed
+fn question(prompt: u16, help: u16, offset: u16) -> Vec<u8> {
+	let mut q = Vec::new();
+	q.extend_from_slice(&prompt.to_le_bytes());
+	q.extend_from_slice(&help.to_le_bytes());
+	q.extend_from_slice(&(offset + 1).to_le_bytes());
+	q.extend_from_slice(&VARSTORE_ID.to_le_bytes());
+	q.extend_from_slice(&offset.to_le_bytes());
+	q.push(0);
+	q
+}
+
+fn form_package() -> Vec<u8> {
+	let mut ifr = Vec::new();
+
+	let mut formset = Vec::new();
+	formset.extend_from_slice(&FORMSET_GUID.to_bytes());
+	formset.extend_from_slice(&STR_TITLE.to_le_bytes());
+	formset.extend_from_slice(&STR_HELP.to_le_bytes());
+	// one class GUID follows
+	formset.push(1);
+	formset.extend_from_slice(&PLATFORM_SETUP_CLASS_GUID.to_bytes());
+	op(&mut ifr, EFI_IFR_FORM_SET_OP, true, &formset);
+
+	let mut varstore = Vec::new();
+	varstore.extend_from_slice(&VARSTORE_ID.to_le_bytes());
+	varstore.extend_from_slice(&OPTIONS_VARIABLE_GUID.0.to_bytes());
+	varstore.extend_from_slice(&(EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS).to_le_bytes());
+	varstore.extend_from_slice(&(OPTIONS_SIZE as u16).to_le_bytes());
+	// the varstore name is ASCII, unlike the variable name itself
+	varstore.extend(OPTIONS_VARIABLE_NAME.iter().map(|&c| u16::from(c) as u8));
+	varstore.push(0);
+	op(&mut ifr, EFI_IFR_VARSTORE_EFI_OP, false, &varstore);
+
+	let mut form = Vec::new();
+	form.extend_from_slice(&FORM_ID.to_le_bytes());
+	form.extend_from_slice(&STR_TITLE.to_le_bytes());
+	op(&mut ifr, EFI_IFR_FORM_OP, true, &form);
+
+	for (prompt, help, offset) in [
+		(STR_SKIP_STOLEN_MEMORY, STR_SKIP_STOLEN_MEMORY_HELP, SKIP_STOLEN_MEMORY_OFFSET),
+		(STR_LEGACY_BDSM, STR_LEGACY_BDSM_HELP, LEGACY_BDSM_OFFSET),
+	] {
+		let mut checkbox = question(prompt, help, offset);
+		checkbox.push(0);
+		op(&mut ifr, EFI_IFR_CHECKBOX_OP, false, &checkbox);
+	}
+
+	let mut one_of = question(STR_LOG_LEVEL, STR_LOG_LEVEL_HELP, LOG_LEVEL_OFFSET);
+	one_of.push(EFI_IFR_NUMERIC_SIZE_1);
+	// minimum, maximum and step
+	one_of.extend_from_slice(&[0, LOG_LEVELS - 1, 0]);
+	op(&mut ifr, EFI_IFR_ONE_OF_OP, true, &one_of);
+
+	for level in 0..LOG_LEVELS {
+		let mut option = Vec::new();
+		option.extend_from_slice(&(STR_LOG_LEVEL_DEFAULT + level as u16).to_le_bytes());
+		option.push(if level == 0 { EFI_IFR_OPTION_DEFAULT } else { 0 });
+		option.push(EFI_IFR_TYPE_NUM_SIZE_8);
+		option.push(level);
+		op(&mut ifr, EFI_IFR_ONE_OF_OPTION_OP, false, &option);
+	}
+
+	// one-of, form and form set
+	end(&mut ifr);
+	end(&mut ifr);
+	end(&mut ifr);
+
+	package(EFI_HII_PACKAGE_FORMS, &ifr)
+}
+
+fn string_package() -> Vec<u8> {
+	// EFI_HII_STRING_PACKAGE_HDR after the generic header: header size, string info offset, the
+	// language window, the language name string and the language
+	let hdr_size = (4 + 4 + 4 + 16 * 2 + 2 + LANGUAGE.len()) as u32;
+
+	let mut body = Vec::new();
+	body.extend_from_slice(&hdr_size.to_le_bytes());
+	body.extend_from_slice(&hdr_size.to_le_bytes());
+	body.extend_from_slice(&[0; 16 * 2]);
+	body.extend_from_slice(&STR_LANGUAGE.to_le_bytes());
+	body.extend_from_slice(LANGUAGE);
+
+	for s in STRINGS {
+		body.push(EFI_HII_SIBT_STRING_UCS2);
+		body.extend(s.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+	}
+	body.push(EFI_HII_SIBT_END);
+
+	package(EFI_HII_PACKAGE_STRINGS, &body)
+}
+
+// EFI_HII_PACKAGE_HEADER: 24-bit length including the header, then the type
+fn package(ty: u8, body: &[u8]) -> Vec<u8> {
+	let len = (4 + body.len()) as u32;
+
+	let mut package = Vec::with_capacity(len as usize);
+	package.extend_from_slice(&(len | (ty as u32) << 24).to_le_bytes());
+	package.extend_from_slice(body);
+	package
+}
+
+fn package_list() -> Vec<u8> {
+	let forms = form_package();
+	let strings = string_package();
+	let end = package(EFI_HII_PACKAGE_END, &[]);
+
+	let mut list = Vec::new();
+	list.extend_from_slice(&PACKAGE_LIST_GUID.to_bytes());
+	list.extend_from_slice(&((16 + 4 + forms.len() + strings.len() + end.len()) as u32).to_le_bytes());
+	list.extend_from_slice(&forms);
+	list.extend_from_slice(&strings);
+	list.extend_from_slice(&end);
+	list
+}
+
+// A missing setup form is only an inconvenience, so failures are logged and otherwise ignored. The
+// form stays registered for the rest of boot services, the driver image is resident anyway.
+pub fn install() {
+	let Ok(database) = boot::get_handle_for_protocol::<HiiDatabase>() else {
+		info!("No HII database, not installing the setup form");
+		return;
+	};
+
+	let params = OpenProtocolParams {
+		handle: database,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let Ok(database) = (unsafe { boot::open_protocol::<HiiDatabase>(params, OpenProtocolAttributes::GetProtocol) }) else {
+		error!("Failed to open the HII database!");
+		return;
+	};
+
+	let handle = match unsafe { boot::install_protocol_interface(None, &DEVICE_PATH_GUID, core::ptr::addr_of!(DEVICE_PATH).cast()) } {
+		Ok(h) => h,
+		Err(e) => {
+			error!("Failed to install the setup form's device path: {:?}!", e.status());
+			return;
+		}
+	};
+
+	if let Err(e) = unsafe { boot::install_protocol_interface(Some(handle), &CONFIG_ACCESS_GUID, core::ptr::addr_of!(CONFIG_ACCESS).cast()) } {
+		error!("Failed to install Config Access for the setup form: {:?}!", e.status());
+		return;
+	}
+
+	// the database copies the packages, so the buffer can go right after
+	let list = package_list();
+	let mut hii_handle = core::ptr::null_mut();
+	let status = unsafe { (database.0.new_package_list)(&database.0, list.as_ptr(), handle.as_ptr(), &mut hii_handle) };
+
+	if status.is_error() {
+		error!("Failed to register the setup form: {:?}!", status);
+		return;
+	}
+
+	info!("Installed the setup form");
+}
@@ -0,0 +1,260 @@
+// A DEFLATE (RFC 1951) decoder for compressed fw_cfg payloads. It decodes straight into the buffer
+// the data is meant for, which has to be exactly as large as the decompressed data, and doesn't
+// allocate. Code lengths are decoded canonically, one bit at a time, which is slow but small, and
+// the payloads are at most a few hundred KiB.
+
+const MAX_BITS: usize = 15;
+const MAX_LITERAL_CODES: usize = 288;
+const MAX_DISTANCE_CODES: usize = 30;
+const END_OF_BLOCK: u16 = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+	3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+	1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+	8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// the order code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+	data: &'a [u8],
+	pos: usize,
+	buf: u32,
+	count: u32,
+}
+
+impl<'a> Bits<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		Bits { data, pos: 0, buf: 0, count: 0 }
+	}
+
+	// `n` bits, least significant first
+	fn bits(&mut self, n: u32) -> Result<u32, &'static str> {
+		while self.count < n {
+			let byte = *self.data.get(self.pos).ok_or("compressed data ends early")?;
+			self.buf |= (byte as u32) << self.count;
+			self.pos += 1;
+			self.count += 8;
+		}
+
+		let value = self.buf & ((1u32 << n) - 1);
+		self.buf = self.buf.checked_shr(n).unwrap_or(0);
+		self.count -= n;
+
+		Ok(value)
+	}
+
+	// stored blocks start at a byte boundary
+	fn align(&mut self) {
+		self.buf = 0;
+		self.count = 0;
+	}
+}
+
+// a canonical Huffman code as the number of codes of each length and the symbols ordered by code
+struct Huffman {
+	counts: [u16; MAX_BITS + 1],
+	symbols: [u16; MAX_LITERAL_CODES],
+}
+
+impl Huffman {
+	// an incomplete code is accepted, a code that is never sent can't be decoded anyway
+	fn new(lengths: &[u8]) -> Result<Self, &'static str> {
+		let mut h = Huffman { counts: [0; MAX_BITS + 1], symbols: [0; MAX_LITERAL_CODES] };
+
+		for &len in lengths {
+			h.counts[len as usize] += 1;
+		}
+
+		let mut left: i32 = 1;
+		for len in 1..=MAX_BITS {
+			left = (left << 1) - h.counts[len] as i32;
+			if left < 0 {
+				return Err("over-subscribed Huffman code");
+			}
+		}
+
+		let mut offsets = [0u16; MAX_BITS + 2];
+		for len in 1..=MAX_BITS {
+			offsets[len + 1] = offsets[len] + h.counts[len];
+		}
+
+		for (symbol, &len) in lengths.iter().enumerate().filter(|&(_, &len)| len != 0) {
+			h.symbols[offsets[len as usize] as usize] = symbol as u16;
+			offsets[len as usize] += 1;
+		}
+
+		Ok(h)
+	}
+
+	fn decode(&self, bits: &mut Bits) -> Result<u16, &'static str> {
+		let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+		for len in 1..=MAX_BITS {
+			code |= bits.bits(1)? as i32;
+			let count = self.counts[len] as i32;
+
+			if code - first < count {
+				return Ok(self.symbols[(index + code - first) as usize]);
+			}
+
+			index += count;
+			first = (first + count) << 1;
+			code <<= 1;
+		}
+
+		Err("invalid Huffman code")
+	}
+}
+
+fn stored(bits: &mut Bits, out: &mut [u8], pos: &mut usize) -> Result<(), &'static str> {
+	bits.align();
+
+	let header = bits.data.get(bits.pos..bits.pos + 4).ok_or("compressed data ends early")?;
+	let len = u16::from_le_bytes([header[0], header[1]]);
+	if len != !u16::from_le_bytes([header[2], header[3]]) {
+		return Err("stored block length check failed");
+	}
+
+	let start = bits.pos + 4;
+	let data = bits.data.get(start..start + len as usize).ok_or("compressed data ends early")?;
+	out.get_mut(*pos..*pos + data.len()).ok_or("decompressed data is larger than declared")?.copy_from_slice(data);
+
+	bits.pos = start + data.len();
+	*pos += data.len();
+
+	Ok(())
+}
+
+fn codes(bits: &mut Bits, out: &mut [u8], pos: &mut usize, literals: &Huffman, distances: &Huffman) -> Result<(), &'static str> {
+	loop {
+		let symbol = literals.decode(bits)?;
+
+		if symbol < END_OF_BLOCK {
+			*out.get_mut(*pos).ok_or("decompressed data is larger than declared")? = symbol as u8;
+			*pos += 1;
+			continue;
+		}
+
+		if symbol == END_OF_BLOCK {
+			return Ok(());
+		}
+
+		let i = (symbol - END_OF_BLOCK - 1) as usize;
+		let Some(&base) = LENGTH_BASE.get(i) else {
+			return Err("invalid length code");
+		};
+		let len = base as usize + bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+
+		let i = distances.decode(bits)? as usize;
+		let Some(&base) = DISTANCE_BASE.get(i) else {
+			return Err("invalid distance code");
+		};
+		let distance = base as usize + bits.bits(DISTANCE_EXTRA[i] as u32)? as usize;
+
+		if distance > *pos {
+			return Err("distance reaches back before the start");
+		}
+
+		if *pos + len > out.len() {
+			return Err("decompressed data is larger than declared");
+		}
+
+		// byte by byte, a match may overlap what it is copying
+		for _ in 0..len {
+			out[*pos] = out[*pos - distance];
+			*pos += 1;
+		}
+	}
+}
+
+fn fixed(bits: &mut Bits, out: &mut [u8], pos: &mut usize) -> Result<(), &'static str> {
+	let mut lengths = [0u8; MAX_LITERAL_CODES];
+	lengths[..144].fill(8);
+	lengths[144..256].fill(9);
+	lengths[256..280].fill(7);
+	lengths[280..].fill(8);
+
+	let literals = Huffman::new(&lengths)?;
+	let distances = Huffman::new(&[5; MAX_DISTANCE_CODES])?;
+
+	codes(bits, out, pos, &literals, &distances)
+}
+
+fn dynamic(bits: &mut Bits, out: &mut [u8], pos: &mut usize) -> Result<(), &'static str> {
+	let literal_count = bits.bits(5)? as usize + 257;
+	let distance_count = bits.bits(5)? as usize + 1;
+	let code_length_count = bits.bits(4)? as usize + 4;
+
+	if literal_count > MAX_LITERAL_CODES || distance_count > MAX_DISTANCE_CODES {
+		return Err("too many codes");
+	}
+
+	let mut lengths = [0u8; MAX_LITERAL_CODES + MAX_DISTANCE_CODES];
+	for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+		lengths[i] = bits.bits(3)? as u8;
+	}
+
+	let code_lengths = Huffman::new(&lengths[..CODE_LENGTH_ORDER.len()])?;
+	let total = literal_count + distance_count;
+	let mut i = 0;
+
+	while i < total {
+		let symbol = code_lengths.decode(bits)?;
+
+		let (len, repeat) = match symbol {
+			0..=15 => (symbol as u8, 1),
+			16 if i == 0 => return Err("repeat without a previous length"),
+			16 => (lengths[i - 1], 3 + bits.bits(2)? as usize),
+			17 => (0, 3 + bits.bits(3)? as usize),
+			_ => (0, 11 + bits.bits(7)? as usize),
+		};
+
+		if i + repeat > total {
+			return Err("code lengths exceed the code count");
+		}
+
+		lengths[i..i + repeat].fill(len);
+		i += repeat;
+	}
+
+	if lengths[END_OF_BLOCK as usize] == 0 {
+		return Err("no end-of-block code");
+	}
+
+	let literals = Huffman::new(&lengths[..literal_count])?;
+	let distances = Huffman::new(&lengths[literal_count..total])?;
+
+	codes(bits, out, pos, &literals, &distances)
+}
+
+// decompresses raw DEFLATE data in `input`, which has to fill `out` exactly
+pub fn inflate(input: &[u8], out: &mut [u8]) -> Result<(), &'static str> {
+	let mut bits = Bits::new(input);
+	let mut pos = 0;
+
+	loop {
+		let last = bits.bits(1)? == 1;
+
+		match bits.bits(2)? {
+			0 => stored(&mut bits, out, &mut pos)?,
+			1 => fixed(&mut bits, out, &mut pos)?,
+			2 => dynamic(&mut bits, out, &mut pos)?,
+			_ => return Err("invalid block type"),
+		}
+
+		if last {
+			break;
+		}
+	}
+
+	if pos != out.len() {
+		return Err("decompressed data is smaller than declared");
+	}
+
+	Ok(())
+}
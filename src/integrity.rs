@@ -0,0 +1,64 @@
+// Optional companion hash files, `<file>-sha256` next to a blob, e.g. `etc/igd-opregion-sha256`. They
+// hold the SHA-256 of the blob either as 32 raw bytes or as `sha256sum` output, and catch a truncated
+// or corrupted host file before the guest driver trips over it. Without one, nothing is checked.
+
+use alloc::format;
+use log::{debug, error};
+use qemu_fw_cfg::FwCfg;
+use uefi::Status;
+
+use crate::{scratch::Scratch, sha256::{self, DIGEST_SIZE}};
+
+const HASH_SUFFIX: &str = "-sha256";
+
+fn hex_digit(c: u8) -> Option<u8> {
+	match c {
+		b'0'..=b'9' => Some(c - b'0'),
+		b'a'..=b'f' => Some(c - b'a' + 10),
+		b'A'..=b'F' => Some(c - b'A' + 10),
+		_ => None,
+	}
+}
+
+// 32 raw bytes, or 64 hex digits optionally followed by whitespace and anything else, like a file name
+fn parse_hash(buf: &[u8]) -> Option<[u8; DIGEST_SIZE]> {
+	if let Ok(raw) = buf.try_into() {
+		return Some(raw);
+	}
+
+	let hex = buf.get(..2 * DIGEST_SIZE)?;
+	if let Some(&c) = buf.get(2 * DIGEST_SIZE) && !c.is_ascii_whitespace() && c != 0 {
+		return None;
+	}
+
+	let mut hash = [0u8; DIGEST_SIZE];
+	for (byte, pair) in hash.iter_mut().zip(hex.chunks_exact(2)) {
+		*byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+	}
+
+	Some(hash)
+}
+
+// checks `data` as read from `name` against `<name>-sha256` if the host passed one
+pub fn verify(fw_cfg: &mut FwCfg, name: &str, data: &[u8]) -> Result<(), Status> {
+	let hash_name = format!("{}{}", name, HASH_SUFFIX);
+	let Some(file) = fw_cfg.find_file(&hash_name) else {
+		return Ok(());
+	};
+
+	let mut buf = Scratch::new(file.size());
+	fw_cfg.read_file_to_buffer(&file, &mut buf);
+
+	let Some(expected) = parse_hash(&buf) else {
+		error!("Malformed {}, expected a SHA-256 as raw bytes or hex digits!", hash_name);
+		return Err(Status::INVALID_PARAMETER);
+	};
+
+	if sha256::digest(data) != expected {
+		error!("{} ({} bytes) doesn't match {}, the file is truncated or corrupted!", name, data.len(), hash_name);
+		return Err(Status::CRC_ERROR);
+	}
+
+	debug!("{} matches {}", name, hash_name);
+	Ok(())
+}
@@ -0,0 +1,2232 @@
+// The reusable core: OpRegion and stolen memory setup, validation and the configuration types. The
+// UEFI entry point and the PciIo notify wiring live in the binary, so other UEFI tools can drive the
+// setup with their own event handling by calling `init` and then `configure_device`.
+
+#![no_std]
+#![feature(strict_provenance, let_chains)]
+
+#![deny(warnings)]
+#![allow(clippy::identity_op)]
+
+extern crate alloc;
+
+mod acpi;
+pub mod allow_list;
+pub mod args;
+pub mod audit;
+pub mod check;
+mod compressed;
+pub mod config;
+#[cfg(target_arch = "aarch64")]
+mod devicetree;
+pub mod dmar;
+mod embedded;
+mod existing;
+pub mod fw_cfg;
+mod fw_cfg_raw;
+pub mod generation;
+pub mod gop;
+pub mod handoff;
+mod inflate;
+mod integrity;
+mod lpc;
+mod memory_attribute;
+pub mod metadata;
+mod mirror;
+mod mmio;
+pub mod opregion;
+pub mod oprom;
+pub mod options;
+pub mod outcome;
+pub mod pci;
+pub mod plan;
+pub mod protocol;
+pub mod quirks;
+pub mod report;
+mod reserved;
+mod s3;
+pub mod scan;
+pub mod scratch;
+pub mod selftest;
+mod sha256;
+mod ssdt;
+mod status_code;
+pub mod status_record;
+pub mod status_screen;
+pub mod stolen;
+mod summary;
+pub mod table;
+mod timestamp;
+mod tpm;
+pub mod trace;
+pub mod variables;
+pub mod vbt;
+mod vga;
+
+use alloc::{format, string::String, vec::Vec};
+use args::Location;
+use config::{IgdConfig, StrictMatch};
+use fw_cfg::FwCfgSource;
+pub use fw_cfg_raw::Transport;
+use core::ptr::NonNull;
+use generation::Generation;
+use log::{debug, info, error, warn, LevelFilter};
+use opregion::{ExtendedVbt, Version};
+use outcome::{DeviceOutcome, Outcome, Region};
+use scratch::Scratch;
+use pci::{ClassCode, PciConfigAccess};
+use qemu_fw_cfg::{FwCfg, FwCfgFile};
+use quirks::Gms;
+use status_code::Failure;
+use uefi::{boot::{self, MemoryType, ScopedProtocol, SearchType}, mem::memory_map::MemoryMap, proto::pci::PciIo, Status};
+use zeroize::Zeroize;
+
+const PAGE_SIZE: usize = 0x1000;
+// Stolen Memory should be aligned to at least 1 MiB, the generation may ask for more
+const STOLEN_MEMORY_ALIGNMENT: usize = 0x100000;
+
+// reads of a non-existent function return all ones
+const PCI_ID_NONE: u16 = 0xFFFF;
+const PCI_CFG_VENDOR_OFFSET: u32 = 0x00;
+const PCI_CFG_DEVICE_OFFSET: u32 = 0x02;
+const PCI_CFG_SUBSYSTEM_OFFSET: u32 = 0x2C;
+const PCI_VENDOR_INTEL: u16 = 0x8086;
+const PCI_CLASS_DISPLAY: u8 = 0x03;
+const PCI_SUBCLASS_VGA_COMPATIBLE: u8 = 0x00;
+const PCI_PROGIF_VGA_CONTROLLER: u8 = 0x00;
+const IGD_CLASS: ClassCode = ClassCode {
+	base: PCI_CLASS_DISPLAY,
+	sub: PCI_SUBCLASS_VGA_COMPATIBLE,
+	prog: PCI_PROGIF_VGA_CONTROLLER,
+};
+
+// QEMU releases and downstream patches don't agree on the names, the first one present wins
+const OPREGION_FILES: &[&str] = &["etc/igd-opregion", "opt/igd-opregion"];
+const BDSM_SIZE_FILES: &[&str] = &["etc/igd-bdsm-size", "opt/igd-bdsm-size"];
+// the host's GGC as a little-endian u16, for sizing stolen memory like the host BIOS did
+const GGC_FILE: &str = "etc/igd-ggc";
+// a stolen memory base the host already set aside, as a little-endian u32 or u64
+const BDSM_BASE_FILE: &str = "etc/igd-bdsm";
+// the host's stolen memory base, as a little-endian u32 or u64, to place the guest's at the same address
+const HOST_BDSM_FILE: &str = "etc/igd-host-bdsm";
+// the IGD's guest address as `[seg:]bus:dev.func` text, for hosts placing it somewhere other than 00:02.0
+const BDF_FILE: &str = "etc/igd-bdf";
+const ALLOW_LIST_FILE: &str = "etc/igd-device-id";
+// the log level as text, for switching to debug output without rebuilding
+const LOG_LEVEL_FILE: &str = "etc/igd-loglevel";
+// an extended VBT passed separately instead of appended to the OpRegion
+const VBT_FILE: &str = "etc/igd-vbt";
+const VBT_PATCH_FILE: &str = "etc/igd-vbt-patch";
+// files larger than this are read page by page instead of in a single transfer
+const CHUNKED_READ_THRESHOLD: usize = 64 * 1024;
+
+const PCI_CFG_ASLS_OFFSET: u32 = 0xFC;
+// highest address anything a 32-bit register points at may occupy, e.g. the OpRegion including an
+// appended extended VBT
+const CEILING_32: u64 = 0xFFFFFFFF;
+const PCI_CFG_BDSM_MIRROR_OFFSET: u32 = 0x5C;
+// Graphics Control, whose GMS field the guest driver derives the stolen memory size from
+const PCI_CFG_GGC_OFFSET: u32 = 0x50;
+// Gen11 and newer have a 64-bit BDSM instead, the low dword at 0xC0 and the high dword at 0xC4
+const PCI_CFG_BDSM64_OFFSET: u32 = 0xC0;
+// the low 20 bits of BDSM hold the lock bit and reserved bits, which may not read back as written
+const BDSM_ADDRESS_MASK: u32 = 0xFFF0_0000;
+
+
+const HOST_BRIDGE_LOCATION: Location = (0, 0, 0, 0);
+const PCI_CFG_TOLUD_OFFSET: u32 = 0xBC;
+const TOLUD_MASK: u32 = 0xFFF0_0000;
+// base of GTT stolen memory in the host bridge, which the guest driver reads back like TOLUD
+const PCI_CFG_BGSM_OFFSET: u32 = 0xB4;
+// QEMU's Q35 MCH at 00:00.0
+const Q35_MCH_DEVICE_ID: u16 = 0x29C0;
+const PCI_CFG_MCH_BDSM_OFFSET: u32 = 0xB0;
+
+pub static mut CONFIG: IgdConfig = IgdConfig::new();
+pub static mut OUTCOME: Outcome = Outcome::new();
+// where the host placed the IGD, from `etc/igd-bdf`
+static mut IGD_BDF: Option<Location> = None;
+// `None` if all matching devices may be configured
+static mut ALLOW_LIST: Option<Vec<allow_list::Entry>> = None;
+#[cfg(target_arch = "aarch64")]
+static mut DT_INFO: Option<devicetree::DtIgdInfo> = None;
+
+// the first of `names` in the fw_cfg directory, along with the name it was found under
+fn find_first_file<S: FwCfgSource>(fw_cfg: &mut S, names: &[&'static str]) -> Option<(&'static str, S::File)> {
+	names.iter().find_map(|&name| fw_cfg.find_file(name).map(|file| (name, file)))
+}
+
+// `etc/igd-opregion.<seg>:<bus>:<dev>.<func>` for the device at `location`, the segment may be left out
+// for 0000, so each Intel GPU of a guest gets its own OpRegion. Then the files shared by all of them.
+fn find_opregion_file<S: FwCfgSource>(fw_cfg: &mut S, location: Option<Location>) -> Option<(String, S::File)> {
+	if let Some((seg, bus, dev, func)) = location {
+		let full = format!("{}.{:04x}:{:02x}:{:02x}.{:x}", OPREGION_FILES[0], seg, bus, dev, func);
+		let short = (seg == 0).then(|| format!("{}.{:02x}:{:02x}.{:x}", OPREGION_FILES[0], bus, dev, func));
+
+		for name in [Some(full), short].into_iter().flatten() {
+			if let Some(file) = fw_cfg.find_file(&name) {
+				return Some((name, file));
+			}
+		}
+	}
+
+	find_first_file(fw_cfg, OPREGION_FILES).map(|(name, file)| (String::from(name), file))
+}
+
+// whether we run under QEMU at all, checked before anything else touches fw_cfg
+pub fn fw_cfg_present() -> bool {
+	fw_cfg_raw::present()
+}
+
+// IO ports on x86, MMIO wherever the device tree or the DSDT describes it
+pub fn fw_cfg_transport() -> Option<Transport> {
+	fw_cfg_raw::transport()
+}
+
+// a handle for the fw_cfg device over its transport
+pub unsafe fn fw_cfg_open() -> Option<FwCfg> {
+	fw_cfg_raw::open()
+}
+
+// fw_cfg is where everything we set up comes from, not having it is an error for every step
+fn open_fw_cfg() -> Result<FwCfg, Status> {
+	unsafe { fw_cfg_open() }.ok_or_else(|| {
+		error!("fw_cfg is not available!");
+		status_code::report(Failure::NoFwCfg);
+		Status::NOT_FOUND
+	})
+}
+
+// load the configuration and the platform description, before any device is configured
+pub unsafe fn init(fw_cfg: &mut FwCfg) {
+	// first, so it already applies to loading the rest
+	if let Some(level) = read_log_level(fw_cfg) {
+		log::set_max_level(level);
+	}
+
+	CONFIG = IgdConfig::load(fw_cfg);
+	IGD_BDF = read_igd_bdf(fw_cfg);
+
+	if let Some(level) = CONFIG.log_level && fw_cfg.find_file(LOG_LEVEL_FILE).is_none() {
+		log::set_max_level(level);
+	}
+
+	ALLOW_LIST = read_allow_list(fw_cfg);
+
+	if let Some(options) = options::read() {
+		CONFIG.apply_options(&options);
+	}
+
+	trace::init(CONFIG.trace_pages);
+
+	#[cfg(target_arch = "aarch64")]
+	{
+		DT_INFO = devicetree::discover();
+	}
+}
+
+// `off`, `error`, `warn`, `info`, `debug` or `trace`, in any case
+fn read_log_level<S: FwCfgSource>(fw_cfg: &mut S) -> Option<LevelFilter> {
+	let file = fw_cfg.find_file(LOG_LEVEL_FILE)?;
+	let mut buf = Scratch::new(fw_cfg.file_size(&file));
+	fw_cfg.read_file(&file, &mut buf);
+
+	let text = core::str::from_utf8(&buf).ok().map(|t| t.trim_end_matches(['\0', '\n', '\r']));
+
+	let level = text.and_then(|t| t.parse().ok());
+
+	if level.is_none() {
+		error!("Ignoring malformed {}, expected off, error, warn, info, debug or trace!", LOG_LEVEL_FILE);
+	}
+
+	level
+}
+
+fn read_igd_bdf<S: FwCfgSource>(fw_cfg: &mut S) -> Option<Location> {
+	let file = fw_cfg.find_file(BDF_FILE)?;
+	let mut buf = Scratch::new(fw_cfg.file_size(&file));
+	fw_cfg.read_file(&file, &mut buf);
+
+	// `-fw_cfg string=` doesn't add a terminator, but a file may end in a newline or NUL
+	let text = core::str::from_utf8(&buf).ok().map(|t| t.trim_end_matches(['\0', '\n', '\r']));
+
+	match text.and_then(args::parse_bdf) {
+		Some(location) => {
+			info!("IGD location {:04x}:{:02x}:{:02x}.{} from {}", location.0, location.1, location.2, location.3, BDF_FILE);
+			Some(location)
+		}
+		None => {
+			error!("Ignoring malformed {}, expected [seg:]bus:dev.func!", BDF_FILE);
+			None
+		}
+	}
+}
+
+// A malformed list doesn't fall back to configuring everything, which is what it is there to prevent,
+// but to an empty one.
+fn read_allow_list<S: FwCfgSource>(fw_cfg: &mut S) -> Option<Vec<allow_list::Entry>> {
+	let file = fw_cfg.find_file(ALLOW_LIST_FILE)?;
+	let mut buf = Scratch::new(fw_cfg.file_size(&file));
+	fw_cfg.read_file(&file, &mut buf);
+
+	match core::str::from_utf8(&buf).map_err(|_| "").and_then(allow_list::parse) {
+		Ok(entries) => {
+			info!("Only configuring the {} device(s) in {}", entries.len(), ALLOW_LIST_FILE);
+			Some(entries)
+		}
+		Err(entry) => {
+			error!("Malformed entry '{}' in {}, not configuring any device!", entry, ALLOW_LIST_FILE);
+			Some(Vec::new())
+		}
+	}
+}
+
+// the IGD is expected at 00:02.0 unless the host or the platform describes it elsewhere
+// e.g. from the command line, takes precedence over everything else
+pub unsafe fn set_igd_location(location: Location) {
+	IGD_BDF = Some(location);
+}
+
+pub fn igd_location() -> Location {
+	if let Some(location) = unsafe { IGD_BDF.or(CONFIG.igd_location) } {
+		return location;
+	}
+
+	let seg = unsafe { CONFIG.igd_segment }.unwrap_or(0) as usize;
+
+	#[cfg(target_arch = "aarch64")]
+	if let Some((bus, dev, func)) = unsafe { DT_INFO }.and_then(|i| i.location) {
+		return (seg, bus as usize, dev as usize, func as usize);
+	}
+
+	(seg, 0, 2, 0)
+}
+
+// memory the platform already set aside for the OpRegion, if it is large enough
+fn opregion_reservation(size: usize) -> Option<NonNull<u8>> {
+	#[cfg(target_arch = "aarch64")]
+	if let Some((base, len)) = unsafe { DT_INFO }.and_then(|i| i.opregion) {
+		if (len as usize) < size {
+			error!("Reserved OpRegion range is too small ({} < {} bytes)!", len, size);
+			return None;
+		}
+
+		return NonNull::new(base as usize as *mut u8);
+	}
+
+	// a fixed address takes precedence
+	if unsafe { CONFIG.opregion_address }.is_some() {
+		return None;
+	}
+
+	reserved::carve_high(size, ceiling_32()).and_then(|addr| NonNull::new(addr as *mut u8))
+}
+
+// the configured allocation ceiling, limited to what a 32-bit register can point at
+fn ceiling_32() -> u64 {
+	unsafe { CONFIG.allocation_ceiling }.map_or(CEILING_32, |c| c.min(CEILING_32))
+}
+
+// an occupied fixed address is an error, placing the range elsewhere would defeat the point
+fn allocate_fixed(name: &str, addr: u64, pages: usize, alignment: usize, ceiling: u64, ty: MemoryType) -> Option<usize> {
+	if addr % alignment as u64 != 0 {
+		error!("Fixed {} address {:#x} isn't aligned to {:#x}!", name, addr, alignment);
+		return None;
+	}
+
+	let Some(last) = addr.checked_add((pages * PAGE_SIZE) as u64 - 1).filter(|&l| l <= ceiling) else {
+		error!("Fixed {} address {:#x} puts it above {:#x}!", name, addr, ceiling);
+		return None;
+	};
+
+	match boot::allocate_pages(boot::AllocateType::Address(addr), ty, pages) {
+		Ok(mem) => {
+			info!("{} placed at its fixed address {:#x}", name, addr);
+			Some(mem.addr().into())
+		}
+		Err(e) => {
+			error!("Fixed {} range [{:#x}, {:#x}] is not available: {:?}!", name, addr, last, e.status());
+			None
+		}
+	}
+}
+
+// ASLS is 32 bits wide, and guest drivers may map the whole OpRegion through a 32-bit window
+fn allocate_opregion(pages: usize, fixed: Option<u64>) -> Option<NonNull<u8>> {
+	let size = pages * PAGE_SIZE;
+	let limit = ceiling_32();
+	let mut ceiling = limit;
+
+	if let Some(fixed) = fixed {
+		return allocate_fixed("OpRegion", fixed, pages, PAGE_SIZE, limit, unsafe { CONFIG.opregion_memory_type })
+			.and_then(|addr| NonNull::new(addr as *mut u8));
+	}
+
+	for _ in 0..2 {
+		let buf = boot::allocate_pages(boot::AllocateType::MaxAddress(ceiling), unsafe { CONFIG.opregion_memory_type }, pages).ok()?;
+		let start: usize = buf.addr().into();
+
+		if start + size - 1 <= limit as usize {
+			return Some(buf);
+		}
+
+		// the firmware only applied the ceiling to the start of the range, retry with room for all of it
+		warn!("OpRegion allocation [{:#x}, {:#x}) crosses {:#x}, retrying lower", start, start + size, limit);
+		unsafe {
+			boot::free_pages(buf, pages).ok()?;
+		}
+		ceiling = limit.checked_sub(size as u64)?;
+	}
+
+	None
+}
+
+// read a large file through DMA, or page by page with progress output if that isn't available
+fn read_chunked(name: &str, buf: &mut [u8]) -> bool {
+	let Some(file) = fw_cfg_raw::find_file(name) else {
+		return false;
+	};
+
+	if fw_cfg_raw::read_file_dma(file, buf) {
+		debug!("Read {} bytes of {} through DMA", buf.len(), name);
+		return true;
+	}
+
+	// selecting the file again restarts it from the beginning, whatever a failed transfer left behind
+	fw_cfg_raw::select(file.key);
+
+	for (i, chunk) in buf.chunks_mut(PAGE_SIZE).enumerate() {
+		fw_cfg_raw::read(chunk);
+
+		if (i + 1) % 16 == 0 {
+			debug!("Read {} of {} bytes of {}", (i + 1) * PAGE_SIZE, buf.len(), name);
+		}
+	}
+
+	true
+}
+
+// size of `etc/igd-vbt`, which is reserved behind the OpRegion in case it has to be placed there
+fn extended_vbt_file_size(fw_cfg: &mut FwCfg) -> usize {
+	fw_cfg.find_file(VBT_FILE).map_or(0, |f| compressed::size(VBT_FILE, f.size()))
+}
+
+// Checks the extended VBT source against RVDS. It comes from `etc/igd-vbt` if present, otherwise it
+// has to be appended to the OpRegion at `offset`. The returned RVDS may have been grown to the file size.
+fn check_extended_vbt(fw_cfg: &mut FwCfg, opregion: &[u8], offset: usize, mut vbt: ExtendedVbt) -> Result<(ExtendedVbt, Option<FwCfgFile>), Status> {
+	let Some(file) = fw_cfg.find_file(VBT_FILE) else {
+		if offset.saturating_add(vbt.rvds as usize) > opregion.len() {
+			error!("Extended VBT of {} bytes is neither in {} nor appended to the OpRegion!", vbt.rvds, VBT_FILE);
+			status_code::report(Failure::MissingFile);
+			return Err(Status::NOT_FOUND);
+		}
+
+		return Ok((vbt, None));
+	};
+
+	let size = compressed::size(VBT_FILE, file.size());
+
+	// a short file would make the guest read past its end
+	if size < vbt.rvds as usize {
+		error!("{} has {} bytes, but RVDS states {}!", VBT_FILE, size, vbt.rvds);
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	if size > vbt.rvds as usize {
+		if !unsafe { CONFIG.accept_larger_vbt } {
+			error!("{} has {} bytes, more than the {} RVDS states!", VBT_FILE, size, vbt.rvds);
+			return Err(Status::INVALID_PARAMETER);
+		}
+
+		info!("Growing RVDS from {} to the {} bytes of {}", vbt.rvds, size, VBT_FILE);
+		vbt.rvds = size as u32;
+	}
+
+	Ok((vbt, Some(file)))
+}
+
+fn read_extended_vbt_file(fw_cfg: &mut FwCfg, file: &FwCfgFile, data: &mut [u8]) -> Result<(), Status> {
+	if compressed::is_compressed(VBT_FILE) {
+		compressed::read(VBT_FILE, data)?;
+	} else if data.len() > CHUNKED_READ_THRESHOLD {
+		if !read_chunked(VBT_FILE, data) {
+			error!("Extended VBT disappeared from the fw_cfg directory!");
+			return Err(Status::NOT_FOUND);
+		}
+	} else {
+		fw_cfg.read_file_to_buffer(file, data);
+	}
+
+	integrity::verify(fw_cfg, VBT_FILE, data)
+}
+
+// place the extended VBT at `offset` into the OpRegion buffer, unless it was appended there already
+fn place_extended_vbt(fw_cfg: &mut FwCfg, buf: &mut [u8], size: usize, offset: usize, vbt: ExtendedVbt) -> Result<ExtendedVbt, Status> {
+	let (vbt, file) = check_extended_vbt(fw_cfg, &buf[..size], offset, vbt)?;
+
+	if let Some(file) = file {
+		let Some(data) = buf.get_mut(offset..offset.saturating_add(vbt.rvds as usize)) else {
+			error!("Extended VBT at +{:#x} doesn't fit the OpRegion buffer of {} bytes!", offset, buf.len());
+			return Err(Status::BUFFER_TOO_SMALL);
+		};
+
+		read_extended_vbt_file(fw_cfg, &file, data)?;
+	}
+
+	Ok(vbt)
+}
+
+// field overrides from `etc/igd-vbt-patch`, applied wherever the VBT ended up and before it is measured
+fn patch_vbt(fw_cfg: &mut FwCfg, vbt: &mut [u8]) -> Result<(), Status> {
+	let Some(file) = fw_cfg.find_file(VBT_PATCH_FILE) else {
+		return Ok(());
+	};
+
+	let mut patch = Scratch::new(file.size());
+	fw_cfg.read_file_to_buffer(&file, &mut patch);
+	integrity::verify(fw_cfg, VBT_PATCH_FILE, &patch)?;
+
+	match vbt::apply_patch(vbt, &patch) {
+		Ok(count) => {
+			info!("Applied {} entries of {} to the VBT", count, VBT_PATCH_FILE);
+			Ok(())
+		}
+		Err(reason) => {
+			error!("Failed to apply {}: {}!", VBT_PATCH_FILE, reason);
+			Err(Status::INVALID_PARAMETER)
+		}
+	}
+}
+
+// OpRegion 2.0 references the extended VBT by its absolute host address, which means nothing in the
+// guest, so it gets a buffer of its own below 4 GiB.
+fn relocate_extended_vbt(fw_cfg: &mut FwCfg, opregion: &[u8], vbt: ExtendedVbt) -> Result<ExtendedVbt, Status> {
+	let (mut vbt, file) = check_extended_vbt(fw_cfg, opregion, opregion::declared_size(opregion), vbt)?;
+	let pages = (vbt.rvds as usize).div_ceil(PAGE_SIZE);
+
+	// the fixed address only applies to the OpRegion itself
+	let Some(buf) = allocate_opregion(pages, None) else {
+		error!("Failed to allocate {} pages for the extended VBT below 4 GiB!", pages);
+		status_code::report(Failure::Allocation);
+		return Err(Status::OUT_OF_RESOURCES);
+	};
+
+	let buf_slice = unsafe { core::slice::from_raw_parts_mut(buf.as_ptr(), pages * PAGE_SIZE) };
+	buf_slice.zeroize();
+
+	let data = &mut buf_slice[..vbt.rvds as usize];
+	match file {
+		Some(file) => {
+			if let Err(status) = read_extended_vbt_file(fw_cfg, &file, data) {
+				unsafe {
+					let _ = boot::free_pages(buf, pages);
+				}
+				return Err(status);
+			}
+		}
+		None => {
+			let offset = opregion::declared_size(opregion);
+			data.copy_from_slice(&opregion[offset..offset + vbt.rvds as usize]);
+		}
+	}
+
+	if let Err(status) = patch_vbt(fw_cfg, &mut buf_slice[..vbt.rvds as usize])
+		.and_then(|_| tpm::measure("IGD extended VBT", &buf_slice[..vbt.rvds as usize])) {
+		unsafe {
+			let _ = boot::free_pages(buf, pages);
+		}
+		return Err(status);
+	}
+
+	let start: usize = buf.addr().into();
+	vbt.rvda = start as u64;
+	info!("Extended VBT @ {:#x} ({} bytes)", vbt.rvda, vbt.rvds);
+
+	Ok(vbt)
+}
+
+// Makes RVDA valid in the guest. From 2.1 on it is an offset from the OpRegion base, so the VBT has to
+// sit inside the OpRegion buffer; 2.0 uses an absolute address, unless the region is upconverted to
+// 2.1 with the VBT placed directly behind the OpRegion, as QEMU and vfio do.
+fn install_extended_vbt(fw_cfg: &mut FwCfg, buf: &mut [u8], size: usize, version: Version) -> Result<(), Status> {
+	let Some(vbt) = opregion::extended_vbt(&buf[..size], version) else {
+		return Ok(());
+	};
+
+	debug!("Extended VBT: RVDA {:#x}, RVDS {:#x}", vbt.rvda, vbt.rvds);
+
+	let relocated = if version >= opregion::VERSION_RELATIVE_RVDA {
+		place_extended_vbt(fw_cfg, buf, size, vbt.rvda as usize, vbt)?
+	} else if unsafe { CONFIG.upconvert_opregion } {
+		let offset = opregion::declared_size(&buf[..size]);
+		let mut vbt = place_extended_vbt(fw_cfg, buf, size, offset, vbt)?;
+
+		vbt.rvda = offset as u64;
+		opregion::set_version(buf, opregion::VERSION_RELATIVE_RVDA);
+		info!("Upconverted OpRegion 2.0 to 2.1, extended VBT at +{:#x}", offset);
+		vbt
+	} else {
+		relocate_extended_vbt(fw_cfg, &buf[..size], vbt)?
+	};
+
+	if relocated.rvda != vbt.rvda || relocated.rvds != vbt.rvds {
+		opregion::set_extended_vbt(buf, relocated);
+		debug!("RVDA/RVDS rewritten from {:#x}/{:#x} to {:#x}/{:#x}", vbt.rvda, vbt.rvds, relocated.rvda, relocated.rvds);
+	}
+
+	Ok(())
+}
+
+// what a fresh OpRegion copy is adjusted with for the device it is meant for
+#[derive(Clone, Copy)]
+struct Fixups {
+	// PCON bits to set and clear
+	pcon: Option<(u32, u32)>,
+	// MBOX bits to clear
+	mbox_clear: u32,
+}
+
+// read `file` into the start of `buf`, which may be larger, handing fw_cfg a slice exactly as large as
+// the file so the rest of `buf` is left as is
+fn read_file_exact<S: FwCfgSource>(fw_cfg: &mut S, file: &S::File, buf: &mut [u8]) {
+	let size = fw_cfg.file_size(file);
+	fw_cfg.read_file(file, &mut buf[..size]);
+}
+
+// copy the OpRegion into the `pages` at `start` and validate it there, `blob` is a mirrored or
+// embedded one used without a file
+fn load_opregion(fw_cfg: &mut FwCfg, opregion: Option<(&str, &FwCfgFile)>, blob: Option<&[u8]>, start: usize, pages: usize, size: usize,
+	fixups: Fixups) -> Result<(), Status> {
+	let end = start + (pages * PAGE_SIZE);
+
+	if end - 1 > ceiling_32() as usize {
+		error!("OpRegion [{:#x}, {:#x}) is above the allocation ceiling {:#x}!", start, end, ceiling_32());
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	debug!("OpRegion occupies [{:#x}, {:#x})", start, end);
+
+	let buf_slice = unsafe {
+		core::slice::from_raw_parts_mut(start as *mut u8, pages * PAGE_SIZE)
+	};
+	buf_slice.zeroize();
+
+	// the buffer is page-rounded, only hand the exact file size to fw_cfg and keep the zeroed tail
+	timestamp::timed("Reading the OpRegion", || {
+		match (opregion, blob) {
+			(Some((name, _)), _) if compressed::is_compressed(name) => compressed::read(name, &mut buf_slice[..size])?,
+			(Some((name, file)), _) if size > CHUNKED_READ_THRESHOLD => {
+				assert!(file.size() == size);
+
+				if !read_chunked(name, &mut buf_slice[..size]) {
+					error!("OpRegion disappeared from the fw_cfg directory!");
+					return Err(Status::NOT_FOUND);
+				}
+			}
+			(Some((_, file)), _) => {
+				assert!(file.size() == size);
+				read_file_exact(fw_cfg, file, buf_slice);
+			}
+			(None, Some(blob)) => buf_slice[..size].copy_from_slice(blob),
+			(None, None) => opregion::synthesize(buf_slice, extended_vbt_file_size(fw_cfg) as u32),
+		}
+
+		Ok(())
+	})?;
+
+	if let Some((name, _)) = opregion {
+		integrity::verify(fw_cfg, name, &buf_slice[..size])?;
+	}
+
+	opregion::dump(&buf_slice[..size], log::Level::Debug);
+	opregion::hexdump(&buf_slice[..size], log::Level::Trace);
+
+	let version = match opregion::validate_header(&buf_slice[..size], size, unsafe { CONFIG.signatures() }) {
+		Ok(version) => version,
+		Err(reason) => {
+			error!("OpRegion {}, leaving ASLS untouched!", reason);
+			return Err(Status::VOLUME_CORRUPTED);
+		}
+	};
+
+	info!("OpRegion version {}.{}", version.major, version.minor);
+
+	timestamp::timed("Setting up the extended VBT", || install_extended_vbt(fw_cfg, buf_slice, size, version))?;
+
+	if fw_cfg.find_file(VBT_PATCH_FILE).is_some() && let Some(range) = opregion::vbt_range(buf_slice) {
+		let Some(vbt) = buf_slice.get_mut(range) else {
+			error!("VBT is outside the OpRegion buffer!");
+			return Err(Status::BUFFER_TOO_SMALL);
+		};
+
+		timestamp::timed("Patching the VBT", || patch_vbt(fw_cfg, vbt))?;
+	}
+
+	// after the extended VBT was set up, which decides whether ASLE has to stay
+	if let Some((old, new)) = opregion::clear_mailboxes(buf_slice, version, fixups.mbox_clear) && old != new {
+		info!("MBOX {:#x} -> {:#x}, cleared the mailboxes nothing services", old, new);
+	}
+
+	if let Some((set, clear)) = fixups.pcon && let Some((old, new)) = opregion::patch_pcon(buf_slice, set, clear) && old != new {
+		info!("PCON {:#x} -> {:#x}", old, new);
+	}
+
+	if opregion::init_backlight(&mut buf_slice[..size]) {
+		info!("Initialized the ASLE backlight fields");
+	}
+
+	// a broken VBT would otherwise only show up as missing panel data in the guest
+	if opregion.is_none() && blob.is_none() && let Err(reason) = opregion::validate_vbt(&buf_slice[size..]) {
+		warn!("{} {}, the guest driver may ignore it", VBT_FILE, reason);
+	}
+
+	Ok(())
+}
+
+// `false` if the firmware refused, which only leaks the range
+fn free_pages(addr: usize, pages: usize) -> bool {
+	NonNull::new(addr as *mut u8).is_some_and(|mem| unsafe { boot::free_pages(mem, pages) }.is_ok())
+}
+
+// A fixed, reserved or device tree address may overlap MMIO, which the guest GPU hangs on, so such a
+// range is swapped for a fresh allocation. Returns the base to use and whether it is ours to free.
+fn avoid_mmio(pci_io: &mut ScopedProtocol<PciIo>, name: &str, base: usize, pages: usize, owned: bool,
+	reallocate: impl FnOnce() -> Option<usize>) -> Result<(usize, bool), Status> {
+	let size = pages * PAGE_SIZE;
+	let Some(window) = mmio::conflict(pci_io, base, size) else {
+		return Ok((base, owned));
+	};
+
+	warn!("{} [{:#x}, {:#x}) overlaps {} [{:#x}, {:#x}), reallocating it", name, base, base + size,
+		window.owner, window.base, window.base + window.size);
+
+	// the old range is only given back afterwards, so the allocator can't hand it out again
+	let new = reallocate();
+	if owned {
+		free_pages(base, pages);
+	}
+
+	let Some(new) = new else {
+		error!("Failed to reallocate {} clear of MMIO!", name);
+		status_code::report(Failure::Allocation);
+		return Err(Status::OUT_OF_RESOURCES);
+	};
+
+	if let Some(window) = mmio::conflict(pci_io, new, size) {
+		error!("{} [{:#x}, {:#x}) overlaps {} as well!", name, new, new + size, window.owner);
+		free_pages(new, pages);
+		status_code::report(Failure::Allocation);
+		return Err(Status::OUT_OF_RESOURCES);
+	}
+
+	info!("{} moved to {:#x}", name, new);
+	Ok((new, true))
+}
+
+// finding the signature is not enough, an existing region has to pass the same checks as a fresh copy
+fn validate_existing(existing: Region) -> Result<Version, &'static str> {
+	let data = unsafe { core::slice::from_raw_parts(existing.base as *const u8, existing.size) };
+	opregion::validate_header(data, existing.size, unsafe { CONFIG.signatures() })
+}
+
+// whether `region` is the OpRegion of a device other than the one at `location`
+fn opregion_claimed(region: Region, location: Option<Location>) -> bool {
+	unsafe { OUTCOME.devices.iter() }
+		.any(|d| Some(d.location) != location && d.opregion.is_some_and(|r| r.base == region.base))
+}
+
+// ASLS is only ever pointed at an OpRegion that passed every check, `loaded` is the outcome of copying,
+// validating and measuring the one at `start`
+fn point_asls<P: PciConfigAccess>(pci_io: &mut P, start: usize, loaded: Result<(), Status>) -> Result<(), Status> {
+	loaded?;
+	write_checked(pci_io, "ASLS", PCI_CFG_ASLS_OFFSET, start as u32, !0).ok_or(Status::DEVICE_ERROR)
+}
+
+pub fn install_opregion(pci_io: &mut ScopedProtocol<PciIo>) -> Result<Region, Status> {
+	let asls = pci::read_u32(pci_io, PCI_CFG_ASLS_OFFSET).unwrap_or(0) as usize;
+
+	// a second run, or OVMF's IgdAssignmentDxe, already did this, another copy would only leak ACPI NVS
+	if unsafe { !CONFIG.force } && let Some(existing) = existing::at_asls(asls, unsafe { CONFIG.signatures() }) {
+		match validate_existing(existing) {
+			Ok(version) => {
+				info!("ASLS already points at OpRegion {}.{} @ {:#x} ({} bytes), leaving it as is",
+					version.major, version.minor, existing.base, existing.size);
+				return Ok(existing);
+			}
+			Err(reason) => warn!("Ignoring the OpRegion ASLS points at: {}", reason),
+		}
+	}
+
+	if unsafe { CONFIG.reuse_opregion && !CONFIG.force } {
+		// a chained loader may have done all of this already, don't allocate a second copy
+		// with several GPUs, an OpRegion already handed to another one is theirs
+		if let Some(existing) = existing::find_existing(asls, unsafe { CONFIG.signatures() })
+			&& !opregion_claimed(existing, pci_io.get_location().ok()) {
+			match validate_existing(existing) {
+				Ok(version) => {
+					info!("Reusing OpRegion {}.{} @ {:#x} ({} bytes) already in ACPI NVS",
+						version.major, version.minor, existing.base, existing.size);
+					if write_checked(pci_io, "ASLS", PCI_CFG_ASLS_OFFSET, existing.base as u32, !0).is_none() {
+						return Err(Status::DEVICE_ERROR);
+					}
+
+					return Ok(existing);
+				}
+				Err(reason) => warn!("Not reusing OpRegion @ {:#x}: {}", existing.base, reason),
+			}
+		}
+	}
+
+	let mut fw_cfg = open_fw_cfg()?;
+	let opregion = find_opregion_file(&mut fw_cfg, pci_io.get_location().ok());
+
+	// QEMU versions without the fw_cfg file may still have vfio mirror the host OpRegion
+	let mirrored = match (&opregion, unsafe { CONFIG.opregion_mirror }) {
+		(None, Some(location)) => mirror::read(pci_io, location, unsafe { CONFIG.signatures() }, unsafe { CONFIG.max_opregion_size }),
+		_ => None,
+	};
+
+	let size = match (&opregion, &mirrored, embedded::OPREGION) {
+		(Some((name, file)), _, _) => {
+			debug!("Using OpRegion from {}", name);
+			compressed::size(name, file.size())
+		}
+		(None, Some(blob), _) => {
+			info!("Using the mirrored host OpRegion");
+			blob.len()
+		}
+		(None, None, Some(blob)) => {
+			info!("Using embedded OpRegion");
+			blob.len()
+		}
+		(None, None, None) if fw_cfg.find_file(VBT_FILE).is_some() => {
+			info!("No OpRegion passed through, synthesizing one around {}", VBT_FILE);
+			opregion::SYNTHESIZED_SIZE
+		}
+		(None, None, None) => {
+			error!("OpRegion not passed through!");
+			status_code::report(Failure::MissingFile);
+			return Err(Status::INVALID_PARAMETER);
+		}
+	};
+
+	if size == 0 {
+		error!("OpRegion has zero size!");
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	if let Some(expected) = metadata::load(&mut fw_cfg).and_then(|m| m.opregion_size) && expected != size {
+		error!("OpRegion size {} doesn't match the metadata size {}!", size, expected);
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	let max_size = unsafe { CONFIG.max_opregion_size };
+	if size > max_size {
+		error!("OpRegion size {} exceeds the maximum of {} bytes!", size, max_size);
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	// room for an extended VBT that may have to be placed behind the OpRegion
+	let pages = (size + extended_vbt_file_size(&mut fw_cfg)).div_ceil(PAGE_SIZE);
+	// a device tree or pre-reserved range belongs to the platform, only memory we allocated is freed again
+	let (buf, owned) = match opregion_reservation(pages * PAGE_SIZE) {
+		Some(reserved) => (reserved, false),
+		None => match timestamp::timed("Allocating the OpRegion", || allocate_opregion(pages, unsafe { CONFIG.opregion_address })) {
+			Some(buf) => (buf, true),
+			None => {
+				error!("Failed to allocate {} pages for the OpRegion below 4 GiB!", pages);
+				status_code::report(Failure::Allocation);
+				return Err(Status::OUT_OF_RESOURCES);
+			}
+		},
+	};
+
+	let (start, owned) = avoid_mmio(pci_io, "OpRegion", buf.addr().into(), pages, owned, || {
+		allocate_opregion(pages, None).map(|buf| buf.addr().into())
+	})?;
+
+	// the configuration covers unusual boards the table gets wrong
+	let fixups = Fixups {
+		pcon: unsafe { CONFIG.pcon }.or_else(|| pci::read_u16(pci_io, PCI_CFG_DEVICE_OFFSET).and_then(quirks::pcon_fixup)),
+		// a vGPU only gets the VBT mailbox, GVT-g services none of the others
+		mbox_clear: if is_mediated(pci_io) { opregion::MBOX_MEDIATED } else { unsafe { CONFIG.mbox_clear } },
+	};
+
+	let blob = mirrored.as_deref().or(embedded::OPREGION);
+	// measured before the guest can see it, including a VBT placed behind it
+	let loaded = load_opregion(&mut fw_cfg, opregion.as_ref().map(|(name, file)| (name.as_str(), file)), blob, start, pages, size, fixups)
+		.and_then(|_| timestamp::timed("Measuring the OpRegion", || {
+			tpm::measure("IGD OpRegion", unsafe { core::slice::from_raw_parts(start as *const u8, pages * PAGE_SIZE) })
+		}));
+
+	if let Err(status) = point_asls(pci_io, start, loaded) {
+		if owned {
+			free_pages(start, pages);
+		}
+
+		return Err(status);
+	}
+
+	// the GOP driver updates the OpRegion itself, faulting it would take video output with it
+	if unsafe { CONFIG.opregion_read_only } && !gop::present(&mut fw_cfg) {
+		memory_attribute::protect(Region { base: start, size: pages * PAGE_SIZE });
+	}
+
+	info!("OpRegion @ {:#x} ({} bytes)", start, size);
+	if owned {
+		log_memory_type("OpRegion", unsafe { CONFIG.opregion_memory_type });
+	}
+
+	Ok(Region { base: start, size })
+}
+
+// TOLUD as programmed in the host bridge, assuming an Intel client chipset layout (0xBC on 00:00.0,
+// bits 31:20 in MiB). QEMU's own i440fx and Q35 bridges don't implement it and read back zero.
+fn read_tolud() -> Option<usize> {
+	let Some(mut host_bridge) = pci::open_shared(HOST_BRIDGE_LOCATION) else {
+		warn!("No host bridge found, can't place stolen memory relative to TOLUD");
+		return None;
+	};
+
+	let tolud = (pci::read_u32(&mut host_bridge, PCI_CFG_TOLUD_OFFSET)? & TOLUD_MASK) as usize;
+
+	if tolud == 0 {
+		warn!("Host bridge doesn't report TOLUD");
+		return None;
+	}
+
+	info!("TOLUD @ {:#x}", tolud);
+	Some(tolud)
+}
+
+// what the guest OS will make of an allocation of memory type `ty`
+fn log_memory_type(name: &str, ty: MemoryType) {
+	match ty {
+		MemoryType::ACPI_NON_VOLATILE => info!("{} is ACPI NVS, E820 type 4: the OS leaves it alone and saves it across hibernation", name),
+		MemoryType::RESERVED => info!("{} is reserved, E820 type 2: the OS leaves it alone, but doesn't save it across hibernation", name),
+		ty => warn!("{} has memory type {:?}, the OS may reuse it!", name, ty),
+	}
+}
+
+// an unknown generation gets the 1 MiB every known one accepts, the configuration overrides both
+pub(crate) fn stolen_memory_alignment(generation: Option<Generation>) -> usize {
+	unsafe { CONFIG.stolen_memory_alignment }.unwrap_or_else(|| generation.map_or(STOLEN_MEMORY_ALIGNMENT, Generation::dsm_alignment))
+}
+
+// try to place stolen memory directly below `limit`, e.g. TOLUD where it sits on real hardware
+fn allocate_stolen_memory_below(limit: usize, pages: usize, alignment: usize) -> Option<usize> {
+	let base = stolen::base_below(limit, pages, alignment)?;
+
+	match boot::allocate_pages(boot::AllocateType::Address(base as u64), unsafe { CONFIG.stolen_memory_memory_type }, pages) {
+		Ok(mem) => Some(mem.addr().into()),
+		Err(e) => {
+			warn!("Can't allocate stolen memory below {:#x} @ {:#x}: {:?}", limit, base, e.status());
+			None
+		}
+	}
+}
+
+// Aligned bases in free memory, highest first, allocated exactly where they are. Needs no padding, so
+// it still works when every free range is only just large enough.
+fn allocate_stolen_memory_exact(pages: usize, ceiling: u64, alignment: usize) -> Option<usize> {
+	let mmap = boot::memory_map(MemoryType::LOADER_DATA).ok()?;
+	let mut candidates = mmap.entries()
+		.filter(|d| d.ty == MemoryType::CONVENTIONAL)
+		.filter_map(|d| stolen::base_within(d.phys_start, d.phys_start + d.page_count * PAGE_SIZE as u64, pages, alignment, ceiling))
+		.collect::<Vec<_>>();
+	candidates.sort_unstable_by(|a, b| b.cmp(a));
+
+	for base in candidates {
+		match boot::allocate_pages(boot::AllocateType::Address(base as u64), unsafe { CONFIG.stolen_memory_memory_type }, pages) {
+			Ok(mem) => return Some(mem.addr().into()),
+			Err(e) => debug!("Stolen memory candidate @ {:#x} is not available: {:?}", base, e.status()),
+		}
+	}
+
+	None
+}
+
+// Overallocates by `padding` pages and trims the allocation down to the aligned range, which only
+// works out with less than the full padding if the firmware happens to place it well. Only a fallback
+// for when no exactly placed candidate could be allocated, as it transiently takes up to `alignment`
+// more memory.
+fn allocate_stolen_memory_padded(pages: usize, ceiling: u64, alignment: usize, padding: usize) -> Option<usize> {
+	let allocation: usize = match boot::allocate_pages(boot::AllocateType::MaxAddress(ceiling),
+		unsafe { CONFIG.stolen_memory_memory_type }, pages + padding) {
+		Ok(mem) => mem.addr().into(),
+		Err(e) => {
+			debug!("Failed to allocate {} pages with {} pages of padding: {:?}", pages, padding, e.status());
+			return None;
+		}
+	};
+
+	let Some(trim) = stolen::trim_padded(allocation, padding, alignment) else {
+		debug!("{} pages of padding @ {:#x} leave no room for an aligned range", padding, allocation);
+		free_pages(allocation, pages + padding);
+		return None;
+	};
+
+	// failing to trim only leaks the surplus, the aligned range itself is fine
+	if trim.head > 0 && !free_pages(allocation, trim.head / PAGE_SIZE) {
+		warn!("Failed to free the alignment padding below stolen memory");
+	}
+
+	if trim.tail > 0 && !free_pages(trim.base + pages * PAGE_SIZE, trim.tail / PAGE_SIZE) {
+		warn!("Failed to free the alignment padding above stolen memory");
+	}
+
+	Some(trim.base)
+}
+
+// With little low memory, the padded allocation may not fit anywhere. So exactly placed candidates are
+// tried first, then ever smaller paddings. A higher ceiling is up to the caller.
+fn allocate_stolen_memory(pages: usize, ceiling: u64, alignment: usize) -> Option<usize> {
+	if let Some(addr) = allocate_stolen_memory_exact(pages, ceiling, alignment) {
+		return Some(addr);
+	}
+
+	warn!("No exactly placed stolen memory below {:#x}, falling back to padded allocations", ceiling);
+
+	for padding in stolen::paddings(alignment) {
+		if let Some(addr) = allocate_stolen_memory_padded(pages, ceiling, alignment, padding) {
+			if padding < stolen::overallocation(alignment) {
+				info!("Stolen memory fit with {} pages of padding", padding);
+			}
+
+			return Some(addr);
+		}
+	}
+
+	error!("Failed to allocate {} pages for stolen memory below {:#x}!", pages, ceiling);
+	None
+}
+
+// the size passed by the host, `None` if there is none
+pub(crate) fn read_bdsm_size<S: FwCfgSource>(fw_cfg: &mut S, gms: Option<Gms>) -> Result<Option<usize>, Status> {
+	let bdsm_size = match metadata::load(fw_cfg).and_then(|m| m.bdsm_size) {
+		Some(size) => size,
+		None => {
+			let Some((name, bdsm)) = find_first_file(fw_cfg, BDSM_SIZE_FILES) else {
+				return read_host_ggc_size(fw_cfg, gms);
+			};
+
+			// older QEMU releases wrote a 32-bit value, current ones a 64-bit one
+			let mut bdsm_buf = Scratch::new(8);
+			match fw_cfg.file_size(&bdsm) {
+				len @ (4 | 8) => fw_cfg.read_file(&bdsm, &mut bdsm_buf[..len]),
+				len => {
+					error!("{} is {} bytes long, expected a 4 or 8-byte size!", name, len);
+					return Err(Status::INVALID_PARAMETER);
+				}
+			}
+
+			let size = u64::from_le_bytes((*bdsm_buf).try_into().unwrap());
+			let Ok(size) = usize::try_from(size) else {
+				error!("{} asks for {:#x} bytes, more than the address space holds!", name, size);
+				return Err(Status::INVALID_PARAMETER);
+			};
+
+			size
+		}
+	};
+
+	validate_bdsm_size(bdsm_size).map(Some)
+}
+
+// a 4 or 8-byte little-endian address in `name`, `None` if there is no such file
+fn read_address<S: FwCfgSource>(fw_cfg: &mut S, name: &str) -> Result<Option<u64>, Status> {
+	let Some(file) = fw_cfg.find_file(name) else {
+		return Ok(None);
+	};
+
+	let mut buf = Scratch::new(8);
+	match fw_cfg.file_size(&file) {
+		len @ (4 | 8) => fw_cfg.read_file(&file, &mut buf[..len]),
+		len => {
+			error!("{} is {} bytes long, expected a 4 or 8-byte address!", name, len);
+			return Err(Status::INVALID_PARAMETER);
+		}
+	}
+
+	Ok(Some(u64::from_le_bytes((*buf).try_into().unwrap())))
+}
+
+// the stolen memory base passed by the host, `None` if there is none
+pub(crate) fn read_bdsm_base<S: FwCfgSource>(fw_cfg: &mut S) -> Result<Option<usize>, Status> {
+	let Some(base) = read_address(fw_cfg, BDSM_BASE_FILE)? else {
+		return Ok(None);
+	};
+
+	// on ia32, a base above 4 GiB can't be addressed
+	let Ok(base) = usize::try_from(base) else {
+		error!("{} places stolen memory at {:#x}, outside the address space!", BDSM_BASE_FILE, base);
+		return Err(Status::INVALID_PARAMETER);
+	};
+
+	Ok(Some(base))
+}
+
+// the host's stolen memory base, for allocating the guest's at the same address
+pub(crate) fn read_host_bdsm<S: FwCfgSource>(fw_cfg: &mut S) -> Result<Option<u64>, Status> {
+	let base = read_address(fw_cfg, HOST_BDSM_FILE)?;
+
+	if let Some(base) = base {
+		info!("Host stolen memory base from {}: {:#x}", HOST_BDSM_FILE, base);
+	}
+
+	Ok(base)
+}
+
+// the stolen memory size the GMS field of the host's GGC selects, `None` if there is no such file
+fn read_host_ggc_size<S: FwCfgSource>(fw_cfg: &mut S, gms: Option<Gms>) -> Result<Option<usize>, Status> {
+	let Some(file) = fw_cfg.find_file(GGC_FILE) else {
+		return Ok(None);
+	};
+
+	let mut buf = Scratch::new(2);
+	match fw_cfg.file_size(&file) {
+		2 => fw_cfg.read_file(&file, &mut buf),
+		len => {
+			error!("{} is {} bytes long, expected a 2-byte register value!", GGC_FILE, len);
+			return Err(Status::INVALID_PARAMETER);
+		}
+	}
+
+	let ggc = u16::from_le_bytes((*buf).try_into().unwrap());
+
+	let Some(gms) = gms else {
+		error!("Can't decode GMS of the host GGC {:#06x} for an unknown generation!", ggc);
+		return Err(Status::UNSUPPORTED);
+	};
+
+	let Some(size) = gms.decode_gms(ggc) else {
+		error!("Host GGC {:#06x} selects no stolen memory size {:?} defines!", ggc, gms);
+		return Err(Status::INVALID_PARAMETER);
+	};
+
+	info!("Stolen memory size from the host GGC {:#06x} in {}: {} MiB", ggc, GGC_FILE, size / 1024 / 1024);
+	validate_bdsm_size(size).map(Some)
+}
+
+fn validate_bdsm_size(bdsm_size: usize) -> Result<usize, Status> {
+	if bdsm_size == 0 {
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	if bdsm_size % PAGE_SIZE != 0 {
+		error!("BDSM size {} is not page-aligned!", bdsm_size);
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	Ok(bdsm_size)
+}
+
+// on some generations stolen memory is as large as one of the BARs
+fn bar_bdsm_size<P: PciConfigAccess>(pci_io: &mut P, bar: u8) -> Result<usize, Status> {
+	let Some(size) = pci::probe_bar_size(pci_io, bar) else {
+		error!("BAR{} is not an implemented memory BAR, can't size stolen memory from it!", bar);
+		return Err(Status::UNSUPPORTED);
+	};
+
+	info!("Sizing stolen memory from BAR{}: {} MiB", bar, size / 1024 / 1024);
+	validate_bdsm_size(size as usize)
+}
+
+// sample the first, middle and last page, which catches zeroing a range offset from the one we use
+fn verify_zeroed(addr: usize, pages: usize) -> bool {
+	let mut ok = true;
+
+	for page in [0, pages / 2, pages - 1] {
+		let base = addr + page * PAGE_SIZE;
+		let data = unsafe { core::slice::from_raw_parts(base as *const u8, PAGE_SIZE) };
+
+		if let Some(offset) = data.iter().position(|&b| b != 0) {
+			error!("StolenMemory page @ {:#x} isn't zeroed (first non-zero byte at +{:#x})!", base, offset);
+			ok = false;
+		}
+	}
+
+	ok
+}
+
+// Write a register and read it back, comparing the bits in `mask`. Some vfio configurations make
+// ASLS or BDSM read-only, which would otherwise only show up as a failing guest driver.
+fn write_checked<P: PciConfigAccess>(pci_io: &mut P, name: &str, offset: u32, value: u32, mask: u32) -> Option<()> {
+	if pci::write_u32(pci_io, offset, value).is_none() {
+		error!("Failed to write {}!", name);
+		status_code::report(Failure::PciWrite);
+		return None;
+	}
+
+	let Some(readback) = pci::read_u32(pci_io, offset) else {
+		error!("Failed to read back {}!", name);
+		return None;
+	};
+
+	if readback & mask != value & mask {
+		error!("{} reads back {:#010x} after writing {:#010x}, the register is probably read-only for us!", name, readback, value);
+		status_code::report(Failure::PciWrite);
+		return None;
+	}
+
+	Some(())
+}
+
+// unknown devices are assumed to have the pre-Gen11 layout, which can also be forced for all of them
+fn uses_bdsm64(generation: Option<Generation>) -> bool {
+	generation.is_some_and(Generation::has_bdsm64) && unsafe { !CONFIG.legacy_bdsm }
+}
+
+// whether both BDSM locations are programmed, for new SKUs the generation table doesn't know yet
+fn uses_dual_bdsm(generation: Option<Generation>) -> bool {
+	generation.is_none() && unsafe { CONFIG.dual_bdsm && !CONFIG.legacy_bdsm }
+}
+
+fn write_bdsm64<P: PciConfigAccess>(pci_io: &mut P, addr: usize) -> Option<()> {
+	write_checked(pci_io, "BDSM", PCI_CFG_BDSM64_OFFSET, addr as u32, BDSM_ADDRESS_MASK)?;
+	write_checked(pci_io, "BDSM (high)", PCI_CFG_BDSM64_OFFSET + 4, (addr as u64 >> 32) as u32, !0)
+}
+
+fn write_bdsm<P: PciConfigAccess>(pci_io: &mut P, generation: Option<Generation>, addr: usize) -> Option<()> {
+	if uses_dual_bdsm(generation) {
+		// only one of them is the real register, so one write sticking is enough
+		let mirror = write_checked(pci_io, "BDSM", PCI_CFG_BDSM_MIRROR_OFFSET, addr as u32, BDSM_ADDRESS_MASK);
+		let bdsm64 = write_bdsm64(pci_io, addr);
+
+		return mirror.or(bdsm64);
+	}
+
+	if uses_bdsm64(generation) {
+		write_bdsm64(pci_io, addr)
+	} else {
+		write_checked(pci_io, "BDSM", PCI_CFG_BDSM_MIRROR_OFFSET, addr as u32, BDSM_ADDRESS_MASK)
+	}
+}
+
+// GFX MMIO BAR and the offset of its 64-bit DSMBASE register mirroring BDSM
+const GTTMMADR_BAR: u8 = 0;
+const MMIO_DSMBASE_OFFSET: u64 = 0x1080C0;
+
+// Program the DSMBASE mirror in BAR0 as well, where the generation has one. On bare metal it is a
+// read-only copy of BDSM, so a write that doesn't stick is only reported.
+fn write_bdsm_mmio(pci_io: &mut PciIo, generation: Option<Generation>, addr: usize) {
+	if !generation.is_some_and(|g| g.has_bdsm_mmio()) {
+		return;
+	}
+
+	let Some(bar) = pci::bar_address(pci_io, GTTMMADR_BAR) else {
+		info!("BAR{} isn't mapped, skipping the MMIO BDSM mirror", GTTMMADR_BAR);
+		return;
+	};
+
+	let (low, high) = (addr as u32, (addr as u64 >> 32) as u32);
+	let written = pci::mmio_write_u32(pci_io, GTTMMADR_BAR, MMIO_DSMBASE_OFFSET, low)
+		.and_then(|_| pci::mmio_write_u32(pci_io, GTTMMADR_BAR, MMIO_DSMBASE_OFFSET + 4, high));
+
+	if written.is_none() {
+		warn!("Failed to write the MMIO BDSM mirror in BAR{} @ {:#x}!", GTTMMADR_BAR, bar);
+		return;
+	}
+
+	let read_low = pci::mmio_read_u32(pci_io, GTTMMADR_BAR, MMIO_DSMBASE_OFFSET);
+	let read_high = pci::mmio_read_u32(pci_io, GTTMMADR_BAR, MMIO_DSMBASE_OFFSET + 4);
+
+	match (read_low, read_high) {
+		(Some(l), Some(h)) if l & BDSM_ADDRESS_MASK == low & BDSM_ADDRESS_MASK && h == high => {
+			debug!("MMIO BDSM mirror set to {:#x}", addr);
+		}
+		(l, h) => warn!("MMIO BDSM mirror reads back {:x?}:{:x?} instead of {:#x}", h, l, addr),
+	}
+}
+
+// the stolen memory base currently programmed, with the lock and reserved low bits masked off
+fn read_bdsm<P: PciConfigAccess>(pci_io: &mut P, generation: Option<Generation>) -> Option<usize> {
+	let read_bdsm64 = |pci_io: &mut P| -> Option<u64> {
+		let low = pci::read_u32(pci_io, PCI_CFG_BDSM64_OFFSET)? as u64;
+		let high = pci::read_u32(pci_io, PCI_CFG_BDSM64_OFFSET + 4)? as u64;
+		Some(high << 32 | low)
+	};
+
+	let bdsm = if uses_dual_bdsm(generation) {
+		// whichever of the two locations took the write
+		match pci::read_u32(pci_io, PCI_CFG_BDSM_MIRROR_OFFSET)? as u64 & BDSM_ADDRESS_MASK as u64 {
+			0 => read_bdsm64(pci_io)?,
+			mirror => mirror,
+		}
+	} else if uses_bdsm64(generation) {
+		read_bdsm64(pci_io)?
+	} else {
+		pci::read_u32(pci_io, PCI_CFG_BDSM_MIRROR_OFFSET)? as u64
+	};
+
+	usize::try_from(bdsm & !(STOLEN_MEMORY_ALIGNMENT as u64 - 1)).ok()
+}
+
+// BDSM only counts as programmed if it points at memory allocated for it, the power-on value of a
+// passed-through register could point anywhere
+fn is_existing_stolen_memory(addr: usize, size: usize) -> bool {
+	let Ok(mmap) = boot::memory_map(MemoryType::LOADER_DATA) else {
+		return false;
+	};
+
+	// in 64 bits, descriptors may describe memory above 4 GiB on ia32 as well
+	let (addr, size) = (addr as u64, size as u64);
+
+	addr != 0 && mmap.entries().any(|d| {
+		let start = d.phys_start;
+		let end = start + d.page_count * PAGE_SIZE as u64;
+
+		matches!(d.ty, MemoryType::ACPI_NON_VOLATILE | MemoryType::RESERVED) && addr >= start && addr + size <= end
+	})
+}
+
+// how Linux reports the type in its E820 table, which is what the guest ends up seeing
+fn e820_name(ty: MemoryType) -> &'static str {
+	match ty {
+		MemoryType::ACPI_NON_VOLATILE => "ACPI NVS",
+		MemoryType::ACPI_RECLAIM => "ACPI data",
+		MemoryType::LOADER_CODE | MemoryType::LOADER_DATA | MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA
+			| MemoryType::CONVENTIONAL => "usable",
+		MemoryType::UNUSABLE => "unusable",
+		_ => "reserved",
+	}
+}
+
+// Walk the memory map entries covering `region` and check that all of it stays reserved at handoff.
+// Neighbouring ranges of the same type may have been merged into the entries, which is harmless, but
+// a gap or an entry the OS may reuse means the guest driver's memory gets handed out.
+fn verify_reservation(name: &str, region: Region, expected: MemoryType) -> Option<bool> {
+	let Ok(mmap) = boot::memory_map(MemoryType::LOADER_DATA) else {
+		warn!("Failed to get the memory map, can't verify the {} reservation", name);
+		return None;
+	};
+
+	let (start, end) = (region.base as u64, (region.base + region.size) as u64);
+	let mut entries = mmap.entries().filter(|d| d.phys_start < end && d.phys_start + d.page_count * PAGE_SIZE as u64 > start)
+		.collect::<Vec<_>>();
+	entries.sort_by_key(|d| d.phys_start);
+
+	let mut covered = start;
+	let mut ok = true;
+
+	for d in &entries {
+		let d_end = d.phys_start + d.page_count * PAGE_SIZE as u64;
+		info!("{}: [mem {:#018x}-{:#018x}] {} ({:?})", name, d.phys_start, d_end - 1, e820_name(d.ty), d.ty);
+
+		if d.phys_start > covered {
+			error!("{} range [{:#x}, {:#x}) isn't in the memory map!", name, covered, d.phys_start);
+			ok = false;
+		}
+
+		match d.ty {
+			ty if ty == expected => {}
+			MemoryType::RESERVED | MemoryType::ACPI_NON_VOLATILE => {
+				info!("{} range @ {:#x} is {:?} instead of {:?}, which is reserved all the same", name, d.phys_start, d.ty, expected);
+			}
+			ty => {
+				error!("{} range @ {:#x} is {:?}, the OS may reuse it!", name, d.phys_start, ty);
+				ok = false;
+			}
+		}
+
+		covered = covered.max(d_end);
+	}
+
+	if covered < end {
+		error!("{} range [{:#x}, {:#x}) isn't in the memory map!", name, covered, end);
+		ok = false;
+	}
+
+	if ok {
+		debug!("{} is reserved in {} memory map entries", name, entries.len());
+	}
+
+	Some(ok)
+}
+
+// make the size the guest driver computes from GMS match what was actually allocated
+fn write_gms<P: PciConfigAccess>(pci_io: &mut P, gms: Option<Gms>, size: usize) {
+	let Some(gms) = gms else {
+		warn!("Not programming GMS of a device with unknown generation");
+		return;
+	};
+
+	let Some((mask, value)) = gms.encode_gms(size) else {
+		warn!("Stolen memory size of {} MiB can't be encoded in GMS on {:?}", size / 1024 / 1024, gms);
+		return;
+	};
+
+	let Some(ggc) = pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET) else {
+		error!("Failed to read GGC!");
+		return;
+	};
+
+	let new = (ggc & !mask) | value;
+	if pci::write_u16(pci_io, PCI_CFG_GGC_OFFSET, new).is_none() {
+		error!("Failed to write GGC!");
+		status_code::report(Failure::PciWrite);
+		return;
+	}
+
+	debug!("GGC {:#06x} -> {:#06x}", ggc, new);
+}
+
+// GTT stolen memory sits directly below the data stolen memory at `dsm`, as on real hardware. `None` if
+// GGC selects none.
+fn install_gtt_stolen_memory(pci_io: &mut ScopedProtocol<PciIo>, generation: Option<Generation>, gms: Option<Gms>, dsm: usize) -> Result<Option<Region>, Status> {
+	let Some(ggc) = pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET) else {
+		error!("Failed to read GGC!");
+		return Err(Status::DEVICE_ERROR);
+	};
+
+	let Some(size) = gms.and_then(|g| g.gtt_stolen_memory_size(ggc)) else {
+		info!("GGC {:#06x} selects no GTT stolen memory", ggc);
+		return Ok(None);
+	};
+
+	let pages = size / PAGE_SIZE;
+	let alignment = generation.map_or(STOLEN_MEMORY_ALIGNMENT, Generation::gsm_alignment);
+	// BGSM is 32 bits wide on every generation
+	let Some(addr) = allocate_stolen_memory_below(dsm, pages, alignment).or_else(|| allocate_stolen_memory(pages, ceiling_32(), alignment)) else {
+		error!("Failed to allocate {} pages for GTT stolen memory!", pages);
+		return Err(Status::OUT_OF_RESOURCES);
+	};
+
+	unsafe {
+		core::slice::from_raw_parts_mut(addr as *mut u8, size).zeroize();
+	}
+
+	// without BGSM nothing points at the allocation
+	let failed = || {
+		free_pages(addr, pages);
+		Err(Status::DEVICE_ERROR)
+	};
+
+	let Some(mut host_bridge) = pci::open_shared(HOST_BRIDGE_LOCATION) else {
+		error!("No host bridge found, can't program BGSM!");
+		return failed();
+	};
+
+	// BGSM has the layout of BDSM
+	if write_checked(&mut host_bridge, "BGSM", PCI_CFG_BGSM_OFFSET, addr as u32, BDSM_ADDRESS_MASK).is_none() {
+		return failed();
+	}
+
+	info!("GTT StolenMemory @ {:#x} ({} MiB)", addr, size / 1024 / 1024);
+
+	Ok(Some(Region { base: addr, size }))
+}
+
+// Copy the stolen memory base into a Q35 host bridge, at the offset the host bridge of Intel client
+// chipsets has it. QEMU leaves 0xB0 of its MCH unimplemented, so a write that doesn't stick isn't
+// fatal. GGC isn't mirrored, 0x50 is where the MCH keeps its extended TSEG size.
+fn mirror_to_mch(stolen_memory: Region) {
+	let Some(mut host_bridge) = pci::open_shared(HOST_BRIDGE_LOCATION) else {
+		debug!("No host bridge found, not mirroring BDSM");
+		return;
+	};
+
+	match pci::read_u32(&mut host_bridge, 0) {
+		Some(ids) if ids == (Q35_MCH_DEVICE_ID as u32) << 16 | PCI_VENDOR_INTEL as u32 => {}
+		Some(ids) => {
+			debug!("Host bridge {:04x}:{:04x} isn't a Q35 MCH, not mirroring BDSM", ids & 0xFFFF, ids >> 16);
+			return;
+		}
+		None => return,
+	}
+
+	if stolen_memory.base as u64 > CEILING_32 {
+		warn!("StolenMemory @ {:#x} is above 4 GiB, not mirroring BDSM into the MCH", stolen_memory.base);
+	} else if write_checked(&mut host_bridge, "MCH BDSM", PCI_CFG_MCH_BDSM_OFFSET, stolen_memory.base as u32, BDSM_ADDRESS_MASK).is_none() {
+		warn!("The MCH doesn't hold BDSM, guest drivers reading it from 00:00.0 won't find stolen memory");
+	} else {
+		info!("Mirrored BDSM {:#x} into the Q35 MCH", stolen_memory.base);
+	}
+}
+
+// A size GMS can't encode leaves the guest driver with a different idea of the stolen memory size
+// than what was allocated, so it is rejected along with the size that would work instead.
+pub(crate) fn check_gms_size(gms: Option<Gms>, size: usize) -> Result<(), Status> {
+	let Some(gms) = gms else {
+		return Ok(());
+	};
+
+	// Gen4/5 aren't programmed at all
+	if gms.encode_gms(32 * 1024 * 1024).is_none() || gms.encode_gms(size).is_some() {
+		return Ok(());
+	}
+
+	match gms.largest_gms_size(size) {
+		Some((largest, value)) => error!("Stolen memory size of {} MiB can't be encoded in GMS on {:?}, the closest below is {} MiB (GMS {:#x}, x-igd-gms={})!",
+			size / 1024 / 1024, gms, largest / 1024 / 1024, value, value),
+		None => error!("Stolen memory size of {} MiB is below the smallest GMS encodes on {:?}!", size / 1024 / 1024, gms),
+	}
+
+	Err(Status::INVALID_PARAMETER)
+}
+
+// Stolen memory the host placed itself, only programmed into the device. It has to be set aside in
+// the memory map already, as nothing would keep the OS from reusing it otherwise.
+fn install_host_stolen_memory(pci_io: &mut ScopedProtocol<PciIo>, generation: Option<Generation>, gms: Option<Gms>, region: Region, alignment: usize) -> Result<Region, Status> {
+	let Region { base, size } = region;
+
+	if base % alignment != 0 {
+		error!("Stolen memory base {:#x} from {} isn't aligned to {} MiB!", base, BDSM_BASE_FILE, alignment / 1024 / 1024);
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	if !uses_bdsm64(generation) && (base + size) as u64 > ceiling_32() + 1 {
+		error!("Stolen memory @ {:#x} from {} doesn't end below {:#x} as a 32-bit BDSM needs!", base, BDSM_BASE_FILE, ceiling_32());
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	if !is_existing_stolen_memory(base, size) {
+		error!("Stolen memory @ {:#x} ({} MiB) from {} isn't reserved or ACPI NVS in the memory map!", base, size / 1024 / 1024, BDSM_BASE_FILE);
+		return Err(Status::INVALID_PARAMETER);
+	}
+
+	// the host's placement is binding, it can only be pointed out
+	if let Some(window) = mmio::conflict(pci_io, base, size) {
+		warn!("Stolen memory @ {:#x} from {} overlaps {} [{:#x}, {:#x}), the guest GPU may hang!", base, BDSM_BASE_FILE,
+			window.owner, window.base, window.base + window.size);
+	}
+
+	if write_bdsm(pci_io, generation, base).is_none() {
+		return Err(Status::DEVICE_ERROR);
+	}
+
+	write_bdsm_mmio(pci_io, generation, base);
+	write_gms(pci_io, gms, size);
+
+	info!("StolenMemory @ {:#x} ({} MiB) as placed by the host in {}", base, size / 1024 / 1024, BDSM_BASE_FILE);
+	Ok(region)
+}
+
+// the data stolen memory, and the GTT stolen memory if that was asked for and GGC selects any
+pub fn install_stolen_memory(pci_io: &mut ScopedProtocol<PciIo>, generation: Option<Generation>, device: u16) -> Result<(Region, Option<Region>), Status> {
+	let mut fw_cfg = open_fw_cfg()?;
+	let gms = Gms::of(generation, device);
+	let bdsm_size = match (read_bdsm_size(&mut fw_cfg, gms)?, unsafe { CONFIG.stolen_memory_bar }) {
+		(Some(size), _) => size,
+		(None, Some(bar)) => bar_bdsm_size(pci_io, bar)?,
+		(None, None) => {
+			error!("BDSM data not passed through!");
+			status_code::report(Failure::MissingFile);
+			return Err(Status::INVALID_PARAMETER);
+		}
+	};
+
+	let pages = bdsm_size / PAGE_SIZE;
+	let alignment = stolen_memory_alignment(generation);
+
+	if unsafe { !CONFIG.force } && let Some(existing) = read_bdsm(pci_io, generation) && is_existing_stolen_memory(existing, bdsm_size) {
+		info!("BDSM already points at stolen memory @ {:#x} ({} MiB), leaving it as is", existing, bdsm_size / 1024 / 1024);
+		return Ok(Region { base: existing, size: bdsm_size });
+	}
+
+	check_gms_size(gms, bdsm_size)?;
+
+	if let Some(base) = read_bdsm_base(&mut fw_cfg)? {
+		return install_host_stolen_memory(pci_io, generation, gms, Region { base, size: bdsm_size }, alignment);
+	}
+
+	// like the other files, the host's base takes precedence over the configuration
+	let host = read_host_bdsm(&mut fw_cfg)?;
+	let fixed = host.or(unsafe { CONFIG.stolen_memory_address });
+	let tolud = if unsafe { CONFIG.tolud_placement && fixed.is_none() } { read_tolud() } else { None };
+	// a 64-bit BDSM can point above 4 GiB, which multi-GiB stolen memory may need. Below is still
+	// preferred, as that is where it lives on real hardware.
+	let bdsm64 = uses_bdsm64(generation);
+	let configured = unsafe { CONFIG.allocation_ceiling };
+	let ceiling = match configured {
+		Some(c) if bdsm64 => c,
+		_ => ceiling_32(),
+	};
+
+	// a range the platform set aside beforehand belongs to it, only memory we allocated is freed again
+	let carved = match fixed {
+		Some(_) => None,
+		None => reserved::carve_low(pages * PAGE_SIZE, alignment, ceiling),
+	};
+
+	let addr = timestamp::timed("Allocating stolen memory", || carved.or_else(|| match fixed {
+		Some(fixed) => {
+			let ceiling = if bdsm64 { configured.unwrap_or(u64::MAX) } else { ceiling_32() };
+			allocate_fixed("StolenMemory", fixed, pages, alignment, ceiling, unsafe { CONFIG.stolen_memory_memory_type })
+		}
+		// placing it below TOLUD must not take it above the ceiling either
+		None => tolud.and_then(|t| allocate_stolen_memory_below(t.min(usize::try_from(ceiling.saturating_add(1)).unwrap_or(usize::MAX)), pages, alignment))
+			.or_else(|| allocate_stolen_memory(pages, ceiling, alignment))
+			.or_else(|| {
+				if !bdsm64 || configured.is_some() {
+					return None;
+				}
+
+				info!("Retrying the stolen memory allocation above 4 GiB");
+				allocate_stolen_memory(pages, u64::MAX, alignment)
+			}),
+	}));
+
+	let Some(addr) = addr else {
+		if let Some(host) = host {
+			error!("Stolen memory can't go to the host's base {:#x} ({} MiB)! If the range is taken, guest RAM covers it, \
+				shrink the guest's memory below 4 GiB or drop {}.", host, bdsm_size / 1024 / 1024, HOST_BDSM_FILE);
+		}
+
+		status_code::report(Failure::Allocation);
+		return Err(Status::OUT_OF_RESOURCES);
+	};
+
+	let (addr, owned) = avoid_mmio(pci_io, "StolenMemory", addr, pages, carved.is_none(), || allocate_stolen_memory(pages, ceiling, alignment))?;
+
+	timestamp::timed("Zeroing stolen memory", || unsafe {
+		core::slice::from_raw_parts_mut(addr as *mut u8, pages * PAGE_SIZE).zeroize();
+	});
+
+	if cfg!(debug_assertions) || unsafe { CONFIG.verify_zeroed } {
+		verify_zeroed(addr, pages);
+	}
+
+	if let Some(tolud) = tolud && addr + (pages * PAGE_SIZE) > tolud {
+		warn!("StolenMemory ends above TOLUD {:#x}, the guest driver may not expect it there", tolud);
+	}
+
+	if timestamp::timed("Writing BDSM", || write_bdsm(pci_io, generation, addr)).is_none() {
+		if owned {
+			free_pages(addr, pages);
+		}
+		return Err(Status::DEVICE_ERROR);
+	}
+	timestamp::timed("Writing the BDSM mirror and GMS", || {
+		write_bdsm_mmio(pci_io, generation, addr);
+		write_gms(pci_io, gms, pages * PAGE_SIZE);
+	});
+
+	info!("StolenMemory @ {:#x} ({} MiB)", addr, (pages * PAGE_SIZE) / 1024 / 1024);
+	log_memory_type("StolenMemory", unsafe { CONFIG.stolen_memory_memory_type });
+
+	// a GTT failure fails the whole step, even though BDSM stays programmed
+	let gtt = if unsafe { CONFIG.gtt_stolen_memory } {
+		install_gtt_stolen_memory(pci_io, generation, gms, addr)?
+	} else {
+		None
+	};
+
+	Ok((Region { base: addr, size: pages * PAGE_SIZE }, gtt))
+}
+
+const PCI_EXT_CAP_SRIOV: u16 = 0x0010;
+
+// GVT-g vGPUs carry whatever device ID the mdev type gives them, usually that of the host IGD, but
+// have a PVINFO page in BAR0 starting with this magic. Their GGC and BDSM are emulated by GVT-g, the
+// stolen memory behind them is the host's.
+const VGT_PVINFO_OFFSET: u64 = 0x78000;
+const VGT_MAGIC: u64 = 0x4776_5447_7654_4776;
+
+pub(crate) fn is_mediated(pci_io: &mut PciIo) -> bool {
+	if let Some(mediated) = unsafe { CONFIG.mediated } {
+		return mediated;
+	}
+
+	// without BAR0 decoded there is no PVINFO to read, and no vGPU the guest driver could use either
+	if pci::bar_address(pci_io, 0).is_none() {
+		return false;
+	}
+
+	let lo = pci::mmio_read_u32(pci_io, 0, VGT_PVINFO_OFFSET);
+	let hi = pci::mmio_read_u32(pci_io, 0, VGT_PVINFO_OFFSET + 4);
+
+	matches!((lo, hi), (Some(lo), Some(hi)) if (hi as u64) << 32 | lo as u64 == VGT_MAGIC)
+}
+
+// VFs of Xe iGPUs with SR-IOV (Gen12 and newer) have the device ID of their PF, but none of its
+// GPU-specific registers, to a VF assigned through vfio GGC and BDSM read as zero. A device with an
+// SR-IOV capability, on the other hand, is the PF itself.
+pub(crate) fn is_virtual_function<P: PciConfigAccess>(pci_io: &mut P, generation: Option<Generation>) -> bool {
+	if let Some(vf) = unsafe { CONFIG.virtual_function } {
+		return vf;
+	}
+
+	if !generation.is_some_and(|g| g >= Generation::Gen12) {
+		return false;
+	}
+
+	if pci::find_extended_capability(pci_io, PCI_EXT_CAP_SRIOV).is_some() {
+		debug!("Device has an SR-IOV capability, it is a PF");
+		return false;
+	}
+
+	let ggc = pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET);
+	let bdsm = read_bdsm(pci_io, generation);
+
+	ggc == Some(0) && bdsm == Some(0)
+}
+
+// every criterion has to match exactly, the first one that doesn't is reported
+fn strict_match(pci_io: &mut ScopedProtocol<PciIo>, strict: &StrictMatch, device: u16, location: Location) -> bool {
+	let (seg, bus, dev, func) = location;
+
+	if device != strict.device_id {
+		info!("Strict match: {:04x}:{:02x}:{:02x}.{} has device ID {:04x}, expected {:04x}",
+			seg, bus, dev, func, device, strict.device_id);
+		return false;
+	}
+
+	let Some(subsystem) = pci::read_u32(pci_io, PCI_CFG_SUBSYSTEM_OFFSET) else {
+		error!("Strict match: failed to read subsystem IDs of {:04x}:{:02x}:{:02x}.{}!", seg, bus, dev, func);
+		return false;
+	};
+
+	let (subsystem_vendor, subsystem_device) = (subsystem as u16, (subsystem >> 16) as u16);
+
+	if subsystem_vendor != strict.subsystem_vendor || subsystem_device != strict.subsystem_device {
+		info!("Strict match: {:04x}:{:02x}:{:02x}.{} has subsystem {:04x}:{:04x}, expected {:04x}:{:04x}",
+			seg, bus, dev, func, subsystem_vendor, subsystem_device, strict.subsystem_vendor, strict.subsystem_device);
+		return false;
+	}
+
+	if location != strict.location {
+		let (eseg, ebus, edev, efunc) = strict.location;
+		info!("Strict match: device is at {:04x}:{:02x}:{:02x}.{}, expected {:04x}:{:02x}:{:02x}.{}",
+			seg, bus, dev, func, eseg, ebus, edev, efunc);
+		return false;
+	}
+
+	true
+}
+
+// Make the IGD report the subsystem IDs from tag 0x002B, for Windows drivers refusing OEM SKUs with the
+// wrong ones. SVID and SID form one write-once register, written as a single dword: if the host BIOS
+// or QEMU already locked it, only the readback shows the write didn't take.
+fn spoof_subsystem<P: PciConfigAccess>(pci_io: &mut P, (vendor, device): (u16, u16)) {
+	let ids = vendor as u32 | (device as u32) << 16;
+
+	if pci::read_u32(pci_io, PCI_CFG_SUBSYSTEM_OFFSET) == Some(ids) {
+		debug!("Subsystem IDs already are {:04x}:{:04x}", vendor, device);
+		return;
+	}
+
+	if pci::write_u32(pci_io, PCI_CFG_SUBSYSTEM_OFFSET, ids).is_none() {
+		error!("Failed to write the subsystem IDs!");
+		status_code::report(Failure::PciWrite);
+		return;
+	}
+
+	match pci::read_u32(pci_io, PCI_CFG_SUBSYSTEM_OFFSET) {
+		Some(now) if now == ids => info!("Subsystem IDs set to {:04x}:{:04x}", vendor, device),
+		now => warn!("Subsystem IDs read back {:08x?} instead of {:08x}, the register is locked", now, ids),
+	}
+}
+
+// the device ID if this is an Intel display controller we should set up
+pub fn match_device<P: PciConfigAccess>(pci_io: &mut P) -> Option<u16> {
+	// read vendor and device ID in one go, so a stale vendor ID can't be paired with a vanished function
+	let Some(ids) = pci::read_u32(pci_io, PCI_CFG_VENDOR_OFFSET) else {
+		error!("Failed to read PCI IDs, skipping device!");
+		return None;
+	};
+
+	let vendor = ids as u16;
+	let device = (ids >> 16) as u16;
+
+	if vendor == PCI_ID_NONE || device == PCI_ID_NONE {
+		debug!("Skipping non-existent function {:04x}:{:04x}", vendor, device);
+		return None;
+	}
+
+	if vendor != PCI_VENDOR_INTEL {
+		return None;
+	}
+
+	let Some(class) = pci::read_class(pci_io) else {
+		error!("Failed to read class code of {:04x}:{:04x}, skipping device!", vendor, device);
+		return None;
+	};
+
+	if class != IGD_CLASS {
+		return None;
+	}
+
+	Some(device)
+}
+
+// The OpRegion always goes first, the guest driver expects it to be valid before it touches stolen
+// memory. `step` only runs once `opregion` is set up, or regardless of it unless `requires_opregion`.
+fn after_opregion<P, T>(pci_io: &mut P, opregion: &Result<Region, Status>, requires_opregion: bool, step: impl FnOnce(&mut P) -> T) -> Option<T> {
+	if opregion.is_err() && requires_opregion {
+		error!("Skipping stolen memory setup as OpRegion setup failed!");
+		return None;
+	}
+
+	Some(step(pci_io))
+}
+
+// returns false if the device isn't an Intel display controller and was left untouched
+pub unsafe fn configure_device(pci_io: &mut ScopedProtocol<PciIo>, igd: Location) -> bool {
+	let Some(device) = match_device(pci_io) else {
+		return false;
+	};
+
+	let Ok(location) = pci_io.get_location() else {
+		error!("Failed to get the location of device {:04x}, skipping it!", device);
+		return false;
+	};
+
+	let (seg, bus, dev, func) = location;
+	info!("Found device {:04x} on segment {:04x}, bus {:02x}, device {:02x}, function {}", device, seg, bus, dev, func);
+
+	if let Some(strict) = CONFIG.strict_match && !strict_match(pci_io, &strict, device, location) {
+		return false;
+	}
+
+	if let Some(entries) = &ALLOW_LIST && !allow_list::allows(entries, PCI_VENDOR_INTEL, device, location) {
+		info!("Device {:04x} at {:04x}:{:02x}:{:02x}.{} is not in {}, leaving it alone", device, seg, bus, dev, func, ALLOW_LIST_FILE);
+		return false;
+	}
+
+	let start = timestamp::now();
+	let mut result = DeviceOutcome::new(location, device);
+
+	// the encodings of the older generations are compiled out
+	#[cfg(not(feature = "legacy"))]
+	if let Some(generation) = result.generation && generation.is_legacy() {
+		error!("Device {:04x} is {:?}, which this build doesn't support!", device, generation);
+		return false;
+	}
+
+	match result.generation {
+		Some(generation) => info!("Device {:04x} is {:?}", device, generation),
+		None if uses_dual_bdsm(None) => warn!("Device {:04x} has an unknown generation, programming both BDSM locations", device),
+		None => warn!("Device {:04x} has an unknown generation, assuming the pre-Gen11 layout", device),
+	}
+
+	// DG1, Arc and the like bring their own memory, but may still want the OpRegion for display bring-up
+	let discrete = Generation::is_discrete(device);
+	// a GVT-g vGPU and an SR-IOV VF share the host's or the PF's stolen memory, which isn't ours to program
+	let mediated = !discrete && is_mediated(pci_io);
+	let vf = !discrete && !mediated && is_virtual_function(pci_io, result.generation);
+
+	let opregion = timestamp::timed("OpRegion setup", || install_opregion(pci_io));
+	let status = opregion.err().unwrap_or(Status::SUCCESS);
+	OUTCOME.record_opregion(status);
+	result.opregion = opregion.ok();
+	result.opregion_status = Some(status);
+
+	if discrete {
+		info!("Device {:04x} is a discrete GPU without stolen memory, only setting up the OpRegion", device);
+	} else if mediated {
+		info!("Device {:04x} is a GVT-g vGPU, only setting up the OpRegion", device);
+	} else if vf {
+		info!("Device {:04x} is an SR-IOV virtual function, only setting up the OpRegion", device);
+	} else if location == igd {
+		if CONFIG.skip_stolen_memory {
+			info!("Skipping stolen memory setup as configured");
+		} else if let Some(stolen_memory) = after_opregion(pci_io, &opregion, CONFIG.stolen_memory_requires_opregion, |pci_io| {
+			// works around firmware dropping a BDSM write that immediately follows the ASLS write
+			if CONFIG.inter_step_delay_us > 0 {
+				info!("Waiting {} us before stolen memory setup", CONFIG.inter_step_delay_us);
+				boot::stall(CONFIG.inter_step_delay_us as usize);
+			}
+
+			timestamp::timed("Stolen memory setup", || install_stolen_memory(pci_io, result.generation, device))
+		}) {
+			let status = stolen_memory.err().unwrap_or(Status::SUCCESS);
+			OUTCOME.record_stolen_memory(status);
+			(result.stolen_memory, result.gtt_stolen_memory) = stolen_memory.map_or((None, None), |(dsm, gsm)| (Some(dsm), gsm));
+			result.stolen_memory_status = Some(status);
+		}
+
+		if let Some(ids) = CONFIG.lpc_ids {
+			lpc::program(location.0, &ids);
+		}
+
+		if let Some(ids) = CONFIG.subsystem_ids {
+			spoof_subsystem(pci_io, ids);
+		}
+	} else {
+		let (seg, bus, dev, func) = igd;
+		info!("Not the IGD at {:04x}:{:02x}:{:02x}.{}, only setting up the OpRegion", seg, bus, dev, func);
+	}
+
+	if result.opregion.is_some() || result.stolen_memory.is_some() {
+		s3::save(pci_io, &result);
+
+		if location == igd {
+			handoff::watch(pci_io, &result);
+		}
+	}
+
+	// some installers probe the device before enabling decoding themselves, and give up on it
+	if CONFIG.enable_decode {
+		match pci::enable_decode(pci_io) {
+			Some((before, after)) if after & pci::PCI_COMMAND_ENABLE == pci::PCI_COMMAND_ENABLE => {
+				info!("PCI command register {:#06x} -> {:#06x}", before, after);
+			}
+			Some((_, after)) => warn!("PCI command register reads back {:#06x}, not all decode bits stuck", after),
+			None => error!("Failed to enable decoding in the PCI command register!"),
+		}
+	}
+
+	if CONFIG.vga_decode && location == igd {
+		vga::route(pci_io, location);
+	}
+
+	// what the OS will see, a range that isn't reserved in the end is what guest crashes come down to
+	if let Some(opregion) = result.opregion {
+		result.opregion_reserved = verify_reservation("OpRegion", opregion, CONFIG.opregion_memory_type);
+	}
+
+	if let Some(stolen_memory) = result.stolen_memory {
+		result.stolen_memory_reserved = verify_reservation("StolenMemory", stolen_memory, CONFIG.stolen_memory_memory_type);
+	}
+
+	// reported together with the data stolen memory, it only counts as reserved if both are
+	if let Some(gtt) = result.gtt_stolen_memory {
+		let reserved = verify_reservation("GTT StolenMemory", gtt, CONFIG.stolen_memory_memory_type);
+		result.stolen_memory_reserved = match (result.stolen_memory_reserved, reserved) {
+			(Some(dsm), Some(gsm)) => Some(dsm && gsm),
+			(dsm, gsm) => dsm.or(gsm),
+		};
+	}
+
+	if result.stolen_memory.is_some() {
+		result.ggc = pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET);
+	}
+
+	if CONFIG.mch_mirror && let Some(stolen_memory) = result.stolen_memory {
+		mirror_to_mch(stolen_memory);
+	}
+
+	result.setup_us = start.and_then(|s| s.elapsed_us());
+	OUTCOME.devices.push(result);
+
+	true
+}
+
+// write ASLS and BDSM again with what `d` records, for when another driver clobbered them
+pub(crate) fn reprogram(pci_io: &mut ScopedProtocol<PciIo>, d: &DeviceOutcome) -> Status {
+	if let Some(opregion) = d.opregion && write_checked(pci_io, "ASLS", PCI_CFG_ASLS_OFFSET, opregion.base as u32, !0).is_none() {
+		return Status::DEVICE_ERROR;
+	}
+
+	if let Some(stolen_memory) = d.stolen_memory {
+		if write_bdsm(pci_io, d.generation, stolen_memory.base).is_none() {
+			return Status::DEVICE_ERROR;
+		}
+
+		write_bdsm_mmio(pci_io, d.generation, stolen_memory.base);
+	}
+
+	info!("Reprogrammed ASLS and BDSM on request");
+	Status::SUCCESS
+}
+
+// make the per-device results available to whoever is interested
+pub unsafe fn publish_results() {
+	table::publish(&OUTCOME.devices);
+
+	// only the IGD itself, the variables have no room for several devices
+	if let Some(igd) = OUTCOME.devices.iter().rev().find(|d| d.location == igd_location()) {
+		variables::write(igd, OUTCOME.status(CONFIG.allow_partial));
+
+		if let Some(stolen_memory) = igd.stolen_memory {
+			dmar::reserve(igd.location, stolen_memory);
+		}
+
+		if CONFIG.ssdt {
+			match igd.opregion {
+				Some(opregion) => ssdt::install(igd.location, opregion),
+				None => info!("No OpRegion on the IGD, not installing an SSDT"),
+			}
+		}
+	}
+
+	if CONFIG.write_result {
+		report::write(&OUTCOME.devices);
+	}
+
+	status_record::write(&OUTCOME.devices, OUTCOME.status(CONFIG.allow_partial), CONFIG.status_serial);
+	summary::log(&OUTCOME.devices);
+}
+
+// configure only the device at `location`, bypassing the notify-driven scan
+pub unsafe fn configure_location(location: Location) -> Status {
+	let (seg, bus, dev, func) = location;
+
+	let handles = match boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()) {
+		Ok(h) => h,
+		Err(e) => {
+			error!("Failed to obtain PCI_IO handle buffer: {:?}!", e.status());
+			return e.status();
+		}
+	};
+
+	for handle in handles.iter() {
+		let Some((mut pci_io, access)) = pci::open_device(*handle) else {
+			continue;
+		};
+
+		if !pci_io.get_location().is_ok_and(|l| l == location) {
+			continue;
+		}
+
+		info!("Configuring {:04x}:{:02x}:{:02x}.{} with {:?} PciIo access", seg, bus, dev, func, access);
+
+		if !configure_device(&mut pci_io, location) {
+			error!("{:04x}:{:02x}:{:02x}.{} is not an Intel display controller!", seg, bus, dev, func);
+			return Status::UNSUPPORTED;
+		}
+
+		publish_results();
+
+		return OUTCOME.status(CONFIG.allow_partial);
+	}
+
+	error!("No PCI device found at {:04x}:{:02x}:{:02x}.{}!", seg, bus, dev, func);
+	Status::NOT_FOUND
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec;
+
+	use super::*;
+	use crate::{fw_cfg::MemoryFwCfg, pci::ConfigSpace};
+
+	const MIB: usize = 1024 * 1024;
+
+	// config space of a function with the given IDs and class code register bytes (0x09-0x0B)
+	fn function(vendor: u16, device: u16, class: [u8; 3]) -> ConfigSpace {
+		let mut space = ConfigSpace([0; 256]);
+		space.0[0..2].copy_from_slice(&vendor.to_le_bytes());
+		space.0[2..4].copy_from_slice(&device.to_le_bytes());
+		space.0[9..12].copy_from_slice(&class);
+		space
+	}
+
+	// Config space that remembers the register of every write, in order. A write split into byte
+	// accesses still counts as one, whichever access strategy the probe settled on.
+	struct Recording {
+		space: ConfigSpace,
+		writes: Vec<u32>,
+	}
+
+	impl Recording {
+		fn new(space: ConfigSpace) -> Self {
+			Recording { space, writes: Vec::new() }
+		}
+	}
+
+	impl PciConfigAccess for Recording {
+		fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+			self.space.config_read(width, offset, count, buf)
+		}
+
+		fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()> {
+			if self.writes.last() != Some(&(offset & !3)) {
+				self.writes.push(offset & !3);
+			}
+
+			self.space.config_write(offset, buf)
+		}
+	}
+
+	// config space whose reads touching `[start, end)` fail, as PciIo reports an error
+	struct Failing {
+		space: ConfigSpace,
+		start: u32,
+		end: u32,
+	}
+
+	impl PciConfigAccess for Failing {
+		fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+			if offset < self.end && offset + width * count as u32 > self.start {
+				return None;
+			}
+
+			self.space.config_read(width, offset, count, buf)
+		}
+
+		fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()> {
+			self.space.config_write(offset, buf)
+		}
+	}
+
+	fn u32_at(space: &ConfigSpace, offset: u32) -> u32 {
+		let offset = offset as usize;
+		u32::from_le_bytes(space.0[offset..offset + 4].try_into().unwrap())
+	}
+
+	#[test]
+	fn matches_intel_vga_controller() {
+		assert_eq!(match_device(&mut function(0x8086, 0x3E92, [0x00, 0x00, 0x03])), Some(0x3E92));
+	}
+
+	#[test]
+	fn rejects_other_functions() {
+		// another vendor's VGA controller
+		assert_eq!(match_device(&mut function(0x1002, 0x3E92, [0x00, 0x00, 0x03])), None);
+		// Intel display controllers that aren't VGA compatible
+		assert_eq!(match_device(&mut function(0x8086, 0x3E92, [0x00, 0x80, 0x03])), None);
+		assert_eq!(match_device(&mut function(0x8086, 0x3E92, [0x01, 0x00, 0x03])), None);
+		// an Intel host bridge
+		assert_eq!(match_device(&mut function(0x8086, 0x3E0F, [0x00, 0x00, 0x06])), None);
+		// no function there
+		assert_eq!(match_device(&mut function(0xFFFF, 0xFFFF, [0xFF, 0xFF, 0xFF])), None);
+		assert_eq!(match_device(&mut function(0x8086, 0xFFFF, [0x00, 0x00, 0x03])), None);
+	}
+
+	#[test]
+	fn failed_id_read_skips_device() {
+		let mut pci_io = Failing { space: function(0x8086, 0x3E92, [0x00, 0x00, 0x03]), start: 0x02, end: 0x04 };
+		assert_eq!(match_device(&mut pci_io), None);
+	}
+
+	#[test]
+	fn failed_class_read_skips_device() {
+		// the IDs alone would match
+		let mut pci_io = Failing { space: function(0x8086, 0x3E92, [0x00, 0x00, 0x03]), start: 0x0B, end: 0x0C };
+		assert_eq!(match_device(&mut pci_io), None);
+	}
+
+	#[test]
+	fn bdsm_size_from_file() {
+		let size = (64 * MIB as u64).to_le_bytes();
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("etc/igd-bdsm-size", &size[..])]), None), Ok(Some(64 * MIB)));
+
+		// older QEMU releases
+		let size = (32 * MIB as u32).to_le_bytes();
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("opt/igd-bdsm-size", &size[..])]), None), Ok(Some(32 * MIB)));
+	}
+
+	#[test]
+	fn bdsm_size_rejects_malformed_files() {
+		let short = [0u8; 3];
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("etc/igd-bdsm-size", &short[..])]), None), Err(Status::INVALID_PARAMETER));
+
+		let unaligned = (MIB as u64 + 1).to_le_bytes();
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("etc/igd-bdsm-size", &unaligned[..])]), None), Err(Status::INVALID_PARAMETER));
+
+		let zero = 0u64.to_le_bytes();
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("etc/igd-bdsm-size", &zero[..])]), None), Err(Status::INVALID_PARAMETER));
+	}
+
+	#[test]
+	fn bdsm_size_from_host_ggc() {
+		let gms = Some(Gms::Generation(Generation::Gen9));
+		let ggc = 0x0200u16.to_le_bytes();
+
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("etc/igd-ggc", &ggc[..])]), gms), Ok(Some(64 * MIB)));
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[("etc/igd-ggc", &ggc[..])]), None), Err(Status::UNSUPPORTED));
+		assert_eq!(read_bdsm_size(&mut MemoryFwCfg(&[]), gms), Ok(None));
+	}
+
+	#[test]
+	fn bdsm64_write_sequence() {
+		let mut pci_io = Recording::new(function(0x8086, 0x8A52, [0x00, 0x00, 0x03]));
+
+		assert_eq!(write_bdsm(&mut pci_io, Some(Generation::Gen11), 0x1_2340_0000), Some(()));
+		// low dword first, each read back before the next one
+		assert_eq!(pci_io.writes, vec![PCI_CFG_BDSM64_OFFSET, PCI_CFG_BDSM64_OFFSET + 4]);
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_BDSM64_OFFSET), 0x2340_0000);
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_BDSM64_OFFSET + 4), 0x1);
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_BDSM_MIRROR_OFFSET), 0);
+		assert_eq!(read_bdsm(&mut pci_io, Some(Generation::Gen11)), Some(0x1_2340_0000));
+	}
+
+	#[test]
+	fn bdsm32_write_sequence() {
+		let mut pci_io = Recording::new(function(0x8086, 0x3E92, [0x00, 0x00, 0x03]));
+
+		assert_eq!(write_bdsm(&mut pci_io, Some(Generation::Gen9), 0x7C00_0000), Some(()));
+		assert_eq!(pci_io.writes, vec![PCI_CFG_BDSM_MIRROR_OFFSET]);
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_BDSM_MIRROR_OFFSET), 0x7C00_0000);
+		assert_eq!(read_bdsm(&mut pci_io, Some(Generation::Gen9)), Some(0x7C00_0000));
+	}
+
+	#[test]
+	fn write_checked_reads_back() {
+		let mut pci_io = Recording::new(ConfigSpace([0; 256]));
+
+		assert_eq!(write_checked(&mut pci_io, "ASLS", PCI_CFG_ASLS_OFFSET, 0x7E12_3000, !0), Some(()));
+		assert_eq!(pci_io.writes, vec![PCI_CFG_ASLS_OFFSET]);
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_ASLS_OFFSET), 0x7E12_3000);
+	}
+
+	#[test]
+	fn failed_validation_leaves_asls() {
+		let mut pci_io = Recording::new(function(0x8086, 0x3E92, [0x00, 0x00, 0x03]));
+		pci_io.space.0[0xFC..0x100].copy_from_slice(&0x7E00_0000u32.to_le_bytes());
+
+		// zeroes have neither the signature nor a size
+		let blank = vec![0u8; 8192];
+		let loaded = opregion::validate_header(&blank, blank.len(), &[*opregion::SIGNATURE])
+			.map(|_| ())
+			.map_err(|_| Status::VOLUME_CORRUPTED);
+
+		assert_eq!(point_asls(&mut pci_io, 0x7F00_0000, loaded), Err(Status::VOLUME_CORRUPTED));
+		assert!(pci_io.writes.is_empty());
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_ASLS_OFFSET), 0x7E00_0000);
+	}
+
+	#[test]
+	fn validated_opregion_sets_asls() {
+		let mut pci_io = Recording::new(function(0x8086, 0x3E92, [0x00, 0x00, 0x03]));
+
+		assert_eq!(point_asls(&mut pci_io, 0x7F00_0000, Ok(())), Ok(()));
+		assert_eq!(u32_at(&pci_io.space, PCI_CFG_ASLS_OFFSET), 0x7F00_0000);
+	}
+
+	#[test]
+	fn opregion_read_covers_the_file() {
+		let data = (0..100).collect::<Vec<u8>>();
+		let mut fw_cfg = MemoryFwCfg(&[("etc/igd-opregion", &data[..])]);
+		let file = fw_cfg.find_file("etc/igd-opregion").unwrap();
+
+		let mut buf = vec![0xFFu8; PAGE_SIZE];
+		read_file_exact(&mut fw_cfg, &file, &mut buf);
+
+		assert_eq!(buf[..100], data[..]);
+		// reading into all of the buffer would have overwritten this with the zeroes past the end of the file
+		assert!(buf[100..].iter().all(|&b| b == 0xFF));
+	}
+
+	// the register writes of stolen memory setup, in the order `install_stolen_memory` does them
+	fn program_stolen_memory<P: PciConfigAccess>(pci_io: &mut P) -> Option<()> {
+		write_bdsm(pci_io, Some(Generation::Gen9), 0x7C00_0000)?;
+		write_gms(pci_io, Some(Gms::Generation(Generation::Gen9)), 64 * MIB);
+		Some(())
+	}
+
+	#[test]
+	fn opregion_before_stolen_memory() {
+		let mut pci_io = Recording::new(function(0x8086, 0x3E92, [0x00, 0x00, 0x03]));
+
+		let opregion = point_asls(&mut pci_io, 0x7F00_0000, Ok(())).map(|_| Region { base: 0x7F00_0000, size: 8192 });
+		assert_eq!(after_opregion(&mut pci_io, &opregion, true, program_stolen_memory), Some(Some(())));
+		assert_eq!(pci_io.writes, vec![PCI_CFG_ASLS_OFFSET, PCI_CFG_BDSM_MIRROR_OFFSET, PCI_CFG_GGC_OFFSET]);
+	}
+
+	#[test]
+	fn failed_opregion_blocks_stolen_memory() {
+		let mut pci_io = Recording::new(function(0x8086, 0x3E92, [0x00, 0x00, 0x03]));
+
+		let opregion = point_asls(&mut pci_io, 0x7F00_0000, Err(Status::VOLUME_CORRUPTED)).map(|_| Region { base: 0x7F00_0000, size: 8192 });
+		assert_eq!(after_opregion(&mut pci_io, &opregion, true, program_stolen_memory), None);
+		assert!(pci_io.writes.is_empty());
+
+		// unless stolen memory is configured not to depend on it
+		assert_eq!(after_opregion(&mut pci_io, &opregion, false, program_stolen_memory), Some(Some(())));
+		assert_eq!(pci_io.writes, vec![PCI_CFG_BDSM_MIRROR_OFFSET, PCI_CFG_GGC_OFFSET]);
+	}
+
+	#[test]
+	fn gms_write_preserves_ggc() {
+		let mut pci_io = ConfigSpace([0; 256]);
+		// GGMS and the low control bits are left alone
+		pci_io.0[0x50..0x52].copy_from_slice(&0x01C2u16.to_le_bytes());
+
+		write_gms(&mut pci_io, Some(Gms::Generation(Generation::Gen9)), 64 * MIB);
+		assert_eq!(pci::read_u16(&mut pci_io, PCI_CFG_GGC_OFFSET), Some(0x02C2));
+	}
+}
@@ -0,0 +1,157 @@
+// Logger writing to the UEFI console and, if QEMU has an isa-debugcon at the port OVMF uses, to that
+// as well. The debug console works before ConOut is usable and from the notify at any TPL, so the
+// log survives wherever the console output doesn't. Optionally, the log also goes to EFI Serial I/O.
+// From the ExitBootServices callback, where no protocol may be used, only the debug console and
+// COM1 are written.
+
+use core::{fmt::{self, Write}, sync::atomic::{AtomicBool, Ordering}};
+use igd_assignment::{handoff, CONFIG};
+use log::{LevelFilter, Log, Metadata, Record};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::console::serial::Serial};
+
+// `-debugcon file:debug.log -global isa-debugcon.iobase=0x402`
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const DEBUGCON: u16 = 0x402;
+// isa-debugcon returns its readback value, 0xE9 by default, while an unused port reads as 0xFF
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const DEBUGCON_READBACK: u8 = 0xE9;
+
+// the 16550 OVMF drives its serial console with
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const COM1: u16 = 0x3F8;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const COM1_LSR: u16 = COM1 + 5;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const LSR_THR_EMPTY: u8 = 0x20;
+
+static HAS_DEBUGCON: AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn probe_debugcon() -> bool {
+	let value: u8;
+	unsafe { core::arch::asm!("in al, dx", in("dx") DEBUGCON, out("al") value, options(nomem, nostack, preserves_flags)) };
+	value == DEBUGCON_READBACK
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+fn probe_debugcon() -> bool {
+	false
+}
+
+struct Debugcon;
+
+impl Write for Debugcon {
+	#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for b in s.bytes() {
+			unsafe { core::arch::asm!("out dx, al", in("dx") DEBUGCON, in("al") b, options(nomem, nostack, preserves_flags)) };
+		}
+
+		Ok(())
+	}
+
+	#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+	fn write_str(&mut self, _s: &str) -> fmt::Result {
+		Ok(())
+	}
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn write_com1(data: &[u8]) {
+	for &b in data {
+		loop {
+			let lsr: u8;
+			unsafe { core::arch::asm!("in al, dx", in("dx") COM1_LSR, out("al") lsr, options(nomem, nostack, preserves_flags)) };
+
+			if lsr & LSR_THR_EMPTY != 0 {
+				break;
+			}
+		}
+
+		unsafe { core::arch::asm!("out dx, al", in("dx") COM1, in("al") b, options(nomem, nostack, preserves_flags)) };
+	}
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+fn write_com1(_data: &[u8]) {}
+
+// opened non-exclusively for every write, so the terminal driver on top of it stays connected
+fn write_serial(data: &[u8]) {
+	let Ok(handle) = boot::get_handle_for_protocol::<Serial>() else {
+		return;
+	};
+
+	let params = OpenProtocolParams { handle, agent: boot::image_handle(), controller: None };
+	let Ok(mut serial) = (unsafe { boot::open_protocol::<Serial>(params, OpenProtocolAttributes::GetProtocol) }) else {
+		return;
+	};
+
+	let _ = serial.write(data);
+}
+
+// formats into a fixed buffer, Serial I/O wants the whole line at once and there may be no allocator
+struct LineBuffer {
+	buf: [u8; 256],
+	len: usize,
+}
+
+impl Write for LineBuffer {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		// longer lines are cut off, leaving room for the line ending
+		let n = s.len().min(self.buf.len() - 2 - self.len);
+		self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+		self.len += n;
+
+		Ok(())
+	}
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+	fn enabled(&self, _metadata: &Metadata) -> bool {
+		true
+	}
+
+	fn log(&self, record: &Record) {
+		let file = record.file().unwrap_or("<unknown>");
+		let line = record.line().unwrap_or(0);
+		let exiting = handoff::EXITING.load(Ordering::Acquire);
+
+		igd_assignment::trace::record(record.level(), file, line, record.args());
+
+		if HAS_DEBUGCON.load(Ordering::Relaxed) {
+			let _ = writeln!(Debugcon, "[{:>5}]: {:>12}@{:03}: {}\r", record.level(), file, line, record.args());
+		}
+
+		if unsafe { CONFIG.serial_log } {
+			let mut buffer = LineBuffer { buf: [0; 256], len: 0 };
+			let _ = write!(buffer, "[{:>5}]: {:>12}@{:03}: {}", record.level(), file, line, record.args());
+			buffer.buf[buffer.len..buffer.len + 2].copy_from_slice(b"\r\n");
+
+			if exiting {
+				write_com1(&buffer.buf[..buffer.len + 2]);
+			} else {
+				write_serial(&buffer.buf[..buffer.len + 2]);
+			}
+		}
+
+		if !exiting {
+			uefi::system::with_stdout(|stdout| {
+				let _ = writeln!(stdout, "[{:>5}]: {:>12}@{:03}: {}", record.level(), file, line, record.args());
+			});
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+pub fn init() {
+	HAS_DEBUGCON.store(probe_debugcon(), Ordering::Relaxed);
+
+	if log::set_logger(&LOGGER).is_ok() {
+		log::set_max_level(LevelFilter::Info);
+	}
+}
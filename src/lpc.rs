@@ -0,0 +1,56 @@
+// LPC bridge quirk: pre-Gen11 Windows drivers only bind if the ISA bridge at 00:1f.0 carries the IDs
+// of an Intel LPC bridge matching the IGD, which QEMU's x-igd-lpc arranges by copying them from the
+// host. This does the same from the firmware, with the IDs passed in the configuration.
+
+use log::{error, info, warn};
+
+use crate::{config::LpcIds, pci::{self, ClassCode}, PCI_CFG_DEVICE_OFFSET, PCI_CFG_SUBSYSTEM_OFFSET, PCI_CFG_VENDOR_OFFSET};
+
+const PCI_CFG_REVISION_OFFSET: u32 = 0x08;
+const PCI_CFG_SUBSYSTEM_DEVICE_OFFSET: u32 = 0x2E;
+
+const PCI_CLASS_BRIDGE: u8 = 0x06;
+const PCI_SUBCLASS_ISA: u8 = 0x01;
+
+// the ISA bridge is function 0 of device 0x1f on the IGD's segment
+pub fn program(seg: usize, ids: &LpcIds) {
+	let location = (seg, 0, 0x1f, 0);
+
+	let Some(mut pci_io) = pci::open_shared(location) else {
+		error!("LPC quirk: no device at {:04x}:00:1f.0!", seg);
+		return;
+	};
+
+	match pci::read_class(&mut pci_io) {
+		Some(ClassCode { base: PCI_CLASS_BRIDGE, sub: PCI_SUBCLASS_ISA, .. }) => {}
+		Some(class) => {
+			error!("LPC quirk: {:04x}:00:1f.0 is not an ISA bridge ({:02x}{:02x}), not touching it!", seg, class.base, class.sub);
+			return;
+		}
+		None => {
+			error!("LPC quirk: failed to read the class code of {:04x}:00:1f.0!", seg);
+			return;
+		}
+	}
+
+	let written = pci::write_u16(&mut pci_io, PCI_CFG_VENDOR_OFFSET, ids.vendor)
+		.and_then(|_| pci::write_u16(&mut pci_io, PCI_CFG_DEVICE_OFFSET, ids.device))
+		.and_then(|_| pci::write_u8(&mut pci_io, PCI_CFG_REVISION_OFFSET, ids.revision))
+		.and_then(|_| pci::write_u16(&mut pci_io, PCI_CFG_SUBSYSTEM_OFFSET, ids.subsystem_vendor))
+		.and_then(|_| pci::write_u16(&mut pci_io, PCI_CFG_SUBSYSTEM_DEVICE_OFFSET, ids.subsystem_device));
+
+	if written.is_none() {
+		error!("LPC quirk: failed to write the IDs of {:04x}:00:1f.0!", seg);
+		return;
+	}
+
+	// the ID registers are read-only on most emulated bridges, which only the readback shows
+	let ids_now = pci::read_u32(&mut pci_io, PCI_CFG_VENDOR_OFFSET);
+	if ids_now != Some(ids.vendor as u32 | ((ids.device as u32) << 16)) {
+		warn!("LPC quirk: {:04x}:00:1f.0 reads back {:08x?}, its ID registers are read-only", seg, ids_now);
+		return;
+	}
+
+	info!("LPC quirk: {:04x}:00:1f.0 now identifies as {:04x}:{:04x} rev {:02x}, subsystem {:04x}:{:04x}", seg,
+		ids.vendor, ids.device, ids.revision, ids.subsystem_vendor, ids.subsystem_device);
+}
@@ -1,202 +1,198 @@
 #![no_main]
 #![no_std]
-#![feature(strict_provenance, let_chains)]
 
 #![deny(warnings)]
-#![allow(clippy::identity_op)]
 
-use core::{ffi::c_void, ptr::NonNull};
-use log::{info, error};
-use qemu_fw_cfg::FwCfg;
-use uefi::{boot::{self, EventType, MemoryType, ScopedProtocol, SearchType}, prelude::*, proto::pci::PciIo, Event};
-use zeroize::Zeroize;
+mod binding;
+mod chainload;
+mod halt;
+mod hii;
+mod logger;
+mod marker;
+mod notify;
+mod panic;
+mod pvpanic;
 
-const PAGE_SIZE: usize = 0x1000;
-// Stolen Memory should be aligned to 1 MiB
-const STOLEN_MEMORY_ALIGNMENT: usize = 0x100000;
-// number of pages we should overallocate in order to ensure alignment
-const STOLEN_MEMORY_OVERALLOCATION: usize = (STOLEN_MEMORY_ALIGNMENT / PAGE_SIZE) - 1;
+extern crate alloc;
 
-const PCI_VENDOR_INTEL: u16 = 0x8086;
-const PCI_CLASS_DISPLAY: u8 = 0x03;
-const PCI_SUBCLASS_VGA_COMPATIBLE: u8 = 0x00;
-const PCI_PROGIF_VGA_CONTROLLER: u8 = 0x00;
+use igd_assignment::{args, dmar, gop, handoff, oprom, protocol, scratch, set_igd_location, status_screen, CONFIG, OUTCOME};
+#[cfg(feature = "shell")]
+use igd_assignment::{audit, check, configure_location, plan, selftest};
+use log::{error, info, warn, LevelFilter};
+use uefi::prelude::*;
 
-const PCI_CFG_ASLS_OFFSET: u32 = 0xFC;
-const PCI_CFG_BDSM_MIRROR_OFFSET: u32 = 0x5C;
-
-static mut PCI_IO_KEY: Option<SearchType<'static>> = None;
-
-fn opregion_setup(pci_io: &mut ScopedProtocol<PciIo>) -> Status {
-	let mut fw_cfg = unsafe { FwCfg::new_for_x86().unwrap() };
-	let opregion = fw_cfg.find_file("etc/igd-opregion");
-
-	if opregion.is_none() {
-		error!("OpRegion not passed through!");
-		return Status::INVALID_PARAMETER;
-	}
-
-	let opregion = opregion.unwrap();
+#[entry]
+fn main(_image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
+	let _entry = panic::EntryGuard::enter();
 
-	if opregion.size() == 0 {
-		error!("OpRegion has zero size!");
-		return Status::INVALID_PARAMETER;
+	#[cfg(not(feature = "silent"))]
+	{
+		uefi::helpers::init().unwrap();
+		logger::init();
 	}
 
-	let pages = opregion.size().div_ceil(PAGE_SIZE);
-	let buf = boot::allocate_pages(boot::AllocateType::MaxAddress(0xFFFFFFFF), MemoryType::ACPI_NON_VOLATILE, pages).unwrap();
-	let buf_slice = unsafe {
-		core::slice::from_raw_parts_mut(buf.as_ptr(), pages * PAGE_SIZE)
-	};
-	buf_slice.zeroize();
-
-	fw_cfg.read_file_to_buffer(&opregion, buf_slice);
-
-	let addr: usize = buf.addr().into();
+	unsafe {
+		// the same image may ship in firmware that also boots outside QEMU, where there is nothing to do
+		if !igd_assignment::fw_cfg_present() {
+			info!("No fw_cfg device, not running under QEMU, nothing to set up");
+			return Status::SUCCESS;
+		}
 
-	pci_io.pci_write(4, PCI_CFG_ASLS_OFFSET, 1, &addr as *const usize as *mut c_void).unwrap();
+		let Some(mut fw_cfg) = igd_assignment::fw_cfg_open() else {
+			error!("fw_cfg is not available, nothing to set up!");
+			return Status::NOT_FOUND;
+		};
+		igd_assignment::init(&mut fw_cfg);
 
-	info!("OpRegion @ {:#x} ({} bytes)", addr, opregion.size());
+		let args = args::parse();
 
-	Status::SUCCESS
-}
-
-fn stolen_memory_setup(pci_io: &mut ScopedProtocol<PciIo>) -> Status {
-	let mut fw_cfg = unsafe { FwCfg::new_for_x86().unwrap() };
+		if args.verbose {
+			log::set_max_level(LevelFilter::Debug);
+		}
 
-	let bdsm = fw_cfg.find_file("etc/igd-bdsm-size");
-	if bdsm.is_none() {
-		error!("BDSM data not passed through!");
-		return Status::INVALID_PARAMETER;
-	}
+		if args.no_bdsm {
+			CONFIG.skip_stolen_memory = true;
+		}
 
-	let bdsm = bdsm.unwrap();
+		if let Some(location) = args.bdf {
+			set_igd_location(location);
+		}
 
-	let mut bdsm_buf: [u8; 8] = [0; 8];
-	fw_cfg.read_file_to_buffer(&bdsm, &mut bdsm_buf);
-	let bdsm_size = usize::from_le_bytes(bdsm_buf);
+		#[cfg(feature = "shell")]
+		{
+			if args.check {
+				return check::run();
+			}
 
-	if bdsm_size == 0 {
-		return Status::INVALID_PARAMETER;
-	}
+			if args.dry_run {
+				return plan::run();
+			}
 
-	if bdsm_size % PAGE_SIZE != 0 {
-		error!("BDSM size {} is not page-aligned!", bdsm_size);
-		return Status::INVALID_PARAMETER;
-	}
+			if args.verify {
+				return audit::run();
+			}
 
-	let pages = bdsm_size / PAGE_SIZE;
-	// we overallocate 1 MiB - 1 page to ensure our stolen memory range has proper alignment
-	let stolen_memory = boot::allocate_pages(boot::AllocateType::MaxAddress(0xFFFFFFFF),
-		MemoryType::ACPI_NON_VOLATILE, pages + STOLEN_MEMORY_OVERALLOCATION).unwrap();
+			if args.selftest {
+				return selftest::run();
+			}
+		}
 
-	unsafe {
-		core::slice::from_raw_parts_mut(stolen_memory.as_ptr(), pages * PAGE_SIZE).zeroize();
-	}
+		scratch::arm();
 
-	// the allocation for stolen memory needs to be aligned to 1 MiB
-	let alignment_needed = stolen_memory.align_offset(STOLEN_MEMORY_ALIGNMENT);
-	let unused_memory_end = (STOLEN_MEMORY_OVERALLOCATION * PAGE_SIZE) - alignment_needed;
-	let aligned_mem = unsafe { stolen_memory.add(alignment_needed) };
-	let addr: usize = aligned_mem.addr().into();
+		#[cfg(feature = "shell")]
+		if let Some(location) = args.configure {
+			let status = configure_location(location);
 
-	assert!(alignment_needed + unused_memory_end == (STOLEN_MEMORY_OVERALLOCATION * PAGE_SIZE));
+			if status.is_error() {
+				scratch::cancel();
+			} else {
+				oprom::load(&mut fw_cfg);
+				gop::load(&mut fw_cfg);
+			}
 
-	if alignment_needed > 0 {
-		unsafe {
-			boot::free_pages(stolen_memory, alignment_needed / PAGE_SIZE).unwrap();
+			show_status(&args, status);
+			return status;
 		}
-	}
 
-	if unused_memory_end > 0 {
-		unsafe {
-			// calculate the pointer to the leftover memory at the end
-			let overhang_ptr = stolen_memory.add(alignment_needed).add(pages * PAGE_SIZE);
-			boot::free_pages(overhang_ptr, unused_memory_end / PAGE_SIZE).unwrap();
+		// --configure is asked for explicitly and may run next to a resident copy, the automatic setup may not
+		if marker::present() {
+			info!("Another copy of the driver is already resident, exiting");
+			scratch::cancel();
+			return Status::ALREADY_STARTED;
 		}
-	}
 
-	pci_io.pci_write(4, PCI_CFG_BDSM_MIRROR_OFFSET, 1, &addr as *const usize as *mut c_void).unwrap();
+		marker::install();
 
-	info!("StolenMemory @ {:#x} ({} MiB)", addr, (pages * PAGE_SIZE) / 1024 / 1024);
+		if CONFIG.driver_binding {
+			let status = binding::install();
 
-	Status::SUCCESS
-}
-
-unsafe extern "efiapi" fn notify(_e: Event, _ctx: Option<NonNull<c_void>>) {
-	assert!(PCI_IO_KEY.is_some());
-
-	while let buf = boot::locate_handle_buffer(PCI_IO_KEY.unwrap()) && buf.is_ok() {
-		match buf {
-			Ok(d) => {
-				assert!(d.len() > 0);
-
-				let mut pci_io = boot::open_protocol_exclusive::<PciIo>(d[0]).unwrap();
-
-				let mut vendor: [u8; 2] = [0; 2];
-				pci_io.pci_read(2, 0, 1, &mut vendor).expect("PCI configuration space read failed");
-
-				if u16::from_le_bytes(vendor) != PCI_VENDOR_INTEL {
-					continue;
-				}
+			if status.is_error() {
+				marker::uninstall();
+				scratch::cancel();
+			} else {
+				hii::install();
+			}
 
-				let mut classes: [u8; 3] = [0; 3];
-				pci_io.pci_read(1, 9, 3, &mut classes).expect("PCI configuration space read failed");
+			return status;
+		}
 
-				if classes[2] != PCI_CLASS_DISPLAY || classes[1] != PCI_SUBCLASS_VGA_COMPATIBLE || classes[0] != PCI_PROGIF_VGA_CONTROLLER {
-					continue;
-				}
+		let status = if CONFIG.wait_for_enumeration { notify::register_enumeration() } else { notify::register() };
 
-				let _ = opregion_setup(&mut pci_io);
+		if status.is_error() {
+			marker::uninstall();
+			scratch::cancel();
+			return status;
+		}
 
-				let (seg, bus, dev, func) = pci_io.get_location().unwrap();
+		// the setup and its outcome come once the PCI bus driver is done
+		if notify::waiting_for_enumeration() {
+			info!("Waiting for PCI enumeration to complete");
+			hii::install();
+			return Status::SUCCESS;
+		}
 
-				if seg != 0 || bus != 0 || dev != 2 || func != 0 {
-					continue;
+		// the setup and its outcome come with ReadyToBoot
+		if CONFIG.defer_to_ready_to_boot {
+			if notify::deferred_count() == 0 && CONFIG.require_device {
+				if CONFIG.strict_failure {
+					halt::halt("No matching device was found", Status::NOT_FOUND);
 				}
 
-				let _ = stolen_memory_setup(&mut pci_io);
+				error!("No matching device was found!");
+				release_resident();
+				return Status::NOT_FOUND;
 			}
-			Err(_) => error!("Failed to obtain PCI_IO handle buffer"),
-		}
-	}
-}
-
-#[entry]
-fn main(_image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
-	uefi::helpers::init().unwrap();
 
-	unsafe {
-		let status = uefi::boot::create_event(EventType::NOTIFY_SIGNAL, uefi::boot::Tpl::CALLBACK, Some(notify), None);
-
-		if status.is_err() {
-			error!("create_event failed with status {:?}!", status.status());
-			return status.status();
+			info!("{} device(s) waiting for ReadyToBoot", notify::deferred_count());
+			hii::install();
+			return Status::SUCCESS;
 		}
 
-		let event = status.unwrap();
-
-		let status = uefi::boot::register_protocol_notify(
-			&uefi_raw::protocol::pci_io::PciIoProtocol::GUID, &event
-		);
+		if let Err(status) = halt::enforce_policy() {
+			// returning an error unloads the image
+			release_resident();
+			show_status(&args, status);
+			// the guest may still boot without the IGD, unless the failure policy said otherwise
+			return chainload::run(&mut fw_cfg, args.chainload, status);
+		}
 
-		if status.is_err() {
-			error!("register_protocol_notify failed with status {:?}!", status.status());
-			let _ = uefi::boot::close_event(event);
-			return status.status();
+		if !OUTCOME.attempted() {
+			// devices showing up later are still handled by the notify
+			info!("No matching device found yet");
+			hii::install();
+			show_status(&args, Status::SUCCESS);
+			return chainload::run(&mut fw_cfg, args.chainload, Status::SUCCESS);
 		}
 
-		PCI_IO_KEY = Some(status.unwrap());
+		if OUTCOME.stolen_memory.is_some_and(|s| s.is_error()) {
+			warn!("Stolen memory setup failed, continuing with OpRegion only");
+		}
 
-		// HACK: kick the event to handle existing PCI_IO protocol instances
-		let status = uefi::boot::signal_event(&event);
+		oprom::load(&mut fw_cfg);
+		gop::load(&mut fw_cfg);
+		hii::install();
+		show_status(&args, Status::SUCCESS);
 
-		if status.is_err() {
-			error!("signal_event failed with status {:?}!", status.status());
-			let _ = uefi::boot::close_event(event);
-			return status.status();
-		}
+		chainload::run(&mut fw_cfg, args.chainload, Status::SUCCESS)
 	}
+}
 
-	Status::SUCCESS
+// Drop everything that would call into the image or point at it once it is unloaded, for the error
+// returns of the entry point and a panic in it. Safe to call whatever was set up so far.
+pub(crate) unsafe fn release_resident() {
+	notify::teardown();
+	notify::teardown_deferred();
+	notify::teardown_enumeration();
+	protocol::uninstall_all();
+	dmar::cancel();
+	handoff::cancel();
+	marker::uninstall();
+	scratch::cancel();
+}
+
+// the results stay on screen when started from the shell, where the log scrolls past
+fn show_status(args: &args::Args, status: Status) {
+	if args.interactive {
+		unsafe { status_screen::show(&OUTCOME.devices, status) };
+	}
 }
@@ -0,0 +1,24 @@
+// A NULL protocol on our image handle, telling a second copy of the driver that one is already
+// resident, e.g. when it is both in the firmware volume and on the ESP. The second copy exits before
+// touching any device.
+
+use core::ptr;
+use log::error;
+use uefi::{boot::{self, SearchType}, guid, Guid};
+
+const MARKER_GUID: Guid = guid!("3c6d1e52-9a47-4b8f-b2e1-6f0d4c8a7e19");
+
+pub(crate) fn present() -> bool {
+	boot::locate_handle_buffer(SearchType::ByProtocol(&MARKER_GUID)).is_ok_and(|handles| !handles.is_empty())
+}
+
+pub(crate) fn install() {
+	if let Err(e) = unsafe { boot::install_protocol_interface(Some(boot::image_handle()), &MARKER_GUID, ptr::null()) } {
+		error!("Failed to install the marker protocol: {:?}!", e.status());
+	}
+}
+
+// the marker must not outlive the image, or no copy would run again until the next boot
+pub(crate) fn uninstall() {
+	let _ = unsafe { boot::uninstall_protocol_interface(boot::image_handle(), &MARKER_GUID, ptr::null()) };
+}
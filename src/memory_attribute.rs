@@ -0,0 +1,57 @@
+// Write protection of the OpRegion once it is populated, through EFI_MEMORY_ATTRIBUTE_PROTOCOL, so a
+// DXE driver or option ROM scribbling over it faults instead of silently handing the guest a corrupt
+// copy. Only the page tables of the firmware are affected, the OS maps the memory as it likes.
+
+use log::{debug, info, warn};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::unsafe_protocol, Status};
+
+use crate::{outcome::Region, PAGE_SIZE};
+
+const EFI_MEMORY_RO: u64 = 0x0000_0000_0002_0000;
+
+#[repr(C)]
+struct MemoryAttributeProtocol {
+	get_memory_attributes: unsafe extern "efiapi" fn(*const MemoryAttributeProtocol, u64, u64, *mut u64) -> Status,
+	set_memory_attributes: unsafe extern "efiapi" fn(*const MemoryAttributeProtocol, u64, u64, u64) -> Status,
+	// followed by ClearMemoryAttributes, which isn't used
+}
+
+#[unsafe_protocol("f4560cf6-40ec-4b4a-a192-bf1d57d0b189")]
+struct MemoryAttribute(MemoryAttributeProtocol);
+
+fn set_read_only(region: Region) -> Result<(), Status> {
+	let handle = boot::get_handle_for_protocol::<MemoryAttribute>().map_err(|e| e.status())?;
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let protocol = unsafe { boot::open_protocol::<MemoryAttribute>(params, OpenProtocolAttributes::GetProtocol) }.map_err(|e| e.status())?;
+	let p = &protocol.0;
+	let (base, len) = (region.base as u64, region.size.next_multiple_of(PAGE_SIZE) as u64);
+
+	let status = unsafe { (p.set_memory_attributes)(p, base, len, EFI_MEMORY_RO) };
+
+	if status.is_error() {
+		return Err(status);
+	}
+
+	let mut attributes = 0;
+	if unsafe { (p.get_memory_attributes)(p, base, len, &mut attributes) }.is_success() {
+		debug!("Memory attributes @ {:#x}: {:#x}", base, attributes);
+	}
+
+	Ok(())
+}
+
+// make the pages of `region` read-only, a firmware without the protocol leaves them writable
+pub fn protect(region: Region) {
+	match set_read_only(region) {
+		Ok(()) => info!("OpRegion @ {:#x} is read-only for the rest of boot", region.base),
+		Err(Status::NOT_FOUND) => debug!("No memory attribute protocol, the OpRegion stays writable"),
+		Err(status) => warn!("Failed to make the OpRegion read-only: {:?}", status),
+	}
+}
+
@@ -0,0 +1,56 @@
+// Optional combined metadata record, so the host can pass the OpRegion and stolen memory sizes as a
+// single coherent unit. Layout: "IGDM", u32 version (1), u64 OpRegion size, u64 stolen memory size,
+// all little-endian. A size of zero means "not specified".
+
+use log::{error, info};
+
+use crate::fw_cfg::FwCfgSource;
+
+const METADATA_FILE: &str = "etc/igd-metadata";
+const METADATA_MAGIC: [u8; 4] = *b"IGDM";
+const METADATA_VERSION: u32 = 1;
+const METADATA_SIZE: usize = 24;
+
+#[derive(Clone, Copy)]
+pub struct Metadata {
+	pub opregion_size: Option<usize>,
+	pub bdsm_size: Option<usize>,
+}
+
+fn size_field(blob: &[u8; METADATA_SIZE], offset: usize) -> Option<usize> {
+	let mut field: [u8; 8] = [0; 8];
+	field.copy_from_slice(&blob[offset..offset + 8]);
+
+	match u64::from_le_bytes(field) {
+		0 => None,
+		size => Some(size as usize),
+	}
+}
+
+pub fn load<S: FwCfgSource>(fw_cfg: &mut S) -> Option<Metadata> {
+	let file = fw_cfg.find_file(METADATA_FILE)?;
+	let size = fw_cfg.file_size(&file);
+
+	if size != METADATA_SIZE {
+		error!("{} has size {}, expected {}!", METADATA_FILE, size, METADATA_SIZE);
+		return None;
+	}
+
+	let mut blob: [u8; METADATA_SIZE] = [0; METADATA_SIZE];
+	fw_cfg.read_file(&file, &mut blob);
+
+	let version = u32::from_le_bytes([blob[4], blob[5], blob[6], blob[7]]);
+
+	if blob[0..4] != METADATA_MAGIC || version != METADATA_VERSION {
+		error!("{} has an invalid signature or unsupported version!", METADATA_FILE);
+		return None;
+	}
+
+	let metadata = Metadata {
+		opregion_size: size_field(&blob, 8),
+		bdsm_size: size_field(&blob, 16),
+	};
+
+	info!("Using sizes from {}", METADATA_FILE);
+	Some(metadata)
+}
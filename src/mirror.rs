@@ -0,0 +1,48 @@
+// The host OpRegion as a vfio quirk mirrors it into a memory BAR of the assigned device (tag 0x002E),
+// for QEMU configurations that predate `etc/igd-opregion`. The mirror is only used if it carries an
+// accepted signature and the size in its header fits, and is copied out in dwords, the access size
+// such a quirk handles.
+
+use alloc::{vec, vec::Vec};
+use log::{debug, warn};
+use uefi::proto::pci::PciIo;
+
+use crate::{opregion, pci};
+
+fn read_dwords(pci_io: &mut PciIo, bar: u8, offset: u32, buf: &mut [u8]) -> Option<()> {
+	for (i, dword) in buf.chunks_exact_mut(4).enumerate() {
+		let value = pci::mmio_read_u32(pci_io, bar, offset as u64 + i as u64 * 4)?;
+		dword.copy_from_slice(&value.to_le_bytes());
+	}
+
+	Some(())
+}
+
+// the OpRegion at `offset` into `bar`, `None` if there is none or it is larger than `max_size`
+pub fn read(pci_io: &mut PciIo, (bar, offset): (u8, u32), signatures: &[[u8; 16]], max_size: usize) -> Option<Vec<u8>> {
+	let mut header = [0u8; opregion::HEADER_SIZE];
+	if read_dwords(pci_io, bar, offset, &mut header).is_none() {
+		warn!("Failed to read the OpRegion mirror in BAR{} at {:#x}", bar, offset);
+		return None;
+	}
+
+	if !signatures.iter().any(|s| header.starts_with(s)) {
+		debug!("No OpRegion mirrored in BAR{} at {:#x}", bar, offset);
+		return None;
+	}
+
+	let size = opregion::declared_size(&header);
+	if size < opregion::HEADER_SIZE || size > max_size {
+		warn!("OpRegion mirror in BAR{} declares {} bytes, ignoring it", bar, size);
+		return None;
+	}
+
+	let mut blob = vec![0u8; size];
+	blob[..opregion::HEADER_SIZE].copy_from_slice(&header);
+
+	let rest = offset + opregion::HEADER_SIZE as u32;
+	read_dwords(pci_io, bar, rest, &mut blob[opregion::HEADER_SIZE..])?;
+	debug!("Found a {} byte OpRegion mirrored in BAR{} at {:#x}", size, bar, offset);
+
+	Some(blob)
+}
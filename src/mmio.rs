@@ -0,0 +1,103 @@
+// The MMIO ranges the OpRegion and stolen memory must stay clear of: the memory BARs of the device
+// and the apertures of the PCI root bridges, which the firmware assigns BARs from. Memory from the
+// allocator never overlaps them, but a fixed, reserved or host-chosen address can, and the guest GPU
+// then hangs on the first access to either.
+
+use alloc::vec::Vec;
+use core::fmt;
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams, SearchType}, proto::unsafe_protocol, Status};
+
+use crate::pci::{self, PciConfigAccess};
+
+const ACPI_QWORD_ADDRESS_SPACE: u8 = 0x8A;
+const ACPI_RESOURCE_MEMORY: u8 = 0;
+// tag, length and resource type, then flags and the u64 fields
+const ACPI_HEADER_SIZE: usize = 3;
+const ACPI_QWORD_MIN_OFFSET: usize = 14;
+const ACPI_QWORD_LEN_OFFSET: usize = 38;
+const ACPI_QWORD_SIZE: usize = 46;
+
+#[repr(C)]
+struct PciRootBridgeIoProtocol {
+	// parent handle, the Poll/Mem/Io/Pci accessors, CopyMem, Map/Unmap, buffers, Flush and attributes
+	_unused: [usize; 17],
+	configuration: unsafe extern "efiapi" fn(*const PciRootBridgeIoProtocol, *mut *const u8) -> Status,
+	segment_number: u32,
+}
+
+#[unsafe_protocol("2f707ebb-4a1a-11d4-9a38-0090273fc14d")]
+struct PciRootBridgeIo(PciRootBridgeIoProtocol);
+
+#[derive(Clone, Copy)]
+pub enum Owner {
+	Bar(u8),
+	RootBridge(u32),
+}
+
+impl fmt::Display for Owner {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Owner::Bar(bar) => write!(f, "BAR{}", bar),
+			Owner::RootBridge(segment) => write!(f, "the MMIO window of the root bridge of segment {:04x}", segment),
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+pub struct Window {
+	pub owner: Owner,
+	pub base: u64,
+	pub size: u64,
+}
+
+fn le64(data: &[u8], offset: usize) -> u64 {
+	u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+// the memory apertures in the ACPI resource descriptors `Configuration` returns
+fn root_bridge_windows(windows: &mut Vec<Window>) {
+	let Ok(handles) = boot::locate_handle_buffer(SearchType::from_proto::<PciRootBridgeIo>()) else {
+		return;
+	};
+
+	for &handle in handles.iter() {
+		let params = OpenProtocolParams { handle, agent: boot::image_handle(), controller: None };
+		let Ok(root_bridge) = (unsafe { boot::open_protocol::<PciRootBridgeIo>(params, OpenProtocolAttributes::GetProtocol) }) else {
+			continue;
+		};
+
+		let mut resources = core::ptr::null();
+		if unsafe { (root_bridge.0.configuration)(&root_bridge.0, &mut resources) }.is_error() || resources.is_null() {
+			continue;
+		}
+
+		// root bridges only use QWORD descriptors, anything else is the end tag or not understood
+		while unsafe { *resources } == ACPI_QWORD_ADDRESS_SPACE {
+			let descriptor = unsafe { core::slice::from_raw_parts(resources, ACPI_QWORD_SIZE) };
+
+			let (base, size) = (le64(descriptor, ACPI_QWORD_MIN_OFFSET), le64(descriptor, ACPI_QWORD_LEN_OFFSET));
+			if descriptor[3] == ACPI_RESOURCE_MEMORY && size > 0 {
+				windows.push(Window { owner: Owner::RootBridge(root_bridge.0.segment_number), base, size });
+			}
+
+			let len = u16::from_le_bytes([descriptor[1], descriptor[2]]) as usize;
+			resources = unsafe { resources.add(ACPI_HEADER_SIZE + len) };
+		}
+	}
+}
+
+fn windows<P: PciConfigAccess>(pci_io: &mut P) -> Vec<Window> {
+	let mut windows: Vec<Window> = pci::memory_bars(pci_io).into_iter()
+		.map(|(bar, base, size)| Window { owner: Owner::Bar(bar), base, size })
+		.collect();
+
+	root_bridge_windows(&mut windows);
+	windows
+}
+
+// the first MMIO range `[base, base + size)` overlaps
+pub fn conflict<P: PciConfigAccess>(pci_io: &mut P, base: usize, size: usize) -> Option<Window> {
+	let (base, end) = (base as u64, base as u64 + size as u64);
+
+	windows(pci_io).into_iter().find(|w| base < w.base.saturating_add(w.size) && w.base < end)
+}
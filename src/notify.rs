@@ -0,0 +1,353 @@
+// The PciIo protocol notify and everything it needs to live across callbacks. There is no
+// UnregisterProtocolNotify, closing the event is what drops the registration, so the event and its
+// search key are kept together and go away together. Remembering which handles were looked at
+// already is left to `scan`.
+//
+// With `defer_to_ready_to_boot`, the notify only records the handles of matching devices, and they are
+// configured from a ReadyToBoot callback, once enumeration, BAR assignment and option ROM dispatch
+// are over.
+//
+// With `wait_for_enumeration`, nothing reacts to PciIo installs, which happen before the PCI bus driver
+// assigned the BARs. Instead, a notify on the PCI Enumeration Complete protocol does a single scan of
+// every device once the bus driver is done, and only then hands over to the PciIo notify if hot-plugged
+// devices were asked for.
+
+use core::{cell::UnsafeCell, ffi::c_void, ops::Deref, ptr::NonNull, sync::atomic::{AtomicBool, Ordering}};
+use igd_assignment::{configure_device, igd_location, pci::{self, Access}, protocol, publish_results, scan::Scan, CONFIG, OUTCOME};
+use log::{error, info, warn};
+use uefi::{boot::{self, EventType, ScopedProtocol, SearchType, Tpl}, guid, proto::pci::PciIo, Event, Guid, Handle, Status};
+
+use crate::halt;
+
+const READY_TO_BOOT_GUID: Guid = guid!("7ce88fb3-4bd7-4679-87a8-a8d8dee50d2b");
+// gEfiPciEnumerationCompleteProtocolGuid, installed without an interface
+const PCI_ENUMERATION_COMPLETE_GUID: Guid = guid!("30cfe3e7-3de1-4586-be20-deaba1b3b793");
+
+// set while one of our callbacks runs, where the panic handler must not exit the image. Notifications
+// at the TPL we run at are queued until we return, but a platform signalling the event directly from
+// a driver we call into would re-enter the notify halfway through a scan.
+pub(crate) static SCANNING: AtomicBool = AtomicBool::new(false);
+
+struct NotifyState {
+	event: Option<Event>,
+	key: Option<SearchType<'static>>,
+	ready_to_boot: Option<Event>,
+	// waiting for the PCI bus driver to finish enumeration
+	enumeration: Option<Event>,
+	// the search key only returns handles installed after registering, the ones present before are
+	// found by a full search the first time the notify runs
+	scanned_existing: bool,
+	scan: Scan<Handle>,
+}
+
+// Boot services only ever run on one CPU, the only concurrency is an event interrupting us. The
+// notify runs at TPL_CALLBACK, so accessing the state at TPL_NOTIFY keeps it out of our way.
+struct TplCell<T>(UnsafeCell<T>);
+
+unsafe impl<T> Sync for TplCell<T> {}
+
+impl<T> TplCell<T> {
+	const fn new(value: T) -> Self {
+		TplCell(UnsafeCell::new(value))
+	}
+
+	fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+		let _tpl = unsafe { boot::raise_tpl(Tpl::NOTIFY) };
+		f(unsafe { &mut *self.0.get() })
+	}
+}
+
+static STATE: TplCell<NotifyState> = TplCell::new(NotifyState {
+	event: None,
+	key: None,
+	ready_to_boot: None,
+	enumeration: None,
+	scanned_existing: false,
+	scan: Scan::new(),
+});
+
+// stop receiving PciIo notifications, safe to call from within the notify itself and more than once
+pub(crate) fn teardown() {
+	let event = STATE.with(|s| {
+		s.key = None;
+		s.event.take()
+	});
+
+	if let Some(event) = event {
+		let _ = boot::close_event(event);
+	}
+}
+
+// drop the ReadyToBoot callback along with the devices waiting for it
+pub(crate) fn teardown_deferred() {
+	let event = STATE.with(|s| {
+		s.scan.clear_deferred();
+		s.ready_to_boot.take()
+	});
+
+	if let Some(event) = event {
+		let _ = boot::close_event(event);
+	}
+}
+
+// stop waiting for PCI enumeration, safe to call more than once
+pub(crate) fn teardown_enumeration() {
+	let event = STATE.with(|s| s.enumeration.take());
+
+	if let Some(event) = event {
+		let _ = boot::close_event(event);
+	}
+}
+
+// whether the enumeration scan is still to come
+pub(crate) fn waiting_for_enumeration() -> bool {
+	STATE.with(|s| s.enumeration.is_some())
+}
+
+pub(crate) fn deferred_count() -> usize {
+	STATE.with(|s| s.scan.deferred_count())
+}
+
+// returns true if this was the IGD, after which no further devices are expected
+unsafe fn configure(handle: Handle, pci_io: &mut ScopedProtocol<PciIo>, access: Access) -> bool {
+	if !configure_device(pci_io, igd_location()) {
+		return false;
+	}
+
+	info!("Configured the device with {:?} PciIo access", access);
+
+	publish_results();
+
+	if let Ok(location) = pci_io.get_location() {
+		protocol::install(handle, location);
+	}
+
+	pci_io.get_location().is_ok_and(|l| l == igd_location())
+}
+
+unsafe extern "efiapi" fn ready_to_boot(_e: Event, _ctx: Option<NonNull<c_void>>) {
+	SCANNING.store(true, Ordering::Release);
+
+	let handles = STATE.with(|s| s.scan.take_deferred());
+	info!("ReadyToBoot, configuring {} deferred device(s)", handles.len());
+
+	for handle in handles {
+		let Some((mut pci_io, access)) = pci::open_device(handle) else {
+			error!("Failed to open PciIo of a deferred device, skipping it!");
+			continue;
+		};
+
+		configure(handle, &mut pci_io, access);
+	}
+
+	// the IGD is configured now, devices showing up later are too late for it
+	teardown();
+	teardown_deferred();
+
+	SCANNING.store(false, Ordering::Release);
+
+	if CONFIG.strict_failure && OUTCOME.attempted() {
+		let status = OUTCOME.status(CONFIG.allow_partial);
+
+		if status.is_error() {
+			halt::halt("Deferred setup failed", status);
+		}
+	}
+}
+
+// Run `f` on the scan state taken out of STATE, so devices are configured at the TPL the callback
+// runs at rather than with notifications blocked. Other callbacks at that TPL wait for us to return,
+// none of them can find the state missing.
+unsafe fn scanning<R>(f: impl FnOnce(&mut Scan<Handle>) -> R) -> R {
+	let mut scan = STATE.with(|s| core::mem::take(&mut s.scan));
+	let result = f(&mut scan);
+	STATE.with(|s| s.scan = scan);
+
+	result
+}
+
+// `Scan::drain` for the handles `next` finds, returns true once listening can stop
+unsafe fn drain<B: Deref<Target = [Handle]>>(next: impl FnMut() -> Option<B>) -> bool {
+	scanning(|scan| scan.drain(next, pci::open_device, |handle, pci_io, access| configure(handle, pci_io, access)))
+}
+
+unsafe extern "efiapi" fn notify(_e: Event, _ctx: Option<NonNull<c_void>>) {
+	// the scan state is taken out while running, and the search key drained below picks up whatever
+	// the nested call was for
+	if SCANNING.swap(true, Ordering::Acquire) {
+		warn!("Ignoring re-entrant PciIo notification");
+		return;
+	}
+
+	let Some(key) = STATE.with(|s| s.key) else {
+		SCANNING.store(false, Ordering::Release);
+		return;
+	};
+
+	let mut existing = !STATE.with(|s| core::mem::replace(&mut s.scanned_existing, true));
+
+	// The first run starts with a ByProtocol search for the handles installed before we registered,
+	// after that it's the search key only. ByRegisterNotify hands out every newly installed handle
+	// once and fails with NOT_FOUND once all of them were returned, which is what terminates the loop.
+	// EDK2 returns a single handle per call, but that isn't guaranteed, so every handle in the buffer
+	// is looked at. A handle returned by both searches, or again after its PciIo was reinstalled,
+	// isn't configured twice, `Scan` skips every handle it has processed already.
+	let done = drain(|| {
+		if core::mem::take(&mut existing) {
+			if let Ok(handles) = boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()) {
+				return Some(handles);
+			}
+		}
+
+		boot::locate_handle_buffer(key).ok()
+	});
+
+	if done {
+		info!("IGD configured, no longer listening for PciIo instances");
+		teardown();
+	}
+
+	SCANNING.store(false, Ordering::Release);
+}
+
+// the ReadyToBoot callback for deferred devices, `None` if setup isn't deferred
+fn create_ready_to_boot() -> Result<Option<Event>, Status> {
+	if !unsafe { CONFIG.defer_to_ready_to_boot } {
+		return Ok(None);
+	}
+
+	let group = NonNull::from(&READY_TO_BOOT_GUID);
+
+	match unsafe { boot::create_event_ex(EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, Some(ready_to_boot), None, Some(group)) } {
+		Ok(e) => Ok(Some(e)),
+		Err(e) => {
+			error!("Failed to create the ReadyToBoot event: {:?}!", e.status());
+			Err(e.status())
+		}
+	}
+}
+
+fn enumeration_complete() -> bool {
+	boot::locate_handle_buffer(SearchType::ByProtocol(&PCI_ENUMERATION_COMPLETE_GUID)).is_ok_and(|h| !h.is_empty())
+}
+
+unsafe extern "efiapi" fn enumerated(_e: Event, _ctx: Option<NonNull<c_void>>) {
+	// our own kick from `register_enumeration` when the bus driver isn't done yet
+	if !enumeration_complete() || !waiting_for_enumeration() {
+		return;
+	}
+
+	teardown_enumeration();
+	info!("PCI enumeration complete, scanning all devices");
+
+	SCANNING.store(true, Ordering::Release);
+
+	let mut handles = Some(boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()));
+	let done = drain(|| match handles.take()? {
+		Ok(handles) => Some(handles),
+		Err(e) => {
+			error!("No PciIo instances after PCI enumeration: {:?}!", e.status());
+			None
+		}
+	});
+
+	SCANNING.store(false, Ordering::Release);
+
+	// hot-plugged devices still come through PciIo installs, the scanned ones are remembered. The
+	// ReadyToBoot callback for deferred devices already exists, and tears down the notify anyway.
+	if CONFIG.keep_listening && !CONFIG.defer_to_ready_to_boot && !done && register().is_error() {
+		error!("Failed to keep listening for PciIo instances after PCI enumeration!");
+	}
+
+	// there is nobody to return the status to, the log has it
+	if !CONFIG.defer_to_ready_to_boot {
+		let _ = halt::enforce_policy();
+	}
+}
+
+// Wait for the PCI Enumeration Complete protocol instead of reacting to PciIo installs. The event is
+// kicked once, in case enumeration was over before the driver loaded, and the scan then runs right away.
+pub(crate) fn register_enumeration() -> Status {
+	let event = match unsafe { boot::create_event(EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, Some(enumerated), None) } {
+		Ok(e) => e,
+		Err(e) => {
+			error!("create_event failed with status {:?}!", e.status());
+			return e.status();
+		}
+	};
+
+	if let Err(e) = boot::register_protocol_notify(&PCI_ENUMERATION_COMPLETE_GUID, &event) {
+		error!("register_protocol_notify failed with status {:?}!", e.status());
+		let _ = boot::close_event(event);
+		return e.status();
+	}
+
+	let deferred_event = match create_ready_to_boot() {
+		Ok(e) => e,
+		Err(status) => {
+			let _ = boot::close_event(event);
+			return status;
+		}
+	};
+
+	let signal = unsafe { event.unsafe_clone() };
+	STATE.with(|s| {
+		s.enumeration = Some(event);
+		s.ready_to_boot = deferred_event;
+	});
+
+	if let Err(e) = boot::signal_event(&signal) {
+		error!("signal_event failed with status {:?}!", e.status());
+		teardown_enumeration();
+		teardown_deferred();
+		return e.status();
+	}
+
+	Status::SUCCESS
+}
+
+// register for PciIo installs, and kick the event once so existing instances are handled right away
+pub(crate) fn register() -> Status {
+	let event = match unsafe { boot::create_event(EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, Some(notify), None) } {
+		Ok(e) => e,
+		Err(e) => {
+			error!("create_event failed with status {:?}!", e.status());
+			return e.status();
+		}
+	};
+
+	let key = match boot::register_protocol_notify(&uefi_raw::protocol::pci_io::PciIoProtocol::GUID, &event) {
+		Ok(k) => k,
+		Err(e) => {
+			error!("register_protocol_notify failed with status {:?}!", e.status());
+			let _ = boot::close_event(event);
+			return e.status();
+		}
+	};
+
+	let deferred_event = match create_ready_to_boot() {
+		Ok(e) => e,
+		Err(status) => {
+			let _ = boot::close_event(event);
+			return status;
+		}
+	};
+
+	// nothing installs PciIo while we run at TPL_APPLICATION, so the notify can't see the state half-set
+	let signal = unsafe { event.unsafe_clone() };
+	STATE.with(|s| {
+		s.event = Some(event);
+		s.key = Some(key);
+		s.ready_to_boot = deferred_event;
+	});
+
+	// kick the event to handle the PciIo instances installed before registering
+	if let Err(e) = boot::signal_event(&signal) {
+		error!("signal_event failed with status {:?}!", e.status());
+		teardown();
+		teardown_deferred();
+		return e.status();
+	}
+
+	Status::SUCCESS
+}
@@ -0,0 +1,606 @@
+// Typed views of the OpRegion and the VBT: the header, mailboxes #1 to #5 and the VBT and BDB headers,
+// with the blocks of a VBT as an iterator. Every field is a byte array or a little-endian wrapper, so
+// the views have an alignment of 1 and can be laid over any byte slice that is long enough, and the
+// accessors are safe on whatever the host passes. Only `core` and `log` are used, and free of UEFI
+// calls, the module is usable on its own by other no_std firmware code.
+
+use core::{fmt, mem::size_of, ops::Range};
+
+use log::{info, log, log_enabled, Level};
+
+pub const SIGNATURE: &[u8; 16] = b"IntelGraphicsMem";
+pub const HEADER_SIZE: usize = 0x100;
+const MAX_MAJOR_VERSION: u8 = 3;
+// platform configuration, whose headless SKU bit makes i915 leave the display hardware off
+pub const PCON_HEADLESS_SKU: u32 = 1 << 13;
+const MBOX_ACPI: u32 = 1 << 0;
+const MBOX_SWSCI: u32 = 1 << 1;
+const MBOX_ASLE: u32 = 1 << 2;
+const MBOX_ASLE_EXT: u32 = 1 << 4;
+// mailboxes that need firmware answering SCIs or ASLE interrupts, which the virtual platform doesn't
+pub const MBOX_UNSERVICED: u32 = MBOX_SWSCI | MBOX_ASLE | MBOX_ASLE_EXT;
+// everything but the VBT mailbox, like the OpRegion GVT-g itself builds for a vGPU
+pub const MBOX_MEDIATED: u32 = MBOX_ACPI | MBOX_UNSERVICED;
+const ASLE_BCLM_ENTRIES: usize = 20;
+const ASLE_BCLP_VALID: u32 = 1 << 31;
+const ASLE_CBLV_VALID: u32 = 1 << 31;
+const ASLE_BCLM_VALID: u16 = 1 << 15;
+
+// where the mailboxes start, #4 holds the VBT
+const ACPI_OFFSET: usize = 0x100;
+const SWSCI_OFFSET: usize = 0x200;
+const ASLE_OFFSET: usize = 0x300;
+const VBT_OFFSET: usize = 0x400;
+const VBT_END: usize = 0x1C00;
+const ASLE_EXT_OFFSET: usize = VBT_END;
+
+pub const VBT_SIGNATURE: &[u8; 4] = b"$VBT";
+pub const VBT_HEADER_SIZE: usize = 0x30;
+const BDB_SIGNATURE: &[u8; 16] = b"BIOS_DATA_BLOCK ";
+
+macro_rules! le {
+	($name:ident, $ty:ty, $len:literal) => {
+		#[derive(Clone, Copy, Default, PartialEq, Eq)]
+		#[repr(transparent)]
+		pub struct $name([u8; $len]);
+
+		impl $name {
+			pub fn get(self) -> $ty {
+				<$ty>::from_le_bytes(self.0)
+			}
+
+			pub fn set(&mut self, value: $ty) {
+				self.0 = value.to_le_bytes();
+			}
+		}
+
+		impl fmt::Debug for $name {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				write!(f, "{:#x}", self.get())
+			}
+		}
+	};
+}
+
+le!(Le16, u16, 2);
+le!(Le32, u32, 4);
+le!(Le64, u64, 8);
+
+// A struct made of byte arrays and the little-endian wrappers only, so it has an alignment of 1 and
+// neither padding nor invalid bit patterns, and any slice at least as long can be viewed as one.
+//
+// # Safety
+//
+// Only to be implemented for `#[repr(C)]` structs that hold up all of the above.
+pub unsafe trait View: Sized {
+	fn from_bytes(buf: &[u8]) -> Option<&Self> {
+		(buf.len() >= size_of::<Self>()).then(|| unsafe { &*(buf.as_ptr() as *const Self) })
+	}
+
+	fn from_bytes_mut(buf: &mut [u8]) -> Option<&mut Self> {
+		(buf.len() >= size_of::<Self>()).then(|| unsafe { &mut *(buf.as_mut_ptr() as *mut Self) })
+	}
+}
+
+#[repr(C)]
+pub struct Header {
+	pub signature: [u8; 16],
+	// in KiB, including all mailboxes
+	pub size: Le32,
+	// reserved, revision, minor, major
+	pub over: [u8; 4],
+	// driver version strings for the system BIOS, video BIOS and graphics driver
+	pub sver: [u8; 32],
+	pub vver: [u8; 16],
+	pub gver: [u8; 16],
+	// bitmask of supported mailboxes
+	pub mbox: Le32,
+	pub dmod: Le32,
+	pub pcon: Le32,
+	pub dver: [u8; 32],
+	pub reserved: [u8; 124],
+}
+
+// mailbox #1, public ACPI methods
+#[repr(C)]
+pub struct AcpiMailbox {
+	pub drdy: Le32,
+	pub csts: Le32,
+	pub cevt: Le32,
+	pub reserved1: [u8; 20],
+	pub didl: [Le32; 8],
+	pub cpdl: [Le32; 8],
+	pub cadl: [Le32; 8],
+	pub nadl: [Le32; 8],
+	pub aslp: Le32,
+	pub tidx: Le32,
+	pub chpd: Le32,
+	pub clid: Le32,
+	pub cdck: Le32,
+	pub sxsw: Le32,
+	pub evts: Le32,
+	pub cnot: Le32,
+	pub nrdy: Le32,
+	pub did2: [Le32; 7],
+	pub cpd2: [Le32; 7],
+	pub reserved2: [u8; 4],
+}
+
+// mailbox #2, software SCI
+#[repr(C)]
+pub struct SwsciMailbox {
+	pub scic: Le32,
+	pub parm: Le32,
+	pub dslp: Le32,
+	pub reserved: [u8; 244],
+}
+
+// mailbox #3, ASLE: backlight, panel fitting and the extended VBT
+#[repr(C)]
+pub struct AsleMailbox {
+	pub ardy: Le32,
+	pub aslc: Le32,
+	pub tche: Le32,
+	pub alsi: Le32,
+	// requested brightness 0-255, valid with the top bit set
+	pub bclp: Le32,
+	pub pfit: Le32,
+	// current brightness 0-100 percent, valid with the top bit set
+	pub cblv: Le32,
+	// percent (14:8) to PWM duty cycle (7:0), valid with the top bit set
+	pub bclm: [Le16; ASLE_BCLM_ENTRIES],
+	pub cpfm: Le32,
+	pub epfm: Le32,
+	pub plut: [u8; 74],
+	pub pfmb: Le32,
+	pub cddv: Le32,
+	pub pcft: Le32,
+	pub srot: Le32,
+	pub iuer: Le32,
+	pub fdss: Le64,
+	pub fdsp: Le32,
+	pub stat: Le32,
+	// extended VBT address and size
+	pub rvda: Le64,
+	pub rvds: Le32,
+	pub reserved: [u8; 58],
+}
+
+// mailbox #5, ASLE extension
+#[repr(C)]
+pub struct AsleExtMailbox {
+	pub phed: Le32,
+	pub bddc: [u8; 256],
+	pub reserved: [u8; 764],
+}
+
+#[repr(C)]
+pub struct VbtHeader {
+	// `$VBT` followed by the platform name
+	pub signature: [u8; 20],
+	pub version: Le16,
+	pub header_size: Le16,
+	pub vbt_size: Le16,
+	pub checksum: u8,
+	pub reserved: u8,
+	pub bdb_offset: Le32,
+	pub aim_offset: [Le32; 4],
+}
+
+#[repr(C)]
+pub struct BdbHeader {
+	pub signature: [u8; 16],
+	pub version: Le16,
+	pub header_size: Le16,
+	pub bdb_size: Le16,
+}
+
+#[repr(C)]
+pub struct BlockHeader {
+	pub id: u8,
+	pub size: Le16,
+}
+
+unsafe impl View for Header {}
+unsafe impl View for AcpiMailbox {}
+unsafe impl View for SwsciMailbox {}
+unsafe impl View for AsleMailbox {}
+unsafe impl View for AsleExtMailbox {}
+unsafe impl View for VbtHeader {}
+unsafe impl View for BdbHeader {}
+unsafe impl View for BlockHeader {}
+
+const _: () = {
+	assert!(size_of::<Header>() == HEADER_SIZE);
+	assert!(size_of::<AcpiMailbox>() == SWSCI_OFFSET - ACPI_OFFSET);
+	assert!(size_of::<SwsciMailbox>() == ASLE_OFFSET - SWSCI_OFFSET);
+	assert!(size_of::<AsleMailbox>() == VBT_OFFSET - ASLE_OFFSET);
+	assert!(size_of::<AsleExtMailbox>() == 0x400);
+	assert!(size_of::<VbtHeader>() == VBT_HEADER_SIZE);
+	assert!(size_of::<BlockHeader>() == 3);
+};
+
+// ordered by major, then minor version
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+	pub major: u8,
+	pub minor: u8,
+}
+
+// the first version where RVDA is relative to the OpRegion base instead of an absolute address
+pub const VERSION_RELATIVE_RVDA: Version = Version { major: 2, minor: 1 };
+
+// a VBT too large for mailbox #4, stored outside the OpRegion mailboxes
+#[derive(Clone, Copy)]
+pub struct ExtendedVbt {
+	pub rvda: u64,
+	pub rvds: u32,
+}
+
+impl Header {
+	pub fn size(&self) -> usize {
+		self.size.get() as usize * 1024
+	}
+
+	pub fn version(&self) -> Version {
+		Version { major: self.over[3], minor: self.over[2] }
+	}
+
+	pub fn set_version(&mut self, version: Version) {
+		self.over[2] = version.minor;
+		self.over[3] = version.major;
+	}
+
+	pub fn revision(&self) -> u8 {
+		self.over[1]
+	}
+
+	// SVER up to its terminator
+	pub fn sver(&self) -> &[u8] {
+		&self.sver[..self.sver.iter().position(|&b| b == 0).unwrap_or(self.sver.len())]
+	}
+}
+
+pub fn header(opregion: &[u8]) -> Option<&Header> {
+	Header::from_bytes(opregion)
+}
+
+pub fn header_mut(opregion: &mut [u8]) -> Option<&mut Header> {
+	Header::from_bytes_mut(opregion)
+}
+
+pub fn acpi(opregion: &[u8]) -> Option<&AcpiMailbox> {
+	AcpiMailbox::from_bytes(opregion.get(ACPI_OFFSET..)?)
+}
+
+pub fn swsci(opregion: &[u8]) -> Option<&SwsciMailbox> {
+	SwsciMailbox::from_bytes(opregion.get(SWSCI_OFFSET..)?)
+}
+
+pub fn asle(opregion: &[u8]) -> Option<&AsleMailbox> {
+	AsleMailbox::from_bytes(opregion.get(ASLE_OFFSET..)?)
+}
+
+pub fn asle_mut(opregion: &mut [u8]) -> Option<&mut AsleMailbox> {
+	AsleMailbox::from_bytes_mut(opregion.get_mut(ASLE_OFFSET..)?)
+}
+
+// mailbox #4, whatever VBT it holds
+pub fn vbt_mailbox(opregion: &[u8]) -> Option<&[u8]> {
+	opregion.get(VBT_OFFSET..VBT_END)
+}
+
+pub fn asle_ext(opregion: &[u8]) -> Option<&AsleExtMailbox> {
+	AsleExtMailbox::from_bytes(opregion.get(ASLE_EXT_OFFSET..)?)
+}
+
+// checks the header against the size of the blob it came from and the accepted signatures, `Err`
+// describes the problem
+pub fn validate_header(header: &[u8], blob_size: usize, signatures: &[[u8; 16]]) -> Result<Version, &'static str> {
+	let Some(header) = Header::from_bytes(header).filter(|_| blob_size >= HEADER_SIZE) else {
+		return Err("header is truncated");
+	};
+
+	let Some(signature) = signatures.iter().find(|&s| header.signature == *s) else {
+		return Err("signature is invalid");
+	};
+
+	if signature != SIGNATURE {
+		info!("OpRegion has the alternate signature '{}'", signature.escape_ascii());
+	}
+
+	let size = header.size();
+
+	if size == 0 {
+		return Err("header states zero size");
+	}
+
+	if size > blob_size {
+		return Err("header size exceeds the passed data");
+	}
+
+	let version = header.version();
+
+	if version.major == 0 || version.major > MAX_MAJOR_VERSION {
+		return Err("version is not supported");
+	}
+
+	Ok(version)
+}
+
+// the version in the header, without any checks
+pub fn version(opregion: &[u8]) -> Option<Version> {
+	header(opregion).map(Header::version)
+}
+
+// size of the OpRegion itself as stated in the header, without anything appended to it
+pub fn declared_size(opregion: &[u8]) -> usize {
+	header(opregion).map_or(0, Header::size)
+}
+
+// RVDA and RVDS only exist from version 2.0 on, and only if the ASLE mailbox is present
+pub fn extended_vbt(opregion: &[u8], version: Version) -> Option<ExtendedVbt> {
+	if version.major < 2 || header(opregion)?.mbox.get() & MBOX_ASLE == 0 {
+		return None;
+	}
+
+	let asle = asle(opregion)?;
+	let vbt = ExtendedVbt {
+		rvda: asle.rvda.get(),
+		rvds: asle.rvds.get(),
+	};
+
+	(vbt.rvda != 0 && vbt.rvds != 0).then_some(vbt)
+}
+
+// Where the VBT is in the OpRegion buffer, behind a relative RVDA or in mailbox #4. `None` for an
+// absolute RVDA, the VBT lives elsewhere then.
+pub fn vbt_range(opregion: &[u8]) -> Option<Range<usize>> {
+	let version = version(opregion)?;
+
+	match extended_vbt(opregion, version) {
+		Some(vbt) if version >= VERSION_RELATIVE_RVDA => Some(vbt.rvda as usize..vbt.rvda as usize + vbt.rvds as usize),
+		Some(_) => None,
+		None => Some(VBT_OFFSET..VBT_END),
+	}
+}
+
+// Clears the `mask` bits in MBOX, returns MBOX before and after. ASLE stays while RVDA/RVDS reference
+// an extended VBT, the guest driver only looks for it with the mailbox present.
+pub fn clear_mailboxes(opregion: &mut [u8], version: Version, mut mask: u32) -> Option<(u32, u32)> {
+	if extended_vbt(opregion, version).is_some() {
+		mask &= !MBOX_ASLE;
+	}
+
+	let header = header_mut(opregion)?;
+	let mbox = header.mbox.get();
+	header.mbox.set(mbox & !mask);
+
+	Some((mbox, header.mbox.get()))
+}
+
+// sets the `set` and clears the `clear` bits in PCON, returns PCON before and after
+pub fn patch_pcon(opregion: &mut [u8], set: u32, clear: u32) -> Option<(u32, u32)> {
+	let header = header_mut(opregion)?;
+	let pcon = header.pcon.get();
+	header.pcon.set((pcon & !clear) | set);
+
+	Some((pcon, header.pcon.get()))
+}
+
+// Full brightness and a linear 10% step level map where the passed fields aren't valid, so backlight
+// control through the OpRegion doesn't start out stuck at 0%. Returns whether anything was set.
+pub fn init_backlight(opregion: &mut [u8]) -> bool {
+	let Some(asle) = asle_mut(opregion) else {
+		return false;
+	};
+
+	let mut changed = false;
+
+	if asle.bclm.iter().all(|e| e.get() == 0) {
+		for (i, entry) in asle.bclm.iter_mut().take(10).enumerate() {
+			let percent = (i as u16 + 1) * 10;
+			entry.set(ASLE_BCLM_VALID | percent << 8 | (percent * 0xFF / 100));
+		}
+
+		changed = true;
+	}
+
+	if asle.bclp.get() & ASLE_BCLP_VALID == 0 {
+		asle.bclp.set(ASLE_BCLP_VALID | 0xFF);
+		changed = true;
+	}
+
+	if asle.cblv.get() & ASLE_CBLV_VALID == 0 {
+		asle.cblv.set(ASLE_CBLV_VALID | 100);
+		changed = true;
+	}
+
+	changed
+}
+
+// a buffer too short for the header is left alone, like one without ASLE is by `set_extended_vbt`
+pub fn set_version(opregion: &mut [u8], version: Version) {
+	if let Some(header) = header_mut(opregion) {
+		header.set_version(version);
+	}
+}
+
+pub fn set_extended_vbt(opregion: &mut [u8], vbt: ExtendedVbt) {
+	if let Some(asle) = asle_mut(opregion) {
+		asle.rvda.set(vbt.rvda);
+		asle.rvds.set(vbt.rvds);
+	}
+}
+
+// what a synthesized OpRegion consists of: the header and mailboxes #1 to #5 without a VBT in #4
+pub const SYNTHESIZED_SIZE: usize = ASLE_EXT_OFFSET + size_of::<AsleExtMailbox>();
+
+// An OpRegion 2.1 wrapper for a VBT passed on its own, which is placed right behind it. The guest driver
+// only needs the header and ASLE mailbox to find the VBT through RVDA/RVDS, everything else stays zero.
+pub fn synthesize(opregion: &mut [u8], vbt_size: u32) {
+	opregion[..SYNTHESIZED_SIZE].fill(0);
+
+	let header = header_mut(opregion).unwrap();
+	header.signature = *SIGNATURE;
+	header.size.set((SYNTHESIZED_SIZE / 1024) as u32);
+	header.set_version(VERSION_RELATIVE_RVDA);
+	header.mbox.set(MBOX_ACPI | MBOX_ASLE);
+
+	set_extended_vbt(opregion, ExtendedVbt {
+		rvda: SYNTHESIZED_SIZE as u64,
+		rvds: vbt_size,
+	});
+}
+
+// size of the VBT as its header states, if it has a valid one that fits `vbt`
+pub fn vbt_size(vbt: &[u8]) -> Option<usize> {
+	let header = VbtHeader::from_bytes(vbt)?;
+	if !header.signature.starts_with(VBT_SIGNATURE) {
+		return None;
+	}
+
+	let size = header.vbt_size.get() as usize;
+	(VBT_HEADER_SIZE..=vbt.len()).contains(&size).then_some(size)
+}
+
+// signature and checksum of a standalone VBT, `Err` describes the problem
+pub fn validate_vbt(vbt: &[u8]) -> Result<(), &'static str> {
+	let Some(header) = VbtHeader::from_bytes(vbt).filter(|h| h.signature.starts_with(VBT_SIGNATURE)) else {
+		return Err("has no $VBT signature");
+	};
+
+	let size = header.vbt_size.get() as usize;
+
+	let Some(data) = vbt.get(..size).filter(|_| size >= VBT_HEADER_SIZE) else {
+		return Err("states a size beyond the data");
+	};
+
+	if data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+		return Err("checksum is invalid");
+	}
+
+	Ok(())
+}
+
+// a BDB block, `range` is where its data is in the VBT
+pub struct Block {
+	pub id: u8,
+	pub range: Range<usize>,
+}
+
+// the blocks of the BDB, up to the first one that doesn't fit it
+pub struct Blocks<'a> {
+	vbt: &'a [u8],
+	offset: usize,
+	end: usize,
+}
+
+impl Iterator for Blocks<'_> {
+	type Item = Block;
+
+	fn next(&mut self) -> Option<Block> {
+		if self.offset + size_of::<BlockHeader>() > self.end {
+			return None;
+		}
+
+		let header = BlockHeader::from_bytes(&self.vbt[self.offset..])?;
+		let data = self.offset + size_of::<BlockHeader>();
+		let end = data + header.size.get() as usize;
+
+		if end > self.end {
+			self.offset = self.end;
+			return None;
+		}
+
+		self.offset = end;
+		Some(Block { id: header.id, range: data..end })
+	}
+}
+
+// `None` if `vbt` has no valid header or BDB
+pub fn blocks(vbt: &[u8]) -> Option<Blocks<'_>> {
+	let vbt = &vbt[..vbt_size(vbt)?];
+	let bdb = VbtHeader::from_bytes(vbt)?.bdb_offset.get() as usize;
+	let header = BdbHeader::from_bytes(vbt.get(bdb..)?)?;
+
+	if header.signature != *BDB_SIGNATURE {
+		return None;
+	}
+
+	Some(Blocks {
+		vbt,
+		offset: bdb + header.header_size.get() as usize,
+		end: (bdb + header.bdb_size.get() as usize).min(vbt.len()),
+	})
+}
+
+// mailboxes announced in MBOX, named like i915 does
+const MAILBOXES: &[(u32, &str)] = &[
+	(MBOX_ACPI, "ACPI"),
+	(MBOX_SWSCI, "SWSCI"),
+	(MBOX_ASLE, "ASLE"),
+	(MBOX_ASLE_EXT, "ASLE_EXT"),
+	(1 << 5, "BACKLIGHT"),
+];
+
+// everything we know about the header in one place, for attaching to bug reports
+pub fn dump(opregion: &[u8], level: Level) {
+	let Some(header) = header(opregion) else {
+		log!(level, "OpRegion is too short to dump ({} bytes)", opregion.len());
+		return;
+	};
+
+	let version = header.version();
+	let mbox = header.mbox.get();
+
+	log!(level, "OpRegion header:\n  signature: '{}'\n  size:      {} KiB\n  version:   {}.{} (revision {})\n  SVER:      '{}'\n  MBOX:      {:#x}",
+		header.signature.escape_ascii(),
+		header.size.get(),
+		version.major, version.minor, header.revision(),
+		header.sver().escape_ascii(),
+		mbox);
+
+	for &(bit, name) in MAILBOXES.iter().filter(|&&(bit, _)| mbox & bit != 0) {
+		log!(level, "  mailbox:   {} ({:#x})", name, bit);
+	}
+
+	match asle(opregion) {
+		Some(asle) => log!(level, "  RVDA:      {:#x}\n  RVDS:      {:#x}", asle.rvda.get(), asle.rvds.get()),
+		None => log!(level, "  RVDA/RVDS: not present"),
+	}
+
+	match vbt_mailbox(opregion).and_then(VbtHeader::from_bytes) {
+		Some(vbt) => log!(level, "  VBT checksum: {:#04x}", vbt.checksum),
+		None => log!(level, "  VBT checksum: not present"),
+	}
+}
+
+// one line of a hexdump, bytes separated by spaces
+struct HexLine<'a>(&'a [u8]);
+
+impl fmt::Display for HexLine<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, b) in self.0.iter().enumerate() {
+			if i > 0 {
+				f.write_str(if i == 8 { "  " } else { " " })?;
+			}
+			write!(f, "{:02x}", b)?;
+		}
+
+		Ok(())
+	}
+}
+
+// the raw header, 16 bytes per line, for the fields `dump` doesn't decode
+pub fn hexdump(opregion: &[u8], level: Level) {
+	if !log_enabled!(level) {
+		return;
+	}
+
+	let header = &opregion[..opregion.len().min(HEADER_SIZE)];
+
+	for (i, line) in header.chunks(16).enumerate() {
+		log!(level, "  {:03x}: {}  |{}|", i * 16, HexLine(line), line.escape_ascii());
+	}
+}
@@ -0,0 +1,183 @@
+// The IGD's option ROM passed in `etc/igd-oprom`, for setups that need what it brings, e.g. boot logos
+// or drivers checking for it. The ROM may hold several images, the first EFI one for our machine type
+// is dispatched against the IGD like the GOP driver in `gop`. Legacy images need a CSM and are skipped.
+
+use alloc::{vec, vec::Vec};
+use core::ffi::c_void;
+use log::{error, info, warn};
+use qemu_fw_cfg::FwCfg;
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::unsafe_protocol, Status};
+
+use crate::{compressed, gop, igd_location, integrity, pci, scratch::Scratch, PCI_VENDOR_INTEL};
+
+pub(crate) const OPROM_FILE: &str = "etc/igd-oprom";
+
+const ROM_SIGNATURE: u16 = 0xAA55;
+const ROM_PCIR_OFFSET: usize = 0x18;
+const ROM_IMAGE_UNIT: usize = 512;
+
+const PCIR_SIGNATURE: &[u8; 4] = b"PCIR";
+const PCIR_VENDOR_OFFSET: usize = 0x04;
+const PCIR_CLASS_OFFSET: usize = 0x0F;
+const PCIR_IMAGE_LENGTH_OFFSET: usize = 0x10;
+const PCIR_CODE_TYPE_OFFSET: usize = 0x14;
+const PCIR_INDICATOR_OFFSET: usize = 0x15;
+const PCIR_SIZE: usize = 0x18;
+const PCIR_CODE_TYPE_EFI: u8 = 0x03;
+const PCIR_LAST_IMAGE: u8 = 0x80;
+
+const PCI_CLASS_DISPLAY: u8 = 0x03;
+
+// EFI PCI expansion ROM header, following the ROM signature
+const EFI_INIT_SIZE_OFFSET: usize = 0x02;
+const EFI_SIGNATURE_OFFSET: usize = 0x04;
+const EFI_MACHINE_TYPE_OFFSET: usize = 0x0A;
+const EFI_COMPRESSION_OFFSET: usize = 0x0C;
+const EFI_IMAGE_OFFSET_OFFSET: usize = 0x16;
+const EFI_SIGNATURE: u32 = 0x0EF1;
+const EFI_COMPRESSED: u16 = 1;
+
+#[cfg(target_arch = "x86_64")]
+const MACHINE_TYPE: u16 = 0x8664;
+#[cfg(target_arch = "x86")]
+const MACHINE_TYPE: u16 = 0x014C;
+#[cfg(target_arch = "aarch64")]
+const MACHINE_TYPE: u16 = 0xAA64;
+
+#[repr(C)]
+struct DecompressProtocol {
+	get_info: unsafe extern "efiapi" fn(*const DecompressProtocol, *const c_void, u32, *mut u32, *mut u32) -> Status,
+	decompress: unsafe extern "efiapi" fn(*const DecompressProtocol, *const c_void, u32, *mut c_void, u32, *mut c_void, u32) -> Status,
+}
+
+#[unsafe_protocol("d8117cfe-94a6-11d4-9a3a-0090273fc14d")]
+struct Decompress(DecompressProtocol);
+
+fn le16(buf: &[u8], offset: usize) -> Option<u16> {
+	Some(u16::from_le_bytes(buf.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+fn le32(buf: &[u8], offset: usize) -> Option<u32> {
+	Some(u32::from_le_bytes(buf.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+// EFI compression, as used by most GOP option ROMs
+fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+	let handle = boot::get_handle_for_protocol::<Decompress>().ok()?;
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let protocol = unsafe { boot::open_protocol::<Decompress>(params, OpenProtocolAttributes::GetProtocol) }.ok()?;
+	let (mut dst_size, mut scratch_size) = (0, 0);
+
+	let status = unsafe { (protocol.0.get_info)(&protocol.0, data.as_ptr() as *const c_void, data.len() as u32, &mut dst_size, &mut scratch_size) };
+	if status.is_error() {
+		return None;
+	}
+
+	let mut dst = vec![0u8; dst_size as usize];
+	let mut scratch = Scratch::new(scratch_size as usize);
+
+	let status = unsafe {
+		(protocol.0.decompress)(&protocol.0, data.as_ptr() as *const c_void, data.len() as u32,
+			dst.as_mut_ptr() as *mut c_void, dst_size, scratch.as_mut_ptr() as *mut c_void, scratch_size)
+	};
+
+	(!status.is_error()).then_some(dst)
+}
+
+// the PE image of the EFI ROM image at the start of `rom`, `Err` with the reason it can't be used
+fn efi_image(rom: &[u8]) -> Result<Vec<u8>, &'static str> {
+	if le32(rom, EFI_SIGNATURE_OFFSET) != Some(EFI_SIGNATURE) {
+		return Err("no EFI image signature");
+	}
+
+	if le16(rom, EFI_MACHINE_TYPE_OFFSET) != Some(MACHINE_TYPE) {
+		return Err("built for another machine type");
+	}
+
+	let size = le16(rom, EFI_INIT_SIZE_OFFSET).ok_or("truncated")? as usize * ROM_IMAGE_UNIT;
+	let offset = le16(rom, EFI_IMAGE_OFFSET_OFFSET).ok_or("truncated")? as usize;
+	let image = rom.get(offset..size.min(rom.len())).filter(|i| !i.is_empty()).ok_or("image out of bounds")?;
+
+	if le16(rom, EFI_COMPRESSION_OFFSET) == Some(EFI_COMPRESSED) {
+		decompress(image).ok_or("decompression failed")
+	} else {
+		Ok(image.to_vec())
+	}
+}
+
+// walks the images in the ROM, returns the first usable EFI one
+fn find_efi_image(rom: &[u8]) -> Option<Vec<u8>> {
+	let mut offset = 0;
+
+	loop {
+		let image = &rom[offset..];
+
+		if le16(image, 0) != Some(ROM_SIGNATURE) {
+			error!("{}: no ROM signature at {:#x}!", OPROM_FILE, offset);
+			return None;
+		}
+
+		let pcir_offset = le16(image, ROM_PCIR_OFFSET)? as usize;
+		let Some(pcir) = image.get(pcir_offset..pcir_offset + PCIR_SIZE).filter(|p| &p[..4] == PCIR_SIGNATURE) else {
+			error!("{}: no PCI data structure in the image at {:#x}!", OPROM_FILE, offset);
+			return None;
+		};
+
+		let vendor = le16(pcir, PCIR_VENDOR_OFFSET)?;
+		if vendor != PCI_VENDOR_INTEL || pcir[PCIR_CLASS_OFFSET] != PCI_CLASS_DISPLAY {
+			error!("{}: image at {:#x} is for vendor {:04x}, class {:02x}, not an Intel display controller!", OPROM_FILE, offset, vendor, pcir[PCIR_CLASS_OFFSET]);
+			return None;
+		}
+
+		if pcir[PCIR_CODE_TYPE_OFFSET] == PCIR_CODE_TYPE_EFI {
+			match efi_image(image) {
+				Ok(efi) => return Some(efi),
+				Err(reason) => warn!("{}: skipping the EFI image at {:#x}, {}", OPROM_FILE, offset, reason),
+			}
+		} else {
+			info!("{}: skipping the image of code type {} at {:#x}, which needs a CSM", OPROM_FILE, pcir[PCIR_CODE_TYPE_OFFSET], offset);
+		}
+
+		let len = le16(pcir, PCIR_IMAGE_LENGTH_OFFSET)? as usize * ROM_IMAGE_UNIT;
+		if pcir[PCIR_INDICATOR_OFFSET] & PCIR_LAST_IMAGE != 0 || len == 0 || offset + len >= rom.len() {
+			error!("{}: no usable EFI image!", OPROM_FILE);
+			return None;
+		}
+
+		offset += len;
+	}
+}
+
+pub fn load(fw_cfg: &mut FwCfg) {
+	let Some(file) = fw_cfg.find_file(OPROM_FILE) else {
+		return;
+	};
+
+	let (seg, bus, dev, func) = igd_location();
+	let Some(igd) = pci::find_handle(igd_location()) else {
+		error!("No IGD at {:04x}:{:02x}:{:02x}.{} to dispatch the option ROM against!", seg, bus, dev, func);
+		return;
+	};
+
+	let Ok(rom) = compressed::load(fw_cfg, OPROM_FILE, &file) else {
+		return;
+	};
+
+	if integrity::verify(fw_cfg, OPROM_FILE, &rom).is_err() {
+		return;
+	}
+
+	let Some(image) = find_efi_image(&rom) else {
+		return;
+	};
+
+	if gop::dispatch(&image, igd) {
+		info!("EFI image from {} ({} bytes) connected to the IGD", OPROM_FILE, image.len());
+	}
+}
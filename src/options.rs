@@ -0,0 +1,52 @@
+// Options set in the firmware setup UI, persisted in a non-volatile UEFI variable for users who
+// can't easily change the QEMU command line. They are applied on top of the configuration blob.
+
+use log::{info, LevelFilter};
+use uefi::{cstr16, guid, runtime::{self, VariableVendor}, CStr16};
+
+pub const OPTIONS_VARIABLE_GUID: VariableVendor = VariableVendor(guid!("e4b2a7c9-2d61-4f3a-9c85-b07d1e6f3a48"));
+pub const OPTIONS_VARIABLE_NAME: &CStr16 = cstr16!("IgdAssignmentSetup");
+
+// layout of the variable, which the setup form's varstore refers to by offset
+pub const SKIP_STOLEN_MEMORY_OFFSET: u16 = 0;
+pub const LEGACY_BDSM_OFFSET: u16 = 1;
+pub const LOG_LEVEL_OFFSET: u16 = 2;
+pub const OPTIONS_SIZE: usize = 3;
+
+#[derive(Clone, Copy, Default)]
+pub struct SetupOptions {
+	pub skip_stolen_memory: bool,
+	pub legacy_bdsm: bool,
+	// `None` keeps the default
+	pub log_level: Option<LevelFilter>,
+}
+
+// 0 keeps the default, then increasingly verbose
+fn log_level(value: u8) -> Option<LevelFilter> {
+	match value {
+		1 => Some(LevelFilter::Error),
+		2 => Some(LevelFilter::Warn),
+		3 => Some(LevelFilter::Info),
+		4 => Some(LevelFilter::Debug),
+		5 => Some(LevelFilter::Trace),
+		_ => None,
+	}
+}
+
+// `None` if the options were never saved, or by a version with a different layout
+pub fn read() -> Option<SetupOptions> {
+	let mut buf = [0u8; OPTIONS_SIZE];
+	let (data, _) = runtime::get_variable(OPTIONS_VARIABLE_NAME, &OPTIONS_VARIABLE_GUID, &mut buf).ok()?;
+
+	let &[skip_stolen_memory, legacy_bdsm, level] = data else {
+		return None;
+	};
+
+	info!("Applying the options from the setup UI");
+
+	Some(SetupOptions {
+		skip_stolen_memory: skip_stolen_memory != 0,
+		legacy_bdsm: legacy_bdsm != 0,
+		log_level: log_level(level),
+	})
+}
@@ -0,0 +1,103 @@
+use alloc::vec::Vec;
+use uefi::Status;
+
+use crate::{args::Location, generation::Generation};
+
+#[derive(Clone, Copy)]
+pub struct Region {
+	pub base: usize,
+	pub size: usize,
+}
+
+// what was set up on a single matching device
+#[derive(Clone, Copy)]
+pub struct DeviceOutcome {
+	pub location: Location,
+	pub device_id: u16,
+	pub generation: Option<Generation>,
+	pub opregion: Option<Region>,
+	pub stolen_memory: Option<Region>,
+	// set up as part of stolen memory, with tag 0x0011
+	pub gtt_stolen_memory: Option<Region>,
+	// `None` if the step wasn't attempted on this device
+	pub opregion_status: Option<Status>,
+	pub stolen_memory_status: Option<Status>,
+	// how long the setup took, `None` without a timestamp counter
+	pub setup_us: Option<u64>,
+	// GGC as left after stolen memory setup
+	pub ggc: Option<u16>,
+	// whether the memory map reserves the whole region, `None` if it couldn't be checked
+	pub opregion_reserved: Option<bool>,
+	pub stolen_memory_reserved: Option<bool>,
+}
+
+impl DeviceOutcome {
+	pub fn new(location: Location, device_id: u16) -> Self {
+		DeviceOutcome {
+			location,
+			device_id,
+			generation: Generation::from_device_id(device_id),
+			opregion: None,
+			stolen_memory: None,
+			gtt_stolen_memory: None,
+			opregion_status: None,
+			stolen_memory_status: None,
+			setup_us: None,
+			ggc: None,
+			opregion_reserved: None,
+			stolen_memory_reserved: None,
+		}
+	}
+}
+
+// aggregate result of the setup steps across all devices, `None` if a step was never attempted
+pub struct Outcome {
+	pub opregion: Option<Status>,
+	pub stolen_memory: Option<Status>,
+	pub devices: Vec<DeviceOutcome>,
+}
+
+impl Outcome {
+	pub const fn new() -> Self {
+		Outcome {
+			opregion: None,
+			stolen_memory: None,
+			devices: Vec::new(),
+		}
+	}
+
+	pub fn record_opregion(&mut self, status: Status) {
+		record(&mut self.opregion, status);
+	}
+
+	pub fn record_stolen_memory(&mut self, status: Status) {
+		record(&mut self.stolen_memory, status);
+	}
+
+	pub fn attempted(&self) -> bool {
+		self.opregion.is_some() || self.stolen_memory.is_some()
+	}
+
+	// a stolen memory step that was never attempted is fine, as it only applies to the IGD at 00:02.0
+	pub fn status(&self, allow_partial: bool) -> Status {
+		match (self.opregion, self.stolen_memory) {
+			(Some(s), _) if s.is_error() => s,
+			(_, Some(s)) if s.is_error() && !(allow_partial && self.opregion.is_some()) => s,
+			_ => Status::SUCCESS,
+		}
+	}
+}
+
+impl Default for Outcome {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// keep the first failure around, later successes must not mask it
+fn record(slot: &mut Option<Status>, status: Status) {
+	match slot {
+		Some(s) if s.is_error() => {}
+		_ => *slot = Some(status),
+	}
+}
@@ -0,0 +1,76 @@
+// Panic handler reporting on the serial port, which works even when the console or the logger is
+// what panicked. There is no unwinding on UEFI targets, so a panic can't return to the firmware as
+// if the callback had completed: a panic in the entry point exits the image with an error, which
+// lets boot continue, one inside the notify halts after reporting.
+
+use core::{fmt::Write, panic::PanicInfo, sync::atomic::{AtomicBool, Ordering}};
+use uefi::{boot, Status};
+
+use crate::notify::SCANNING;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const COM1: u16 = 0x3F8;
+// OVMF's debug console, shown with `-debugcon file:...` or `-debugcon stdio`
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const DEBUGCON: u16 = 0x402;
+
+// set while the entry point runs, exiting the image is only possible from there
+static IN_ENTRY: AtomicBool = AtomicBool::new(false);
+
+pub struct EntryGuard;
+
+impl EntryGuard {
+	pub fn enter() -> Self {
+		IN_ENTRY.store(true, Ordering::Release);
+		EntryGuard
+	}
+}
+
+impl Drop for EntryGuard {
+	fn drop(&mut self) {
+		IN_ENTRY.store(false, Ordering::Release);
+	}
+}
+
+struct Serial;
+
+impl Write for Serial {
+	#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+	fn write_str(&mut self, s: &str) -> core::fmt::Result {
+		for b in s.bytes() {
+			unsafe {
+				core::arch::asm!("out dx, al", in("dx") COM1, in("al") b, options(nomem, nostack, preserves_flags));
+				core::arch::asm!("out dx, al", in("dx") DEBUGCON, in("al") b, options(nomem, nostack, preserves_flags));
+			}
+		}
+
+		Ok(())
+	}
+
+	#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+	fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+		Ok(())
+	}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	let _ = writeln!(Serial, "\r\nigd-assignment: {}\r", info);
+
+	// at TPL_CALLBACK inside the notify, exiting the image isn't allowed
+	if IN_ENTRY.load(Ordering::Acquire) && !SCANNING.load(Ordering::Acquire) {
+		let _ = writeln!(Serial, "igd-assignment: unloading\r");
+
+		unsafe {
+			// the image is unloaded, so the notify must never fire again and nobody may call into it
+			crate::release_resident();
+			boot::exit(boot::image_handle(), Status::ABORTED, 0, core::ptr::null_mut());
+		}
+	}
+
+	let _ = writeln!(Serial, "igd-assignment: halting\r");
+
+	loop {
+		core::hint::spin_loop();
+	}
+}
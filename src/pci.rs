@@ -0,0 +1,542 @@
+use alloc::vec::Vec;
+use core::{ffi::c_void, sync::atomic::{AtomicU8, Ordering}};
+use log::{debug, info, warn};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol, SearchType}, proto::pci::PciIo, Handle};
+
+use crate::args::Location;
+
+// the PciIo handle of the device at `location`
+pub fn find_handle(location: Location) -> Option<Handle> {
+	let handles = boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()).ok()?;
+
+	handles.iter().copied().find(|&handle| {
+		open_handle_shared(handle).is_some_and(|pci_io| pci_io.get_location().is_ok_and(|l| l == location))
+	})
+}
+
+fn open_handle_shared(handle: Handle) -> Option<ScopedProtocol<PciIo>> {
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	unsafe { boot::open_protocol::<PciIo>(params, OpenProtocolAttributes::GetProtocol) }.ok()
+}
+
+// non-exclusive access to the first device with the given IDs
+pub fn open_by_id(vendor: u16, device: u16) -> Option<ScopedProtocol<PciIo>> {
+	let handles = boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()).ok()?;
+	let ids = (device as u32) << 16 | vendor as u32;
+
+	handles.iter().find_map(|&handle| open_handle_shared(handle).and_then(|mut pci_io| {
+		(read_u32(&mut pci_io, 0)? == ids).then_some(pci_io)
+	}))
+}
+
+// non-exclusive access to every PCI device, e.g. for walking the bridges
+pub fn open_all_shared() -> Vec<ScopedProtocol<PciIo>> {
+	let Ok(handles) = boot::locate_handle_buffer(SearchType::from_proto::<PciIo>()) else {
+		return Vec::new();
+	};
+
+	handles.iter().filter_map(|&handle| open_handle_shared(handle)).collect()
+}
+
+// non-exclusive access, for peeking at devices we don't own such as the host bridge
+pub fn open_shared(location: Location) -> Option<ScopedProtocol<PciIo>> {
+	open_handle_shared(find_handle(location)?)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+	Exclusive,
+	// GET_PROTOCOL, the device stays with the driver owning it
+	Shared,
+}
+
+// Exclusive access where possible. That fails if a GOP or platform driver already owns the device,
+// non-exclusive access is still enough for the config space accesses we need.
+pub fn open_device(handle: Handle) -> Option<(ScopedProtocol<PciIo>, Access)> {
+	if let Ok(pci_io) = boot::open_protocol_exclusive::<PciIo>(handle) {
+		return Some((pci_io, Access::Exclusive));
+	}
+
+	let pci_io = open_handle_shared(handle)?;
+	debug!("PciIo is owned by another driver, falling back to shared access");
+
+	Some((pci_io, Access::Shared))
+}
+
+const PCI_CFG_COMMAND_OFFSET: u32 = 0x04;
+// I/O space and memory space decoding
+const PCI_COMMAND_DECODE: u16 = 0x3;
+const PCI_COMMAND_MEMORY: u16 = 0x2;
+const PCI_COMMAND_BUS_MASTER: u16 = 0x4;
+// what the host BIOS leaves enabled on the IGD
+pub const PCI_COMMAND_ENABLE: u16 = PCI_COMMAND_DECODE | PCI_COMMAND_BUS_MASTER;
+const PCI_CFG_CLASS_OFFSET: u32 = 0x09;
+const PCI_CFG_BAR0_OFFSET: u32 = 0x10;
+const PCI_BAR_COUNT: u8 = 6;
+const PCI_BAR_IO: u32 = 0x1;
+const PCI_BAR_TYPE_MASK: u32 = 0x6;
+const PCI_BAR_TYPE_64: u32 = 0x4;
+const PCI_BAR_MEM_FLAGS_MASK: u32 = 0xF;
+
+// The 24-bit class code register starts at 0x09 with the programming interface, followed by the
+// subclass at 0x0A and the base class at 0x0B, so in a 3-byte read the base class comes last.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClassCode {
+	pub base: u8,
+	pub sub: u8,
+	pub prog: u8,
+}
+
+// How config space accesses are issued. Some firmwares only implement byte-granular PciIo config
+// accesses, so this is probed once on the first device and used for all following accesses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccessStrategy {
+	// accesses of the natural width, e.g. a single dword read for a 32-bit register
+	Native,
+	// every access split into single-byte accesses
+	Bytewise,
+}
+
+const STRATEGY_UNKNOWN: u8 = 0;
+const STRATEGY_NATIVE: u8 = 1;
+const STRATEGY_BYTEWISE: u8 = 2;
+
+static STRATEGY: AtomicU8 = AtomicU8::new(STRATEGY_UNKNOWN);
+
+// Raw config space accesses, which everything in here is built on. Implemented by PciIo, and by
+// `ConfigSpace` for running the register sequences against memory instead of a device.
+pub trait PciConfigAccess {
+	// `count` accesses of `width` bytes (1, 2 or 4) starting at `offset`
+	fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()>;
+	// a single access as wide as `buf`
+	fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()>;
+}
+
+impl PciConfigAccess for PciIo {
+	fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+		self.pci_read(width, offset, count, buf).ok()
+	}
+
+	fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()> {
+		self.pci_write(buf.len() as u32, offset, 1, buf.as_mut_ptr() as *mut c_void).ok()
+	}
+}
+
+impl PciConfigAccess for ScopedProtocol<PciIo> {
+	fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+		(**self).config_read(width, offset, count, buf)
+	}
+
+	fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()> {
+		(**self).config_write(offset, buf)
+	}
+}
+
+// 256 bytes of config space in memory, registers take whatever is written to them
+pub struct ConfigSpace(pub [u8; 256]);
+
+impl PciConfigAccess for ConfigSpace {
+	fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+		let len = width as usize * count;
+		buf[..len].copy_from_slice(self.0.get(offset as usize..offset as usize + len)?);
+
+		Some(())
+	}
+
+	fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()> {
+		self.0.get_mut(offset as usize..offset as usize + buf.len())?.copy_from_slice(buf);
+
+		Some(())
+	}
+}
+
+fn read_raw<P: PciConfigAccess>(pci_io: &mut P, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+	pci_io.config_read(width, offset, count, buf)
+}
+
+fn read_bytewise<P: PciConfigAccess>(pci_io: &mut P, offset: u32, buf: &mut [u8]) -> Option<()> {
+	for (i, b) in buf.iter_mut().enumerate() {
+		read_raw(pci_io, 1, offset + i as u32, 1, core::slice::from_mut(b))?;
+	}
+
+	Some(())
+}
+
+// compare a dword read of the IDs against the same bytes read individually
+fn probe<P: PciConfigAccess>(pci_io: &mut P) -> AccessStrategy {
+	let mut native: [u8; 4] = [0; 4];
+	let mut bytewise: [u8; 4] = [0; 4];
+
+	let native_ok = read_raw(pci_io, 4, 0, 1, &mut native).is_some();
+	let bytewise_ok = read_bytewise(pci_io, 0, &mut bytewise).is_some();
+
+	let strategy = if native_ok && (!bytewise_ok || native == bytewise) {
+		AccessStrategy::Native
+	} else {
+		AccessStrategy::Bytewise
+	};
+
+	info!("Using {:?} PCI configuration space accesses", strategy);
+	strategy
+}
+
+fn strategy<P: PciConfigAccess>(pci_io: &mut P) -> AccessStrategy {
+	match STRATEGY.load(Ordering::Relaxed) {
+		STRATEGY_NATIVE => AccessStrategy::Native,
+		STRATEGY_BYTEWISE => AccessStrategy::Bytewise,
+		_ => {
+			let strategy = probe(pci_io);
+			let raw = match strategy {
+				AccessStrategy::Native => STRATEGY_NATIVE,
+				AccessStrategy::Bytewise => STRATEGY_BYTEWISE,
+			};
+
+			STRATEGY.store(raw, Ordering::Relaxed);
+			strategy
+		}
+	}
+}
+
+// PciIo doesn't report how much was read, so after any error the buffer contents can't be trusted and
+// the accessors return `None` instead of a partially filled value
+pub fn read_bytes<const N: usize>(pci_io: &mut impl PciConfigAccess, offset: u32) -> Option<[u8; N]> {
+	let mut buf: [u8; N] = [0; N];
+
+	match strategy(pci_io) {
+		AccessStrategy::Native => read_raw(pci_io, 1, offset, N, &mut buf)?,
+		AccessStrategy::Bytewise => read_bytewise(pci_io, offset, &mut buf)?,
+	}
+
+	Some(buf)
+}
+
+pub fn read_u32<P: PciConfigAccess>(pci_io: &mut P, offset: u32) -> Option<u32> {
+	let mut buf: [u8; 4] = [0; 4];
+
+	match strategy(pci_io) {
+		AccessStrategy::Native => read_raw(pci_io, 4, offset, 1, &mut buf)?,
+		AccessStrategy::Bytewise => read_bytewise(pci_io, offset, &mut buf)?,
+	}
+
+	Some(u32::from_le_bytes(buf))
+}
+
+pub fn read_u16<P: PciConfigAccess>(pci_io: &mut P, offset: u32) -> Option<u16> {
+	let mut buf: [u8; 2] = [0; 2];
+
+	match strategy(pci_io) {
+		AccessStrategy::Native => read_raw(pci_io, 2, offset, 1, &mut buf)?,
+		AccessStrategy::Bytewise => read_bytewise(pci_io, offset, &mut buf)?,
+	}
+
+	Some(u16::from_le_bytes(buf))
+}
+
+// a single access as wide as `buf`, which has to be 1, 2 or 4 bytes
+fn write_bytes<P: PciConfigAccess>(pci_io: &mut P, offset: u32, buf: &mut [u8]) -> Option<()> {
+	match strategy(pci_io) {
+		AccessStrategy::Native => pci_io.config_write(offset, buf),
+		AccessStrategy::Bytewise => {
+			for (i, b) in buf.iter_mut().enumerate() {
+				pci_io.config_write(offset + i as u32, core::slice::from_mut(b))?;
+			}
+
+			Some(())
+		}
+	}
+}
+
+pub fn write_u8<P: PciConfigAccess>(pci_io: &mut P, offset: u32, value: u8) -> Option<()> {
+	write_bytes(pci_io, offset, &mut [value])
+}
+
+pub fn write_u16<P: PciConfigAccess>(pci_io: &mut P, offset: u32, value: u16) -> Option<()> {
+	write_bytes(pci_io, offset, &mut value.to_le_bytes())
+}
+
+pub fn write_u32<P: PciConfigAccess>(pci_io: &mut P, offset: u32, value: u32) -> Option<()> {
+	write_bytes(pci_io, offset, &mut value.to_le_bytes())
+}
+
+pub fn read_class<P: PciConfigAccess>(pci_io: &mut P) -> Option<ClassCode> {
+	let [prog, sub, base] = read_bytes::<3>(pci_io, PCI_CFG_CLASS_OFFSET)?;
+
+	Some(ClassCode { base, sub, prog })
+}
+
+const PCI_EXT_CAP_START: u32 = 0x100;
+// enough to walk every possible capability in 4 KiB of extended config space
+const PCI_EXT_CAP_MAX: usize = (4096 - 256) / 4;
+
+// offset of PCIe extended capability `id`, `None` if the device has none or no extended config space
+pub fn find_extended_capability<P: PciConfigAccess>(pci_io: &mut P, id: u16) -> Option<u32> {
+	let mut offset = PCI_EXT_CAP_START;
+
+	for _ in 0..PCI_EXT_CAP_MAX {
+		let header = read_u32(pci_io, offset)?;
+
+		// all ones where there is no extended config space, zero for an empty list
+		if header == 0 || header == 0xFFFFFFFF {
+			return None;
+		}
+
+		if header as u16 == id {
+			return Some(offset);
+		}
+
+		offset = header >> 20;
+		if offset < PCI_EXT_CAP_START {
+			return None;
+		}
+	}
+
+	None
+}
+
+// disables decoding for as long as it lives, the saved command register is restored on every exit path
+struct DecodeGuard<'a, P: PciConfigAccess> {
+	pci_io: &'a mut P,
+	command: u16,
+}
+
+impl<'a, P: PciConfigAccess> DecodeGuard<'a, P> {
+	fn new(pci_io: &'a mut P) -> Option<Self> {
+		let command = read_u16(pci_io, PCI_CFG_COMMAND_OFFSET)?;
+		write_u16(pci_io, PCI_CFG_COMMAND_OFFSET, command & !PCI_COMMAND_DECODE)?;
+
+		Some(DecodeGuard { pci_io, command })
+	}
+}
+
+impl<P: PciConfigAccess> Drop for DecodeGuard<'_, P> {
+	fn drop(&mut self) {
+		if write_u16(self.pci_io, PCI_CFG_COMMAND_OFFSET, self.command).is_none() {
+			warn!("Failed to restore the PCI command register to {:#x}!", self.command);
+		}
+	}
+}
+
+// set I/O and memory space decoding and bus mastering, returns the command register before and after
+pub fn enable_decode<P: PciConfigAccess>(pci_io: &mut P) -> Option<(u16, u16)> {
+	let command = read_u16(pci_io, PCI_CFG_COMMAND_OFFSET)?;
+	let enabled = command | PCI_COMMAND_ENABLE;
+
+	if enabled != command {
+		write_u16(pci_io, PCI_CFG_COMMAND_OFFSET, enabled)?;
+	}
+
+	Some((command, read_u16(pci_io, PCI_CFG_COMMAND_OFFSET)?))
+}
+
+// address memory BAR `bar` is assigned, `None` if it is an I/O BAR, unassigned or not decoded
+pub fn bar_address<P: PciConfigAccess>(pci_io: &mut P, bar: u8) -> Option<u64> {
+	if bar >= PCI_BAR_COUNT || read_u16(pci_io, PCI_CFG_COMMAND_OFFSET)? & PCI_COMMAND_MEMORY == 0 {
+		return None;
+	}
+
+	let offset = PCI_CFG_BAR0_OFFSET + bar as u32 * 4;
+	let lo = read_u32(pci_io, offset)?;
+
+	if lo & PCI_BAR_IO != 0 {
+		return None;
+	}
+
+	let hi = if lo & PCI_BAR_TYPE_MASK == PCI_BAR_TYPE_64 && bar + 1 < PCI_BAR_COUNT { read_u32(pci_io, offset + 4)? } else { 0 };
+	let addr = (hi as u64) << 32 | (lo & !PCI_BAR_MEM_FLAGS_MASK) as u64;
+
+	(addr != 0).then_some(addr)
+}
+
+// MMIO accesses relative to memory BAR `bar`, PciIo takes care of the mapping
+pub fn mmio_read_u32(pci_io: &mut PciIo, bar: u8, offset: u64) -> Option<u32> {
+	let mut buf: [u8; 4] = [0; 4];
+	pci_io.mem_read(4, bar, offset, 1, &mut buf).ok()?;
+
+	Some(u32::from_le_bytes(buf))
+}
+
+pub fn mmio_write_u32(pci_io: &mut PciIo, bar: u8, offset: u64, value: u32) -> Option<()> {
+	let mut buf = value.to_le_bytes();
+	pci_io.mem_write(4, bar, offset, 1, buf.as_mut_ptr() as *mut c_void).ok()
+}
+
+pub fn mmio_write_u8(pci_io: &mut PciIo, bar: u8, offset: u64, mut value: u8) -> Option<()> {
+	pci_io.mem_write(1, bar, offset, 1, &mut value as *mut u8 as *mut c_void).ok()
+}
+
+// write all ones to `offset` and return what sticks, restoring the original value afterwards
+fn probe_mask<P: PciConfigAccess>(pci_io: &mut P, offset: u32) -> Option<u32> {
+	let original = read_u32(pci_io, offset)?;
+	let mask = write_u32(pci_io, offset, 0xFFFFFFFF).and_then(|_| read_u32(pci_io, offset));
+	write_u32(pci_io, offset, original)?;
+
+	mask
+}
+
+// size of memory BAR `bar`, `None` for I/O or unimplemented BARs. Decoding is off while the BAR
+// holds all ones, so the device doesn't claim whatever address range that happens to cover.
+pub fn probe_bar_size<P: PciConfigAccess>(pci_io: &mut P, bar: u8) -> Option<u64> {
+	if bar >= PCI_BAR_COUNT {
+		return None;
+	}
+
+	let offset = PCI_CFG_BAR0_OFFSET + bar as u32 * 4;
+	let lo = read_u32(pci_io, offset)?;
+
+	if lo & PCI_BAR_IO != 0 {
+		return None;
+	}
+
+	let is_64 = lo & PCI_BAR_TYPE_MASK == PCI_BAR_TYPE_64;
+	if is_64 && bar + 1 == PCI_BAR_COUNT {
+		return None;
+	}
+
+	let guard = DecodeGuard::new(pci_io)?;
+	let lo_mask = probe_mask(guard.pci_io, offset)? & !PCI_BAR_MEM_FLAGS_MASK;
+	let hi_mask = if is_64 { probe_mask(guard.pci_io, offset + 4)? } else { 0xFFFFFFFF };
+	drop(guard);
+
+	let mask = ((hi_mask as u64) << 32) | lo_mask as u64;
+	if lo_mask == 0 {
+		return None;
+	}
+
+	Some((!mask).wrapping_add(1))
+}
+
+// the assigned memory BARs of the device as (BAR, address, size), the upper half of a 64-bit one skipped
+pub fn memory_bars<P: PciConfigAccess>(pci_io: &mut P) -> Vec<(u8, u64, u64)> {
+	let mut bars = Vec::new();
+	let mut bar = 0;
+
+	while bar < PCI_BAR_COUNT {
+		let lo = read_u32(pci_io, PCI_CFG_BAR0_OFFSET + bar as u32 * 4).unwrap_or(0);
+		let next = if lo & (PCI_BAR_IO | PCI_BAR_TYPE_MASK) == PCI_BAR_TYPE_64 { bar + 2 } else { bar + 1 };
+
+		if let Some(addr) = bar_address(pci_io, bar) && let Some(size) = probe_bar_size(pci_io, bar) {
+			bars.push((bar, addr, size));
+		}
+
+		bar = next;
+	}
+
+	bars
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn short_reads_return_none() {
+		let mut space = ConfigSpace([0xAA; 256]);
+
+		// the access runs past the end of config space, only part of it could be read
+		assert_eq!(read_u32(&mut space, 0xFE), None);
+		assert_eq!(read_u16(&mut space, 0xFF), None);
+		assert_eq!(read_bytes::<3>(&mut space, 0xFE), None);
+		assert_eq!(read_u32(&mut space, 0xFC), Some(0xAAAA_AAAA));
+	}
+
+	// a 32-bit memory BAR0 of `size` bytes, whose reads fail while decoding is off if `fail_undecoded`
+	struct BarSpace {
+		space: ConfigSpace,
+		size: u32,
+		fail_undecoded: bool,
+		// every value written to the command register
+		commands: Vec<u16>,
+	}
+
+	impl BarSpace {
+		fn new(command: u16, bar: u32, size: u32) -> Self {
+			let mut space = ConfigSpace([0; 256]);
+			space.0[0x04..0x06].copy_from_slice(&command.to_le_bytes());
+			space.0[0x10..0x14].copy_from_slice(&bar.to_le_bytes());
+
+			BarSpace { space, size, fail_undecoded: false, commands: Vec::new() }
+		}
+
+		fn command(&self) -> u16 {
+			u16::from_le_bytes([self.space.0[0x04], self.space.0[0x05]])
+		}
+	}
+
+	impl PciConfigAccess for BarSpace {
+		fn config_read(&mut self, width: u32, offset: u32, count: usize, buf: &mut [u8]) -> Option<()> {
+			let bar = offset < 0x14 && offset + width * count as u32 > 0x10;
+			if bar && self.fail_undecoded && self.command() & PCI_COMMAND_DECODE == 0 {
+				return None;
+			}
+
+			self.space.config_read(width, offset, count, buf)
+		}
+
+		fn config_write(&mut self, offset: u32, buf: &mut [u8]) -> Option<()> {
+			self.space.config_write(offset, buf)?;
+
+			if offset == PCI_CFG_COMMAND_OFFSET && buf.len() >= 2 {
+				self.commands.push(self.command());
+			}
+
+			// the address bits below the BAR size don't stick
+			if offset == PCI_CFG_BAR0_OFFSET && buf.len() == 4 {
+				let value = u32::from_le_bytes(self.space.0[0x10..0x14].try_into().unwrap()) & !(self.size - 1);
+				self.space.0[0x10..0x14].copy_from_slice(&value.to_le_bytes());
+			}
+
+			Some(())
+		}
+	}
+
+	#[test]
+	fn bar_probe_restores_command_register() {
+		let mut space = BarSpace::new(0x0007, 0xE000_0000, 0x1000_0000);
+
+		assert_eq!(probe_bar_size(&mut space, 0), Some(0x1000_0000));
+		// decoding was off for the probe only
+		assert_eq!(space.commands, [0x0004, 0x0007]);
+		assert_eq!(space.command(), 0x0007);
+		assert_eq!(read_u32(&mut space, PCI_CFG_BAR0_OFFSET), Some(0xE000_0000));
+	}
+
+	#[test]
+	fn failed_bar_probe_restores_command_register() {
+		let mut space = BarSpace::new(0x0007, 0xE000_0000, 0x1000_0000);
+		space.fail_undecoded = true;
+
+		assert_eq!(probe_bar_size(&mut space, 0), None);
+		assert_eq!(space.command(), 0x0007);
+	}
+
+	#[test]
+	fn class_code_byte_order() {
+		let mut space = ConfigSpace([0; 256]);
+		// revision, then programming interface, subclass and base class
+		space.0[0x08..0x0C].copy_from_slice(&[0x07, 0x01, 0x80, 0x03]);
+
+		assert_eq!(read_class(&mut space), Some(ClassCode { base: 0x03, sub: 0x80, prog: 0x01 }));
+	}
+
+	#[test]
+	fn class_code_of_known_devices() {
+		for (bytes, class) in [
+			// VGA compatible controller
+			([0x00, 0x00, 0x03], ClassCode { base: 0x03, sub: 0x00, prog: 0x00 }),
+			// host bridge
+			([0x00, 0x00, 0x06], ClassCode { base: 0x06, sub: 0x00, prog: 0x00 }),
+			// xHCI controller
+			([0x30, 0x03, 0x0C], ClassCode { base: 0x0C, sub: 0x03, prog: 0x30 }),
+		] {
+			let mut space = ConfigSpace([0; 256]);
+			space.0[0x09..0x0C].copy_from_slice(&bytes);
+
+			assert_eq!(read_class(&mut space), Some(class));
+		}
+	}
+}
@@ -0,0 +1,191 @@
+// Dry run: everything a real run would do, worked out from fw_cfg, the configuration and the
+// current device state, but without allocating memory or writing to any device. Unlike `check`,
+// this describes the setup instead of judging it.
+
+use log::{error, info};
+use qemu_fw_cfg::FwCfg;
+use uefi::{boot::ScopedProtocol, proto::pci::PciIo, Status};
+
+use crate::{
+	ceiling_32, check_gms_size, compressed, embedded, existing, extended_vbt_file_size, find_opregion_file,
+	fw_cfg_open, generation::Generation, igd_location, is_existing_stolen_memory, is_mediated, is_virtual_function,
+	match_device, mirror, pci, quirks::Gms, read_bdsm, read_bdsm_base, read_bdsm_size, read_host_bdsm, read_tolud,
+	stolen_memory_alignment, uses_bdsm64, validate_existing, CONFIG, PAGE_SIZE, PCI_CFG_ASLS_OFFSET,
+	PCI_CFG_BDSM64_OFFSET, PCI_CFG_BDSM_MIRROR_OFFSET, PCI_CFG_GGC_OFFSET,
+};
+
+fn plan_opregion(fw_cfg: &mut FwCfg, pci_io: &mut ScopedProtocol<PciIo>) {
+	let asls = pci::read_u32(pci_io, PCI_CFG_ASLS_OFFSET).unwrap_or(0) as usize;
+	info!("OpRegion: ASLS currently {:#010x}", asls);
+
+	if unsafe { !CONFIG.force } && let Some(existing) = existing::at_asls(asls, unsafe { CONFIG.signatures() })
+		&& validate_existing(existing).is_ok() {
+		info!("OpRegion: would leave the existing OpRegion @ {:#x} ({} bytes) as is", existing.base, existing.size);
+		return;
+	}
+
+	let opregion = find_opregion_file(fw_cfg, pci_io.get_location().ok());
+	let mirrored = match (&opregion, unsafe { CONFIG.opregion_mirror }) {
+		(None, Some(location)) => mirror::read(pci_io, location, unsafe { CONFIG.signatures() }, unsafe { CONFIG.max_opregion_size }),
+		_ => None,
+	};
+
+	let size = match (opregion, mirrored, embedded::OPREGION) {
+		(Some((name, file)), _, _) => {
+			let size = compressed::size(&name, file.size());
+			info!("OpRegion: would copy {} ({} bytes)", name, size);
+			size
+		}
+		(None, Some(blob), _) => {
+			info!("OpRegion: would copy the mirrored host OpRegion ({} bytes)", blob.len());
+			blob.len()
+		}
+		(None, None, Some(blob)) => {
+			info!("OpRegion: would copy the embedded OpRegion ({} bytes)", blob.len());
+			blob.len()
+		}
+		(None, None, None) => {
+			error!("OpRegion: not passed through, setup would fail!");
+			return;
+		}
+	};
+
+	let vbt = extended_vbt_file_size(fw_cfg);
+	if vbt > 0 {
+		info!("OpRegion: {} bytes reserved behind it for the extended VBT", vbt);
+	}
+
+	let pages = (size + vbt).div_ceil(PAGE_SIZE);
+	match unsafe { CONFIG.opregion_address } {
+		Some(fixed) => info!("OpRegion: would allocate {} pages at the fixed address {:#x}", pages, fixed),
+		None => info!("OpRegion: would allocate {} pages of {:?} ending at or below {:#x}", pages, unsafe { CONFIG.opregion_memory_type }, ceiling_32()),
+	}
+
+	info!("OpRegion: would write ASLS ({:#04x}) with the allocated address", PCI_CFG_ASLS_OFFSET);
+}
+
+fn plan_stolen_memory(fw_cfg: &mut FwCfg, pci_io: &mut ScopedProtocol<PciIo>, generation: Option<Generation>, device: u16) {
+	if unsafe { CONFIG.skip_stolen_memory } {
+		info!("StolenMemory: skipped as configured");
+		return;
+	}
+
+	let gms = Gms::of(generation, device);
+	let size = match (read_bdsm_size(fw_cfg, gms), unsafe { CONFIG.stolen_memory_bar }) {
+		(Ok(Some(size)), _) => size,
+		// probing the BAR means writing to it, which a dry run must not do
+		(Ok(None), Some(bar)) => {
+			info!("StolenMemory: would be sized from BAR{} at setup time", bar);
+			0
+		}
+		(Ok(None), None) => {
+			error!("StolenMemory: size not passed through, setup would fail!");
+			return;
+		}
+		(Err(status), _) => {
+			error!("StolenMemory: invalid size ({:?}), setup would fail!", status);
+			return;
+		}
+	};
+
+	let bdsm64 = uses_bdsm64(generation);
+	let offset = if bdsm64 { PCI_CFG_BDSM64_OFFSET } else { PCI_CFG_BDSM_MIRROR_OFFSET };
+	let current = read_bdsm(pci_io, generation).unwrap_or(0);
+	info!("StolenMemory: BDSM ({} bit at {:#04x}) currently {:#x}", if bdsm64 { 64 } else { 32 }, offset, current);
+
+	if unsafe { !CONFIG.force } && size > 0 && is_existing_stolen_memory(current, size) {
+		info!("StolenMemory: would leave the existing stolen memory @ {:#x} as is", current);
+		return;
+	}
+
+	if size > 0 && check_gms_size(gms, size).is_err() {
+		error!("StolenMemory: the size can't be encoded, setup would fail!");
+		return;
+	}
+
+	match read_bdsm_base(fw_cfg) {
+		Ok(Some(base)) => {
+			info!("StolenMemory: would use {:#x} as placed by the host, without allocating", base);
+			return;
+		}
+		Ok(None) => {}
+		Err(status) => {
+			error!("StolenMemory: invalid host-provided base ({:?}), setup would fail!", status);
+			return;
+		}
+	}
+
+	if size > 0 {
+		info!("StolenMemory: would allocate {} MiB ({} pages), aligned to {} MiB", size / 1024 / 1024, size / PAGE_SIZE,
+			stolen_memory_alignment(generation) / 1024 / 1024);
+	}
+
+	let host = match read_host_bdsm(fw_cfg) {
+		Ok(host) => host,
+		Err(status) => {
+			error!("StolenMemory: invalid host stolen memory base ({:?}), setup would fail!", status);
+			return;
+		}
+	};
+
+	match unsafe { (host, CONFIG.stolen_memory_address, CONFIG.allocation_ceiling) } {
+		(Some(host), _, _) => info!("StolenMemory: at the host's base {:#x}", host),
+		(None, Some(fixed), _) => info!("StolenMemory: at the fixed address {:#x}", fixed),
+		(None, None, ceiling) => {
+			if unsafe { CONFIG.tolud_placement } && let Some(tolud) = read_tolud() {
+				info!("StolenMemory: preferably directly below TOLUD {:#x}", tolud);
+			}
+
+			match (bdsm64, ceiling) {
+				(true, Some(ceiling)) => info!("StolenMemory: ending at or below {:#x}", ceiling),
+				(true, None) => info!("StolenMemory: below 4 GiB if possible, otherwise anywhere"),
+				(false, _) => info!("StolenMemory: ending at or below {:#x}", ceiling_32()),
+			}
+		}
+	}
+
+	match (gms, pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET)) {
+		(Some(gms), Some(ggc)) if size > 0 => match gms.encode_gms(size) {
+			Some((mask, value)) => info!("StolenMemory: would update GGC {:#06x} -> {:#06x}", ggc, (ggc & !mask) | value),
+			None => info!("StolenMemory: size can't be encoded in GMS, GGC {:#06x} would stay", ggc),
+		},
+		_ => info!("StolenMemory: would leave GGC alone"),
+	}
+}
+
+pub fn run() -> Status {
+	let Some(mut fw_cfg) = (unsafe { fw_cfg_open() }) else {
+		error!("fw_cfg is not available, nothing would be set up!");
+		return Status::NOT_FOUND;
+	};
+
+	let igd = igd_location();
+	let (seg, bus, dev, func) = igd;
+
+	let Some(mut pci_io) = pci::open_shared(igd) else {
+		error!("No PCI device at {:04x}:{:02x}:{:02x}.{}!", seg, bus, dev, func);
+		return Status::NOT_FOUND;
+	};
+
+	let Some(device) = match_device(&mut pci_io) else {
+		error!("{:04x}:{:02x}:{:02x}.{} is not an Intel display controller!", seg, bus, dev, func);
+		return Status::UNSUPPORTED;
+	};
+
+	let generation = Generation::from_device_id(device);
+	info!("Dry run for device {:04x} ({:?}) at {:04x}:{:02x}:{:02x}.{}, nothing will be changed", device, generation, seg, bus, dev, func);
+
+	plan_opregion(&mut fw_cfg, &mut pci_io);
+
+	if Generation::is_discrete(device) {
+		info!("StolenMemory: would be skipped, the device is a discrete GPU");
+	} else if is_mediated(&mut pci_io) {
+		info!("StolenMemory: would be skipped, the device is a GVT-g vGPU");
+	} else if is_virtual_function(&mut pci_io, generation) {
+		info!("StolenMemory: would be skipped, the device is an SR-IOV virtual function");
+	} else {
+		plan_stolen_memory(&mut fw_cfg, &mut pci_io, generation, device);
+	}
+
+	Status::SUCCESS
+}
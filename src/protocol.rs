@@ -0,0 +1,128 @@
+// The IgdAssignment protocol, installed on the handle of every device we configured so that other
+// drivers, e.g. a platform GOP or an OEM DXE module, can ask what was set up and have the registers
+// programmed again after touching them, instead of racing us on the same registers.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::ffi::c_void;
+use log::error;
+use uefi::{boot, guid, Guid, Handle, Status};
+
+use crate::{args::Location, pci, reprogram, OUTCOME};
+
+pub const IGD_ASSIGNMENT_PROTOCOL_GUID: Guid = guid!("2c6b4a31-8f0e-4d7a-b5c9-1e3d7f9a6b24");
+
+const IGD_ASSIGNMENT_PROTOCOL_REVISION: u32 = 1;
+
+// what was programmed, a base/size of zero means the step was not performed or failed
+#[repr(C)]
+pub struct IgdAssignmentInfo {
+	pub segment: u16,
+	pub bus: u8,
+	pub device: u8,
+	pub function: u8,
+	pub opregion_base: u64,
+	pub opregion_size: u64,
+	pub bdsm_base: u64,
+	pub bdsm_size: u64,
+}
+
+#[repr(C)]
+pub struct IgdAssignmentProtocol {
+	pub revision: u32,
+	pub get_info: unsafe extern "efiapi" fn(*const IgdAssignmentProtocol, *mut IgdAssignmentInfo) -> Status,
+	// write ASLS and BDSM again with the addresses already set up, nothing is allocated anew
+	pub reprogram: unsafe extern "efiapi" fn(*const IgdAssignmentProtocol) -> Status,
+	// not part of the interface, consumers must not rely on anything past `reprogram`
+	location: Location,
+}
+
+// installed instances, so they can be removed again before the image is unloaded
+static mut INSTALLED: Vec<(Handle, Box<IgdAssignmentProtocol>)> = Vec::new();
+
+unsafe extern "efiapi" fn get_info(this: *const IgdAssignmentProtocol, info: *mut IgdAssignmentInfo) -> Status {
+	if this.is_null() || info.is_null() {
+		return Status::INVALID_PARAMETER;
+	}
+
+	let location = (*this).location;
+	let Some(d) = OUTCOME.devices.iter().rev().find(|d| d.location == location) else {
+		return Status::NOT_FOUND;
+	};
+
+	let (seg, bus, dev, func) = location;
+	info.write(IgdAssignmentInfo {
+		segment: seg as u16,
+		bus: bus as u8,
+		device: dev as u8,
+		function: func as u8,
+		opregion_base: d.opregion.map_or(0, |r| r.base as u64),
+		opregion_size: d.opregion.map_or(0, |r| r.size as u64),
+		bdsm_base: d.stolen_memory.map_or(0, |r| r.base as u64),
+		bdsm_size: d.stolen_memory.map_or(0, |r| r.size as u64),
+	});
+
+	Status::SUCCESS
+}
+
+unsafe extern "efiapi" fn reprogram_device(this: *const IgdAssignmentProtocol) -> Status {
+	if this.is_null() {
+		return Status::INVALID_PARAMETER;
+	}
+
+	let location = (*this).location;
+	let Some(d) = OUTCOME.devices.iter().rev().find(|d| d.location == location).copied() else {
+		return Status::NOT_FOUND;
+	};
+
+	// shared access, the caller is likely the driver managing the device
+	let Some(mut pci_io) = pci::open_shared(location) else {
+		return Status::NOT_FOUND;
+	};
+
+	reprogram(&mut pci_io, &d)
+}
+
+pub unsafe fn install(handle: Handle, location: Location) {
+	if INSTALLED.iter().any(|(h, _)| *h == handle) {
+		return;
+	}
+
+	let protocol = Box::new(IgdAssignmentProtocol {
+		revision: IGD_ASSIGNMENT_PROTOCOL_REVISION,
+		get_info,
+		reprogram: reprogram_device,
+		location,
+	});
+
+	let interface = &*protocol as *const IgdAssignmentProtocol as *const c_void;
+	if let Err(e) = boot::install_protocol_interface(Some(handle), &IGD_ASSIGNMENT_PROTOCOL_GUID, interface) {
+		error!("Failed to install the IgdAssignment protocol: {:?}!", e.status());
+		return;
+	}
+
+	INSTALLED.push((handle, protocol));
+}
+
+fn remove(handle: Handle, protocol: Box<IgdAssignmentProtocol>) {
+	let interface = &*protocol as *const IgdAssignmentProtocol as *const c_void;
+
+	if unsafe { boot::uninstall_protocol_interface(handle, &IGD_ASSIGNMENT_PROTOCOL_GUID, interface) }.is_err() {
+		// someone still has it open, leaking it beats a dangling interface
+		error!("Failed to uninstall the IgdAssignment protocol!");
+		Box::leak(protocol);
+	}
+}
+
+pub unsafe fn uninstall(handle: Handle) {
+	if let Some(i) = INSTALLED.iter().position(|(h, _)| *h == handle) {
+		let (handle, protocol) = INSTALLED.remove(i);
+		remove(handle, protocol);
+	}
+}
+
+// the interfaces point into our image, they have to go before it is unloaded
+pub unsafe fn uninstall_all() {
+	for (handle, protocol) in INSTALLED.drain(..) {
+		remove(handle, protocol);
+	}
+}
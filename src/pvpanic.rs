@@ -0,0 +1,52 @@
+// Telling the host about a fatal setup failure through QEMU's pvpanic device (tag 0x0029), so the
+// management layer sees a panic event right away instead of a VM booting to a black screen. The ISA
+// device sits at port 0x505 on x86, `pvpanic-pci` has the same register at the start of BAR0.
+
+use igd_assignment::pci;
+use log::{info, warn};
+
+// what both devices report as supported, and write to signal a guest panic
+const PVPANIC_PANICKED: u8 = 1 << 0;
+
+const PVPANIC_PCI_VENDOR: u16 = 0x1B36;
+const PVPANIC_PCI_DEVICE: u16 = 0x0011;
+const PVPANIC_PCI_BAR: u8 = 0;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+const PVPANIC_PORT: u16 = 0x505;
+
+// an absent device reads as all ones
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+fn signal_isa() -> bool {
+	let supported: u8;
+	unsafe { core::arch::asm!("in al, dx", in("dx") PVPANIC_PORT, out("al") supported, options(nomem, nostack, preserves_flags)) };
+
+	if supported == 0xFF || supported & PVPANIC_PANICKED == 0 {
+		return false;
+	}
+
+	unsafe { core::arch::asm!("out dx, al", in("dx") PVPANIC_PORT, in("al") PVPANIC_PANICKED, options(nomem, nostack, preserves_flags)) };
+	true
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+fn signal_isa() -> bool {
+	false
+}
+
+fn signal_pci() -> bool {
+	let Some(mut pci_io) = pci::open_by_id(PVPANIC_PCI_VENDOR, PVPANIC_PCI_DEVICE) else {
+		return false;
+	};
+
+	pci::mmio_write_u8(&mut pci_io, PVPANIC_PCI_BAR, 0, PVPANIC_PANICKED).is_some()
+}
+
+// depending on the `-action panic=` setting, QEMU may pause or stop the VM right away
+pub(crate) fn signal() {
+	info!("Reporting the failure to the host through pvpanic");
+
+	if !signal_isa() && !signal_pci() {
+		warn!("No pvpanic device to report the failure to");
+	}
+}
@@ -0,0 +1,207 @@
+// Platforms that don't follow the register layout of their generation, keyed by device ID, so their
+// special cases live in one table instead of being spread over the setup code.
+//
+// Braswell and Cherry Trail are Gen8, but have the Gen6/7 GGC layout: GMS in bits 7:3, in 32 MiB steps
+// and, from 0x11 on, 4 MiB steps from 8 and from 36 MiB, and GGMS in bits 9:8 as 2, 4 or 8 MiB.
+// Broxton and Gemini Lake host firmware may flag the OpRegion as belonging to a headless SKU, upon
+// which the guest driver disables the display hardware.
+
+use core::fmt;
+
+use crate::{generation::Generation, opregion};
+
+const MIB: usize = 1024 * 1024;
+
+// how GMS and GGMS in GGC encode the stolen memory sizes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Gms {
+	Generation(Generation),
+	Cherryview,
+}
+
+#[derive(Clone, Copy)]
+pub struct Quirk {
+	// replaces the GMS encoding of the generation
+	pub gms: Option<Gms>,
+	// PCON bits to set and clear in a fresh OpRegion copy
+	pub pcon: Option<(u32, u32)>,
+}
+
+const NONE: Quirk = Quirk { gms: None, pcon: None };
+const HEADLESS_SKU: Option<(u32, u32)> = Some((0, opregion::PCON_HEADLESS_SKU));
+
+// first and last device ID of each range, sorted by the first ID
+const QUIRKS: &[(u16, u16, Quirk)] = &[
+	(0x0A84, 0x0A84, Quirk { pcon: HEADLESS_SKU, ..NONE }), // Broxton
+	(0x1A84, 0x1A85, Quirk { pcon: HEADLESS_SKU, ..NONE }), // Broxton
+	(0x22B0, 0x22B3, Quirk { gms: Some(Gms::Cherryview), ..NONE }), // Braswell, Cherry Trail
+	(0x3184, 0x3185, Quirk { pcon: HEADLESS_SKU, ..NONE }), // Gemini Lake
+	(0x5A84, 0x5A85, Quirk { pcon: HEADLESS_SKU, ..NONE }), // Broxton
+];
+
+pub fn lookup(device: u16) -> Option<&'static Quirk> {
+	QUIRKS.iter()
+		.find(|&&(first, last, _)| (first..=last).contains(&device))
+		.map(|(_, _, quirk)| quirk)
+}
+
+// the PCON bits to set and clear for `device`, `None` if its OpRegion is fine as passed
+pub fn pcon_fixup(device: u16) -> Option<(u32, u32)> {
+	lookup(device)?.pcon
+}
+
+fn encode_cherryview(size: usize) -> Option<(u16, u16)> {
+	let value = match size / MIB {
+		_ if size % (4 * MIB) != 0 => return None,
+		mib @ 32..=512 if mib % 32 == 0 => mib / 32,
+		mib @ 8..=28 => 0x11 + (mib - 8) / 4,
+		mib @ 36..=68 => 0x17 + (mib - 36) / 4,
+		_ => return None,
+	};
+
+	Some((0x00F8, (value as u16) << 3))
+}
+
+fn decode_cherryview(ggc: u16) -> Option<usize> {
+	let size = match (ggc >> 3) & 0x1F {
+		0 => return None,
+		value @ 0x01..=0x10 => value as usize * 32 * MIB,
+		value @ 0x11..=0x16 => (value as usize - 0x11) * 4 * MIB + 8 * MIB,
+		value => (value as usize - 0x17) * 4 * MIB + 36 * MIB,
+	};
+
+	Some(size)
+}
+
+impl Gms {
+	// the encoding `device` uses, `None` for an unknown generation without a quirk
+	pub fn of(generation: Option<Generation>, device: u16) -> Option<Self> {
+		lookup(device).and_then(|q| q.gms).or(generation.map(Gms::Generation))
+	}
+
+	// Graphics Mode Select field of GGC, as (mask, value) for a stolen memory size of `size` bytes
+	pub fn encode_gms(self, size: usize) -> Option<(u16, u16)> {
+		match self {
+			Gms::Generation(generation) => generation.encode_gms(size),
+			Gms::Cherryview => encode_cherryview(size),
+		}
+	}
+
+	// the stolen memory size GMS in `ggc` selects, `None` if GMS is zero or a value the platform
+	// doesn't define
+	pub fn decode_gms(self, ggc: u16) -> Option<usize> {
+		match self {
+			Gms::Generation(generation) => generation.decode_gms(ggc),
+			Gms::Cherryview => decode_cherryview(ggc),
+		}
+	}
+
+	// The largest stolen memory size GMS can encode that doesn't exceed `size`, along with the GMS field
+	// value for it; `None` if there is none, or no GMS encoding is supported for the platform.
+	pub fn largest_gms_size(self, size: usize) -> Option<(usize, u16)> {
+		let (mask, _) = self.encode_gms(32 * MIB)?;
+		let shift = mask.trailing_zeros();
+		let sizes = (1..=mask >> shift).filter_map(|gms| Some((self.decode_gms(gms << shift)?, gms)));
+
+		sizes.filter(|&(s, _)| s <= size).max_by_key(|&(s, _)| s)
+	}
+
+	// size of the GTT stolen memory selected by the GGMS field of GGC, `None` if there is none
+	pub fn gtt_stolen_memory_size(self, ggc: u16) -> Option<usize> {
+		match self {
+			Gms::Generation(generation) => generation.gtt_stolen_memory_size(ggc),
+			Gms::Cherryview => match (ggc >> 8) & 0x3 {
+				0 => None,
+				ggms => Some((1usize << ggms) * MIB),
+			},
+		}
+	}
+}
+
+impl fmt::Debug for Gms {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Gms::Generation(generation) => generation.fmt(f),
+			Gms::Cherryview => f.write_str("Cherryview"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SKYLAKE: Gms = Gms::Generation(Generation::Gen9);
+
+	#[test]
+	fn gen8_gms_encoding() {
+		assert_eq!(SKYLAKE.encode_gms(32 * MIB), Some((0xFF00, 0x0100)));
+		assert_eq!(SKYLAKE.encode_gms(2048 * MIB), Some((0xFF00, 0x4000)));
+		assert_eq!(SKYLAKE.encode_gms(4 * MIB), Some((0xFF00, 0xF000)));
+		assert_eq!(SKYLAKE.encode_gms(60 * MIB), Some((0xFF00, 0xFE00)));
+		assert_eq!(SKYLAKE.encode_gms(2080 * MIB), None);
+		assert_eq!(SKYLAKE.encode_gms(MIB), None);
+	}
+
+	#[test]
+	fn gen8_gms_decoding() {
+		assert_eq!(SKYLAKE.decode_gms(0x0100), Some(32 * MIB));
+		assert_eq!(SKYLAKE.decode_gms(0x02C1), Some(64 * MIB));
+		assert_eq!(SKYLAKE.decode_gms(0xF000), Some(4 * MIB));
+		assert_eq!(SKYLAKE.decode_gms(0x0000), None);
+		assert_eq!(SKYLAKE.decode_gms(0x4100), None);
+	}
+
+	#[cfg(feature = "legacy")]
+	#[test]
+	fn gen6_gms_encoding() {
+		let gms = Gms::Generation(Generation::Gen7);
+
+		assert_eq!(gms.encode_gms(64 * MIB), Some((0x00F8, 0x0010)));
+		assert_eq!(gms.decode_gms(0x0010), Some(64 * MIB));
+		assert_eq!(gms.encode_gms(48 * MIB), None);
+	}
+
+	#[test]
+	fn cherryview_gms_encoding() {
+		assert_eq!(Gms::Cherryview.encode_gms(64 * MIB), Some((0x00F8, 0x0010)));
+		assert_eq!(Gms::Cherryview.encode_gms(8 * MIB), Some((0x00F8, 0x0088)));
+		assert_eq!(Gms::Cherryview.encode_gms(36 * MIB), Some((0x00F8, 0x00B8)));
+		assert_eq!(Gms::Cherryview.encode_gms(6 * MIB), None);
+	}
+
+	#[test]
+	fn gms_round_trip() {
+		for gms in [SKYLAKE, Gms::Cherryview] {
+			for size in (0..=2048 * MIB).step_by(4 * MIB) {
+				if let Some((mask, value)) = gms.encode_gms(size) {
+					assert_eq!(value & !mask, 0);
+					assert_eq!(gms.decode_gms(value), Some(size), "{:?} {} MiB", gms, size / MIB);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn largest_gms_size_below() {
+		assert_eq!(SKYLAKE.largest_gms_size(100 * MIB), Some((96 * MIB, 3)));
+		// the 4 MiB steps are closer than the 32 MiB ones
+		assert_eq!(SKYLAKE.largest_gms_size(50 * MIB), Some((48 * MIB, 0xFB)));
+		assert_eq!(SKYLAKE.largest_gms_size(2 * MIB), None);
+	}
+
+	#[test]
+	fn gtt_stolen_memory_sizes() {
+		assert_eq!(SKYLAKE.gtt_stolen_memory_size(0x0000), None);
+		assert_eq!(SKYLAKE.gtt_stolen_memory_size(0x0040), Some(2 * MIB));
+		assert_eq!(SKYLAKE.gtt_stolen_memory_size(0x00C0), Some(8 * MIB));
+		assert_eq!(Gms::Cherryview.gtt_stolen_memory_size(0x0100), Some(2 * MIB));
+	}
+
+	#[test]
+	fn quirk_overrides_generation() {
+		assert_eq!(Gms::of(Some(Generation::Gen8), 0x22B0), Some(Gms::Cherryview));
+		assert_eq!(Gms::of(Some(Generation::Gen9), 0x3E92), Some(SKYLAKE));
+		assert_eq!(Gms::of(None, 0x3E92), None);
+	}
+}
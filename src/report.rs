@@ -0,0 +1,59 @@
+// Machine-readable result record written back to the host, for test harnesses driving QEMU.
+//
+// Layout (all fields little-endian, version 1):
+//   header: "IGDR", u16 version, u16 entry count, u32 entry size
+//   entries: u16 segment, u8 bus, u8 device, u8 function, u8 reserved, u16 device ID,
+//            u64 OpRegion status, u64 stolen memory status,
+//            u64 OpRegion base, u64 OpRegion size, u64 stolen memory base, u64 stolen memory size
+// Statuses are EFI_STATUS values, or all ones if the step wasn't attempted on that device.
+
+use alloc::vec::Vec;
+use log::{error, info};
+use uefi::Status;
+
+use crate::{fw_cfg_raw, outcome::DeviceOutcome};
+
+const RESULT_FILE: &str = "etc/igd-result";
+const RESULT_MAGIC: [u8; 4] = *b"IGDR";
+const RESULT_VERSION: u16 = 1;
+const RESULT_ENTRY_SIZE: u32 = 56;
+const STATUS_NOT_ATTEMPTED: u64 = u64::MAX;
+
+fn status(status: Option<Status>) -> u64 {
+	status.map_or(STATUS_NOT_ATTEMPTED, |s| s.0 as u64)
+}
+
+pub fn serialize(devices: &[DeviceOutcome]) -> Vec<u8> {
+	let mut record = Vec::with_capacity(12 + devices.len() * RESULT_ENTRY_SIZE as usize);
+
+	record.extend_from_slice(&RESULT_MAGIC);
+	record.extend_from_slice(&RESULT_VERSION.to_le_bytes());
+	record.extend_from_slice(&(devices.len() as u16).to_le_bytes());
+	record.extend_from_slice(&RESULT_ENTRY_SIZE.to_le_bytes());
+
+	for d in devices {
+		let (seg, bus, dev, func) = d.location;
+
+		record.extend_from_slice(&(seg as u16).to_le_bytes());
+		record.extend_from_slice(&[bus as u8, dev as u8, func as u8, 0]);
+		record.extend_from_slice(&d.device_id.to_le_bytes());
+		record.extend_from_slice(&status(d.opregion_status).to_le_bytes());
+		record.extend_from_slice(&status(d.stolen_memory_status).to_le_bytes());
+		record.extend_from_slice(&d.opregion.map_or(0, |r| r.base as u64).to_le_bytes());
+		record.extend_from_slice(&d.opregion.map_or(0, |r| r.size as u64).to_le_bytes());
+		record.extend_from_slice(&d.stolen_memory.map_or(0, |r| r.base as u64).to_le_bytes());
+		record.extend_from_slice(&d.stolen_memory.map_or(0, |r| r.size as u64).to_le_bytes());
+	}
+
+	record
+}
+
+pub fn write(devices: &[DeviceOutcome]) {
+	let record = serialize(devices);
+
+	if fw_cfg_raw::write_file(RESULT_FILE, &record) {
+		info!("Wrote {} byte result record to {}", record.len(), RESULT_FILE);
+	} else {
+		error!("Failed to write the result record, is {} a writable fw_cfg file?", RESULT_FILE);
+	}
+}
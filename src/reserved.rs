@@ -0,0 +1,149 @@
+// A range the platform reserved for the OpRegion and stolen memory before DXE, so neither has to be
+// allocated late in boot, where a large aligned allocation below 4 GiB may fail on small guests or
+// fragment what is left. It is found, in this order, in:
+//
+//   `etc/igd-reserved`, as a little-endian u64 base followed by a u64 size
+//   a configuration table with GUID `IGD_RESERVED_GUID`, pointing at the same two u64s
+//   a GUID extension HOB with that GUID, holding them
+//
+// The range has to be reserved or ACPI NVS in the memory map already. Stolen memory is carved from its
+// start and the OpRegion from its end, so the alignment of the former wastes as little as possible.
+
+use core::ptr::addr_of_mut;
+use log::{debug, error, info};
+use uefi::{guid, Guid};
+
+use crate::{fw_cfg_raw, is_existing_stolen_memory, PAGE_SIZE};
+
+const RESERVED_FILE: &str = "etc/igd-reserved";
+pub const IGD_RESERVED_GUID: Guid = guid!("b5e0a3c4-7d19-4f62-8e4b-2a6c9d1f07e3");
+
+const HOB_LIST_GUID: Guid = guid!("7739f24c-93d7-11d4-9a3a-0090273fc14d");
+const HOB_TYPE_GUID_EXTENSION: u16 = 0x0004;
+const HOB_TYPE_END_OF_LIST: u16 = 0xFFFF;
+const HOB_HEADER_SIZE: usize = 8;
+const RESERVED_SIZE: usize = 16;
+
+// what is still free of the range, `[low, high)`
+#[derive(Clone, Copy)]
+struct Carver {
+	low: usize,
+	high: usize,
+}
+
+// `None` until the first lookup, then whatever it found
+static mut RESERVED: Option<Option<Carver>> = None;
+
+fn parse(data: &[u8]) -> Option<(u64, u64)> {
+	let data = data.get(..RESERVED_SIZE)?;
+	Some((u64::from_le_bytes(data[..8].try_into().unwrap()), u64::from_le_bytes(data[8..].try_into().unwrap())))
+}
+
+fn from_file() -> Option<(u64, u64)> {
+	let file = fw_cfg_raw::find_file(RESERVED_FILE)?;
+
+	if file.size != RESERVED_SIZE {
+		error!("{} is {} bytes long, expected a u64 base and size!", RESERVED_FILE, file.size);
+		return None;
+	}
+
+	let mut buf = [0u8; RESERVED_SIZE];
+	fw_cfg_raw::select(file.key);
+	fw_cfg_raw::read(&mut buf);
+
+	parse(&buf)
+}
+
+fn from_config_table() -> Option<(u64, u64)> {
+	let addr = uefi::system::with_config_table(|entries| {
+		entries.iter().find(|e| e.guid == IGD_RESERVED_GUID).map(|e| e.address as *const u8)
+	})?;
+
+	parse(unsafe { core::slice::from_raw_parts(addr, RESERVED_SIZE) })
+}
+
+fn from_hob() -> Option<(u64, u64)> {
+	let mut hob = uefi::system::with_config_table(|entries| {
+		entries.iter().find(|e| e.guid == HOB_LIST_GUID).map(|e| e.address as *const u8)
+	})?;
+
+	loop {
+		let header = unsafe { core::slice::from_raw_parts(hob, HOB_HEADER_SIZE) };
+		let ty = u16::from_le_bytes([header[0], header[1]]);
+		let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+
+		if ty == HOB_TYPE_END_OF_LIST || len < HOB_HEADER_SIZE {
+			return None;
+		}
+
+		if ty == HOB_TYPE_GUID_EXTENSION && len >= HOB_HEADER_SIZE + 16 + RESERVED_SIZE {
+			let hob = unsafe { core::slice::from_raw_parts(hob, len) };
+			let guid = Guid::from_bytes(hob[HOB_HEADER_SIZE..HOB_HEADER_SIZE + 16].try_into().unwrap());
+
+			if guid == IGD_RESERVED_GUID {
+				return parse(&hob[HOB_HEADER_SIZE + 16..]);
+			}
+		}
+
+		hob = unsafe { hob.add(len) };
+	}
+}
+
+fn discover() -> Option<Carver> {
+	let (source, (base, size)) = from_file().map(|r| (RESERVED_FILE, r))
+		.or_else(|| from_config_table().map(|r| ("the configuration table", r)))
+		.or_else(|| from_hob().map(|r| ("the HOB list", r)))?;
+
+	let (Ok(base), Ok(size)) = (usize::try_from(base), usize::try_from(size)) else {
+		error!("Reserved range {:#x}+{:#x} from {} is outside the address space!", base, size, source);
+		return None;
+	};
+
+	if base % PAGE_SIZE != 0 || size == 0 || size % PAGE_SIZE != 0 {
+		error!("Reserved range {:#x}+{:#x} from {} isn't a whole number of pages!", base, size, source);
+		return None;
+	}
+
+	if !is_existing_stolen_memory(base, size) {
+		error!("Reserved range {:#x}+{:#x} from {} isn't reserved or ACPI NVS in the memory map!", base, size, source);
+		return None;
+	}
+
+	info!("Using the reserved range [{:#x}, {:#x}) from {}", base, base + size, source);
+	Some(Carver { low: base, high: base + size })
+}
+
+fn carver() -> Option<&'static mut Carver> {
+	unsafe {
+		let reserved = &mut *addr_of_mut!(RESERVED);
+		reserved.get_or_insert_with(discover).as_mut()
+	}
+}
+
+// `size` bytes from the start of what is left, aligned to `alignment` and ending at or below `limit`
+pub fn carve_low(size: usize, alignment: usize, limit: u64) -> Option<usize> {
+	let carver = carver()?;
+	let base = carver.low.next_multiple_of(alignment);
+
+	if base.checked_add(size)? > carver.high || (base + size - 1) as u64 > limit {
+		debug!("No room for {:#x} bytes aligned to {:#x} in the reserved range", size, alignment);
+		return None;
+	}
+
+	carver.low = base + size;
+	Some(base)
+}
+
+// `size` bytes, rounded up to pages, from the end of what is left
+pub fn carve_high(size: usize, limit: u64) -> Option<usize> {
+	let carver = carver()?;
+	let base = carver.high.checked_sub(size.next_multiple_of(PAGE_SIZE))?;
+
+	if base < carver.low || (base + size - 1) as u64 > limit {
+		debug!("No room for {:#x} bytes in the reserved range", size);
+		return None;
+	}
+
+	carver.high = base;
+	Some(base)
+}
@@ -0,0 +1,99 @@
+// ASLS, BDSM and GGC are lost when the guest goes through S3, and nothing but us knows how to program
+// them again, breaking resume with GVT-d. Where the firmware has EFI_S3_SAVE_STATE_PROTOCOL, the
+// registers are recorded in the boot script, which it replays on resume.
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use log::{debug, error, info};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::unsafe_protocol, Status};
+
+use crate::{outcome::DeviceOutcome, pci::{self, PciConfigAccess}, uses_bdsm64, PCI_CFG_ASLS_OFFSET, PCI_CFG_BDSM64_OFFSET, PCI_CFG_BDSM_MIRROR_OFFSET, PCI_CFG_GGC_OFFSET};
+
+const EFI_BOOT_SCRIPT_PCI_CONFIGURATION2_WRITE_OPCODE: u16 = 0x0B;
+
+const EFI_BOOT_SCRIPT_WIDTH_UINT16: usize = 1;
+const EFI_BOOT_SCRIPT_WIDTH_UINT32: usize = 2;
+
+// `Write` is variadic, but integer arguments are passed the same way as by a fixed signature, so it
+// is declared with the arguments of the one opcode used
+#[repr(C)]
+struct S3SaveStateProtocol {
+	write: unsafe extern "efiapi" fn(*const S3SaveStateProtocol, u16, usize, usize, u64, usize, *const c_void) -> Status,
+	// followed by Insert, Label and Compare, which aren't used
+}
+
+#[unsafe_protocol("e857caf6-c046-45dc-be3f-ee0765fba887")]
+struct S3SaveState(S3SaveStateProtocol);
+
+enum Value {
+	U16(u16),
+	U32(u32),
+}
+
+// what was set up on the device, as it reads back now
+fn registers<P: PciConfigAccess>(pci_io: &mut P, d: &DeviceOutcome) -> Option<Vec<(u32, Value)>> {
+	let mut regs = Vec::new();
+
+	if d.opregion.is_some() {
+		regs.push((PCI_CFG_ASLS_OFFSET, Value::U32(pci::read_u32(pci_io, PCI_CFG_ASLS_OFFSET)?)));
+	}
+
+	if d.stolen_memory.is_some() {
+		regs.push((PCI_CFG_GGC_OFFSET, Value::U16(pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET)?)));
+
+		if uses_bdsm64(d.generation) {
+			regs.push((PCI_CFG_BDSM64_OFFSET, Value::U32(pci::read_u32(pci_io, PCI_CFG_BDSM64_OFFSET)?)));
+			regs.push((PCI_CFG_BDSM64_OFFSET + 4, Value::U32(pci::read_u32(pci_io, PCI_CFG_BDSM64_OFFSET + 4)?)));
+		} else {
+			regs.push((PCI_CFG_BDSM_MIRROR_OFFSET, Value::U32(pci::read_u32(pci_io, PCI_CFG_BDSM_MIRROR_OFFSET)?)));
+		}
+	}
+
+	Some(regs)
+}
+
+pub fn save<P: PciConfigAccess>(pci_io: &mut P, d: &DeviceOutcome) {
+	let Ok(handle) = boot::get_handle_for_protocol::<S3SaveState>() else {
+		debug!("No S3 save state protocol, the IGD setup won't survive S3");
+		return;
+	};
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let Ok(s3) = (unsafe { boot::open_protocol::<S3SaveState>(params, OpenProtocolAttributes::GetProtocol) }) else {
+		error!("Failed to open the S3 save state protocol!");
+		return;
+	};
+
+	let Some(regs) = registers(pci_io, d) else {
+		error!("Failed to read back the registers to save for S3!");
+		return;
+	};
+
+	let (seg, bus, dev, func) = d.location;
+
+	for (offset, value) in &regs {
+		// EFI_PCI_ROOT_BRIDGE_IO_PROTOCOL address format
+		let address = ((bus as u64) << 24) | ((dev as u64) << 16) | ((func as u64) << 8) | *offset as u64;
+
+		let status = match value {
+			Value::U16(v) => unsafe {
+				(s3.0.write)(&s3.0, EFI_BOOT_SCRIPT_PCI_CONFIGURATION2_WRITE_OPCODE, EFI_BOOT_SCRIPT_WIDTH_UINT16, seg, address, 1, v as *const u16 as *const c_void)
+			},
+			Value::U32(v) => unsafe {
+				(s3.0.write)(&s3.0, EFI_BOOT_SCRIPT_PCI_CONFIGURATION2_WRITE_OPCODE, EFI_BOOT_SCRIPT_WIDTH_UINT32, seg, address, 1, v as *const u32 as *const c_void)
+			},
+		};
+
+		if status.is_error() {
+			error!("Failed to save the write to {:#04x} in the S3 boot script: {:?}!", offset, status);
+			return;
+		}
+	}
+
+	info!("Saved {} register writes in the S3 boot script", regs.len());
+}
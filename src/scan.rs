@@ -0,0 +1,196 @@
+// Which PciIo handles were looked at already, for the notify and the enumeration scan. Free of UEFI
+// calls, opening a handle and configuring the device behind it are up to the caller.
+//
+// Every handle that was configured or deferred is remembered, so a reinstalled PciIo or a handle
+// showing up in both the initial scan and the search key is handled exactly once, while devices
+// attached later, e.g. hot-plugged with `keep_listening`, are still picked up.
+
+use alloc::vec::Vec;
+use core::{mem, ops::Deref};
+use log::{error, info};
+
+use crate::{match_device, pci::PciConfigAccess, CONFIG};
+
+pub struct Scan<H> {
+	// matching devices waiting for ReadyToBoot
+	deferred: Vec<H>,
+	// configured or deferred already
+	processed: Vec<H>,
+}
+
+impl<H: Copy + PartialEq> Scan<H> {
+	pub const fn new() -> Self {
+		Scan { deferred: Vec::new(), processed: Vec::new() }
+	}
+
+	pub fn deferred_count(&self) -> usize {
+		self.deferred.len()
+	}
+
+	pub fn take_deferred(&mut self) -> Vec<H> {
+		mem::take(&mut self.deferred)
+	}
+
+	pub fn clear_deferred(&mut self) {
+		self.deferred.clear();
+	}
+
+	// Look at a handle not seen yet, `open` gives access to its config space and `configure` returns
+	// true if it was the IGD. Returns true once listening can stop.
+	pub fn process<P: PciConfigAccess, A>(&mut self, handle: H, open: impl FnOnce(H) -> Option<(P, A)>, configure: impl FnOnce(H, &mut P, A) -> bool) -> bool {
+		if self.processed.contains(&handle) {
+			return false;
+		}
+
+		let Some((mut pci_io, access)) = open(handle) else {
+			error!("Failed to open PciIo, skipping device!");
+			return false;
+		};
+
+		if unsafe { CONFIG.defer_to_ready_to_boot } {
+			if let Some(device) = match_device(&mut pci_io) {
+				info!("Deferring the setup of device {:04x} to ReadyToBoot", device);
+				self.deferred.push(handle);
+				self.processed.push(handle);
+			}
+
+			return false;
+		}
+
+		if match_device(&mut pci_io).is_none() {
+			return false;
+		}
+
+		// a device that failed setup isn't retried when its PciIo is reinstalled either
+		self.processed.push(handle);
+
+		// the IGD is the only device we expect, unless hot-plugged ones were asked for
+		configure(handle, &mut pci_io, access) && !unsafe { CONFIG.keep_listening }
+	}
+
+	// process the handles of every batch `next` returns until it runs dry, or listening can stop
+	pub fn drain<B, P, A>(&mut self, mut next: impl FnMut() -> Option<B>, mut open: impl FnMut(H) -> Option<(P, A)>, mut configure: impl FnMut(H, &mut P, A) -> bool) -> bool
+	where
+		B: Deref<Target = [H]>,
+		P: PciConfigAccess,
+	{
+		while let Some(handles) = next() {
+			if handles.iter().any(|&handle| self.process(handle, &mut open, &mut configure)) {
+				return true;
+			}
+		}
+
+		false
+	}
+}
+
+impl<H: Copy + PartialEq> Default for Scan<H> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec;
+
+	use super::*;
+	use crate::pci::ConfigSpace;
+
+	const HOST_BRIDGE: usize = 0;
+	const NIC: usize = 1;
+	const IGD: usize = 2;
+
+	// config space of the function behind `handle`, class code register bytes at 0x09-0x0B
+	fn open(handle: usize) -> Option<(ConfigSpace, ())> {
+		let (vendor, device, class): (u16, u16, [u8; 3]) = match handle {
+			HOST_BRIDGE => (0x8086, 0x29C0, [0x00, 0x00, 0x06]),
+			NIC => (0x1AF4, 0x1000, [0x00, 0x00, 0x02]),
+			_ => (0x8086, 0x3E92, [0x00, 0x00, 0x03]),
+		};
+
+		let mut space = ConfigSpace([0; 256]);
+		space.0[0..2].copy_from_slice(&vendor.to_le_bytes());
+		space.0[2..4].copy_from_slice(&device.to_le_bytes());
+		space.0[9..12].copy_from_slice(&class);
+
+		Some((space, ()))
+	}
+
+	#[test]
+	fn other_devices_end_the_scan() {
+		let mut scan = Scan::new();
+		let mut batches = vec![vec![HOST_BRIDGE, NIC]].into_iter();
+		let mut calls = 0;
+		let mut configured = 0;
+
+		let done = scan.drain(
+			|| {
+				calls += 1;
+				batches.next()
+			},
+			open,
+			|_, _, _| {
+				configured += 1;
+				true
+			},
+		);
+
+		assert!(!done);
+		assert_eq!(configured, 0);
+		// the batch, then the search running dry
+		assert_eq!(calls, 2);
+	}
+
+	#[test]
+	fn reinstalled_igd_is_configured_once() {
+		let mut scan = Scan::new();
+		// the initial search and the search key both return the IGD, and its PciIo is reinstalled
+		let mut batches = vec![vec![HOST_BRIDGE, IGD], vec![IGD], vec![NIC, IGD]].into_iter();
+		let mut configured = Vec::new();
+
+		let done = scan.drain(
+			|| batches.next(),
+			open,
+			|handle, _, _| {
+				configured.push(handle);
+				false
+			},
+		);
+
+		assert!(!done);
+		assert_eq!(configured, [IGD]);
+	}
+
+	#[test]
+	fn configured_igd_stops_the_scan() {
+		let mut scan = Scan::new();
+		let mut batches = vec![vec![IGD, NIC], vec![NIC]].into_iter();
+		let mut calls = 0;
+
+		let done = scan.drain(
+			|| {
+				calls += 1;
+				batches.next()
+			},
+			open,
+			|handle, _, _| handle == IGD,
+		);
+
+		assert!(done);
+		assert_eq!(calls, 1);
+	}
+
+	#[test]
+	fn failed_open_is_retried() {
+		let mut scan = Scan::new();
+		let mut configured = 0;
+
+		assert!(!scan.process(IGD, |_| None::<(ConfigSpace, ())>, |_, _, _| true));
+		assert!(scan.process(IGD, open, |_, _, _| {
+			configured += 1;
+			true
+		}));
+		assert_eq!(configured, 1);
+	}
+}
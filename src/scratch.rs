@@ -0,0 +1,85 @@
+// Temporary buffers for fw_cfg files and the like, wiped when dropped. Each one is tracked in a small
+// registry while it lives, so anything still around at ExitBootServices, e.g. left behind by a notify
+// that never finished, is wiped before the OS takes over. Neither freeing nor allocating is allowed
+// from that callback, so the sweep only zeroizes, and the OS reclaims the memory as boot services data.
+
+use alloc::{vec, vec::Vec};
+use core::{ffi::c_void, ops::{Deref, DerefMut}, ptr::NonNull};
+use log::{error, warn};
+use uefi::{boot::{self, EventType, Tpl}, Event};
+use zeroize::Zeroize;
+
+// more than are ever alive at once, a buffer beyond that still gets wiped on drop
+const REGISTRY_SIZE: usize = 16;
+
+static mut REGISTRY: [Option<(usize, usize)>; REGISTRY_SIZE] = [None; REGISTRY_SIZE];
+static mut EVENT: Option<Event> = None;
+
+pub struct Scratch(Vec<u8>);
+
+impl Scratch {
+	pub fn new(len: usize) -> Self {
+		let buf = vec![0u8; len];
+
+		unsafe {
+			match REGISTRY.iter_mut().find(|e| e.is_none()) {
+				Some(entry) => *entry = Some((buf.as_ptr() as usize, len)),
+				None => warn!("Scratch registry is full, a {}-byte buffer isn't tracked", len),
+			}
+		}
+
+		Scratch(buf)
+	}
+}
+
+impl Deref for Scratch {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+impl DerefMut for Scratch {
+	fn deref_mut(&mut self) -> &mut [u8] {
+		&mut self.0
+	}
+}
+
+impl Drop for Scratch {
+	fn drop(&mut self) {
+		self.0.zeroize();
+
+		unsafe {
+			let addr = self.0.as_ptr() as usize;
+			if let Some(entry) = REGISTRY.iter_mut().find(|e| e.is_some_and(|(a, _)| a == addr)) {
+				*entry = None;
+			}
+		}
+	}
+}
+
+unsafe extern "efiapi" fn exit_boot_services(_event: Event, _ctx: Option<NonNull<c_void>>) {
+	for (addr, len) in REGISTRY.iter_mut().filter_map(|e| e.take()) {
+		core::slice::from_raw_parts_mut(addr as *mut u8, len).zeroize();
+	}
+}
+
+// wipe what is left at ExitBootServices, for as long as the image stays resident
+pub unsafe fn arm() {
+	if EVENT.is_some() {
+		return;
+	}
+
+	match boot::create_event(EventType::SIGNAL_EXIT_BOOT_SERVICES, Tpl::NOTIFY, Some(exit_boot_services), None) {
+		Ok(event) => EVENT = Some(event),
+		Err(e) => error!("Failed to create the scratch buffer cleanup event: {:?}!", e.status()),
+	}
+}
+
+// the event must not outlive the image
+pub unsafe fn cancel() {
+	if let Some(event) = EVENT.take() {
+		let _ = boot::close_event(event);
+	}
+}
@@ -0,0 +1,97 @@
+// Self-test for CI runs under QEMU: exercises the pieces a real run is built on, fw_cfg, the aligned
+// allocator and config space access, with fixed inputs, so a harness can assert on the PASS/FAIL
+// lines without a particular device setup. Memory is freed again and config space only gets back
+// what it held.
+
+use log::{error, info};
+use qemu_fw_cfg::FwCfg;
+use uefi::Status;
+
+use crate::{
+	allocate_stolen_memory, ceiling_32, check::report, free_pages, fw_cfg_open, fw_cfg_raw, igd_location, pci, PAGE_SIZE,
+	PCI_CFG_ASLS_OFFSET, PCI_VENDOR_INTEL,
+};
+
+// a small range at the strictest alignment any generation asks for
+const ALLOCATION_PAGES: usize = 256;
+const ALLOCATION_ALIGNMENT: usize = 8 * 1024 * 1024;
+
+// the directory as read through the raw interface has to agree with what qemu_fw_cfg finds
+fn test_fw_cfg(fw_cfg: &mut FwCfg) -> Result<(), &'static str> {
+	let files = fw_cfg_raw::list_files();
+
+	if files.is_empty() {
+		return Err("empty file directory");
+	}
+
+	for (name, entry) in &files {
+		match fw_cfg.find_file(name) {
+			Some(file) if file.size() == entry.size => {}
+			Some(_) => return Err("file sizes differ between the two interfaces"),
+			None => return Err("file listed in the directory can't be found by name"),
+		}
+	}
+
+	if let Some(transport) = fw_cfg_raw::transport() {
+		info!("fw_cfg lists {} files over {:?}", files.len(), transport);
+	}
+	Ok(())
+}
+
+fn test_allocation() -> Result<(), &'static str> {
+	let ceiling = ceiling_32();
+	let base = allocate_stolen_memory(ALLOCATION_PAGES, ceiling, ALLOCATION_ALIGNMENT).ok_or("allocation failed")?;
+	let end = base + ALLOCATION_PAGES * PAGE_SIZE;
+	free_pages(base, ALLOCATION_PAGES);
+
+	if base % ALLOCATION_ALIGNMENT != 0 {
+		return Err("base isn't aligned");
+	}
+
+	if (end - 1) as u64 > ceiling {
+		return Err("range ends above the ceiling");
+	}
+
+	info!("Allocated and freed [{:#x}, {:#x})", base, end);
+	Ok(())
+}
+
+// ASLS is written with what it already holds, which no device acts upon
+fn test_config_space() -> Result<(), &'static str> {
+	let mut pci_io = pci::open_shared(igd_location()).ok_or("no device at the IGD location")?;
+
+	if pci::read_u16(&mut pci_io, 0).ok_or("vendor ID read failed")? != PCI_VENDOR_INTEL {
+		return Err("not an Intel device");
+	}
+
+	let asls = pci::read_u32(&mut pci_io, PCI_CFG_ASLS_OFFSET).ok_or("ASLS read failed")?;
+	if pci::read_bytes::<4>(&mut pci_io, PCI_CFG_ASLS_OFFSET).map(u32::from_le_bytes) != Some(asls) {
+		return Err("dword and byte reads of ASLS differ");
+	}
+
+	pci::write_u32(&mut pci_io, PCI_CFG_ASLS_OFFSET, asls).ok_or("ASLS write failed")?;
+	if pci::read_u32(&mut pci_io, PCI_CFG_ASLS_OFFSET) != Some(asls) {
+		return Err("ASLS reads back differently");
+	}
+
+	Ok(())
+}
+
+pub fn run() -> Status {
+	let Some(mut fw_cfg) = (unsafe { fw_cfg_open() }) else {
+		report("fw_cfg", Err("not found"));
+		return Status::NOT_FOUND;
+	};
+
+	let mut passed = report("fw_cfg", test_fw_cfg(&mut fw_cfg));
+	passed &= report("Aligned allocation", test_allocation());
+	passed &= report("IGD config space", test_config_space());
+
+	if passed {
+		info!("Self-test passed");
+		Status::SUCCESS
+	} else {
+		error!("Self-test failed!");
+		Status::ABORTED
+	}
+}
@@ -0,0 +1,189 @@
+// A small SSDT describing the IGD for guests that look for it in ACPI rather than only in ASLS: the
+// OpRegion address, the _DOD display list and backlight stubs on a single internal panel. The AML is
+// put together by hand, it is equivalent to:
+//
+//   Scope (\_SB.PCI0) {
+//     Device (GFX0) {
+//       Name (_ADR, 0x00020000)
+//       Name (ASLB, <OpRegion base>)
+//       Name (ASLL, <OpRegion size>)
+//       Method (_DOS, 1) {}
+//       Method (_DOD) { Return (Package () { 0x80000400 }) }
+//       Device (DD1F) {
+//         Name (_ADR, 0x0400)
+//         Method (_BCL) { Return (Package () { 100, 50, 10, 20, ..., 100 }) }
+//         Method (_BCM, 1) {}
+//         Method (_BQC) { Return (100) }
+//       }
+//     }
+//   }
+
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use log::{error, info};
+
+use crate::{acpi, args::Location, outcome::Region};
+
+const SSDT_SIGNATURE: &[u8; 4] = b"SSDT";
+const SSDT_REVISION: u8 = 2;
+const OEM_TABLE_ID: &[u8; 8] = b"IGDSSDT ";
+
+const AML_ZERO_OP: u8 = 0x00;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_DWORD_PREFIX: u8 = 0x0C;
+const AML_QWORD_PREFIX: u8 = 0x0E;
+const AML_SCOPE_OP: u8 = 0x10;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_METHOD_OP: u8 = 0x14;
+const AML_DUAL_NAME_PREFIX: u8 = 0x2E;
+const AML_ROOT_CHAR: u8 = b'\\';
+const AML_EXT_OP_PREFIX: u8 = 0x5B;
+const AML_DEVICE_OP: u8 = 0x82;
+const AML_RETURN_OP: u8 = 0xA4;
+
+// _DOD ID of the internal panel: ACPI-defined scheme, type "internal digital flat panel", index 0
+const PANEL_ID: u32 = 0x8000_0400;
+// brightness levels in percent
+const BRIGHTNESS_LEVELS: [u8; 10] = [10, 20, 30, 40, 50, 60, 70, 80, 90, 100];
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// PkgLength counts itself, and takes up to three more bytes for lengths that don't fit in 6 bits
+fn with_pkg_length(op: &[u8], body: Vec<u8>) -> Vec<u8> {
+	let extra = match body.len() + 1 {
+		0..=0x3F => 0,
+		len if len + 1 <= 0xFFF => 1,
+		len if len + 2 <= 0xF_FFFF => 2,
+		_ => 3,
+	};
+	let len = body.len() + 1 + extra;
+
+	let mut out = op.to_vec();
+
+	if extra == 0 {
+		out.push(len as u8);
+	} else {
+		out.push(((extra as u8) << 6) | (len & 0xF) as u8);
+		for i in 0..extra {
+			out.push((len >> (4 + 8 * i)) as u8);
+		}
+	}
+
+	out.extend(body);
+	out
+}
+
+fn integer(value: u64) -> Vec<u8> {
+	match value {
+		0 => vec![AML_ZERO_OP],
+		1..=0xFF => vec![AML_BYTE_PREFIX, value as u8],
+		0x100..=0xFFFF_FFFF => {
+			let mut out = vec![AML_DWORD_PREFIX];
+			out.extend_from_slice(&(value as u32).to_le_bytes());
+			out
+		}
+		_ => {
+			let mut out = vec![AML_QWORD_PREFIX];
+			out.extend_from_slice(&value.to_le_bytes());
+			out
+		}
+	}
+}
+
+fn name(seg: &[u8; 4], value: Vec<u8>) -> Vec<u8> {
+	let mut out = vec![AML_NAME_OP];
+	out.extend_from_slice(seg);
+	out.extend(value);
+	out
+}
+
+fn package(elements: &[u64]) -> Vec<u8> {
+	let mut body = vec![elements.len() as u8];
+	for &e in elements {
+		body.extend(integer(e));
+	}
+
+	with_pkg_length(&[AML_PACKAGE_OP], body)
+}
+
+fn method(seg: &[u8; 4], args: u8, term: Vec<u8>) -> Vec<u8> {
+	let mut body = seg.to_vec();
+	body.push(args);
+	body.extend(term);
+
+	with_pkg_length(&[AML_METHOD_OP], body)
+}
+
+fn returns(value: Vec<u8>) -> Vec<u8> {
+	let mut out = vec![AML_RETURN_OP];
+	out.extend(value);
+	out
+}
+
+fn device(seg: &[u8; 4], terms: &[Vec<u8>]) -> Vec<u8> {
+	let mut body = seg.to_vec();
+	body.extend(terms.concat());
+
+	with_pkg_length(&[AML_EXT_OP_PREFIX, AML_DEVICE_OP], body)
+}
+
+fn build(location: Location, opregion: Region) -> Vec<u8> {
+	let (_, _, dev, func) = location;
+
+	// full power and on battery first, then the levels
+	let mut levels = vec![100, 50];
+	levels.extend(BRIGHTNESS_LEVELS.iter().map(|&l| l as u64));
+
+	let panel = device(b"DD1F", &[
+		name(b"_ADR", integer((PANEL_ID & 0xFFFF) as u64)),
+		method(b"_BCL", 0, returns(package(&levels))),
+		method(b"_BCM", 1, vec![]),
+		method(b"_BQC", 0, returns(integer(100))),
+	]);
+
+	let gfx = device(b"GFX0", &[
+		name(b"_ADR", integer(((dev as u64) << 16) | func as u64)),
+		name(b"ASLB", integer(opregion.base as u64)),
+		name(b"ASLL", integer(opregion.size as u64)),
+		method(b"_DOS", 1, vec![]),
+		method(b"_DOD", 0, returns(package(&[PANEL_ID as u64]))),
+		panel,
+	]);
+
+	let mut scope = vec![AML_ROOT_CHAR, AML_DUAL_NAME_PREFIX];
+	scope.extend_from_slice(b"_SB_PCI0");
+	scope.extend(gfx);
+	let aml = with_pkg_length(&[AML_SCOPE_OP], scope);
+
+	let mut table = acpi::header(SSDT_SIGNATURE, SSDT_REVISION, OEM_TABLE_ID);
+	table.extend(aml);
+	acpi::finish(&mut table);
+
+	table
+}
+
+// Only for an IGD on the root bus of segment 0, which is what \_SB.PCI0 describes. Installed once,
+// later calls are ignored.
+pub fn install(location: Location, opregion: Region) {
+	let (seg, bus, dev, func) = location;
+
+	if seg != 0 || bus != 0 {
+		info!("Not installing an SSDT for {:04x}:{:02x}:{:02x}.{}, which isn't below \\_SB.PCI0", seg, bus, dev, func);
+		return;
+	}
+
+	if INSTALLED.load(Ordering::Acquire) {
+		return;
+	}
+
+	let table = build(location, opregion);
+
+	if let Err(status) = acpi::install(&table) {
+		error!("Failed to install the SSDT: {:?}!", status);
+		return;
+	}
+
+	INSTALLED.store(true, Ordering::Release);
+	info!("Installed an SSDT for the IGD ({} bytes)", table.len());
+}
@@ -0,0 +1,71 @@
+// Major failures reported as PI status codes too, so platforms that collect them, e.g. on a BMC or
+// in the firmware's debug log, record our errors alongside those of the other DXE drivers.
+
+use core::ffi::c_void;
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::unsafe_protocol, Guid, Status};
+
+use crate::protocol::IGD_ASSIGNMENT_PROTOCOL_GUID;
+
+const EFI_ERROR_CODE: u32 = 0x0000_0002;
+const EFI_ERROR_MAJOR: u32 = 0x8000_0000;
+
+// EFI_PERIPHERAL | EFI_PERIPHERAL_LOCAL_CONSOLE, the subclass video controllers are reported under
+const EFI_PERIPHERAL_LOCAL_CONSOLE: u32 = 0x0101_0000;
+
+const EFI_P_EC_NOT_DETECTED: u32 = 0x0003;
+const EFI_P_EC_NOT_CONFIGURED: u32 = 0x0004;
+const EFI_P_EC_CONTROLLER_ERROR: u32 = 0x0006;
+const EFI_P_EC_RESOURCE_CONFLICT: u32 = 0x0009;
+
+#[repr(C)]
+struct StatusCodeProtocol {
+	report_status_code: unsafe extern "efiapi" fn(u32, u32, u32, *const Guid, *const c_void) -> Status,
+}
+
+#[unsafe_protocol("d2b2b828-0826-48a7-b3df-983c006024f0")]
+struct StatusCode(StatusCodeProtocol);
+
+#[derive(Clone, Copy)]
+pub enum Failure {
+	// fw_cfg itself is missing
+	NoFwCfg,
+	// a file setup can't do without isn't passed through
+	MissingFile,
+	// OpRegion, extended VBT or stolen memory couldn't be allocated
+	Allocation,
+	// a config space write failed or didn't stick
+	PciWrite,
+}
+
+impl Failure {
+	fn operation(self) -> u32 {
+		match self {
+			Failure::NoFwCfg => EFI_P_EC_NOT_DETECTED,
+			Failure::MissingFile => EFI_P_EC_NOT_CONFIGURED,
+			Failure::Allocation => EFI_P_EC_RESOURCE_CONFLICT,
+			Failure::PciWrite => EFI_P_EC_CONTROLLER_ERROR,
+		}
+	}
+}
+
+// best effort, most platforms without a consumer don't install the protocol at all
+pub fn report(failure: Failure) {
+	let Ok(handle) = boot::get_handle_for_protocol::<StatusCode>() else {
+		return;
+	};
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let Ok(status_code) = (unsafe { boot::open_protocol::<StatusCode>(params, OpenProtocolAttributes::GetProtocol) }) else {
+		return;
+	};
+
+	unsafe {
+		(status_code.0.report_status_code)(EFI_ERROR_CODE | EFI_ERROR_MAJOR, EFI_PERIPHERAL_LOCAL_CONSOLE | failure.operation(), 0,
+			&IGD_ASSIGNMENT_PROTOCOL_GUID, core::ptr::null());
+	}
+}
@@ -0,0 +1,169 @@
+// Compact status record for CI pipelines and host-side scripts, stored in the volatile
+// `IgdAssignRecord` variable, written back through fw_cfg DMA if the host provides `etc/igd-status`
+// and, with tag 0x0028, printed to the first Serial I/O device in hex between `STATUS_BEGIN` and
+// `STATUS_END` lines.
+//
+// Layout (all fields little-endian, version 1):
+//   header: "IGDS", u16 version, u16 length of the TLVs following it
+//   TLVs: u16 tag, u16 length, value, as in `etc/igd-config`
+//
+// Tags up to 0x000F describe the whole setup, the ones from 0x0010 on the device whose 0x0010 entry
+// came last. Unknown tags are to be skipped by consumers, so tags can be added without a new version.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+use log::{info, warn};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, cstr16, proto::console::serial::Serial,
+	runtime::{self, VariableAttributes}, Status};
+
+use crate::{fw_cfg_raw, outcome::DeviceOutcome, variables::IGD_VARIABLE_GUID};
+
+const STATUS_FILE: &str = "etc/igd-status";
+const STATUS_MAGIC: [u8; 4] = *b"IGDS";
+const STATUS_VERSION: u16 = 1;
+
+pub const STATUS_BEGIN: &str = "IGD-STATUS-BEGIN";
+pub const STATUS_END: &str = "IGD-STATUS-END";
+
+// u64 EFI_STATUS of the whole setup
+const TAG_STATUS: u16 = 0x0001;
+// u64 microseconds spent configuring all devices, if the firmware has a timestamp counter
+const TAG_TOTAL_TIME: u16 = 0x0002;
+// u16 segment, u8 bus, device and function, u16 device ID, starts a device
+const TAG_DEVICE: u16 = 0x0010;
+// u8 graphics IP major version followed by the generation name in ASCII, absent if unknown
+const TAG_GENERATION: u16 = 0x0011;
+// u64 EFI_STATUS, absent if the step wasn't attempted
+const TAG_OPREGION_STATUS: u16 = 0x0012;
+// u64 base, u64 size
+const TAG_OPREGION: u16 = 0x0013;
+const TAG_STOLEN_MEMORY_STATUS: u16 = 0x0014;
+const TAG_STOLEN_MEMORY: u16 = 0x0015;
+// u64 microseconds spent configuring the device
+const TAG_SETUP_TIME: u16 = 0x0016;
+// u8 1 if the memory map reserves the whole region, 0 if the OS may reuse part of it, absent if unchecked
+const TAG_OPREGION_RESERVED: u16 = 0x0017;
+const TAG_STOLEN_MEMORY_RESERVED: u16 = 0x0018;
+
+fn push(record: &mut Vec<u8>, tag: u16, value: &[u8]) {
+	record.extend_from_slice(&tag.to_le_bytes());
+	record.extend_from_slice(&(value.len() as u16).to_le_bytes());
+	record.extend_from_slice(value);
+}
+
+fn push_u64(record: &mut Vec<u8>, tag: u16, value: u64) {
+	push(record, tag, &value.to_le_bytes());
+}
+
+fn push_region(record: &mut Vec<u8>, tag: u16, base: usize, size: usize) {
+	let mut value = [0u8; 16];
+	value[..8].copy_from_slice(&(base as u64).to_le_bytes());
+	value[8..].copy_from_slice(&(size as u64).to_le_bytes());
+	push(record, tag, &value);
+}
+
+pub fn serialize(devices: &[DeviceOutcome], status: Status) -> Vec<u8> {
+	let mut tlvs = Vec::new();
+
+	push_u64(&mut tlvs, TAG_STATUS, status.0 as u64);
+
+	if let Some(total) = devices.iter().map(|d| d.setup_us).sum::<Option<u64>>() {
+		push_u64(&mut tlvs, TAG_TOTAL_TIME, total);
+	}
+
+	for d in devices {
+		let (seg, bus, dev, func) = d.location;
+		let mut device = [0u8; 7];
+		device[..2].copy_from_slice(&(seg as u16).to_le_bytes());
+		device[2..5].copy_from_slice(&[bus as u8, dev as u8, func as u8]);
+		device[5..].copy_from_slice(&d.device_id.to_le_bytes());
+		push(&mut tlvs, TAG_DEVICE, &device);
+
+		if let Some(generation) = d.generation {
+			let mut value = Vec::from([generation.number()]);
+			value.extend_from_slice(format!("{:?}", generation).as_bytes());
+			push(&mut tlvs, TAG_GENERATION, &value);
+		}
+
+		if let Some(status) = d.opregion_status {
+			push_u64(&mut tlvs, TAG_OPREGION_STATUS, status.0 as u64);
+		}
+
+		if let Some(r) = d.opregion {
+			push_region(&mut tlvs, TAG_OPREGION, r.base, r.size);
+		}
+
+		if let Some(status) = d.stolen_memory_status {
+			push_u64(&mut tlvs, TAG_STOLEN_MEMORY_STATUS, status.0 as u64);
+		}
+
+		if let Some(r) = d.stolen_memory {
+			push_region(&mut tlvs, TAG_STOLEN_MEMORY, r.base, r.size);
+		}
+
+		if let Some(us) = d.setup_us {
+			push_u64(&mut tlvs, TAG_SETUP_TIME, us);
+		}
+
+		if let Some(reserved) = d.opregion_reserved {
+			push(&mut tlvs, TAG_OPREGION_RESERVED, &[reserved as u8]);
+		}
+
+		if let Some(reserved) = d.stolen_memory_reserved {
+			push(&mut tlvs, TAG_STOLEN_MEMORY_RESERVED, &[reserved as u8]);
+		}
+	}
+
+	let mut record = Vec::with_capacity(8 + tlvs.len());
+	record.extend_from_slice(&STATUS_MAGIC);
+	record.extend_from_slice(&STATUS_VERSION.to_le_bytes());
+	record.extend_from_slice(&(tlvs.len() as u16).to_le_bytes());
+	record.extend_from_slice(&tlvs);
+
+	record
+}
+
+// the record in hex between the marker lines, as one write so it can't be interleaved with the log
+fn print_serial(record: &[u8]) {
+	let Ok(handle) = boot::get_handle_for_protocol::<Serial>() else {
+		warn!("No Serial I/O device to print the status record to");
+		return;
+	};
+
+	let params = OpenProtocolParams { handle, agent: boot::image_handle(), controller: None };
+	let Ok(mut serial) = (unsafe { boot::open_protocol::<Serial>(params, OpenProtocolAttributes::GetProtocol) }) else {
+		return;
+	};
+
+	let mut text = String::with_capacity(record.len() * 2 + 64);
+	let _ = write!(text, "\r\n{}\r\n", STATUS_BEGIN);
+	for b in record {
+		let _ = write!(text, "{:02x}", b);
+	}
+	let _ = write!(text, "\r\n{}\r\n", STATUS_END);
+
+	let _ = serial.write(text.as_bytes());
+}
+
+pub fn write(devices: &[DeviceOutcome], status: Status, serial: bool) {
+	let record = serialize(devices, status);
+	let attributes = VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS;
+
+	match runtime::set_variable(cstr16!("IgdAssignRecord"), &IGD_VARIABLE_GUID, attributes, &record) {
+		Ok(()) => info!("Stored the {} byte status record", record.len()),
+		Err(e) => warn!("Failed to set the IgdAssignRecord variable: {:?}", e.status()),
+	}
+
+	// the host opts in by adding the file, it has to be writable and large enough for the record
+	if fw_cfg_raw::find_file(STATUS_FILE).is_some() {
+		if fw_cfg_raw::write_file(STATUS_FILE, &record) {
+			info!("Wrote the status record to {}", STATUS_FILE);
+		} else {
+			warn!("Failed to write the status record, is {} a writable fw_cfg file of at least {} bytes?", STATUS_FILE, record.len());
+		}
+	}
+
+	if serial {
+		print_serial(&record);
+	}
+}
@@ -0,0 +1,149 @@
+// A status screen on ConOut for runs from the UEFI shell: the devices, the fw_cfg files, where
+// everything ended up and how each step went, in one table that stays on screen instead of log lines
+// scrolling past. It is drawn once setup is done, the log still has the details.
+
+use alloc::{format, string::String};
+use core::fmt::Write;
+use uefi::{proto::console::text::{Color, Output}, Status};
+
+use crate::{fw_cfg_raw, outcome::DeviceOutcome, summary};
+
+const WIDTH: usize = 78;
+const NAME_WIDTH: usize = 56;
+
+fn color(out: &mut Output, foreground: Color) {
+	let _ = out.set_color(foreground, Color::Black);
+}
+
+fn heading(out: &mut Output, title: &str) {
+	color(out, Color::White);
+	let _ = writeln!(out);
+	let _ = writeln!(out, "{}", title);
+	color(out, Color::LightGray);
+	let _ = writeln!(out, "{:-<1$}", "", WIDTH);
+}
+
+// `None` if the step wasn't attempted
+fn mark(out: &mut Output, status: Option<Status>) {
+	let (foreground, text) = match status {
+		Some(s) if s.is_error() => (Color::LightRed, "FAIL"),
+		Some(_) => (Color::LightGreen, " OK "),
+		None => (Color::DarkGray, "SKIP"),
+	};
+
+	let _ = out.write_str("[");
+	color(out, foreground);
+	let _ = out.write_str(text);
+	color(out, Color::LightGray);
+	let _ = out.write_str("]");
+}
+
+fn step(out: &mut Output, name: &str, status: Option<Status>, detail: &str) {
+	let _ = write!(out, "  ");
+	mark(out, status);
+	let _ = writeln!(out, " {:<14} {}", name, detail);
+}
+
+// only shown when it went wrong, a region the OS may reuse is what guest crashes come down to
+fn reserved(out: &mut Output, reserved: Option<bool>) {
+	if reserved == Some(false) {
+		color(out, Color::LightRed);
+		let _ = writeln!(out, "         {:<14} no, the OS may reuse it", "Reserved");
+		color(out, Color::LightGray);
+	}
+}
+
+fn location(d: &DeviceOutcome) -> String {
+	let (seg, bus, dev, func) = d.location;
+	format!("{:04x}:{:02x}:{:02x}.{}", seg, bus, dev, func)
+}
+
+fn devices(out: &mut Output, devices: &[DeviceOutcome]) {
+	heading(out, "Devices");
+
+	if devices.is_empty() {
+		let _ = writeln!(out, "  none configured");
+		return;
+	}
+
+	for d in devices {
+		let generation = d.generation.map_or(String::from("unknown generation"), |g| format!("{:?}", g));
+		let _ = writeln!(out, "  {}  8086:{:04x}  {}", location(d), d.device_id, generation);
+	}
+}
+
+// only ours, the rest of the directory is QEMU's own
+fn files(out: &mut Output) {
+	heading(out, "fw_cfg files");
+
+	let files = fw_cfg_raw::list_files();
+	let mut any = false;
+
+	for (name, file) in files.iter().filter(|(name, _)| name.contains("/igd-")) {
+		let name = name.get(..NAME_WIDTH).unwrap_or(name);
+		let _ = writeln!(out, "  {:<2$} {:>10} bytes", name, file.size, NAME_WIDTH);
+		any = true;
+	}
+
+	if !any {
+		let _ = writeln!(out, "  none");
+	}
+}
+
+fn results(out: &mut Output, devices: &[DeviceOutcome]) {
+	heading(out, "Results");
+
+	for d in devices {
+		let _ = writeln!(out, "  {}", location(d));
+
+		let opregion = match (d.opregion, d.opregion_status) {
+			(Some(r), _) => format!("{:#x}, {} bytes", r.base, r.size),
+			(None, Some(status)) => format!("{:?}", status),
+			(None, None) => String::new(),
+		};
+		step(out, "OpRegion", d.opregion_status, &opregion);
+
+		reserved(out, d.opregion_reserved);
+
+		if let Some(r) = d.opregion {
+			let vbt = summary::vbt_location(r).map_or(String::from("none"), |(addr, kind)| format!("{:#x}, {}", addr, kind));
+			let _ = writeln!(out, "         {:<14} {}", "VBT", vbt);
+		}
+
+		let stolen_memory = match (d.stolen_memory, d.stolen_memory_status) {
+			(Some(r), _) => format!("{:#x}, {} MiB", r.base, r.size / 1024 / 1024),
+			(None, Some(status)) => format!("{:?}", status),
+			(None, None) => String::new(),
+		};
+		step(out, "StolenMemory", d.stolen_memory_status, &stolen_memory);
+		reserved(out, d.stolen_memory_reserved);
+
+		if let Some(ggc) = d.ggc {
+			let _ = writeln!(out, "         {:<14} {:#06x}", "GGC", ggc);
+		}
+	}
+}
+
+// draws the screen for `devices`, with `status` as the overall result
+pub fn show(devices: &[DeviceOutcome], status: Status) {
+	uefi::system::with_stdout(|out| {
+		let _ = out.clear();
+
+		color(out, Color::White);
+		let _ = writeln!(out, "IGD assignment {}", env!("CARGO_PKG_VERSION"));
+		color(out, Color::LightGray);
+
+		self::devices(out, devices);
+		files(out);
+
+		if !devices.is_empty() {
+			results(out, devices);
+		}
+
+		let _ = writeln!(out);
+		let _ = write!(out, "Overall: ");
+		mark(out, Some(status));
+		let _ = writeln!(out, " {:?}", status);
+		let _ = writeln!(out, "The log on the debug console has the details.");
+	});
+}
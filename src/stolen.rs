@@ -0,0 +1,129 @@
+// Stolen memory placement arithmetic, free of UEFI calls so it can be reasoned about (and run) on its
+// own. The allocation itself stays with the callers in the crate root.
+
+use crate::PAGE_SIZE;
+
+// how an overallocated range is cut down to an aligned one, all in bytes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Trim {
+	// padding to free below the aligned base
+	pub head: usize,
+	pub base: usize,
+	// padding to free above the aligned range
+	pub tail: usize,
+}
+
+// pages to overallocate by so an allocation always has room for a range aligned to `alignment`
+pub fn overallocation(alignment: usize) -> usize {
+	alignment / PAGE_SIZE - 1
+}
+
+// `allocation` is the page-aligned start of an allocation overallocated by `overallocation(alignment)`
+// pages, which always has room for the aligned range, however it is placed
+pub fn trim(allocation: usize, alignment: usize) -> Trim {
+	trim_padded(allocation, overallocation(alignment), alignment).unwrap()
+}
+
+// like `trim` for an allocation overallocated by only `padding` pages, `None` if it happens to be
+// placed such that the aligned range doesn't fit
+pub fn trim_padded(allocation: usize, padding: usize, alignment: usize) -> Option<Trim> {
+	let padding = padding * PAGE_SIZE;
+	let head = allocation.next_multiple_of(alignment) - allocation;
+
+	(head <= padding).then_some(Trim {
+		head,
+		base: allocation + head,
+		tail: padding - head,
+	})
+}
+
+// the paddings to try in turn when memory is tight, from the one that always works down to none
+pub fn paddings(alignment: usize) -> impl Iterator<Item = usize> {
+	let full = overallocation(alignment);
+	core::iter::successors(Some(full), |&p| (p > 0).then_some(p / 2))
+}
+
+// the highest base aligned to `alignment` that still has `pages` end at or below `limit`
+pub fn base_below(limit: usize, pages: usize, alignment: usize) -> Option<usize> {
+	Some(limit.checked_sub(pages * PAGE_SIZE)? & !(alignment - 1))
+}
+
+// the highest base aligned to `alignment` for `pages` within the free range `[start, end)`, ending at
+// or below `ceiling`
+pub fn base_within(start: u64, end: u64, pages: usize, alignment: usize, ceiling: u64) -> Option<usize> {
+	let limit = end.min(ceiling.saturating_add(1)).min(usize::MAX as u64) as usize;
+	base_below(limit, pages, alignment).filter(|&base| base as u64 >= start)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MIB: usize = 1024 * 1024;
+
+	#[test]
+	fn trim_aligned_allocation() {
+		// all of the padding is above the range
+		assert_eq!(trim(0x4000_0000, MIB), Trim { head: 0, base: 0x4000_0000, tail: MIB - PAGE_SIZE });
+	}
+
+	#[test]
+	fn trim_unaligned_allocation() {
+		assert_eq!(trim(0x4000_3000, MIB), Trim { head: 0xFD000, base: 0x4010_0000, tail: 0x2000 });
+		assert_eq!(trim(0x400F_F000, MIB), Trim { head: PAGE_SIZE, base: 0x4010_0000, tail: 0xFE000 });
+	}
+
+	#[test]
+	fn trim_every_placement() {
+		for alignment in [PAGE_SIZE, MIB, 8 * MIB] {
+			let padding = overallocation(alignment) * PAGE_SIZE;
+
+			for page in 0..alignment / PAGE_SIZE {
+				let allocation = 0x8000_0000 + page * PAGE_SIZE;
+				let t = trim(allocation, alignment);
+
+				assert_eq!(t.base % alignment, 0);
+				assert_eq!(t.base, allocation + t.head);
+				assert_eq!(t.head + t.tail, padding);
+			}
+		}
+	}
+
+	#[test]
+	fn trim_padded_fits() {
+		assert_eq!(trim_padded(0x400F_8000, 16, MIB), Some(Trim { head: 0x8000, base: 0x4010_0000, tail: 0x8000 }));
+		assert_eq!(trim_padded(0x4000_0000, 0, MIB), Some(Trim { head: 0, base: 0x4000_0000, tail: 0 }));
+	}
+
+	#[test]
+	fn trim_padded_too_little() {
+		assert_eq!(trim_padded(0x4000_3000, 16, MIB), None);
+		assert_eq!(trim_padded(0x400F_F000, 0, MIB), None);
+	}
+
+	#[test]
+	fn paddings_halve_down_to_none() {
+		assert!(paddings(MIB).eq([255, 127, 63, 31, 15, 7, 3, 1, 0]));
+		assert!(paddings(PAGE_SIZE).eq([0]));
+	}
+
+	#[test]
+	fn base_below_aligns_down() {
+		assert_eq!(base_below(0x8000_0000, 256, MIB), Some(0x7FF0_0000));
+		assert_eq!(base_below(0x7FFF_F000, 256, MIB), Some(0x7FE0_0000));
+		assert_eq!(base_below(0x1000, 2, MIB), None);
+	}
+
+	#[test]
+	fn base_within_free_range() {
+		let pages = 16 * MIB / PAGE_SIZE;
+
+		assert_eq!(base_within(0x1000_0000, 0x2000_0000, pages, MIB, 0xFFFF_FFFF), Some(0x1F00_0000));
+		// capped by the ceiling, not the end of the range
+		assert_eq!(base_within(0xF000_0000, 0x1_8000_0000, pages, MIB, 0xFFFF_FFFF), Some(0xFF00_0000));
+		// too small once aligned
+		assert_eq!(base_within(0x1000_0000, 0x1080_0000, pages, MIB, 0xFFFF_FFFF), None);
+		// entirely above the ceiling
+		assert_eq!(base_within(0x1_0000_0000, 0x2_0000_0000, pages, MIB, 0xFFFF_FFFF), None);
+	}
+}
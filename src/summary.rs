@@ -0,0 +1,84 @@
+// One block summarizing everything the driver changed, logged once setup is done, meant to be pasted
+// into bug reports as is.
+
+use log::{info, warn};
+
+use crate::{opregion, outcome::{DeviceOutcome, Region}};
+
+// where the VBT of the OpRegion at `r` is, read back from its header
+pub(crate) fn vbt_location(r: Region) -> Option<(usize, &'static str)> {
+	let data = unsafe { core::slice::from_raw_parts(r.base as *const u8, r.size) };
+	let version = opregion::version(data)?;
+
+	match opregion::extended_vbt(data, version) {
+		Some(vbt) if version >= opregion::VERSION_RELATIVE_RVDA => Some((r.base + vbt.rvda as usize, "extended, relative RVDA")),
+		Some(vbt) => Some((vbt.rvda as usize, "extended, absolute RVDA")),
+		None => opregion::vbt_range(data).map(|range| (r.base + range.start, "mailbox #4")),
+	}
+}
+
+fn log_device(d: &DeviceOutcome) {
+	let (seg, bus, dev, func) = d.location;
+	match d.generation {
+		Some(g) => info!("Device:       {:04x}:{:02x}:{:02x}.{}, ID {:04x}, {:?}", seg, bus, dev, func, d.device_id, g),
+		None => info!("Device:       {:04x}:{:02x}:{:02x}.{}, ID {:04x}, unknown generation", seg, bus, dev, func, d.device_id),
+	}
+
+	match (d.opregion, d.opregion_status) {
+		(Some(r), _) => {
+			let data = unsafe { core::slice::from_raw_parts(r.base as *const u8, r.size) };
+			match opregion::version(data) {
+				Some(v) => info!("OpRegion:     {:#x} ({} bytes), version {}.{}", r.base, r.size, v.major, v.minor),
+				None => info!("OpRegion:     {:#x} ({} bytes)", r.base, r.size),
+			}
+
+			match vbt_location(r) {
+				Some((addr, kind)) => info!("VBT:          {:#x} ({})", addr, kind),
+				None => info!("VBT:          none"),
+			}
+		}
+		(None, Some(status)) => info!("OpRegion:     failed ({:?})", status),
+		(None, None) => info!("OpRegion:     not attempted"),
+	}
+
+	match (d.stolen_memory, d.stolen_memory_status) {
+		(Some(r), _) => info!("StolenMemory: {:#x} ({} MiB)", r.base, r.size / 1024 / 1024),
+		(None, Some(status)) => info!("StolenMemory: failed ({:?})", status),
+		(None, None) => info!("StolenMemory: not attempted"),
+	}
+
+	if let Some(r) = d.gtt_stolen_memory {
+		info!("GTT:          {:#x} ({} MiB)", r.base, r.size / 1024 / 1024);
+	}
+
+	if let Some(ggc) = d.ggc {
+		info!("GGC:          {:#06x}", ggc);
+	}
+
+	for (name, reserved) in [("OpRegion", d.opregion_reserved), ("StolenMemory", d.stolen_memory_reserved)] {
+		if reserved == Some(false) {
+			warn!("{} isn't reserved in the memory map, the OS may reuse it!", name);
+		}
+	}
+}
+
+pub fn log(devices: &[DeviceOutcome]) {
+	if devices.is_empty() {
+		return;
+	}
+
+	info!("===== IGD assignment summary =====");
+
+	for d in devices {
+		log_device(d);
+	}
+
+	let consumed: usize = devices.iter()
+		.flat_map(|d| [d.opregion, d.stolen_memory, d.gtt_stolen_memory])
+		.flatten()
+		.map(|r| r.size)
+		.sum();
+
+	info!("Memory:       {} KiB set aside for {} device(s)", consumed / 1024, devices.len());
+	info!("==================================");
+}
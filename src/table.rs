@@ -0,0 +1,115 @@
+// EFI configuration table describing every device we configured, for bootloaders and OS tooling.
+//
+// Layout (all fields little-endian, version 1):
+//   IgdTableHeader, immediately followed by `count` IgdDeviceInfo entries of `entry_size` bytes each.
+// Consumers must use `entry_size` to step through the entries, so fields can be appended later
+// without breaking them. A base/size of zero means the corresponding step was not performed, the
+// statuses are EFI_STATUS values, or all ones if the step wasn't attempted on that device.
+
+use core::{ffi::c_void, mem::size_of, ptr::NonNull};
+use log::error;
+use uefi::{boot::{self, MemoryType}, guid, Guid, Status};
+
+use crate::outcome::DeviceOutcome;
+
+pub const IGD_TABLE_GUID: Guid = guid!("8ee3a5d1-3c7f-4b5e-9d2a-6e1f0c4b7a92");
+
+const IGD_TABLE_SIGNATURE: [u8; 4] = *b"IGDT";
+const IGD_TABLE_VERSION: u32 = 1;
+const STATUS_NOT_ATTEMPTED: u64 = u64::MAX;
+
+#[repr(C)]
+struct IgdTableHeader {
+	signature: [u8; 4],
+	version: u32,
+	count: u32,
+	entry_size: u32,
+}
+
+#[repr(C)]
+struct IgdDeviceInfo {
+	segment: u16,
+	bus: u8,
+	device: u8,
+	function: u8,
+	// 0 if unknown
+	generation: u8,
+	device_id: u16,
+	opregion_base: u64,
+	opregion_size: u64,
+	bdsm_base: u64,
+	bdsm_size: u64,
+	opregion_status: u64,
+	stolen_memory_status: u64,
+	bgsm_base: u64,
+	bgsm_size: u64,
+}
+
+// the previously installed table, freed once it has been replaced
+static mut CURRENT: Option<NonNull<u8>> = None;
+
+fn status(status: Option<Status>) -> u64 {
+	status.map_or(STATUS_NOT_ATTEMPTED, |s| s.0 as u64)
+}
+
+impl IgdDeviceInfo {
+	fn new(d: &DeviceOutcome) -> Self {
+		let (seg, bus, dev, func) = d.location;
+
+		IgdDeviceInfo {
+			segment: seg as u16,
+			bus: bus as u8,
+			device: dev as u8,
+			function: func as u8,
+			generation: d.generation.map_or(0, |g| g.number()),
+			device_id: d.device_id,
+			opregion_base: d.opregion.map_or(0, |r| r.base as u64),
+			opregion_size: d.opregion.map_or(0, |r| r.size as u64),
+			bdsm_base: d.stolen_memory.map_or(0, |r| r.base as u64),
+			bdsm_size: d.stolen_memory.map_or(0, |r| r.size as u64),
+			opregion_status: status(d.opregion_status),
+			stolen_memory_status: status(d.stolen_memory_status),
+			bgsm_base: d.gtt_stolen_memory.map_or(0, |r| r.base as u64),
+			bgsm_size: d.gtt_stolen_memory.map_or(0, |r| r.size as u64),
+		}
+	}
+}
+
+// (re)install the table, called whenever the set of configured devices changed
+pub fn publish(devices: &[DeviceOutcome]) {
+	let size = size_of::<IgdTableHeader>() + devices.len() * size_of::<IgdDeviceInfo>();
+
+	// the table has to outlive ExitBootServices
+	let buf = match boot::allocate_pool(MemoryType::RUNTIME_SERVICES_DATA, size) {
+		Ok(buf) => buf,
+		Err(e) => {
+			error!("Failed to allocate the configuration table: {:?}!", e.status());
+			return;
+		}
+	};
+
+	unsafe {
+		let header = buf.as_ptr() as *mut IgdTableHeader;
+		header.write_unaligned(IgdTableHeader {
+			signature: IGD_TABLE_SIGNATURE,
+			version: IGD_TABLE_VERSION,
+			count: devices.len() as u32,
+			entry_size: size_of::<IgdDeviceInfo>() as u32,
+		});
+
+		let entries = header.add(1) as *mut IgdDeviceInfo;
+		for (i, d) in devices.iter().enumerate() {
+			entries.add(i).write_unaligned(IgdDeviceInfo::new(d));
+		}
+
+		if let Err(e) = boot::install_configuration_table(&IGD_TABLE_GUID, buf.as_ptr() as *const c_void) {
+			error!("Failed to install the configuration table: {:?}!", e.status());
+			let _ = boot::free_pool(buf);
+			return;
+		}
+
+		if let Some(old) = CURRENT.replace(buf) {
+			let _ = boot::free_pool(old);
+		}
+	}
+}
@@ -0,0 +1,77 @@
+// Timing of the setup steps through EFI_TIMESTAMP_PROTOCOL, for the status record and the debug log.
+// OVMF only has it with TimestampDxe included, without it no timings are recorded.
+
+use log::{debug, log_enabled, Level};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::unsafe_protocol, Status};
+
+#[repr(C)]
+struct TimestampProperties {
+	frequency: u64,
+	end_value: u64,
+}
+
+#[repr(C)]
+struct TimestampProtocol {
+	get_timestamp: unsafe extern "efiapi" fn() -> u64,
+	get_properties: unsafe extern "efiapi" fn(*mut TimestampProperties) -> Status,
+}
+
+#[unsafe_protocol("afbfde41-2e6e-4262-ba65-62b9236e5495")]
+struct Timestamp(TimestampProtocol);
+
+// a counter value along with the properties needed to turn differences into time
+#[derive(Clone, Copy)]
+pub struct Instant {
+	ticks: u64,
+	frequency: u64,
+	end_value: u64,
+}
+
+pub fn now() -> Option<Instant> {
+	let handle = boot::get_handle_for_protocol::<Timestamp>().ok()?;
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let protocol = unsafe { boot::open_protocol::<Timestamp>(params, OpenProtocolAttributes::GetProtocol) }.ok()?;
+	let mut properties = TimestampProperties { frequency: 0, end_value: 0 };
+
+	if unsafe { (protocol.0.get_properties)(&mut properties) }.is_error() || properties.frequency == 0 {
+		return None;
+	}
+
+	Some(Instant {
+		ticks: unsafe { (protocol.0.get_timestamp)() },
+		frequency: properties.frequency,
+		end_value: properties.end_value,
+	})
+}
+
+impl Instant {
+	// microseconds since `self`, allowing for the counter wrapping once
+	pub fn elapsed_us(&self) -> Option<u64> {
+		let now = now()?;
+		let ticks = match now.ticks.checked_sub(self.ticks) {
+			Some(t) => t,
+			None => self.end_value - self.ticks + now.ticks + 1,
+		};
+
+		Some((ticks as u128 * 1_000_000 / self.frequency as u128) as u64)
+	}
+}
+
+// runs `f`, logging how long it took at debug level, e.g. reading a large VBT through port I/O
+pub fn timed<T>(what: &str, f: impl FnOnce() -> T) -> T {
+	// don't look up the protocol twice per step when nobody sees the result
+	let start = if log_enabled!(Level::Debug) { now() } else { None };
+	let result = f();
+
+	if let Some(us) = start.and_then(|s| s.elapsed_us()) {
+		debug!("{} took {} us", what, us);
+	}
+
+	result
+}
@@ -0,0 +1,63 @@
+// Measurement of the OpRegion and VBT into the TPM, so what the host injected shows up in the
+// event log and PCRs of attested guests instead of changing the boot silently.
+
+use alloc::{vec, vec::Vec};
+use core::mem::MaybeUninit;
+use log::{debug, error, info};
+use uefi::{boot::{self, OpenProtocolAttributes, OpenProtocolParams}, proto::tcg::{v2::{HashLogExtendEventFlags, PcrEventInputs, Tcg}, EventType, PcrIndex}, Status};
+
+use crate::CONFIG;
+
+// platform configuration, where data the host hands to the firmware belongs
+const PCR_INDEX: PcrIndex = PcrIndex(1);
+// room for the TCG2 event header in front of the event data
+const EVENT_HEADER_SIZE: usize = 64;
+
+// UEFI_PLATFORM_FIRMWARE_BLOB2: description length and text, then base and length
+fn blob_event_data(description: &str, base: usize, len: usize) -> Vec<u8> {
+	let mut data = Vec::with_capacity(1 + description.len() + 16);
+
+	data.push(description.len() as u8);
+	data.extend_from_slice(description.as_bytes());
+	data.extend_from_slice(&(base as u64).to_le_bytes());
+	data.extend_from_slice(&(len as u64).to_le_bytes());
+
+	data
+}
+
+// Without a TPM there is nothing to do. With one, a failed measurement is an error, as the guest would
+// otherwise run with firmware data its attestation doesn't cover.
+pub fn measure(description: &str, data: &[u8]) -> Result<(), Status> {
+	if unsafe { !CONFIG.measure } {
+		return Ok(());
+	}
+
+	let Ok(handle) = boot::get_handle_for_protocol::<Tcg>() else {
+		debug!("No TCG2 protocol, not measuring the {}", description);
+		return Ok(());
+	};
+
+	let params = OpenProtocolParams {
+		handle,
+		agent: boot::image_handle(),
+		controller: None,
+	};
+
+	let mut tcg = unsafe { boot::open_protocol::<Tcg>(params, OpenProtocolAttributes::GetProtocol) }.map_err(|e| {
+		error!("Failed to open the TCG2 protocol: {:?}!", e.status());
+		e.status()
+	})?;
+
+	let event_data = blob_event_data(description, data.as_ptr() as usize, data.len());
+	let mut buf = vec![MaybeUninit::uninit(); EVENT_HEADER_SIZE + event_data.len()];
+	let event = PcrEventInputs::new_in_buffer(&mut buf, PCR_INDEX, EventType::EFI_PLATFORM_FIRMWARE_BLOB2, &event_data)
+		.map_err(|_| Status::BUFFER_TOO_SMALL)?;
+
+	if let Err(e) = tcg.hash_log_extend_event(HashLogExtendEventFlags::empty(), data, event) {
+		error!("Failed to measure the {}: {:?}!", description, e.status());
+		return Err(Status::SECURITY_VIOLATION);
+	}
+
+	info!("Measured the {} ({} bytes) into PCR{}", description, data.len(), PCR_INDEX.0);
+	Ok(())
+}
@@ -0,0 +1,129 @@
+// A copy of the log in a ring buffer of ACPI NVS pages (tag 0x002D), which the OS leaves alone, so the
+// setup can be traced from the guest after boot without a serial console. The buffer is found through
+// the `IgdTraceBase` variable or a configuration table with GUID `IGD_TRACE_GUID`, both holding its
+// address, e.g. for `dd if=/dev/mem` on Linux.
+//
+// Layout (all fields little-endian, version 1):
+//   header: "IGDT", u16 version, u16 header size, u32 size of the ring, u32 lines written,
+//           u64 bytes written
+//   ring: one `<number> <level> <file>@<line>: <message>\n` after the other, wrapping around
+//
+// Once more bytes were written than the ring holds, the oldest data starts at `written % size`, and
+// everything up to the first newline there is a partially overwritten line to be dropped.
+
+use core::{ffi::c_void, fmt::{self, Arguments, Write}, mem::size_of, ptr::{addr_of, addr_of_mut},
+	sync::atomic::{AtomicBool, AtomicPtr, Ordering}};
+use log::{info, warn, Level};
+use uefi::{boot::{self, AllocateType, MemoryType}, cstr16, guid, runtime::{self, VariableAttributes}, Guid};
+
+use crate::{variables::IGD_VARIABLE_GUID, PAGE_SIZE};
+
+pub const IGD_TRACE_GUID: Guid = guid!("3c8e51d2-6a0f-4b7e-9d24-f1a7c05e8b69");
+
+const TRACE_MAGIC: [u8; 4] = *b"IGDT";
+const TRACE_VERSION: u16 = 1;
+
+#[repr(C)]
+struct TraceHeader {
+	magic: [u8; 4],
+	version: u16,
+	header_size: u16,
+	size: u32,
+	lines: u32,
+	written: u64,
+}
+
+static HEADER: AtomicPtr<TraceHeader> = AtomicPtr::new(core::ptr::null_mut());
+// set while a line is written, a line logged from a notify interrupting it is dropped
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+// writes bytes at the current position, wrapping around at the end of the ring
+struct Ring {
+	header: *mut TraceHeader,
+	data: *mut u8,
+	size: u64,
+}
+
+impl Write for Ring {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		unsafe {
+			let written = addr_of_mut!((*self.header).written);
+			let mut pos = written.read_volatile();
+
+			for b in s.bytes() {
+				self.data.add((pos % self.size) as usize).write_volatile(b);
+				pos += 1;
+			}
+
+			written.write_volatile(pos);
+		}
+
+		Ok(())
+	}
+}
+
+// `pages` of ACPI NVS below 4 GiB, so 32-bit tools can reach them too
+pub fn init(pages: u8) {
+	if pages == 0 || !HEADER.load(Ordering::Acquire).is_null() {
+		return;
+	}
+
+	let buf = match boot::allocate_pages(AllocateType::MaxAddress(u32::MAX as u64), MemoryType::ACPI_NON_VOLATILE, pages as usize) {
+		Ok(buf) => buf,
+		Err(e) => {
+			warn!("Failed to allocate {} pages for the trace buffer: {:?}", pages, e.status());
+			return;
+		}
+	};
+
+	let header = buf.as_ptr() as *mut TraceHeader;
+	let size = pages as usize * PAGE_SIZE - size_of::<TraceHeader>();
+
+	unsafe {
+		header.write_volatile(TraceHeader {
+			magic: TRACE_MAGIC,
+			version: TRACE_VERSION,
+			header_size: size_of::<TraceHeader>() as u16,
+			size: size as u32,
+			lines: 0,
+			written: 0,
+		});
+	}
+
+	let base = buf.as_ptr() as u64;
+	let attributes = VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS;
+
+	if let Err(e) = runtime::set_variable(cstr16!("IgdTraceBase"), &IGD_VARIABLE_GUID, attributes, &base.to_le_bytes()) {
+		warn!("Failed to set the IgdTraceBase variable: {:?}", e.status());
+	}
+
+	if let Err(e) = unsafe { boot::install_configuration_table(&IGD_TRACE_GUID, buf.as_ptr() as *const c_void) } {
+		warn!("Failed to install the trace buffer configuration table: {:?}", e.status());
+	}
+
+	HEADER.store(header, Ordering::Release);
+	info!("Tracing to {:#x}, {} bytes", base, size);
+}
+
+// appends a line, usable up to and inside ExitBootServices as it doesn't allocate
+pub fn record(level: Level, file: &str, line: u32, args: &Arguments) {
+	let header = HEADER.load(Ordering::Acquire);
+
+	if header.is_null() || BUSY.swap(true, Ordering::Acquire) {
+		return;
+	}
+
+	unsafe {
+		let lines = addr_of_mut!((*header).lines);
+		let mut ring = Ring {
+			header,
+			data: (header as *mut u8).add(size_of::<TraceHeader>()),
+			size: addr_of!((*header).size).read_volatile() as u64,
+		};
+
+		let _ = writeln!(ring, "{:06} {:>5} {}@{}: {}", lines.read_volatile(), level, file, line, args);
+		lines.write_volatile(lines.read_volatile().wrapping_add(1));
+	}
+
+	BUSY.store(false, Ordering::Release);
+}
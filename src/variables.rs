@@ -0,0 +1,27 @@
+// Volatile UEFI variables with the results for the IGD, readable from the guest through efivarfs,
+// e.g. /sys/firmware/efi/efivars/IgdOpRegionBase-<guid>. Each holds a little-endian u64, the status
+// is the EFI_STATUS of the whole setup. They are gone on the next boot, so they always describe the
+// current one.
+
+use log::warn;
+use uefi::{cstr16, guid, runtime::{self, VariableAttributes, VariableVendor}, CStr16, Status};
+
+use crate::outcome::DeviceOutcome;
+
+pub const IGD_VARIABLE_GUID: VariableVendor = VariableVendor(guid!("5d0f7c3e-94a1-4b8e-a6d2-73c1e8b94f05"));
+
+fn set(name: &CStr16, value: u64) {
+	let attributes = VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS;
+
+	if let Err(e) = runtime::set_variable(name, &IGD_VARIABLE_GUID, attributes, &value.to_le_bytes()) {
+		warn!("Failed to set the {} variable: {:?}", name, e.status());
+	}
+}
+
+pub fn write(igd: &DeviceOutcome, status: Status) {
+	set(cstr16!("IgdOpRegionBase"), igd.opregion.map_or(0, |r| r.base as u64));
+	set(cstr16!("IgdOpRegionSize"), igd.opregion.map_or(0, |r| r.size as u64));
+	set(cstr16!("IgdBdsmBase"), igd.stolen_memory.map_or(0, |r| r.base as u64));
+	set(cstr16!("IgdBdsmSize"), igd.stolen_memory.map_or(0, |r| r.size as u64));
+	set(cstr16!("IgdAssignStatus"), status.0 as u64);
+}
@@ -0,0 +1,107 @@
+// VBT field overrides, for patching a VBT in place after copying it (`etc/igd-vbt-patch`), on top of
+// the block iterator of `opregion`. Free of UEFI calls like it.
+//
+// A patch is a sequence of entries, all little-endian:
+//
+//   u8 block ID, 0 for offsets from the start of the VBT itself
+//   u16 offset into the block data
+//   u16 length
+//   length bytes written there
+//
+// A patch applies completely or not at all.
+//
+// The OpRegion header itself has no checksum, only the VBT does, so `fix_checksum` is needed wherever
+// bytes inside the VBT change, not for RVDA/RVDS, MBOX or other header and mailbox fields.
+
+use core::ops::Range;
+use log::debug;
+
+use crate::opregion::{self, VbtHeader, View};
+
+const ENTRY_HEADER_SIZE: usize = 5;
+
+fn le16(buf: &[u8], offset: usize) -> Option<u16> {
+	Some(u16::from_le_bytes(buf.get(offset..offset + 2)?.try_into().unwrap()))
+}
+
+// where the data of block `id` lives in `vbt`
+pub fn find_block(vbt: &[u8], id: u8) -> Option<Range<usize>> {
+	opregion::blocks(vbt)?.find(|block| block.id == id).map(|block| block.range)
+}
+
+// the range entry `(id, offset, len)` writes to
+fn target(vbt: &[u8], id: u8, offset: usize, len: usize) -> Result<Range<usize>, &'static str> {
+	let block = match id {
+		0 => 0..opregion::vbt_size(vbt).ok_or("VBT has no valid header")?,
+		id => find_block(vbt, id).ok_or("block is not in the VBT")?,
+	};
+
+	let start = block.start + offset;
+
+	if start + len > block.end {
+		return Err("entry exceeds its block");
+	}
+
+	Ok(start..start + len)
+}
+
+fn entries(patch: &[u8]) -> impl Iterator<Item = Result<(u8, usize, &[u8]), &'static str>> {
+	let mut rest = patch;
+
+	core::iter::from_fn(move || {
+		if rest.is_empty() {
+			return None;
+		}
+
+		let Some(header) = rest.get(..ENTRY_HEADER_SIZE) else {
+			rest = &[];
+			return Some(Err("entry header is truncated"));
+		};
+
+		let (id, offset, len) = (header[0], le16(header, 1)? as usize, le16(header, 3)? as usize);
+
+		let Some(data) = rest.get(ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + len) else {
+			rest = &[];
+			return Some(Err("entry data is truncated"));
+		};
+
+		rest = &rest[ENTRY_HEADER_SIZE + len..];
+		Some(Ok((id, offset, data)))
+	})
+}
+
+// recompute the checksum byte over the size the header states
+pub fn fix_checksum(vbt: &mut [u8]) {
+	let Some(size) = opregion::vbt_size(vbt) else {
+		return;
+	};
+
+	let header = VbtHeader::from_bytes_mut(vbt).unwrap();
+	let old = header.checksum;
+	header.checksum = 0;
+
+	let sum = vbt[..size].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+	let new = 0u8.wrapping_sub(sum);
+	VbtHeader::from_bytes_mut(vbt).unwrap().checksum = new;
+
+	debug!("VBT checksum {:#04x} -> {:#04x}", old, new);
+}
+
+// apply all entries of `patch` and fix up the checksum, returns the number of entries
+pub fn apply_patch(vbt: &mut [u8], patch: &[u8]) -> Result<usize, &'static str> {
+	// on a copy, an entry may change the layout the entries after it are looked up in
+	let mut patched = vbt.to_vec();
+	let mut count = 0;
+
+	for entry in entries(patch) {
+		let (id, offset, data) = entry?;
+		let range = target(&patched, id, offset, data.len())?;
+		patched[range].copy_from_slice(data);
+		count += 1;
+	}
+
+	fix_checksum(&mut patched);
+	vbt.copy_from_slice(&patched);
+
+	Ok(count)
+}
@@ -0,0 +1,114 @@
+// Pre-OS display output through the assigned IGD (tag 0x0030). The legacy VGA ranges are routed to it
+// by setting VGA Enable in the bridges above it and clearing it in every other bridge, as only one
+// path may claim them, and the IGD and its bridges get memory and IO decoding. Boot splash and legacy
+// VGA output then reach the passed-through display before the OS driver loads, whether a GOP driver
+// takes over from there or not.
+
+use log::{debug, info, warn};
+
+use crate::{args::Location, pci::{self, PciConfigAccess}, PCI_CFG_GGC_OFFSET};
+
+const PCI_CFG_HEADER_TYPE_OFFSET: u32 = 0x0E;
+const PCI_HEADER_TYPE_MASK: u16 = 0x7F;
+const PCI_HEADER_TYPE_BRIDGE: u16 = 0x01;
+// primary, secondary and subordinate bus number in the low three bytes
+const PCI_CFG_BUS_NUMBERS_OFFSET: u32 = 0x18;
+const PCI_CFG_BRIDGE_CONTROL_OFFSET: u32 = 0x3E;
+const PCI_BRIDGE_CONTROL_VGA: u16 = 1 << 3;
+// IVD, set if the IGD doesn't claim the VGA ranges
+const GGC_VGA_DISABLE: u16 = 1 << 1;
+
+// the secondary and subordinate bus of a PCI-to-PCI bridge, `None` for anything else
+fn bridge_buses<P: PciConfigAccess>(pci_io: &mut P) -> Option<(usize, usize)> {
+	if pci::read_u16(pci_io, PCI_CFG_HEADER_TYPE_OFFSET)? & PCI_HEADER_TYPE_MASK != PCI_HEADER_TYPE_BRIDGE {
+		return None;
+	}
+
+	let buses = pci::read_u32(pci_io, PCI_CFG_BUS_NUMBERS_OFFSET)?;
+	Some((((buses >> 8) & 0xFF) as usize, ((buses >> 16) & 0xFF) as usize))
+}
+
+fn set_vga_enable<P: PciConfigAccess>(pci_io: &mut P, location: Location, enable: bool) {
+	let (seg, bus, dev, func) = location;
+	let Some(control) = pci::read_u16(pci_io, PCI_CFG_BRIDGE_CONTROL_OFFSET) else {
+		warn!("Failed to read the bridge control of {:04x}:{:02x}:{:02x}.{}", seg, bus, dev, func);
+		return;
+	};
+
+	let new = if enable { control | PCI_BRIDGE_CONTROL_VGA } else { control & !PCI_BRIDGE_CONTROL_VGA };
+	if new == control {
+		return;
+	}
+
+	if pci::write_u16(pci_io, PCI_CFG_BRIDGE_CONTROL_OFFSET, new).is_none()
+		|| pci::read_u16(pci_io, PCI_CFG_BRIDGE_CONTROL_OFFSET) != Some(new) {
+		warn!("VGA Enable doesn't stick on bridge {:04x}:{:02x}:{:02x}.{}", seg, bus, dev, func);
+		return;
+	}
+
+	if enable {
+		info!("VGA Enable set on bridge {:04x}:{:02x}:{:02x}.{}", seg, bus, dev, func);
+	} else {
+		info!("VGA Enable cleared on bridge {:04x}:{:02x}:{:02x}.{}, the IGD gets the VGA ranges", seg, bus, dev, func);
+	}
+}
+
+// the IGD itself decodes the VGA ranges only with IVD clear, which vfio may not let through
+fn enable_igd<P: PciConfigAccess>(pci_io: &mut P) {
+	match pci::enable_decode(pci_io) {
+		Some((_, after)) if after & pci::PCI_COMMAND_ENABLE == pci::PCI_COMMAND_ENABLE => {}
+		Some((_, after)) => warn!("IGD command register reads back {:#06x}, not all decode bits stuck", after),
+		None => warn!("Failed to enable decoding on the IGD"),
+	}
+
+	let Some(ggc) = pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET) else {
+		return;
+	};
+
+	if ggc & GGC_VGA_DISABLE == 0 {
+		return;
+	}
+
+	let new = ggc & !GGC_VGA_DISABLE;
+	if pci::write_u16(pci_io, PCI_CFG_GGC_OFFSET, new).is_none() || pci::read_u16(pci_io, PCI_CFG_GGC_OFFSET) != Some(new) {
+		warn!("GGC {:#06x} keeps VGA decoding disabled on the IGD, only the UEFI framebuffer is available", ggc);
+		return;
+	}
+
+	debug!("GGC {:#06x} -> {:#06x}, VGA decoding enabled", ggc, new);
+}
+
+// route the VGA ranges to the IGD at `location`, whose PciIo is `pci_io`
+pub fn route<P: PciConfigAccess>(pci_io: &mut P, location: Location) {
+	let (seg, bus, _, _) = location;
+	let mut upstream = 0;
+
+	for mut bridge in pci::open_all_shared() {
+		let Ok(bridge_location) = bridge.get_location() else {
+			continue;
+		};
+
+		if bridge_location.0 != seg {
+			continue;
+		}
+
+		let Some((secondary, subordinate)) = bridge_buses(&mut bridge) else {
+			continue;
+		};
+
+		let above = (secondary..=subordinate).contains(&bus);
+		set_vga_enable(&mut bridge, bridge_location, above);
+
+		if above {
+			if pci::enable_decode(&mut bridge).is_none() {
+				warn!("Failed to enable decoding on bridge {:04x}:{:02x}:{:02x}.{}", bridge_location.0, bridge_location.1,
+					bridge_location.2, bridge_location.3);
+			}
+			upstream += 1;
+		}
+	}
+
+	enable_igd(pci_io);
+
+	info!("VGA ranges routed to the IGD through {} bridge(s)", upstream);
+}
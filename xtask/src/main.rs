@@ -0,0 +1,52 @@
+// `cargo xtask smoke [smoke_test.py arguments]`: builds the EFI binary and runs the QEMU/OVMF smoke
+// test against it, so a regression in the programming sequence shows up with a single command.
+
+use std::{
+	env,
+	path::{Path, PathBuf},
+	process::{Command, ExitCode},
+};
+
+const USAGE: &str = "usage: cargo xtask smoke --ovmf-code OVMF_CODE.fd --ovmf-vars OVMF_VARS.fd --device DEVICE [...]";
+
+fn workspace_root() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().to_path_buf()
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+	let status = command.status().map_err(|e| format!("can't run {:?}: {}", command.get_program(), e))?;
+
+	if !status.success() {
+		return Err(format!("{:?} failed with {}", command.get_program(), status));
+	}
+
+	Ok(())
+}
+
+fn smoke(args: &[String]) -> Result<(), String> {
+	let root = workspace_root();
+	let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+
+	// the EFI target and its linker flags come from .cargo/config.toml
+	run(Command::new(cargo).current_dir(&root).args(["build", "--release", "-p", "igd-assignment"]))?;
+
+	let efi = root.join("target/x86_64-unknown-uefi/release/igd-assignment.efi");
+	run(Command::new(root.join("scripts/smoke_test.py")).current_dir(&root).arg("--efi").arg(efi).args(args))
+}
+
+fn main() -> ExitCode {
+	let args: Vec<String> = env::args().skip(1).collect();
+
+	let result = match args.first().map(String::as_str) {
+		Some("smoke") => smoke(&args[1..]),
+		_ => Err(USAGE.to_string()),
+	};
+
+	match result {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("xtask: {}", e);
+			ExitCode::FAILURE
+		}
+	}
+}